@@ -1,11 +1,16 @@
 use std::env::current_dir;
 use std::fs::create_dir_all;
+use std::path::Path;
 
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 use cosmwasm_std::HandleResponse;
 
-use secretswap::PairInfo;
-use secretswap_factory::msg::{ConfigResponse, HandleMsg, InitMsg, PairsResponse, QueryMsg};
+use secretswap::{PairInfo, PairSettings};
+use secretswap_factory::msg::{
+    AdminResponse, ConfigResponse, CreatePairsResponse, DataEndpointResponse, HandleMsg, InitMsg,
+    PairTokenCodeResponse, PairsResponse, PendingPairFeeResponse, PendingSettingsResponse,
+    QueryMsg, TokenCodeHistoryResponse,
+};
 
 fn main() {
     let mut out_dir = current_dir().unwrap();
@@ -13,11 +18,26 @@ fn main() {
     create_dir_all(&out_dir).unwrap();
     remove_schemas(&out_dir).unwrap();
 
-    export_schema(&schema_for!(InitMsg), &out_dir);
-    export_schema(&schema_for!(HandleMsg), &out_dir);
-    export_schema(&schema_for!(HandleResponse), &out_dir);
-    export_schema(&schema_for!(QueryMsg), &out_dir);
-    export_schema(&schema_for!(PairInfo), &out_dir);
-    export_schema(&schema_for!(PairsResponse), &out_dir);
-    export_schema(&schema_for!(ConfigResponse), &out_dir);
+    export_schemas(&out_dir);
+}
+
+/// Every message the factory takes and every response it gives
+pub fn export_schemas(out_dir: &Path) {
+    let out_dir = &out_dir.to_path_buf();
+
+    export_schema(&schema_for!(InitMsg), out_dir);
+    export_schema(&schema_for!(HandleMsg), out_dir);
+    export_schema(&schema_for!(HandleResponse), out_dir);
+    export_schema(&schema_for!(QueryMsg), out_dir);
+    export_schema(&schema_for!(PairInfo), out_dir);
+    export_schema(&schema_for!(PairsResponse), out_dir);
+    export_schema(&schema_for!(ConfigResponse), out_dir);
+    export_schema(&schema_for!(PairSettings), out_dir);
+    export_schema(&schema_for!(PendingSettingsResponse), out_dir);
+    export_schema(&schema_for!(PendingPairFeeResponse), out_dir);
+    export_schema(&schema_for!(AdminResponse), out_dir);
+    export_schema(&schema_for!(DataEndpointResponse), out_dir);
+    export_schema(&schema_for!(TokenCodeHistoryResponse), out_dir);
+    export_schema(&schema_for!(PairTokenCodeResponse), out_dir);
+    export_schema(&schema_for!(CreatePairsResponse), out_dir);
 }
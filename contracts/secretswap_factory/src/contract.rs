@@ -1,17 +1,39 @@
 use cosmwasm_std::{
-    log, to_binary, Api, Binary, CanonicalAddr, CosmosMsg, Env, Extern, HandleResponse,
+    log, to_binary, Api, Binary, CanonicalAddr, CosmosMsg, Decimal, Env, Extern, HandleResponse,
     HandleResult, HumanAddr, InitResponse, Querier, StdError, StdResult, Storage, Uint128, WasmMsg,
 };
 use secret_toolkit::crypto::{sha_256, Prng};
 
 use secretswap::{
-    AssetInfo, Factory, Fee, InitHook, PairInfo, PairInfoRaw, PairInitMsg, PairSettings,
-    SwapDataEndpoint,
+    AssetInfo, AssetInfoRaw, CurveType, Factory, Fee, InitHook, PairInfo, PairInfoRaw, PairInitMsg,
+    PairSettings, SwapDataEndpoint, MAX_NOISE_BPS,
 };
 
-use crate::msg::{ConfigResponse, HandleMsg, InitMsg, PairsResponse, QueryMsg};
-use crate::querier::query_liquidity_token;
-use crate::state::{read_config, read_pair, read_pairs, store_config, store_pair, Config};
+use crate::msg::{
+    AdminResponse, ConfigResponse, CreatePairsResponse, DataEndpointResponse, HandleMsg, InitMsg,
+    PairFee, PairHandleMsg, PairTokenCodeResponse, PairsResponse, PendingPairFeeResponse,
+    PendingSettingsResponse, QueryMsg, TokenCodeHistoryResponse, TokenCodeVersion,
+};
+use crate::querier::{query_liquidity_token, query_pair_info};
+use crate::state::{
+    append_token_code_version, is_blocked_pair, read_config, read_pair, read_pair_asset_infos,
+    read_pair_count, read_pair_fee, read_pair_token_code, read_pairs, read_pending_admin,
+    read_pending_pair_fee, read_pending_settings, read_token_code_history, remove_pair,
+    remove_pair_asset_infos, remove_pair_fee, remove_pending_admin, remove_pending_pair_fee,
+    remove_pending_settings, store_blocked_pair, store_config, store_pair, store_pair_asset_infos,
+    store_pair_fee, store_pair_token_code, store_pending_admin, store_pending_pair_fee,
+    store_pending_settings, Config, PairTokenCode, PendingAdmin, PendingPairFee, PendingSettings,
+};
+
+/// Minimum delay before a pricing, endpoint or noise change applies, about 12 hours
+/// of blocks
+pub const SETTINGS_TIMELOCK_BLOCKS: u64 = 7200;
+
+/// Blocks a proposed admin has to claim the role, about a week
+pub const ADMIN_PROPOSAL_EXPIRY_BLOCKS: u64 = 100_000;
+
+/// Most pairs a CreatePairs batch instantiates, to stay under the block gas limit
+pub const MAX_CREATE_PAIRS_BATCH: usize = 10;
 
 pub fn init<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
@@ -31,12 +53,32 @@ pub fn init<S: Storage, A: Api, Q: Querier>(
             swap_fee: Fee {
                 commission_rate_nom: Uint128(3),
                 commission_rate_denom: Uint128(1000),
+                min_commission: Uint128::zero(),
             },
             swap_data_endpoint: None,
+            curve: CurveType::ConstantProduct,
+            initial_share_multiplier: None,
+            paused: false,
+            min_reserve: None,
+            noise_bps_max: None,
+            noise_per_block: false,
+            private_logs: false,
+            max_block_volume_bps: None,
+            reserve_floor_bps: 0,
+            price_oracle: None,
+            fee_on_input: false,
         },
     };
 
     store_config(&mut deps.storage, &config)?;
+    append_token_code_version(
+        &mut deps.storage,
+        TokenCodeVersion {
+            code_id: msg.token_code_id,
+            code_hash: msg.token_code_hash,
+            since_height: env.block.height,
+        },
+    )?;
 
     let mut messages: Vec<CosmosMsg> = vec![];
     if let Some(hook) = msg.init_hook {
@@ -61,29 +103,72 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
 ) -> HandleResult {
     match msg {
         HandleMsg::UpdateConfig {
-            owner,
-            token_code_id,
             pair_code_id,
             pair_code_hash,
-            token_code_hash,
-            swap_fee,
-            swap_data_endpoint,
+            initial_share_multiplier,
         } => try_update_config(
             deps,
             env,
-            owner,
-            token_code_id,
             pair_code_id,
             pair_code_hash,
-            token_code_hash,
-            swap_fee,
-            swap_data_endpoint,
+            initial_share_multiplier,
         ),
         HandleMsg::CreatePair {
             asset_infos,
             init_hook,
-        } => try_create_pair(deps, env, asset_infos, init_hook),
+            restricted,
+            curve,
+        } => try_create_pair(deps, env, asset_infos, init_hook, restricted, curve),
+        HandleMsg::CreatePairs { pairs } => try_create_pairs(deps, env, pairs),
         HandleMsg::Register { asset_infos } => try_register(deps, env, asset_infos),
+        HandleMsg::ProposeSettings {
+            settings,
+            effective_at_height,
+        } => try_propose_settings(deps, env, settings, effective_at_height),
+        HandleMsg::ApplySettings {} => try_apply_settings(deps, env),
+        HandleMsg::SetDataEndpoint { endpoint } => try_set_data_endpoint(deps, env, endpoint),
+        HandleMsg::SetNoiseConfig {
+            noise_bps_max,
+            noise_per_block,
+            private_logs,
+        } => try_set_noise_config(deps, env, noise_bps_max, noise_per_block, private_logs),
+        HandleMsg::SetPairFee {
+            pair,
+            commission_rate_nom,
+            commission_rate_denom,
+        } => try_set_pair_fee(deps, env, pair, commission_rate_nom, commission_rate_denom),
+        HandleMsg::RemovePairFee { pair } => try_remove_pair_fee(deps, env, pair),
+        HandleMsg::ApplyPairFee { pair } => try_apply_pair_fee(deps, env, pair),
+        HandleMsg::SetPaused { paused } => try_set_paused(deps, env, paused),
+        HandleMsg::ProposeAdmin { address } => try_propose_admin(deps, env, address),
+        HandleMsg::ClaimAdmin {} => try_claim_admin(deps, env),
+        HandleMsg::UpdateTokenCode { code_id, code_hash } => {
+            try_update_token_code(deps, env, code_id, code_hash)
+        }
+        HandleMsg::MigratePairToken { pair } => try_migrate_pair_token(deps, env, pair),
+        HandleMsg::DeregisterPair { asset_infos } => try_deregister_pair(deps, env, asset_infos),
+        HandleMsg::RegisterPairManually {
+            pair_address,
+            pair_code_hash,
+        } => try_register_pair_manually(deps, env, pair_address, pair_code_hash),
+        HandleMsg::SetPairFullExit { pair, enabled } => {
+            try_set_pair_full_exit(deps, env, pair, enabled)
+        }
+        HandleMsg::RotatePairViewingKeys { pair } => try_rotate_pair_viewing_keys(deps, env, pair),
+        HandleMsg::RampPairAmp {
+            pair,
+            target_amp,
+            end_height,
+        } => try_ramp_pair_amp(deps, env, pair, target_amp, end_height),
+        HandleMsg::ResetPairCircuitBreaker { pair } => {
+            try_reset_pair_circuit_breaker(deps, env, pair)
+        }
+        HandleMsg::AddToPairWhitelist { pair, addresses } => {
+            try_update_pair_whitelist(deps, env, pair, addresses, true)
+        }
+        HandleMsg::RemoveFromPairWhitelist { pair, addresses } => {
+            try_update_pair_whitelist(deps, env, pair, addresses, false)
+        }
     }
 }
 
@@ -91,52 +176,755 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
 pub fn try_update_config<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
-    owner: Option<HumanAddr>,
-    token_code_id: Option<u64>,
     pair_code_id: Option<u64>,
     pair_code_hash: Option<String>,
-    token_code_hash: Option<String>,
-    swap_fee: Option<Fee>,
-    swap_data_endpoint: Option<SwapDataEndpoint>,
+    initial_share_multiplier: Option<Uint128>,
 ) -> HandleResult {
     let mut config: Config = read_config(&deps.storage)?;
+    assert_admin(deps, &env, &config)?;
+
+    if let Some(pair_code_id) = pair_code_id {
+        config.pair_code_id = pair_code_id;
+    }
+
+    if let Some(pair_code_hash) = pair_code_hash {
+        config.pair_code_hash = pair_code_hash;
+    }
+
+    // the pricing settings and data endpoint only change behind the settings
+    // timelock, the LP token code through UpdateTokenCode
+    if let Some(initial_share_multiplier) = initial_share_multiplier {
+        config.pair_settings.initial_share_multiplier = Some(initial_share_multiplier);
+    }
+
+    validate_pair_settings(&config.pair_settings)?;
+    store_config(&mut deps.storage, &config)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("status", "success")], // See https://github.com/CosmWasm/wasmd/pull/386
+        data: None,
+    })
+}
 
-    // permission check
+/// Every admin-gated handle goes through here
+fn assert_admin<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    env: &Env,
+    config: &Config,
+) -> StdResult<()> {
     if deps.api.canonical_address(&env.message.sender)? != config.owner {
         return Err(StdError::unauthorized());
     }
+    Ok(())
+}
 
-    if let Some(owner) = owner {
-        config.owner = deps.api.canonical_address(&owner)?;
+fn validate_pair_settings(settings: &PairSettings) -> StdResult<()> {
+    let swap_fee = &settings.swap_fee;
+    if swap_fee.commission_rate_denom.is_zero()
+        || swap_fee.commission_rate_nom > swap_fee.commission_rate_denom
+    {
+        return Err(StdError::generic_err(
+            "commission rate must be between 0 and 1",
+        ));
     }
+    if settings.curve == (CurveType::Stable { amp: 0 }) {
+        return Err(StdError::generic_err("amp must be positive"));
+    }
+    if let CurveType::Weighted { .. } = settings.curve {
+        return Err(StdError::generic_err(
+            "Weights are set per pair, when it's created",
+        ));
+    }
+    if settings.initial_share_multiplier == Some(Uint128::zero()) {
+        return Err(StdError::generic_err(
+            "initial_share_multiplier must be positive",
+        ));
+    }
+    if let Some(price_oracle) = &settings.price_oracle {
+        if price_oracle.max_deviation_bps == 0 {
+            return Err(StdError::generic_err(
+                "price_oracle.max_deviation_bps must be positive",
+            ));
+        }
+    }
+    if settings.reserve_floor_bps > 10_000 {
+        return Err(StdError::generic_err(
+            "reserve_floor_bps must not exceed 10000",
+        ));
+    }
+    if settings.max_block_volume_bps == Some(0) {
+        return Err(StdError::generic_err(
+            "max_block_volume_bps must be positive",
+        ));
+    }
+    if settings.noise_bps_max > Some(MAX_NOISE_BPS) {
+        return Err(StdError::generic_err(format!(
+            "noise_bps_max must not exceed {}",
+            MAX_NOISE_BPS
+        )));
+    }
+    Ok(())
+}
+
+/// Whether `a` and `b` price swaps the same way and report them to the same endpoint
+fn same_pricing(a: &PairSettings, b: &PairSettings) -> bool {
+    a.swap_fee == b.swap_fee
+        && a.swap_data_endpoint == b.swap_data_endpoint
+        && a.curve == b.curve
+        && a.fee_on_input == b.fee_on_input
+        && a.price_oracle == b.price_oracle
+        && a.reserve_floor_bps == b.reserve_floor_bps
+        && a.max_block_volume_bps == b.max_block_volume_bps
+}
 
-    if let Some(token_code_id) = token_code_id {
-        config.token_code_id = token_code_id;
+fn same_noise_config(a: &PairSettings, b: &PairSettings) -> bool {
+    a.noise_bps_max == b.noise_bps_max
+        && a.noise_per_block == b.noise_per_block
+        && a.private_logs == b.private_logs
+}
+
+// Only owner can execute it
+pub fn try_propose_settings<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    settings: PairSettings,
+    effective_at_height: u64,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    assert_admin(deps, &env, &config)?;
+
+    validate_pair_settings(&settings)?;
+    if effective_at_height < env.block.height {
+        return Err(StdError::generic_err(
+            "effective_at_height must not be in the past",
+        ));
+    }
+    // pairs read their pricing live, so a sudden change could front-run every trader,
+    // and a new endpoint would learn about their swaps before they could notice
+    if !same_pricing(&settings, &config.pair_settings)
+        && effective_at_height < env.block.height.saturating_add(SETTINGS_TIMELOCK_BLOCKS)
+    {
+        return Err(StdError::generic_err(format!(
+            "A pricing or endpoint change can take effect {} blocks from now at the earliest",
+            SETTINGS_TIMELOCK_BLOCKS
+        )));
+    }
+    // LPs and traders rely on the privacy posture they signed up for
+    if !same_noise_config(&settings, &config.pair_settings)
+        && effective_at_height < env.block.height.saturating_add(SETTINGS_TIMELOCK_BLOCKS)
+    {
+        return Err(StdError::generic_err(format!(
+            "A noise or private_logs change can take effect {} blocks from now at the earliest",
+            SETTINGS_TIMELOCK_BLOCKS
+        )));
     }
 
-    if let Some(pair_code_id) = pair_code_id {
-        config.pair_code_id = pair_code_id;
+    // replaces any earlier proposal
+    store_pending_settings(
+        &mut deps.storage,
+        &PendingSettings {
+            settings,
+            effective_at_height,
+        },
+    )?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "propose_settings"),
+            log("effective_at_height", effective_at_height),
+        ],
+        data: None,
+    })
+}
+
+// Only owner can execute it
+pub fn try_set_data_endpoint<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    endpoint: Option<SwapDataEndpoint>,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    assert_admin(deps, &env, &config)?;
+
+    let earliest = env.block.height.saturating_add(SETTINGS_TIMELOCK_BLOCKS);
+    // amends a pending proposal rather than dropping it
+    let pending = match read_pending_settings(&deps.storage)? {
+        Some(pending) => PendingSettings {
+            settings: PairSettings {
+                swap_data_endpoint: endpoint.clone(),
+                ..pending.settings
+            },
+            effective_at_height: pending.effective_at_height.max(earliest),
+        },
+        None => PendingSettings {
+            settings: PairSettings {
+                swap_data_endpoint: endpoint.clone(),
+                ..config.pair_settings
+            },
+            effective_at_height: earliest,
+        },
+    };
+    store_pending_settings(&mut deps.storage, &pending)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "set_data_endpoint"),
+            log(
+                "endpoint",
+                endpoint.map_or_else(|| "none".to_string(), |endpoint| endpoint.address.0),
+            ),
+            log("effective_at_height", pending.effective_at_height),
+        ],
+        data: None,
+    })
+}
+
+/// Queues a new privacy posture for every pair, the same way SetDataEndpoint does
+pub fn try_set_noise_config<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    noise_bps_max: Option<u16>,
+    noise_per_block: bool,
+    private_logs: bool,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    assert_admin(deps, &env, &config)?;
+
+    let earliest = env.block.height.saturating_add(SETTINGS_TIMELOCK_BLOCKS);
+    let (settings, effective_at_height) = match read_pending_settings(&deps.storage)? {
+        Some(pending) => (pending.settings, pending.effective_at_height.max(earliest)),
+        None => (config.pair_settings, earliest),
+    };
+    let pending = PendingSettings {
+        settings: PairSettings {
+            noise_bps_max,
+            noise_per_block,
+            private_logs,
+            ..settings
+        },
+        effective_at_height,
+    };
+    validate_pair_settings(&pending.settings)?;
+    store_pending_settings(&mut deps.storage, &pending)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "set_noise_config"),
+            log("effective_at_height", pending.effective_at_height),
+        ],
+        data: None,
+    })
+}
+
+// Only owner can execute it
+pub fn try_set_pair_fee<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    pair: HumanAddr,
+    commission_rate_nom: Uint128,
+    commission_rate_denom: Uint128,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    assert_admin(deps, &env, &config)?;
+
+    if commission_rate_denom.is_zero() || commission_rate_nom > commission_rate_denom {
+        return Err(StdError::generic_err(
+            "commission rate must be between 0 and 1",
+        ));
+    }
+    let global_fee = &config.pair_settings.swap_fee;
+    if Decimal::from_ratio(commission_rate_nom, commission_rate_denom)
+        > Decimal::from_ratio(
+            global_fee.commission_rate_nom,
+            global_fee.commission_rate_denom,
+        )
+    {
+        return Err(StdError::generic_err(
+            "A pair's commission rate can't exceed the global rate",
+        ));
     }
 
-    if let Some(token_code_hash) = token_code_hash {
-        config.token_code_hash = token_code_hash;
+    let effective_at_height = queue_pair_fee(
+        deps,
+        &env,
+        &pair,
+        Some(PairFee {
+            commission_rate_nom,
+            commission_rate_denom,
+        }),
+    )?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "set_pair_fee"),
+            log("pair", pair.as_str()),
+            log(
+                "commission_rate",
+                format!("{}/{}", commission_rate_nom, commission_rate_denom),
+            ),
+            log("effective_at_height", effective_at_height),
+        ],
+        data: None,
+    })
+}
+
+// Only owner can execute it
+pub fn try_remove_pair_fee<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    pair: HumanAddr,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    assert_admin(deps, &env, &config)?;
+
+    let effective_at_height = queue_pair_fee(deps, &env, &pair, None)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "remove_pair_fee"),
+            log("pair", pair.as_str()),
+            log("effective_at_height", effective_at_height),
+        ],
+        data: None,
+    })
+}
+
+/// Queues a commission change for `pair` behind the settings timelock, replacing any
+/// pending one. Like a global fee change, a sudden one would front-run its traders.
+fn queue_pair_fee<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    pair: &HumanAddr,
+    fee: Option<PairFee>,
+) -> StdResult<u64> {
+    let effective_at_height = env.block.height.saturating_add(SETTINGS_TIMELOCK_BLOCKS);
+    store_pending_pair_fee(
+        &mut deps.storage,
+        &deps.api.canonical_address(pair)?,
+        &PendingPairFee {
+            fee,
+            effective_at_height,
+        },
+    )?;
+    Ok(effective_at_height)
+}
+
+pub fn try_apply_pair_fee<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    pair: HumanAddr,
+) -> HandleResult {
+    let pair_raw = deps.api.canonical_address(&pair)?;
+    let pending = read_pending_pair_fee(&deps.storage, &pair_raw)?
+        .ok_or_else(|| StdError::generic_err("No commission change is pending for the pair"))?;
+    if env.block.height < pending.effective_at_height {
+        return Err(StdError::generic_err(format!(
+            "The pending commission change takes effect at height {}",
+            pending.effective_at_height
+        )));
     }
 
-    if let Some(pair_code_hash) = pair_code_hash {
-        config.token_code_hash = pair_code_hash;
+    match pending.fee {
+        Some(fee) => store_pair_fee(&mut deps.storage, &pair_raw, &fee)?,
+        None => remove_pair_fee(&mut deps.storage, &pair_raw),
     }
+    remove_pending_pair_fee(&mut deps.storage, &pair_raw);
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "apply_pair_fee"), log("pair", pair.as_str())],
+        data: None,
+    })
+}
+
+// Only owner can execute it
+pub fn try_set_paused<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    paused: bool,
+) -> HandleResult {
+    let mut config: Config = read_config(&deps.storage)?;
+    assert_admin(deps, &env, &config)?;
+
+    config.pair_settings.paused = paused;
+    store_config(&mut deps.storage, &config)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "set_paused"), log("paused", paused)],
+        data: None,
+    })
+}
 
-    if let Some(swap_fee) = swap_fee {
-        config.pair_settings.swap_fee = swap_fee;
+// Only owner can execute it
+pub fn try_propose_admin<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    address: HumanAddr,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    assert_admin(deps, &env, &config)?;
+
+    let expires_at_height = env
+        .block
+        .height
+        .saturating_add(ADMIN_PROPOSAL_EXPIRY_BLOCKS);
+    store_pending_admin(
+        &mut deps.storage,
+        &PendingAdmin {
+            address: deps.api.canonical_address(&address)?,
+            expires_at_height,
+        },
+    )?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "propose_admin"),
+            log("pending_admin", address.as_str()),
+            log("expires_at_height", expires_at_height),
+        ],
+        data: None,
+    })
+}
+
+pub fn try_claim_admin<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> HandleResult {
+    let pending = read_pending_admin(&deps.storage)?
+        .ok_or_else(|| StdError::generic_err("No admin change is pending"))?;
+    if deps.api.canonical_address(&env.message.sender)? != pending.address {
+        return Err(StdError::unauthorized());
     }
+    if env.block.height > pending.expires_at_height {
+        return Err(StdError::generic_err(format!(
+            "The admin proposal expired at height {}",
+            pending.expires_at_height
+        )));
+    }
+
+    let mut config: Config = read_config(&deps.storage)?;
+    config.owner = pending.address;
+    store_config(&mut deps.storage, &config)?;
+    remove_pending_admin(&mut deps.storage);
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "claim_admin"),
+            log("admin", env.message.sender.as_str()),
+        ],
+        data: None,
+    })
+}
 
-    config.pair_settings.swap_data_endpoint = swap_data_endpoint;
+// Only owner can execute it
+pub fn try_update_token_code<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    code_id: u64,
+    code_hash: String,
+) -> HandleResult {
+    let mut config: Config = read_config(&deps.storage)?;
+    assert_admin(deps, &env, &config)?;
 
+    if read_token_code_history(&deps.storage)?.is_empty() {
+        // factories created before the history was kept, record what pairs used so far
+        append_token_code_version(
+            &mut deps.storage,
+            TokenCodeVersion {
+                code_id: config.token_code_id,
+                code_hash: config.token_code_hash.clone(),
+                since_height: 0,
+            },
+        )?;
+    }
+    append_token_code_version(
+        &mut deps.storage,
+        TokenCodeVersion {
+            code_id,
+            code_hash: code_hash.clone(),
+            since_height: env.block.height,
+        },
+    )?;
+    config.token_code_id = code_id;
+    config.token_code_hash = code_hash;
     store_config(&mut deps.storage, &config)?;
 
     Ok(HandleResponse {
         messages: vec![],
-        log: vec![log("status", "success")], // See https://github.com/CosmWasm/wasmd/pull/386
+        log: vec![log("action", "update_token_code"), log("code_id", code_id)],
+        data: None,
+    })
+}
+
+// Only owner can execute it
+pub fn try_migrate_pair_token<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    pair: HumanAddr,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    assert_admin(deps, &env, &config)?;
+
+    let pair_raw = deps.api.canonical_address(&pair)?;
+    let asset_infos = read_pair_asset_infos(&deps.storage, &pair_raw)?
+        .ok_or_else(|| StdError::generic_err(format!("{} is not a registered pair", pair)))?;
+    let mut token_code = read_pair_token_code(&deps.storage, &asset_infos)?.ok_or_else(|| {
+        StdError::generic_err(format!(
+            "{} was created before token codes were recorded",
+            pair
+        ))
+    })?;
+    if token_code.code_id == config.token_code_id {
+        return Err(StdError::generic_err(
+            "The pair already uses the current token code",
+        ));
+    }
+    token_code.code_id = config.token_code_id;
+    store_pair_token_code(&mut deps.storage, &asset_infos, &token_code)?;
+
+    let mut rng = Prng::new(
+        &config.prng_seed,
+        &[&env.block.time.to_be_bytes()[..], pair_raw.as_slice()].concat(),
+    );
+
+    Ok(HandleResponse {
+        messages: vec![CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: pair.clone(),
+            callback_code_hash: config.pair_code_hash,
+            msg: to_binary(&PairHandleMsg::UpdateLiquidityToken {
+                token_code_id: config.token_code_id,
+                token_code_hash: config.token_code_hash,
+                prng_seed: Binary::from(&rng.rand_bytes()),
+            })?,
+            send: vec![],
+        })],
+        log: vec![
+            log("action", "migrate_pair_token"),
+            log("pair", pair.as_str()),
+            log("code_id", token_code.code_id),
+        ],
+        data: None,
+    })
+}
+
+// Only owner can execute it
+pub fn try_set_pair_full_exit<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    pair: HumanAddr,
+    enabled: bool,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    assert_admin(deps, &env, &config)?;
+
+    Ok(HandleResponse {
+        messages: vec![CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: pair.clone(),
+            callback_code_hash: config.pair_code_hash,
+            msg: to_binary(&PairHandleMsg::SetFullExit { enabled })?,
+            send: vec![],
+        })],
+        log: vec![
+            log("action", "set_pair_full_exit"),
+            log("pair", pair.as_str()),
+            log("enabled", enabled),
+        ],
+        data: None,
+    })
+}
+
+// Only owner can execute it
+pub fn try_update_pair_whitelist<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    pair: HumanAddr,
+    addresses: Vec<HumanAddr>,
+    add: bool,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    assert_admin(deps, &env, &config)?;
+
+    let (action, msg) = if add {
+        (
+            "add_to_pair_whitelist",
+            PairHandleMsg::AddToWhitelist { addresses },
+        )
+    } else {
+        (
+            "remove_from_pair_whitelist",
+            PairHandleMsg::RemoveFromWhitelist { addresses },
+        )
+    };
+
+    // the addresses stay out of the logs, like in the pair
+    Ok(HandleResponse {
+        messages: vec![CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: pair.clone(),
+            callback_code_hash: config.pair_code_hash,
+            msg: to_binary(&msg)?,
+            send: vec![],
+        })],
+        log: vec![log("action", action), log("pair", pair.as_str())],
+        data: None,
+    })
+}
+
+// Only owner can execute it
+pub fn try_rotate_pair_viewing_keys<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    pair: HumanAddr,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    assert_admin(deps, &env, &config)?;
+
+    Ok(HandleResponse {
+        messages: vec![CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: pair.clone(),
+            callback_code_hash: config.pair_code_hash,
+            msg: to_binary(&PairHandleMsg::RotateViewingKeys {})?,
+            send: vec![],
+        })],
+        log: vec![
+            log("action", "rotate_pair_viewing_keys"),
+            log("pair", pair.as_str()),
+        ],
+        data: None,
+    })
+}
+
+// Only owner can execute it
+pub fn try_ramp_pair_amp<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    pair: HumanAddr,
+    target_amp: u64,
+    end_height: u64,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    assert_admin(deps, &env, &config)?;
+
+    // the pair checks the ramp against its current amp
+    Ok(HandleResponse {
+        messages: vec![CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: pair.clone(),
+            callback_code_hash: config.pair_code_hash,
+            msg: to_binary(&PairHandleMsg::RampAmp {
+                target_amp,
+                end_height,
+            })?,
+            send: vec![],
+        })],
+        log: vec![
+            log("action", "ramp_pair_amp"),
+            log("pair", pair.as_str()),
+            log("target_amp", target_amp),
+            log("end_height", end_height),
+        ],
+        data: None,
+    })
+}
+
+// Only owner can execute it
+pub fn try_reset_pair_circuit_breaker<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    pair: HumanAddr,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    assert_admin(deps, &env, &config)?;
+
+    Ok(HandleResponse {
+        messages: vec![CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: pair.clone(),
+            callback_code_hash: config.pair_code_hash,
+            msg: to_binary(&PairHandleMsg::ResetCircuitBreaker {})?,
+            send: vec![],
+        })],
+        log: vec![
+            log("action", "reset_pair_circuit_breaker"),
+            log("pair", pair.as_str()),
+        ],
+        data: None,
+    })
+}
+
+// Only owner can execute it
+pub fn try_deregister_pair<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    asset_infos: [AssetInfo; 2],
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    assert_admin(deps, &env, &config)?;
+
+    let raw_infos = [asset_infos[0].to_raw(&deps)?, asset_infos[1].to_raw(&deps)?];
+    let pair_info: PairInfoRaw = read_pair(&deps.storage, &raw_infos)?;
+    remove_pair(&mut deps.storage, &raw_infos)?;
+
+    let mut messages = vec![];
+    if pair_info.contract_addr != CanonicalAddr::default() {
+        // a blocklisted pair can't register into the slot of a new pair for
+        // the same assets, and stays paused however often it refreshes
+        store_blocked_pair(&mut deps.storage, &pair_info.contract_addr)?;
+        remove_pair_asset_infos(&mut deps.storage, &pair_info.contract_addr);
+        remove_pair_fee(&mut deps.storage, &pair_info.contract_addr);
+        remove_pending_pair_fee(&mut deps.storage, &pair_info.contract_addr);
+
+        let pair = deps.api.human_address(&pair_info.contract_addr)?;
+        messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: pair.clone(),
+            callback_code_hash: config.pair_code_hash,
+            msg: to_binary(&PairHandleMsg::PushSettings {
+                settings: query_pair_settings(deps, Some(pair))?,
+            })?,
+            send: vec![],
+        }));
+    }
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            log("action", "deregister_pair"),
+            log("pair", format!("{}-{}", asset_infos[0], asset_infos[1])),
+        ],
+        data: None,
+    })
+}
+
+pub fn try_apply_settings<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> HandleResult {
+    let pending = read_pending_settings(&deps.storage)?
+        .ok_or_else(|| StdError::generic_err("No settings are pending"))?;
+    if env.block.height < pending.effective_at_height {
+        return Err(StdError::generic_err(format!(
+            "The pending settings take effect at height {}",
+            pending.effective_at_height
+        )));
+    }
+
+    let mut config: Config = read_config(&deps.storage)?;
+    config.pair_settings = PairSettings {
+        paused: config.pair_settings.paused,
+        ..pending.settings
+    };
+    store_config(&mut deps.storage, &config)?;
+    remove_pending_settings(&mut deps.storage);
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "apply_settings")],
         data: None,
     })
 }
@@ -148,8 +936,103 @@ pub fn try_create_pair<S: Storage, A: Api, Q: Querier>(
     env: Env,
     asset_infos: [AssetInfo; 2],
     init_hook: Option<InitHook>,
+    restricted: bool,
+    curve: Option<CurveType>,
 ) -> HandleResult {
     let config: Config = read_config(&deps.storage)?;
+    let mut rng = Prng::new(&config.prng_seed, &env.block.time.to_be_bytes());
+    let (instantiate_msg, _) = create_pair(
+        deps,
+        &env,
+        &config,
+        &asset_infos,
+        restricted,
+        curve,
+        &mut rng,
+    )?;
+
+    let mut messages: Vec<CosmosMsg> = vec![instantiate_msg];
+    if let Some(hook) = init_hook {
+        messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: hook.contract_addr,
+            msg: hook.msg,
+            send: vec![],
+            callback_code_hash: hook.code_hash,
+        }));
+    }
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            log("action", "create_pair"),
+            log("pair", format!("{}-{}", asset_infos[0], asset_infos[1])),
+        ],
+        data: None,
+    })
+}
+
+// Anyone can execute it to create up to MAX_CREATE_PAIRS_BATCH pairs
+pub fn try_create_pairs<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    pairs: Vec<[AssetInfo; 2]>,
+) -> HandleResult {
+    if pairs.is_empty() || pairs.len() > MAX_CREATE_PAIRS_BATCH {
+        return Err(StdError::generic_err(format!(
+            "A batch creates between 1 and {} pairs",
+            MAX_CREATE_PAIRS_BATCH
+        )));
+    }
+    let mut keys: Vec<Vec<u8>> = vec![];
+    for asset_infos in pairs.iter() {
+        let raw_infos = [asset_infos[0].to_raw(&deps)?, asset_infos[1].to_raw(&deps)?];
+        let key = AssetInfoRaw::canonical_pair_key(&raw_infos);
+        if keys.contains(&key) {
+            return Err(StdError::generic_err(format!(
+                "Pair {}-{} appears twice in the batch",
+                asset_infos[0], asset_infos[1]
+            )));
+        }
+        if read_pair(&deps.storage, &raw_infos).is_ok() {
+            return Err(StdError::generic_err(format!(
+                "Pair {}-{} already exists",
+                asset_infos[0], asset_infos[1]
+            )));
+        }
+        keys.push(key);
+    }
+
+    // everything is checked before the first pair is stored, and a failing
+    // instantiation reverts the whole batch with the transaction
+    let config: Config = read_config(&deps.storage)?;
+    let mut rng = Prng::new(&config.prng_seed, &env.block.time.to_be_bytes());
+    let mut messages: Vec<CosmosMsg> = vec![];
+    let mut labels: Vec<String> = vec![];
+    for asset_infos in pairs.iter() {
+        let (instantiate_msg, label) =
+            create_pair(deps, &env, &config, asset_infos, false, None, &mut rng)?;
+        messages.push(instantiate_msg);
+        labels.push(label);
+    }
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![log("action", "create_pairs"), log("count", pairs.len())],
+        data: Some(to_binary(&CreatePairsResponse { labels })?),
+    })
+}
+
+/// Reserves the registry entry of a new pair and builds its instantiation, which
+/// registers the pair through its init hook. Returns the message and the pair's label.
+fn create_pair<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    config: &Config,
+    asset_infos: &[AssetInfo; 2],
+    restricted: bool,
+    curve: Option<CurveType>,
+    rng: &mut Prng,
+) -> StdResult<(CosmosMsg, String)> {
     let raw_infos = [asset_infos[0].to_raw(&deps)?, asset_infos[1].to_raw(&deps)?];
     if read_pair(&deps.storage, &raw_infos).is_ok() {
         return Err(StdError::generic_err("Pair already exists"));
@@ -160,7 +1043,7 @@ pub fn try_create_pair<S: Storage, A: Api, Q: Querier>(
         &PairInfoRaw {
             liquidity_token: CanonicalAddr::default(),
             contract_addr: CanonicalAddr::default(),
-            asset_infos: raw_infos,
+            asset_infos: raw_infos.clone(),
             token_code_hash: config.pair_code_hash.clone(),
             asset0_volume: Uint128(0),
             asset1_volume: Uint128(0),
@@ -170,53 +1053,60 @@ pub fn try_create_pair<S: Storage, A: Api, Q: Querier>(
             },
         },
     )?;
+    store_pair_token_code(
+        &mut deps.storage,
+        &raw_infos,
+        &PairTokenCode {
+            created_code_id: config.token_code_id,
+            code_id: config.token_code_id,
+        },
+    )?;
 
-    let mut rng = Prng::new(&config.prng_seed, &env.block.time.to_be_bytes());
     let pair_seed = rng.rand_bytes();
+    let label = format!(
+        "{}-{}-pair-{}-{}",
+        asset_infos[0],
+        asset_infos[1],
+        env.contract.address.clone(),
+        config.pair_code_id
+    );
 
-    let mut messages: Vec<CosmosMsg> = vec![CosmosMsg::Wasm(WasmMsg::Instantiate {
+    let instantiate_msg = CosmosMsg::Wasm(WasmMsg::Instantiate {
         code_id: config.pair_code_id,
         send: vec![],
-        label: format!(
-            "{}-{}-pair-{}-{}",
-            asset_infos[0],
-            asset_infos[1],
-            env.contract.address.clone(),
-            config.pair_code_id
-        ),
+        label: label.clone(),
         msg: to_binary(&PairInitMsg {
             asset_infos: asset_infos.clone(),
             token_code_id: config.token_code_id,
             token_code_hash: config.token_code_hash.clone(),
             init_hook: Some(InitHook {
-                contract_addr: env.contract.address,
-                code_hash: env.contract_code_hash,
+                contract_addr: env.contract.address.clone(),
+                code_hash: env.contract_code_hash.clone(),
                 msg: to_binary(&HandleMsg::Register {
                     asset_infos: asset_infos.clone(),
                 })?,
             }),
             prng_seed: Binary::from(&pair_seed),
+            settings_ttl_blocks: None,
+            wrap_config: None,
+            lp_token_name: None,
+            lp_token_symbol: None,
+            lp_token_decimals: None,
+            generic_label: false,
+            admin: None,
+            expected_factory: Some(Factory {
+                address: env.contract.address.clone(),
+                code_hash: env.contract_code_hash.clone(),
+            }),
+            existing_liquidity_token: None,
+            restricted,
+            curve,
+            weights: None,
         })?,
-        callback_code_hash: config.pair_code_hash,
-    })];
+        callback_code_hash: config.pair_code_hash.clone(),
+    });
 
-    if let Some(hook) = init_hook {
-        messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
-            contract_addr: hook.contract_addr,
-            msg: hook.msg,
-            send: vec![],
-            callback_code_hash: hook.code_hash,
-        }));
-    }
-
-    Ok(HandleResponse {
-        messages,
-        log: vec![
-            log("action", "create_pair"),
-            log("pair", format!("{}-{}", asset_infos[0], asset_infos[1])),
-        ],
-        data: None,
-    })
+    Ok((instantiate_msg, label))
 }
 
 /// create pair execute this message
@@ -227,23 +1117,28 @@ pub fn try_register<S: Storage, A: Api, Q: Querier>(
 ) -> HandleResult {
     let raw_infos = [asset_infos[0].to_raw(&deps)?, asset_infos[1].to_raw(&deps)?];
     let pair_info: PairInfoRaw = read_pair(&deps.storage, &raw_infos)?;
-    if pair_info.contract_addr != CanonicalAddr::default() {
+    let pair_contract = env.message.sender;
+    let pair_raw = deps.api.canonical_address(&pair_contract)?;
+    if is_blocked_pair(&deps.storage, &pair_raw)? {
+        return Err(StdError::generic_err("The pair was deregistered"));
+    }
+    // a registered pair comes back after MigratePairToken to update its LP token
+    if pair_info.contract_addr != CanonicalAddr::default() && pair_info.contract_addr != pair_raw {
         return Err(StdError::generic_err("Pair was already registered"));
     }
 
-    let pair_contract = env.message.sender;
-
     let config = read_config(&deps.storage)?;
 
     let liquidity_token = query_liquidity_token(&deps, &pair_contract, &config.pair_code_hash)?;
     store_pair(
         &mut deps.storage,
         &PairInfoRaw {
-            contract_addr: deps.api.canonical_address(&pair_contract)?,
+            contract_addr: pair_raw.clone(),
             liquidity_token: deps.api.canonical_address(&liquidity_token)?,
             ..pair_info
         },
     )?;
+    store_pair_asset_infos(&mut deps.storage, &pair_raw, &raw_infos)?;
 
     Ok(HandleResponse {
         messages: vec![],
@@ -255,6 +1150,74 @@ pub fn try_register<S: Storage, A: Api, Q: Querier>(
     })
 }
 
+// Only owner can execute it
+pub fn try_register_pair_manually<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    pair_address: HumanAddr,
+    pair_code_hash: String,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    assert_admin(deps, &env, &config)?;
+
+    let pair_raw = deps.api.canonical_address(&pair_address)?;
+    if is_blocked_pair(&deps.storage, &pair_raw)? {
+        return Err(StdError::generic_err("The pair was deregistered"));
+    }
+
+    let pair_info: PairInfo = query_pair_info(&deps, &pair_address, &pair_code_hash)?;
+    if pair_info.contract_addr != pair_address {
+        return Err(StdError::generic_err(format!(
+            "The pair reports its address as {}",
+            pair_info.contract_addr
+        )));
+    }
+    // the pair reads its settings from its factory, another one would never reach it
+    if pair_info.factory.address != env.contract.address
+        || pair_info.factory.code_hash != env.contract_code_hash
+    {
+        return Err(StdError::generic_err(format!(
+            "The pair belongs to factory {}",
+            pair_info.factory.address
+        )));
+    }
+
+    let raw_infos = [
+        pair_info.asset_infos[0].to_raw(&deps)?,
+        pair_info.asset_infos[1].to_raw(&deps)?,
+    ];
+    if read_pair(&deps.storage, &raw_infos).is_ok() {
+        return Err(StdError::generic_err("Pair already exists"));
+    }
+
+    store_pair(
+        &mut deps.storage,
+        &PairInfoRaw {
+            liquidity_token: deps.api.canonical_address(&pair_info.liquidity_token)?,
+            contract_addr: pair_raw.clone(),
+            asset_infos: raw_infos.clone(),
+            token_code_hash: pair_code_hash,
+            asset0_volume: Uint128(0),
+            asset1_volume: Uint128(0),
+            factory: pair_info.factory,
+        },
+    )?;
+    store_pair_asset_infos(&mut deps.storage, &pair_raw, &raw_infos)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "register_pair_manually"),
+            log(
+                "pair",
+                format!("{}-{}", pair_info.asset_infos[0], pair_info.asset_infos[1]),
+            ),
+            log("pair_contract_addr", pair_address),
+        ],
+        data: None,
+    })
+}
+
 pub fn query<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
     msg: QueryMsg,
@@ -265,7 +1228,15 @@ pub fn query<S: Storage, A: Api, Q: Querier>(
         QueryMsg::Pairs { start_after, limit } => {
             to_binary(&query_pairs(deps, start_after, limit)?)
         }
-        QueryMsg::PairSettings {} => to_binary(&query_pair_settings(deps)?),
+        QueryMsg::PairSettings { pair } => to_binary(&query_pair_settings(deps, pair)?),
+        QueryMsg::PendingSettings {} => to_binary(&query_pending_settings(deps)?),
+        QueryMsg::PendingPairFee { pair } => to_binary(&query_pending_pair_fee(deps, pair)?),
+        QueryMsg::Admin {} => to_binary(&query_admin(deps)?),
+        QueryMsg::DataEndpoint {} => to_binary(&query_data_endpoint(deps)?),
+        QueryMsg::TokenCodeHistory {} => to_binary(&query_token_code_history(deps)?),
+        QueryMsg::PairTokenCode { asset_infos } => {
+            to_binary(&query_pair_token_code(deps, asset_infos)?)
+        }
     }
 }
 
@@ -291,6 +1262,9 @@ pub fn query_pair<S: Storage, A: Api, Q: Querier>(
 ) -> StdResult<PairInfo> {
     let raw_infos = [asset_infos[0].to_raw(&deps)?, asset_infos[1].to_raw(&deps)?];
     let pair_info: PairInfoRaw = read_pair(&deps.storage, &raw_infos)?;
+    if is_blocked_pair(&deps.storage, &pair_info.contract_addr)? {
+        return Err(StdError::generic_err("no pair data stored"));
+    }
     pair_info.to_normal(&deps)
 }
 
@@ -306,15 +1280,102 @@ pub fn query_pairs<S: Storage, A: Api, Q: Querier>(
     };
 
     let pairs: Vec<PairInfo> = read_pairs(&deps, start_after, limit)?;
-    let resp = PairsResponse { pairs };
+    let resp = PairsResponse {
+        pairs,
+        total: read_pair_count(&deps.storage)?,
+    };
 
     Ok(resp)
 }
 
+/// The applied settings, a proposal only shows up here after ApplySettings and a
+/// commission change for the pair after ApplyPairFee
 pub fn query_pair_settings<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
+    pair: Option<HumanAddr>,
 ) -> StdResult<PairSettings> {
-    let config = read_config(&deps.storage)?;
+    let mut config = read_config(&deps.storage)?;
+
+    if let Some(pair) = pair {
+        let pair = deps.api.canonical_address(&pair)?;
+        if let Some(pair_fee) = read_pair_fee(&deps.storage, &pair)? {
+            config.pair_settings.swap_fee.commission_rate_nom = pair_fee.commission_rate_nom;
+            config.pair_settings.swap_fee.commission_rate_denom = pair_fee.commission_rate_denom;
+        }
+        if is_blocked_pair(&deps.storage, &pair)? {
+            config.pair_settings.paused = true;
+        }
+    }
 
     Ok(config.pair_settings)
 }
+
+pub fn query_pending_settings<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<Option<PendingSettingsResponse>> {
+    Ok(
+        read_pending_settings(&deps.storage)?.map(|pending| PendingSettingsResponse {
+            settings: pending.settings,
+            effective_at_height: pending.effective_at_height,
+        }),
+    )
+}
+
+pub fn query_pending_pair_fee<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    pair: HumanAddr,
+) -> StdResult<Option<PendingPairFeeResponse>> {
+    let pair = deps.api.canonical_address(&pair)?;
+    Ok(
+        read_pending_pair_fee(&deps.storage, &pair)?.map(|pending| PendingPairFeeResponse {
+            fee: pending.fee,
+            effective_at_height: pending.effective_at_height,
+        }),
+    )
+}
+
+pub fn query_admin<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<AdminResponse> {
+    let config: Config = read_config(&deps.storage)?;
+    let pending = read_pending_admin(&deps.storage)?;
+    Ok(AdminResponse {
+        admin: deps.api.human_address(&config.owner)?,
+        pending_admin: pending
+            .as_ref()
+            .map(|pending| deps.api.human_address(&pending.address))
+            .transpose()?,
+        pending_admin_expires_at_height: pending.map(|pending| pending.expires_at_height),
+    })
+}
+
+/// The applied endpoint, a queued change shows up in PendingSettings until ApplySettings
+pub fn query_data_endpoint<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<DataEndpointResponse> {
+    let config: Config = read_config(&deps.storage)?;
+    Ok(DataEndpointResponse {
+        endpoint: config.pair_settings.swap_data_endpoint,
+    })
+}
+
+pub fn query_token_code_history<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<TokenCodeHistoryResponse> {
+    Ok(TokenCodeHistoryResponse {
+        versions: read_token_code_history(&deps.storage)?,
+    })
+}
+
+pub fn query_pair_token_code<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    asset_infos: [AssetInfo; 2],
+) -> StdResult<Option<PairTokenCodeResponse>> {
+    let raw_infos = [asset_infos[0].to_raw(&deps)?, asset_infos[1].to_raw(&deps)?];
+    Ok(
+        read_pair_token_code(&deps.storage, &raw_infos)?.map(|token_code| PairTokenCodeResponse {
+            created_code_id: token_code.created_code_id,
+            code_id: token_code.code_id,
+        }),
+    )
+}
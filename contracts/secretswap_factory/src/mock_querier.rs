@@ -2,13 +2,15 @@ use std::collections::HashMap;
 
 use cosmwasm_std::testing::{MockApi, MockQuerier, MockStorage, MOCK_CONTRACT_ADDR};
 use cosmwasm_std::{
-    from_slice, to_binary, Api, Coin, Empty, Extern, HumanAddr, Querier, QuerierResult,
-    QueryRequest, SystemError, WasmQuery,
+    from_binary, from_slice, to_binary, Api, Coin, Empty, Extern, HumanAddr, Querier,
+    QuerierResult, QueryRequest, SystemError, WasmQuery,
 };
 use cosmwasm_storage::to_length_prefixed;
 
 use secretswap::{AssetInfoRaw, PairInfo, PairInfoRaw};
 
+use crate::querier::QueryMsgPair;
+
 /// mock_dependencies is a drop-in replacement for cosmwasm_std::testing::mock_dependencies
 /// this uses our CustomQuerier.
 pub fn mock_dependencies(
@@ -113,6 +115,20 @@ impl WasmMockQuerier {
                     panic!("DO NOT ENTER HERE")
                 }
             }
+            QueryRequest::Wasm(WasmQuery::Smart {
+                contract_addr, msg, ..
+            }) => match from_binary(msg) {
+                Ok(QueryMsgPair::Pair {}) => {
+                    match self.terraswap_pair_querier.pairs.get(contract_addr) {
+                        Some(pair_info) => Ok(to_binary(pair_info)),
+                        None => Err(SystemError::InvalidRequest {
+                            error: format!("PairInfo is not found for {}", contract_addr),
+                            request: msg.as_slice().into(),
+                        }),
+                    }
+                }
+                _ => panic!("DO NOT ENTER HERE"),
+            },
             _ => self.base.handle_query(request),
         }
     }
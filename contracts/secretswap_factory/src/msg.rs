@@ -1,8 +1,8 @@
-use cosmwasm_std::{Binary, HumanAddr};
+use cosmwasm_std::{Binary, HumanAddr, Uint128};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use secretswap::{AssetInfo, Fee, InitHook, PairInfo, PairSettings, SwapDataEndpoint};
+use secretswap::{AssetInfo, CurveType, InitHook, PairInfo, PairSettings, SwapDataEndpoint};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct InitMsg {
@@ -20,13 +20,10 @@ pub struct InitMsg {
 pub enum HandleMsg {
     /// UpdateConfig update relevant code IDs
     UpdateConfig {
-        owner: Option<HumanAddr>,
-        token_code_id: Option<u64>,
         pair_code_id: Option<u64>,
         pair_code_hash: Option<String>,
-        token_code_hash: Option<String>,
-        swap_fee: Option<Fee>,
-        swap_data_endpoint: Option<SwapDataEndpoint>,
+        /// Scales the first LP supply of new deposits into empty pairs
+        initial_share_multiplier: Option<Uint128>,
     },
     /// CreatePair instantiates pair contract
     CreatePair {
@@ -34,19 +31,155 @@ pub enum HandleMsg {
         asset_infos: [AssetInfo; 2],
         /// Init hook for after works
         init_hook: Option<InitHook>,
+        /// Only addresses whitelisted with AddToPairWhitelist may trade and provide
+        /// liquidity in the pair
+        #[serde(default)]
+        restricted: bool,
+        /// Curve the pair swaps on instead of this factory's, e.g. stable for pegged assets
+        #[serde(default)]
+        curve: Option<CurveType>,
     },
+    /// CreatePairs creates up to `MAX_CREATE_PAIRS_BATCH` pairs at once, all or none.
+    /// The response data is a `CreatePairsResponse`.
+    CreatePairs { pairs: Vec<[AssetInfo; 2]> },
     /// Register is invoked from created pair contract after initialzation
     Register { asset_infos: [AssetInfo; 2] },
+    /// Queues new pair settings. A change to the swap fee, curve, `fee_on_input`,
+    /// price oracle, reserve floor, block volume limit, data endpoint or noise must be
+    /// at least `SETTINGS_TIMELOCK_BLOCKS` away so traders can react before it applies.
+    /// `paused` is ignored, SetPaused controls it.
+    ProposeSettings {
+        settings: PairSettings,
+        effective_at_height: u64,
+    },
+    /// Queues a new endpoint for the swap data callback, None removes it. Applies with
+    /// ApplySettings after `SETTINGS_TIMELOCK_BLOCKS`, on top of any pending proposal.
+    SetDataEndpoint { endpoint: Option<SwapDataEndpoint> },
+    /// Queues the noise and log privacy of every pair, applied with ApplySettings
+    /// after `SETTINGS_TIMELOCK_BLOCKS` on top of any pending proposal
+    SetNoiseConfig {
+        noise_bps_max: Option<u16>,
+        noise_per_block: bool,
+        private_logs: bool,
+    },
+    /// Applies the proposed settings once their effective height is reached, anyone can call it
+    ApplySettings {},
+    /// Queues a commission rate override for one pair, applied with ApplyPairFee after
+    /// `SETTINGS_TIMELOCK_BLOCKS`. It replaces any change pending for the pair, and
+    /// can't exceed the global rate, raising that goes through ProposeSettings.
+    SetPairFee {
+        pair: HumanAddr,
+        commission_rate_nom: Uint128,
+        commission_rate_denom: Uint128,
+    },
+    /// Queues putting the pair back on the global commission rate, like SetPairFee
+    RemovePairFee { pair: HumanAddr },
+    /// Applies the pending commission change of `pair` once its effective height is
+    /// reached, anyone can call it
+    ApplyPairFee { pair: HumanAddr },
+    /// Halts or resumes swaps and deposits on every pair, takes effect right away
+    SetPaused { paused: bool },
+    /// Offers the admin role to `address`, which has to claim it before the offer expires.
+    /// A new proposal replaces the previous one.
+    ProposeAdmin { address: HumanAddr },
+    /// Accepts a pending ProposeAdmin, only the proposed address can call it
+    ClaimAdmin {},
+    /// Sets the LP token code of new pairs and appends it to the token code history
+    UpdateTokenCode { code_id: u64, code_hash: String },
+    /// Moves a registered pair to the current LP token code. The pair only accepts
+    /// it while its LP token has no supply.
+    MigratePairToken { pair: HumanAddr },
+    /// Removes a pair from the registry and blocklists its contract, which is paused
+    /// for good. The same assets can be paired again with CreatePair.
+    DeregisterPair { asset_infos: [AssetInfo; 2] },
+    /// Adds a pair that was instantiated without CreatePair to the registry. The pair
+    /// must already name this factory as its own.
+    RegisterPairManually {
+        pair_address: HumanAddr,
+        pair_code_hash: String,
+    },
+    /// Lets liquidity providers of `pair` withdraw below the `min_reserve` floor
+    SetPairFullExit { pair: HumanAddr, enabled: bool },
+    /// Gives `pair` new viewing keys for its token balances
+    RotatePairViewingKeys { pair: HumanAddr },
+    /// Reopens `pair` after its reserve circuit breaker paused it
+    ResetPairCircuitBreaker { pair: HumanAddr },
+    /// Moves the amp of the stable `pair` to `target_amp`, linearly until `end_height`
+    RampPairAmp {
+        pair: HumanAddr,
+        target_amp: u64,
+        end_height: u64,
+    },
+    /// Approves `addresses` to trade and provide liquidity in the restricted `pair`
+    AddToPairWhitelist {
+        pair: HumanAddr,
+        addresses: Vec<HumanAddr>,
+    },
+    /// Revokes `addresses` from the whitelist of `pair`
+    RemoveFromPairWhitelist {
+        pair: HumanAddr,
+        addresses: Vec<HumanAddr>,
+    },
+}
+
+/// Pair handles the factory calls, copied from secretswap_pair
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PairHandleMsg {
+    UpdateLiquidityToken {
+        token_code_id: u64,
+        token_code_hash: String,
+        prng_seed: Binary,
+    },
+    PushSettings {
+        settings: PairSettings,
+    },
+    SetFullExit {
+        enabled: bool,
+    },
+    RotateViewingKeys {},
+    ResetCircuitBreaker {},
+    RampAmp {
+        target_amp: u64,
+        end_height: u64,
+    },
+    AddToWhitelist {
+        addresses: Vec<HumanAddr>,
+    },
+    RemoveFromWhitelist {
+        addresses: Vec<HumanAddr>,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
     Config {},
-    PairSettings {},
+    /// Settings for `pair`, with its commission override applied if it has one.
+    /// Deregistered pairs always get `paused`.
+    PairSettings {
+        pair: Option<HumanAddr>,
+    },
+    /// Settings waiting for ApplySettings, if any
+    PendingSettings {},
+    /// Commission change of `pair` waiting for ApplyPairFee, if any
+    PendingPairFee {
+        pair: HumanAddr,
+    },
+    /// Current admin and the proposed one, if any
+    Admin {},
+    /// Endpoint pairs currently send swap data to
+    DataEndpoint {},
+    /// Every LP token code the factory has used, oldest first
+    TokenCodeHistory {},
+    /// LP token code the pair was created with and the one it runs now, if recorded
+    PairTokenCode {
+        asset_infos: [AssetInfo; 2],
+    },
     Pair {
         asset_infos: [AssetInfo; 2],
     },
+    /// Registered pairs in creation order, 10 per page by default and at most 30
     Pairs {
         start_after: Option<[AssetInfo; 2]>,
         limit: Option<u32>,
@@ -64,8 +197,68 @@ pub struct ConfigResponse {
     pub pair_settings: PairSettings,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PendingSettingsResponse {
+    pub settings: PairSettings,
+    pub effective_at_height: u64,
+}
+
+/// Commission rate a pair charges instead of the global one
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PairFee {
+    pub commission_rate_nom: Uint128,
+    pub commission_rate_denom: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PendingPairFeeResponse {
+    /// None puts the pair back on the global rate
+    pub fee: Option<PairFee>,
+    pub effective_at_height: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AdminResponse {
+    pub admin: HumanAddr,
+    pub pending_admin: Option<HumanAddr>,
+    /// Last height at which `pending_admin` can claim
+    pub pending_admin_expires_at_height: Option<u64>,
+}
+
+/// An LP token code and the height it was set at
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TokenCodeVersion {
+    pub code_id: u64,
+    pub code_hash: String,
+    pub since_height: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TokenCodeHistoryResponse {
+    pub versions: Vec<TokenCodeVersion>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PairTokenCodeResponse {
+    pub created_code_id: u64,
+    pub code_id: u64,
+}
+
+/// Labels of the pairs a CreatePairs instantiates, in batch order
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CreatePairsResponse {
+    pub labels: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DataEndpointResponse {
+    pub endpoint: Option<SwapDataEndpoint>,
+}
+
 // We define a custom struct for each query response
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct PairsResponse {
     pub pairs: Vec<PairInfo>,
+    /// Number of registered pairs
+    pub total: u64,
 }
@@ -13,17 +13,22 @@ pub enum QueryMsgPair {
     Pair {},
 }
 
-pub fn query_liquidity_token<S: Storage, A: Api, Q: Querier>(
+pub fn query_pair_info<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
     contract_addr: &HumanAddr,
     code_hash: &String,
-) -> StdResult<HumanAddr> {
-    // load price form the oracle
-    let pair_info: PairInfo = deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+) -> StdResult<PairInfo> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
         callback_code_hash: code_hash.clone(),
         contract_addr: contract_addr.clone(),
         msg: to_binary(&QueryMsgPair::Pair {})?,
-    }))?;
+    }))
+}
 
-    Ok(pair_info.liquidity_token)
+pub fn query_liquidity_token<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    contract_addr: &HumanAddr,
+    code_hash: &String,
+) -> StdResult<HumanAddr> {
+    Ok(query_pair_info(deps, contract_addr, code_hash)?.liquidity_token)
 }
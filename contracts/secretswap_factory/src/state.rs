@@ -3,9 +3,34 @@ use cosmwasm_storage::{Bucket, ReadonlyBucket, ReadonlySingleton, Singleton};
 use schemars::JsonSchema;
 use secretswap::{AssetInfoRaw, PairInfo, PairInfoRaw, PairSettings};
 use serde::{Deserialize, Serialize};
+
+use crate::msg::{PairFee, TokenCodeVersion};
+
 static KEY_CONFIG: &[u8] = b"config";
+/// Legacy registry index, a single list of every pair key
 static PAIR_TRACKER: &[u8] = b"pair_tracker";
 static PREFIX_PAIR_INFO: &[u8] = b"pair_info";
+static KEY_PAIR_COUNT: &[u8] = b"pair_count";
+/// Pair keys by creation index
+static PREFIX_PAIR_KEYS: &[u8] = b"pair_keys";
+/// Creation index by pair key
+static PREFIX_PAIR_INDEX: &[u8] = b"pair_index";
+static KEY_PENDING_SETTINGS: &[u8] = b"pending_settings";
+/// Commission overrides by pair contract
+static PREFIX_PAIR_FEE: &[u8] = b"pair_fee";
+/// Queued commission override changes by pair contract
+static PREFIX_PENDING_PAIR_FEE: &[u8] = b"pending_pair_fee";
+static KEY_PENDING_ADMIN: &[u8] = b"pending_admin";
+static KEY_TOKEN_CODE_HISTORY: &[u8] = b"token_code_history";
+/// LP token code ids by pair key
+static PREFIX_PAIR_TOKEN_CODE: &[u8] = b"pair_token_code";
+/// Asset infos by pair contract
+static PREFIX_PAIR_ASSET_INFOS: &[u8] = b"pair_asset_infos";
+/// Pair contracts removed by DeregisterPair
+static PREFIX_BLOCKED_PAIR: &[u8] = b"blocked_pair";
+/// Creation slots left empty by DeregisterPair
+static KEY_REMOVED_PAIR_COUNT: &[u8] = b"removed_pair_count";
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Config {
     pub owner: CanonicalAddr,
@@ -16,38 +41,283 @@ pub struct Config {
     pub prng_seed: Vec<u8>,
     pub pair_settings: PairSettings,
 }
+
+/// Pair settings proposed by the owner, applied once `effective_at_height` is reached
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PendingSettings {
+    pub settings: PairSettings,
+    pub effective_at_height: u64,
+}
+
+/// Commission override change for one pair, applied once `effective_at_height` is reached
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PendingPairFee {
+    /// None puts the pair back on the global rate
+    pub fee: Option<PairFee>,
+    pub effective_at_height: u64,
+}
+
+/// Admin handover waiting for the new admin to claim it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PendingAdmin {
+    pub address: CanonicalAddr,
+    pub expires_at_height: u64,
+}
+
+/// LP token code a pair was created with and the one it runs now
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PairTokenCode {
+    pub created_code_id: u64,
+    pub code_id: u64,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default, JsonSchema)]
 pub struct PairTracker(pub Vec<Vec<u8>>);
-pub fn store_pair_tracker<S: Storage>(storage: &mut S, data: &PairTracker) -> StdResult<()> {
-    Singleton::new(storage, PAIR_TRACKER).save(data)
+
+fn read_pair_tracker<S: Storage>(storage: &S) -> StdResult<PairTracker> {
+    Ok(ReadonlySingleton::new(storage, PAIR_TRACKER)
+        .may_load()?
+        .unwrap_or_default())
+}
+
+/// Moves the legacy tracker into the indexed registry, once
+fn migrate_pair_tracker<S: Storage>(storage: &mut S) -> StdResult<()> {
+    if ReadonlySingleton::<S, u64>::new(storage, KEY_PAIR_COUNT)
+        .may_load()?
+        .is_some()
+    {
+        return Ok(());
+    }
+    let tracker = read_pair_tracker(storage)?;
+    for (index, key) in tracker.0.iter().enumerate() {
+        append_pair_key(storage, index as u64, key)?;
+    }
+    Singleton::new(storage, KEY_PAIR_COUNT).save(&(tracker.0.len() as u64))?;
+    Singleton::<S, PairTracker>::new(storage, PAIR_TRACKER).remove();
+    Ok(())
+}
+
+fn append_pair_key<S: Storage>(storage: &mut S, index: u64, key: &[u8]) -> StdResult<()> {
+    Bucket::new(PREFIX_PAIR_KEYS, storage).save(&index.to_be_bytes(), &key.to_vec())?;
+    Bucket::new(PREFIX_PAIR_INDEX, storage).save(key, &index)
+}
+
+/// Number of creation slots, including the ones of deregistered pairs
+fn read_pair_slots<S: Storage>(storage: &S) -> StdResult<u64> {
+    match ReadonlySingleton::new(storage, KEY_PAIR_COUNT).may_load()? {
+        Some(count) => Ok(count),
+        // not migrated yet
+        None => Ok(read_pair_tracker(storage)?.0.len() as u64),
+    }
+}
+
+fn read_removed_pair_count<S: Storage>(storage: &S) -> StdResult<u64> {
+    Ok(ReadonlySingleton::new(storage, KEY_REMOVED_PAIR_COUNT)
+        .may_load()?
+        .unwrap_or_default())
+}
+
+/// Number of registered pairs
+pub fn read_pair_count<S: Storage>(storage: &S) -> StdResult<u64> {
+    Ok(read_pair_slots(storage)?.saturating_sub(read_removed_pair_count(storage)?))
 }
-pub fn read_pair_tracker<S: Storage>(storage: &S) -> StdResult<PairTracker> {
-    ReadonlySingleton::new(storage, PAIR_TRACKER).load()
+
+fn read_pair_key_at<S: Storage>(storage: &S, index: u64) -> StdResult<Vec<u8>> {
+    match ReadonlySingleton::<S, u64>::new(storage, KEY_PAIR_COUNT).may_load()? {
+        Some(_) => ReadonlyBucket::new(PREFIX_PAIR_KEYS, storage).load(&index.to_be_bytes()),
+        None => read_pair_tracker(storage)?
+            .0
+            .get(index as usize)
+            .cloned()
+            .ok_or_else(|| StdError::generic_err("no pair data stored")),
+    }
+}
+
+fn read_pair_index<S: Storage>(storage: &S, key: &[u8]) -> StdResult<Option<u64>> {
+    match ReadonlySingleton::<S, u64>::new(storage, KEY_PAIR_COUNT).may_load()? {
+        Some(_) => ReadonlyBucket::new(PREFIX_PAIR_INDEX, storage).may_load(key),
+        None => Ok(read_pair_tracker(storage)?
+            .0
+            .iter()
+            .position(|k| k.as_slice() == key)
+            .map(|index| index as u64)),
+    }
 }
+
 pub fn store_config<S: Storage>(storage: &mut S, data: &Config) -> StdResult<()> {
     Singleton::new(storage, KEY_CONFIG).save(data)
 }
+
 pub fn read_config<S: Storage>(storage: &S) -> StdResult<Config> {
     ReadonlySingleton::new(storage, KEY_CONFIG).load()
 }
+
+pub fn store_pending_settings<S: Storage>(
+    storage: &mut S,
+    data: &PendingSettings,
+) -> StdResult<()> {
+    Singleton::new(storage, KEY_PENDING_SETTINGS).save(data)
+}
+
+pub fn read_pending_settings<S: Storage>(storage: &S) -> StdResult<Option<PendingSettings>> {
+    ReadonlySingleton::new(storage, KEY_PENDING_SETTINGS).may_load()
+}
+
+pub fn remove_pending_settings<S: Storage>(storage: &mut S) {
+    Singleton::<S, PendingSettings>::new(storage, KEY_PENDING_SETTINGS).remove()
+}
+
+pub fn store_pair_fee<S: Storage>(
+    storage: &mut S,
+    pair: &CanonicalAddr,
+    data: &PairFee,
+) -> StdResult<()> {
+    Bucket::new(PREFIX_PAIR_FEE, storage).save(pair.as_slice(), data)
+}
+
+pub fn read_pair_fee<S: Storage>(storage: &S, pair: &CanonicalAddr) -> StdResult<Option<PairFee>> {
+    ReadonlyBucket::new(PREFIX_PAIR_FEE, storage).may_load(pair.as_slice())
+}
+
+pub fn remove_pair_fee<S: Storage>(storage: &mut S, pair: &CanonicalAddr) {
+    Bucket::<S, PairFee>::new(PREFIX_PAIR_FEE, storage).remove(pair.as_slice())
+}
+
+pub fn store_pending_pair_fee<S: Storage>(
+    storage: &mut S,
+    pair: &CanonicalAddr,
+    data: &PendingPairFee,
+) -> StdResult<()> {
+    Bucket::new(PREFIX_PENDING_PAIR_FEE, storage).save(pair.as_slice(), data)
+}
+
+pub fn read_pending_pair_fee<S: Storage>(
+    storage: &S,
+    pair: &CanonicalAddr,
+) -> StdResult<Option<PendingPairFee>> {
+    ReadonlyBucket::new(PREFIX_PENDING_PAIR_FEE, storage).may_load(pair.as_slice())
+}
+
+pub fn remove_pending_pair_fee<S: Storage>(storage: &mut S, pair: &CanonicalAddr) {
+    Bucket::<S, PendingPairFee>::new(PREFIX_PENDING_PAIR_FEE, storage).remove(pair.as_slice())
+}
+
+pub fn store_pending_admin<S: Storage>(storage: &mut S, data: &PendingAdmin) -> StdResult<()> {
+    Singleton::new(storage, KEY_PENDING_ADMIN).save(data)
+}
+
+pub fn read_pending_admin<S: Storage>(storage: &S) -> StdResult<Option<PendingAdmin>> {
+    ReadonlySingleton::new(storage, KEY_PENDING_ADMIN).may_load()
+}
+
+pub fn remove_pending_admin<S: Storage>(storage: &mut S) {
+    Singleton::<S, PendingAdmin>::new(storage, KEY_PENDING_ADMIN).remove()
+}
+
+pub fn append_token_code_version<S: Storage>(
+    storage: &mut S,
+    version: TokenCodeVersion,
+) -> StdResult<()> {
+    let mut history = read_token_code_history(storage)?;
+    history.push(version);
+    Singleton::new(storage, KEY_TOKEN_CODE_HISTORY).save(&history)
+}
+
+/// Empty for factories created before the history was kept, until the next UpdateTokenCode
+pub fn read_token_code_history<S: Storage>(storage: &S) -> StdResult<Vec<TokenCodeVersion>> {
+    Ok(ReadonlySingleton::new(storage, KEY_TOKEN_CODE_HISTORY)
+        .may_load()?
+        .unwrap_or_default())
+}
+
+pub fn store_pair_token_code<S: Storage>(
+    storage: &mut S,
+    asset_infos: &[AssetInfoRaw; 2],
+    data: &PairTokenCode,
+) -> StdResult<()> {
+    let key = pair_key(storage, asset_infos);
+    Bucket::new(PREFIX_PAIR_TOKEN_CODE, storage).save(&key, data)
+}
+
+/// `None` for pairs created before token codes were recorded
+pub fn read_pair_token_code<S: Storage>(
+    storage: &S,
+    asset_infos: &[AssetInfoRaw; 2],
+) -> StdResult<Option<PairTokenCode>> {
+    ReadonlyBucket::new(PREFIX_PAIR_TOKEN_CODE, storage).may_load(&pair_key(storage, asset_infos))
+}
+
+pub fn store_pair_asset_infos<S: Storage>(
+    storage: &mut S,
+    pair: &CanonicalAddr,
+    asset_infos: &[AssetInfoRaw; 2],
+) -> StdResult<()> {
+    Bucket::new(PREFIX_PAIR_ASSET_INFOS, storage).save(pair.as_slice(), asset_infos)
+}
+
+/// `None` for unknown contracts and pairs registered before the lookup was kept
+pub fn read_pair_asset_infos<S: Storage>(
+    storage: &S,
+    pair: &CanonicalAddr,
+) -> StdResult<Option<[AssetInfoRaw; 2]>> {
+    ReadonlyBucket::new(PREFIX_PAIR_ASSET_INFOS, storage).may_load(pair.as_slice())
+}
+
 pub fn store_pair<S: Storage>(storage: &mut S, data: &PairInfoRaw) -> StdResult<()> {
-    let mut asset_infos = data.asset_infos.clone().to_vec();
-    asset_infos.sort_by(|a, b| a.as_bytes().cmp(&b.as_bytes()));
-    let key = &[asset_infos[0].as_bytes(), asset_infos[1].as_bytes()].concat();
+    let key = pair_key(storage, &data.asset_infos);
     let mut pair_bucket: Bucket<S, PairInfoRaw> = Bucket::new(PREFIX_PAIR_INFO, storage);
-    pair_bucket.save(key, &data)?;
+    pair_bucket.save(&key, &data)?;
 
-    let mut tracker = read_pair_tracker(storage).unwrap_or_default();
-    let key_as_vec = key.to_vec();
-    if !tracker.0.iter().any(|i| *i == key_as_vec) {
+    migrate_pair_tracker(storage)?;
+    if read_pair_index(storage, &key)?.is_none() {
         // new pair
-        tracker.0.push(key_as_vec);
-        store_pair_tracker(storage, &tracker)
-    } else {
-        // pair already stored in pair_tracker
-        Ok(())
+        let count = read_pair_slots(storage)?;
+        append_pair_key(storage, count, &key)?;
+        Singleton::new(storage, KEY_PAIR_COUNT).save(&(count + 1))?;
     }
+    Ok(())
 }
+
+/// Removes a pair from the registry. Its creation slot stays behind empty, so the
+/// same assets can be paired again under a new slot.
+pub fn remove_pair<S: Storage>(storage: &mut S, asset_infos: &[AssetInfoRaw; 2]) -> StdResult<()> {
+    migrate_pair_tracker(storage)?;
+    let key = pair_key(storage, asset_infos);
+    Bucket::<S, PairInfoRaw>::new(PREFIX_PAIR_INFO, storage).remove(&key);
+    Bucket::<S, u64>::new(PREFIX_PAIR_INDEX, storage).remove(&key);
+    Bucket::<S, PairTokenCode>::new(PREFIX_PAIR_TOKEN_CODE, storage).remove(&key);
+    let removed = read_removed_pair_count(storage)?;
+    Singleton::new(storage, KEY_REMOVED_PAIR_COUNT).save(&(removed + 1))
+}
+
+pub fn remove_pair_asset_infos<S: Storage>(storage: &mut S, pair: &CanonicalAddr) {
+    Bucket::<S, [AssetInfoRaw; 2]>::new(PREFIX_PAIR_ASSET_INFOS, storage).remove(pair.as_slice())
+}
+
+pub fn store_blocked_pair<S: Storage>(storage: &mut S, pair: &CanonicalAddr) -> StdResult<()> {
+    Bucket::new(PREFIX_BLOCKED_PAIR, storage).save(pair.as_slice(), &true)
+}
+
+pub fn is_blocked_pair<S: Storage>(storage: &S, pair: &CanonicalAddr) -> StdResult<bool> {
+    Ok(ReadonlyBucket::<S, bool>::new(PREFIX_BLOCKED_PAIR, storage)
+        .may_load(pair.as_slice())?
+        .unwrap_or_default())
+}
+
+/// Registry key of a pair, the same whichever order its assets are given in.
+/// Pairs created before `canonical_pair_key` stay under their legacy key.
+fn pair_key<S: Storage>(storage: &S, asset_infos: &[AssetInfoRaw; 2]) -> Vec<u8> {
+    let mut sorted = asset_infos.to_vec();
+    sorted.sort_by(|a, b| a.as_bytes().cmp(&b.as_bytes()));
+    let legacy_key = [sorted[0].as_bytes(), sorted[1].as_bytes()].concat();
+    let pair_bucket: ReadonlyBucket<S, PairInfoRaw> =
+        ReadonlyBucket::new(PREFIX_PAIR_INFO, storage);
+    match pair_bucket.may_load(&legacy_key) {
+        Ok(Some(_)) => legacy_key,
+        _ => AssetInfoRaw::canonical_pair_key(asset_infos),
+    }
+}
+
 pub fn read_pair_by_key<S: Storage>(storage: &S, asset_infos: &[u8]) -> StdResult<PairInfoRaw> {
     let pair_bucket: ReadonlyBucket<S, PairInfoRaw> =
         ReadonlyBucket::new(PREFIX_PAIR_INFO, storage);
@@ -56,64 +326,52 @@ pub fn read_pair_by_key<S: Storage>(storage: &S, asset_infos: &[u8]) -> StdResul
         Err(_e) => Err(StdError::generic_err("no pair data stored")),
     }
 }
+
 pub fn read_pair<S: Storage>(
     storage: &S,
     asset_infos: &[AssetInfoRaw; 2],
 ) -> StdResult<PairInfoRaw> {
-    let mut asset_infos = asset_infos.clone().to_vec();
-    asset_infos.sort_by(|a, b| a.as_bytes().cmp(&b.as_bytes()));
-    let pair_bucket: ReadonlyBucket<S, PairInfoRaw> =
-        ReadonlyBucket::new(PREFIX_PAIR_INFO, storage);
-    match pair_bucket.load(&[asset_infos[0].as_bytes(), asset_infos[1].as_bytes()].concat()) {
-        Ok(v) => Ok(v),
-        Err(_e) => Err(StdError::generic_err("no pair data stored")),
-    }
+    read_pair_by_key(storage, &pair_key(storage, asset_infos))
 }
+
 // settings for pagination
 const MAX_LIMIT: u32 = 30;
+
 const DEFAULT_LIMIT: u32 = 10;
+
+/// Registered pairs in creation order, each read on its own so a page never
+/// loads the whole registry. Slots of deregistered pairs are skipped.
 pub fn read_pairs<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
     start_after: Option<[AssetInfoRaw; 2]>,
     limit: Option<u32>,
 ) -> StdResult<Vec<PairInfo>> {
-    //return pair_bucket.load()
-    let tracker = read_pair_tracker(&deps.storage)?;
-    let mut iter = tracker.0.iter();
-    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
-    if let Some(start) = calc_range_start(start_after) {
-        iter.position(|key| key == &start);
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as u64;
+    let start = match start_after {
+        Some(asset_infos) => {
+            read_pair_index(&deps.storage, &pair_key(&deps.storage, &asset_infos))?
+                .ok_or_else(|| StdError::generic_err("start_after is not a registered pair"))?
+                .saturating_add(1)
+        }
+        None => 0,
     };
+    let slots = read_pair_slots(&deps.storage)?;
+
     let mut pairs = vec![];
-    for _ in 0..limit {
-        let info = iter.next();
-        if info.is_none() {
+    for index in start..slots {
+        if pairs.len() as u64 >= limit {
             break;
         }
-        pairs.push(read_pair_by_key(&deps.storage, info.unwrap())?.to_normal(&deps)?)
+        let key = read_pair_key_at(&deps.storage, index)?;
+        // the key was removed, or paired again under a later slot
+        if read_pair_index(&deps.storage, &key)? != Some(index) {
+            continue;
+        }
+        let pair = read_pair_by_key(&deps.storage, &key)?;
+        if is_blocked_pair(&deps.storage, &pair.contract_addr)? {
+            continue;
+        }
+        pairs.push(pair.to_normal(&deps)?);
     }
     Ok(pairs)
-    //Ok(vec![])
-    // todo: fix
-    // pair_bucket
-    //     .range(start.as_deref(), None, Order::Ascending)
-    //     .take(limit)
-    //     .map(|item| {
-    //         let (_, v) = item?;
-    //         v.to_normal(&deps)
-    //     })
-    //     .collect()
-}
-// this will set the first key after the provided key, by appending a 1 byte
-fn calc_range_start(start_after: Option<[AssetInfoRaw; 2]>) -> Option<Vec<u8>> {
-    start_after.map(|asset_infos| {
-        let mut asset_infos = asset_infos.to_vec();
-        asset_infos.sort_by(|a, b| a.as_bytes().cmp(&b.as_bytes()));
-        let mut v = [asset_infos[0].as_bytes(), asset_infos[1].as_bytes()]
-            .concat()
-            .as_slice()
-            .to_vec();
-        v.push(1);
-        v
-    })
 }
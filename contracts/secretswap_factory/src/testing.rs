@@ -1,14 +1,25 @@
 use cosmwasm_std::testing::{mock_env, MOCK_CONTRACT_ADDR};
 use cosmwasm_std::{
-    from_binary, log, to_binary, CanonicalAddr, CosmosMsg, HumanAddr, StdError, WasmMsg,
+    from_binary, log, to_binary, Api, Binary, CanonicalAddr, CosmosMsg, Env, Extern, HandleResult,
+    HumanAddr, Querier, StdError, Storage, Uint128, WasmMsg,
 };
 
-use secretswap::{AssetInfo, InitHook, PairInfo, PairInitMsg};
+use secretswap::{
+    AssetInfo, CurveType, Factory, Fee, InitHook, PairInfo, PairInfoRaw, PairInitMsg, PairSettings,
+    PriceOracle, SwapDataEndpoint, MAX_NOISE_BPS,
+};
 
-use crate::contract::{handle, init, query};
+use crate::contract::{
+    handle, init, query, ADMIN_PROPOSAL_EXPIRY_BLOCKS, MAX_CREATE_PAIRS_BATCH,
+    SETTINGS_TIMELOCK_BLOCKS,
+};
 use crate::mock_querier::mock_dependencies;
-use crate::msg::{ConfigResponse, HandleMsg, InitMsg, PairsResponse, QueryMsg};
-use crate::state::read_pair;
+use crate::msg::{
+    AdminResponse, ConfigResponse, CreatePairsResponse, DataEndpointResponse, HandleMsg, InitMsg,
+    PairFee, PairHandleMsg, PairTokenCodeResponse, PairsResponse, PendingPairFeeResponse,
+    PendingSettingsResponse, QueryMsg, TokenCodeHistoryResponse, TokenCodeVersion,
+};
+use crate::state::{read_pair, store_pair, store_pair_asset_infos};
 
 #[test]
 fn proper_initialization() {
@@ -35,42 +46,13 @@ fn proper_initialization() {
 #[test]
 fn update_config() {
     let mut deps = mock_dependencies(20, &[]);
+    init_factory(&mut deps);
 
-    let msg = InitMsg {
-        pair_code_id: 321u64,
-        token_code_id: 123u64,
-        init_hook: None,
-    };
-
-    let env = mock_env("addr0000", &[]);
-
-    // we can just call .unwrap() to assert this was a success
-    let _res = init(&mut deps, env, msg).unwrap();
-
-    // update owner
     let env = mock_env("addr0000", &[]);
     let msg = HandleMsg::UpdateConfig {
-        owner: Some(HumanAddr("addr0001".to_string())),
-        pair_code_id: None,
-        token_code_id: None,
-    };
-
-    let res = handle(&mut deps, env, msg).unwrap();
-    assert_eq!(0, res.messages.len());
-
-    // it worked, let's query the state
-    let query_res = query(&deps, QueryMsg::Config {}).unwrap();
-    let config_res: ConfigResponse = from_binary(&query_res).unwrap();
-    assert_eq!(123u64, config_res.token_code_id);
-    assert_eq!(321u64, config_res.pair_code_id);
-    assert_eq!(HumanAddr::from("addr0001"), config_res.owner);
-
-    // update left items
-    let env = mock_env("addr0001", &[]);
-    let msg = HandleMsg::UpdateConfig {
-        owner: None,
         pair_code_id: Some(100u64),
-        token_code_id: Some(200u64),
+        pair_code_hash: Some("new_pair_code_hash".to_string()),
+        initial_share_multiplier: Some(Uint128(1000u128)),
     };
 
     let res = handle(&mut deps, env, msg).unwrap();
@@ -79,16 +61,21 @@ fn update_config() {
     // it worked, let's query the state
     let query_res = query(&deps, QueryMsg::Config {}).unwrap();
     let config_res: ConfigResponse = from_binary(&query_res).unwrap();
-    assert_eq!(200u64, config_res.token_code_id);
+    assert_eq!(123u64, config_res.token_code_id);
     assert_eq!(100u64, config_res.pair_code_id);
-    assert_eq!(HumanAddr::from("addr0001"), config_res.owner);
+    assert_eq!("new_pair_code_hash", config_res.pair_code_hash);
+    assert_eq!(HumanAddr::from("addr0000"), config_res.owner);
+    assert_eq!(
+        config_res.pair_settings.initial_share_multiplier,
+        Some(Uint128(1000u128))
+    );
 
     // Unauthorzied err
-    let env = mock_env("addr0000", &[]);
+    let env = mock_env("addr0001", &[]);
     let msg = HandleMsg::UpdateConfig {
-        owner: None,
         pair_code_id: None,
-        token_code_id: None,
+        pair_code_hash: None,
+        initial_share_multiplier: None,
     };
 
     let res = handle(&mut deps, env, msg);
@@ -125,6 +112,8 @@ fn create_pair() {
     let msg = HandleMsg::CreatePair {
         asset_infos: asset_infos.clone(),
         init_hook: None,
+        restricted: false,
+        curve: None,
     };
 
     let env = mock_env("addr0000", &[]);
@@ -191,6 +180,8 @@ fn register() {
     let msg = HandleMsg::CreatePair {
         asset_infos: asset_infos.clone(),
         init_hook: None,
+        restricted: false,
+        curve: None,
     };
 
     let env = mock_env("addr0000", &[]);
@@ -262,6 +253,8 @@ fn register() {
     let msg = HandleMsg::CreatePair {
         asset_infos: asset_infos_2.clone(),
         init_hook: None,
+        restricted: false,
+        curve: None,
     };
 
     let env = mock_env("addr0000", &[]);
@@ -346,3 +339,927 @@ fn register() {
         }]
     );
 }
+
+fn query_admin<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> AdminResponse {
+    from_binary(&query(deps, QueryMsg::Admin {}).unwrap()).unwrap()
+}
+
+fn init_factory<S: Storage, A: Api, Q: Querier>(deps: &mut Extern<S, A, Q>) {
+    let msg = InitMsg {
+        pair_code_id: 321u64,
+        token_code_id: 123u64,
+        init_hook: None,
+        token_code_hash: "token_code_hash".to_string(),
+        pair_code_hash: "pair_code_hash".to_string(),
+        prng_seed: Binary::from(b"seed".to_vec()),
+    };
+    let _res = init(deps, mock_env("addr0000", &[]), msg).unwrap();
+}
+
+fn env_at(sender: &str, height: u64) -> Env {
+    let mut env = mock_env(sender, &[]);
+    env.block.height = height;
+    env
+}
+
+fn query_endpoint<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> Option<SwapDataEndpoint> {
+    let res: DataEndpointResponse =
+        from_binary(&query(deps, QueryMsg::DataEndpoint {}).unwrap()).unwrap();
+    res.endpoint
+}
+
+#[test]
+fn two_step_admin_transfer() {
+    let mut deps = mock_dependencies(20, &[]);
+    init_factory(&mut deps);
+
+    // only the admin can propose
+    let msg = HandleMsg::ProposeAdmin {
+        address: HumanAddr::from("addr0001"),
+    };
+    match handle(&mut deps, env_at("addr0001", 1000), msg.clone()) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    let _res = handle(&mut deps, env_at("addr0000", 1000), msg).unwrap();
+    assert_eq!(
+        query_admin(&deps),
+        AdminResponse {
+            admin: HumanAddr::from("addr0000"),
+            pending_admin: Some(HumanAddr::from("addr0001")),
+            pending_admin_expires_at_height: Some(1000 + ADMIN_PROPOSAL_EXPIRY_BLOCKS),
+        }
+    );
+
+    // claim by wrong address
+    match handle(
+        &mut deps,
+        env_at("addr0002", 1001),
+        HandleMsg::ClaimAdmin {},
+    ) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    // claim after expiry
+    let expired = 1001 + ADMIN_PROPOSAL_EXPIRY_BLOCKS;
+    match handle(
+        &mut deps,
+        env_at("addr0001", expired),
+        HandleMsg::ClaimAdmin {},
+    ) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            format!(
+                "The admin proposal expired at height {}",
+                1000 + ADMIN_PROPOSAL_EXPIRY_BLOCKS
+            )
+        ),
+        _ => panic!("Must return generic error"),
+    }
+    assert_eq!(query_admin(&deps).admin, HumanAddr::from("addr0000"));
+
+    // successful handover
+    let msg = HandleMsg::ProposeAdmin {
+        address: HumanAddr::from("addr0001"),
+    };
+    let _res = handle(&mut deps, env_at("addr0000", expired), msg).unwrap();
+    let _res = handle(
+        &mut deps,
+        env_at("addr0001", expired + 1),
+        HandleMsg::ClaimAdmin {},
+    )
+    .unwrap();
+    assert_eq!(
+        query_admin(&deps),
+        AdminResponse {
+            admin: HumanAddr::from("addr0001"),
+            pending_admin: None,
+            pending_admin_expires_at_height: None,
+        }
+    );
+
+    // the old admin lost access, the new one has it
+    let msg = HandleMsg::SetPaused { paused: true };
+    match handle(&mut deps, env_at("addr0000", expired + 2), msg.clone()) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    let _res = handle(&mut deps, env_at("addr0001", expired + 2), msg).unwrap();
+
+    // nothing left to claim
+    match handle(
+        &mut deps,
+        env_at("addr0001", expired + 3),
+        HandleMsg::ClaimAdmin {},
+    ) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "No admin change is pending"),
+        _ => panic!("Must return generic error"),
+    }
+}
+
+#[test]
+fn set_data_endpoint() {
+    let mut deps = mock_dependencies(20, &[]);
+    init_factory(&mut deps);
+
+    let endpoint = SwapDataEndpoint {
+        address: HumanAddr::from("endpoint0000"),
+        code_hash: "endpoint_code_hash".to_string(),
+    };
+
+    let msg = HandleMsg::SetDataEndpoint {
+        endpoint: Some(endpoint.clone()),
+    };
+    match handle(&mut deps, env_at("addr0001", 1000), msg.clone()) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    let _res = handle(&mut deps, env_at("addr0000", 1000), msg).unwrap();
+
+    // queued behind the timelock
+    let res = query(&deps, QueryMsg::PendingSettings {}).unwrap();
+    let pending: Option<PendingSettingsResponse> = from_binary(&res).unwrap();
+    let pending = pending.unwrap();
+    assert_eq!(pending.settings.swap_data_endpoint, Some(endpoint.clone()));
+    assert_eq!(pending.effective_at_height, 1000 + SETTINGS_TIMELOCK_BLOCKS);
+    assert_eq!(query_endpoint(&deps), None);
+
+    let ready = 1000 + SETTINGS_TIMELOCK_BLOCKS;
+    assert!(handle(
+        &mut deps,
+        env_at("addr0002", ready - 1),
+        HandleMsg::ApplySettings {}
+    )
+    .is_err());
+    let _res = handle(
+        &mut deps,
+        env_at("addr0002", ready),
+        HandleMsg::ApplySettings {},
+    )
+    .unwrap();
+    assert_eq!(query_endpoint(&deps), Some(endpoint.clone()));
+
+    // pairs get the endpoint with their settings
+    let res = query(
+        &deps,
+        QueryMsg::PairSettings {
+            pair: Some(HumanAddr::from("pair0000")),
+        },
+    )
+    .unwrap();
+    let settings: PairSettings = from_binary(&res).unwrap();
+    assert_eq!(settings.swap_data_endpoint, Some(endpoint));
+
+    // ProposeSettings can't bypass the timelock for the endpoint
+    let msg = HandleMsg::ProposeSettings {
+        settings: PairSettings {
+            swap_data_endpoint: None,
+            ..settings
+        },
+        effective_at_height: ready + 1,
+    };
+    match handle(&mut deps, env_at("addr0000", ready), msg) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            format!(
+                "A pricing or endpoint change can take effect {} blocks from now at the earliest",
+                SETTINGS_TIMELOCK_BLOCKS
+            )
+        ),
+        _ => panic!("Must return generic error"),
+    }
+
+    // removal takes the same route
+    let msg = HandleMsg::SetDataEndpoint { endpoint: None };
+    let _res = handle(&mut deps, env_at("addr0000", ready), msg).unwrap();
+    let done = ready + SETTINGS_TIMELOCK_BLOCKS;
+    let _res = handle(
+        &mut deps,
+        env_at("addr0002", done),
+        HandleMsg::ApplySettings {},
+    )
+    .unwrap();
+    assert_eq!(query_endpoint(&deps), None);
+}
+
+fn pair0000_commission_rate<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> (Uint128, Uint128) {
+    let msg = QueryMsg::PairSettings {
+        pair: Some(HumanAddr::from("pair0000")),
+    };
+    let settings: PairSettings = from_binary(&query(deps, msg).unwrap()).unwrap();
+    (
+        settings.swap_fee.commission_rate_nom,
+        settings.swap_fee.commission_rate_denom,
+    )
+}
+
+fn pair0000_pending_fee<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> Option<PendingPairFeeResponse> {
+    let msg = QueryMsg::PendingPairFee {
+        pair: HumanAddr::from("pair0000"),
+    };
+    from_binary(&query(deps, msg).unwrap()).unwrap()
+}
+
+#[test]
+fn pair_fee_is_timelocked() {
+    let mut deps = mock_dependencies(20, &[]);
+    init_factory(&mut deps);
+    let pair = HumanAddr::from("pair0000");
+    let discount = PairFee {
+        commission_rate_nom: Uint128(1),
+        commission_rate_denom: Uint128(1000),
+    };
+
+    // a pair can't charge more than the global rate
+    let msg = HandleMsg::SetPairFee {
+        pair: pair.clone(),
+        commission_rate_nom: Uint128(4),
+        commission_rate_denom: Uint128(1000),
+    };
+    match handle(&mut deps, env_at("addr0000", 1000), msg) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "A pair's commission rate can't exceed the global rate")
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    let msg = HandleMsg::SetPairFee {
+        pair: pair.clone(),
+        commission_rate_nom: discount.commission_rate_nom,
+        commission_rate_denom: discount.commission_rate_denom,
+    };
+    match handle(&mut deps, env_at("addr0001", 1000), msg.clone()) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    let res = handle(&mut deps, env_at("addr0000", 1000), msg).unwrap();
+    let ready = 1000 + SETTINGS_TIMELOCK_BLOCKS;
+    assert_eq!(
+        res.log,
+        vec![
+            log("action", "set_pair_fee"),
+            log("pair", "pair0000"),
+            log("commission_rate", "1/1000"),
+            log("effective_at_height", ready),
+        ]
+    );
+
+    // the pair keeps the global rate until the change is applied
+    assert_eq!(pair0000_commission_rate(&deps), (Uint128(3), Uint128(1000)));
+    assert_eq!(
+        pair0000_pending_fee(&deps),
+        Some(PendingPairFeeResponse {
+            fee: Some(discount),
+            effective_at_height: ready,
+        })
+    );
+    let msg = HandleMsg::ApplyPairFee { pair: pair.clone() };
+    match handle(&mut deps, env_at("addr0002", ready - 1), msg.clone()) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            format!(
+                "The pending commission change takes effect at height {}",
+                ready
+            )
+        ),
+        _ => panic!("Must return generic error"),
+    }
+    let _res = handle(&mut deps, env_at("addr0002", ready), msg.clone()).unwrap();
+    assert_eq!(pair0000_commission_rate(&deps), (Uint128(1), Uint128(1000)));
+    assert_eq!(pair0000_pending_fee(&deps), None);
+    match handle(&mut deps, env_at("addr0002", ready), msg.clone()) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "No commission change is pending for the pair")
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    // removing the discount can't front-run the pair's traders either
+    let remove = HandleMsg::RemovePairFee { pair: pair.clone() };
+    let _res = handle(&mut deps, env_at("addr0000", ready), remove).unwrap();
+    let done = ready + SETTINGS_TIMELOCK_BLOCKS;
+    assert_eq!(
+        pair0000_pending_fee(&deps),
+        Some(PendingPairFeeResponse {
+            fee: None,
+            effective_at_height: done,
+        })
+    );
+    assert!(handle(&mut deps, env_at("addr0002", done - 1), msg.clone()).is_err());
+    assert_eq!(pair0000_commission_rate(&deps), (Uint128(1), Uint128(1000)));
+    let _res = handle(&mut deps, env_at("addr0002", done), msg).unwrap();
+    assert_eq!(pair0000_commission_rate(&deps), (Uint128(3), Uint128(1000)));
+}
+
+fn pair_token_code<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    asset_infos: &[AssetInfo; 2],
+) -> Option<PairTokenCodeResponse> {
+    let msg = QueryMsg::PairTokenCode {
+        asset_infos: asset_infos.clone(),
+    };
+    from_binary(&query(deps, msg).unwrap()).unwrap()
+}
+
+#[test]
+fn token_code_history() {
+    let mut deps = mock_dependencies(20, &[]);
+    init_factory(&mut deps);
+
+    let token = |contract_addr: &str| AssetInfo::Token {
+        contract_addr: HumanAddr::from(contract_addr),
+        token_code_hash: "token_code_hash".to_string(),
+        viewing_key: "".to_string(),
+    };
+    let first_pair = [token("asset0000"), token("asset0001")];
+    let second_pair = [token("asset0000"), token("asset0002")];
+
+    let msg = HandleMsg::CreatePair {
+        asset_infos: first_pair.clone(),
+        init_hook: None,
+        restricted: false,
+        curve: None,
+    };
+    let _res = handle(&mut deps, env_at("addr0000", 1000), msg).unwrap();
+
+    let msg = HandleMsg::UpdateTokenCode {
+        code_id: 200u64,
+        code_hash: "token_code_hash_v2".to_string(),
+    };
+    match handle(&mut deps, env_at("addr0001", 2000), msg.clone()) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    let _res = handle(&mut deps, env_at("addr0000", 2000), msg).unwrap();
+
+    let msg = HandleMsg::CreatePair {
+        asset_infos: second_pair.clone(),
+        init_hook: None,
+        restricted: false,
+        curve: None,
+    };
+    let res = handle(&mut deps, env_at("addr0000", 3000), msg).unwrap();
+    match &res.messages[0] {
+        CosmosMsg::Wasm(WasmMsg::Instantiate { msg, .. }) => {
+            let pair_init_msg: PairInitMsg = from_binary(msg).unwrap();
+            assert_eq!(pair_init_msg.token_code_id, 200u64);
+            assert_eq!(pair_init_msg.token_code_hash, "token_code_hash_v2");
+        }
+        _ => panic!("Must instantiate the pair"),
+    }
+
+    assert_eq!(
+        pair_token_code(&deps, &first_pair),
+        Some(PairTokenCodeResponse {
+            created_code_id: 123u64,
+            code_id: 123u64,
+        })
+    );
+    assert_eq!(
+        pair_token_code(&deps, &second_pair),
+        Some(PairTokenCodeResponse {
+            created_code_id: 200u64,
+            code_id: 200u64,
+        })
+    );
+
+    let res = query(&deps, QueryMsg::TokenCodeHistory {}).unwrap();
+    let history: TokenCodeHistoryResponse = from_binary(&res).unwrap();
+    assert_eq!(
+        history.versions,
+        vec![
+            TokenCodeVersion {
+                code_id: 123u64,
+                code_hash: "token_code_hash".to_string(),
+                since_height: mock_env("addr0000", &[]).block.height,
+            },
+            TokenCodeVersion {
+                code_id: 200u64,
+                code_hash: "token_code_hash_v2".to_string(),
+                since_height: 2000,
+            },
+        ]
+    );
+
+    // only pairs that registered with the factory can be migrated
+    let msg = HandleMsg::MigratePairToken {
+        pair: HumanAddr::from("pair0000"),
+    };
+    match handle(&mut deps, env_at("addr0000", 3000), msg) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "pair0000 is not a registered pair")
+        }
+        _ => panic!("Must return generic error"),
+    }
+}
+
+/// Records `pair` the way Register does, without querying the pair contract
+fn register_pair<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    asset_infos: &[AssetInfo; 2],
+    pair: &str,
+) {
+    let raw_infos = [
+        asset_infos[0].to_raw(deps).unwrap(),
+        asset_infos[1].to_raw(deps).unwrap(),
+    ];
+    let pair_raw = deps.api.canonical_address(&HumanAddr::from(pair)).unwrap();
+    let pair_info = read_pair(&deps.storage, &raw_infos).unwrap();
+    store_pair(
+        &mut deps.storage,
+        &PairInfoRaw {
+            contract_addr: pair_raw.clone(),
+            liquidity_token: deps
+                .api
+                .canonical_address(&HumanAddr::from(format!("{}-lp", pair)))
+                .unwrap(),
+            ..pair_info
+        },
+    )
+    .unwrap();
+    store_pair_asset_infos(&mut deps.storage, &pair_raw, &raw_infos).unwrap();
+}
+
+fn query_pair_list<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> PairsResponse {
+    let msg = QueryMsg::Pairs {
+        start_after: None,
+        limit: None,
+    };
+    from_binary(&query(deps, msg).unwrap()).unwrap()
+}
+
+#[test]
+fn deregister_pair() {
+    let mut deps = mock_dependencies(20, &[]);
+    init_factory(&mut deps);
+
+    let token = |contract_addr: &str| AssetInfo::Token {
+        contract_addr: HumanAddr::from(contract_addr),
+        token_code_hash: "token_code_hash".to_string(),
+        viewing_key: "".to_string(),
+    };
+    let fake_pair = [token("asset0000"), token("fakeusdc")];
+    let other_pair = [token("asset0000"), token("asset0001")];
+    for (asset_infos, pair) in [(&fake_pair, "pair0000"), (&other_pair, "pair0001")].iter() {
+        let msg = HandleMsg::CreatePair {
+            asset_infos: (*asset_infos).clone(),
+            init_hook: None,
+            restricted: false,
+            curve: None,
+        };
+        let _res = handle(&mut deps, env_at("addr0000", 1000), msg).unwrap();
+        register_pair(&mut deps, asset_infos, pair);
+    }
+    assert_eq!(query_pair_list(&deps).total, 2);
+
+    let msg = HandleMsg::DeregisterPair {
+        asset_infos: fake_pair.clone(),
+    };
+    match handle(&mut deps, env_at("addr0001", 2000), msg.clone()) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    let res = handle(&mut deps, env_at("addr0000", 2000), msg).unwrap();
+
+    // the pair is paused in the same transaction
+    let config: ConfigResponse = from_binary(&query(&deps, QueryMsg::Config {}).unwrap()).unwrap();
+    let paused = PairSettings {
+        paused: true,
+        ..config.pair_settings
+    };
+    assert_eq!(
+        res.messages,
+        vec![CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: HumanAddr::from("pair0000"),
+            callback_code_hash: "pair_code_hash".to_string(),
+            msg: to_binary(&PairHandleMsg::PushSettings {
+                settings: paused.clone(),
+            })
+            .unwrap(),
+            send: vec![],
+        })]
+    );
+    // and stays paused when it refreshes its settings
+    let msg = QueryMsg::PairSettings {
+        pair: Some(HumanAddr::from("pair0000")),
+    };
+    let settings: PairSettings = from_binary(&query(&deps, msg).unwrap()).unwrap();
+    assert_eq!(settings, paused);
+
+    // gone from discovery
+    let msg = QueryMsg::Pair {
+        asset_infos: fake_pair.clone(),
+    };
+    assert!(query(&deps, msg).is_err());
+    let pairs = query_pair_list(&deps);
+    assert_eq!(pairs.total, 1);
+    assert_eq!(
+        pairs
+            .pairs
+            .iter()
+            .map(|pair| pair.contract_addr.clone())
+            .collect::<Vec<_>>(),
+        vec![HumanAddr::from("pair0001")]
+    );
+    let msg = HandleMsg::DeregisterPair {
+        asset_infos: fake_pair.clone(),
+    };
+    match handle(&mut deps, env_at("addr0000", 2000), msg) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "no pair data stored"),
+        _ => panic!("Must return generic error"),
+    }
+
+    // the same assets can be paired again, the old contract can't take the slot
+    let msg = HandleMsg::CreatePair {
+        asset_infos: fake_pair.clone(),
+        init_hook: None,
+        restricted: false,
+        curve: None,
+    };
+    let _res = handle(&mut deps, env_at("addr0000", 3000), msg).unwrap();
+    let msg = HandleMsg::Register {
+        asset_infos: fake_pair.clone(),
+    };
+    match handle(&mut deps, env_at("pair0000", 3000), msg) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "The pair was deregistered"),
+        _ => panic!("Must return generic error"),
+    }
+    register_pair(&mut deps, &fake_pair, "pair0002");
+
+    let pairs = query_pair_list(&deps);
+    assert_eq!(pairs.total, 2);
+    assert_eq!(
+        pairs
+            .pairs
+            .iter()
+            .map(|pair| pair.contract_addr.clone())
+            .collect::<Vec<_>>(),
+        vec![HumanAddr::from("pair0001"), HumanAddr::from("pair0002")]
+    );
+    let msg = QueryMsg::Pair {
+        asset_infos: fake_pair.clone(),
+    };
+    let pair: PairInfo = from_binary(&query(&deps, msg).unwrap()).unwrap();
+    assert_eq!(pair.contract_addr, HumanAddr::from("pair0002"));
+}
+
+#[test]
+fn register_pair_manually() {
+    let mut deps = mock_dependencies(20, &[]);
+    init_factory(&mut deps);
+
+    let token = |contract_addr: &str| AssetInfo::Token {
+        contract_addr: HumanAddr::from(contract_addr),
+        token_code_hash: "token_code_hash".to_string(),
+        viewing_key: "".to_string(),
+    };
+    let asset_infos = [token("asset0000"), token("asset0001")];
+    let pair_info = |pair: &str, factory: Factory| PairInfo {
+        asset_infos: asset_infos.clone(),
+        contract_addr: HumanAddr::from(pair),
+        liquidity_token: HumanAddr::from(format!("{}-lp", pair)),
+        token_code_hash: "token_code_hash".to_string(),
+        asset0_volume: Uint128::zero(),
+        asset1_volume: Uint128::zero(),
+        factory,
+        asset_decimals: None,
+        asset_symbols: None,
+        created_at_height: None,
+        created_at_time: None,
+    };
+    let env = mock_env("addr0000", &[]);
+    let this_factory = Factory {
+        address: env.contract.address.clone(),
+        code_hash: env.contract_code_hash.clone(),
+    };
+    let other_factory = Factory {
+        address: HumanAddr::from("factory0001"),
+        code_hash: env.contract_code_hash.clone(),
+    };
+    deps.querier.with_terraswap_pairs(&[
+        (
+            &HumanAddr::from("pair0000"),
+            &pair_info("pair0000", this_factory.clone()),
+        ),
+        (
+            &HumanAddr::from("pair0001"),
+            &pair_info("pair0001", other_factory),
+        ),
+    ]);
+    let register = |pair: &str| HandleMsg::RegisterPairManually {
+        pair_address: HumanAddr::from(pair),
+        pair_code_hash: "pair_code_hash".to_string(),
+    };
+
+    match handle(&mut deps, mock_env("addr0001", &[]), register("pair0000")) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    // a pair of another factory would keep reading its settings from there
+    match handle(&mut deps, mock_env("addr0000", &[]), register("pair0001")) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "The pair belongs to factory factory0001")
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    let _res = handle(&mut deps, mock_env("addr0000", &[]), register("pair0000")).unwrap();
+    let msg = QueryMsg::Pair {
+        asset_infos: [asset_infos[1].clone(), asset_infos[0].clone()],
+    };
+    let pair: PairInfo = from_binary(&query(&deps, msg).unwrap()).unwrap();
+    assert_eq!(pair.contract_addr, HumanAddr::from("pair0000"));
+    assert_eq!(pair.liquidity_token, HumanAddr::from("pair0000-lp"));
+    assert_eq!(pair.factory, this_factory);
+    assert_eq!(query_pair_list(&deps).total, 1);
+
+    match handle(&mut deps, mock_env("addr0000", &[]), register("pair0000")) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Pair already exists"),
+        _ => panic!("Must return generic error"),
+    }
+}
+
+#[test]
+fn create_pairs() {
+    let mut deps = mock_dependencies(20, &[]);
+    init_factory(&mut deps);
+
+    let token = |contract_addr: String| AssetInfo::Token {
+        contract_addr: HumanAddr::from(contract_addr),
+        token_code_hash: "token_code_hash".to_string(),
+        viewing_key: "".to_string(),
+    };
+    let pair = |i: usize| {
+        [
+            token("asset0000".to_string()),
+            token(format!("asset{:04}", i)),
+        ]
+    };
+
+    let msg = HandleMsg::CreatePair {
+        asset_infos: pair(1),
+        init_hook: None,
+        restricted: false,
+        curve: None,
+    };
+    let _res = handle(&mut deps, env_at("addr0000", 1000), msg).unwrap();
+
+    let create_pairs = |pairs: Vec<[AssetInfo; 2]>| HandleMsg::CreatePairs { pairs };
+    let assert_rejected = |res: HandleResult, expected: &str| match res {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, expected),
+        _ => panic!("Must return generic error"),
+    };
+
+    let too_many = (2..3 + MAX_CREATE_PAIRS_BATCH).map(pair).collect();
+    assert_rejected(
+        handle(&mut deps, env_at("addr0000", 2000), create_pairs(too_many)),
+        &format!(
+            "A batch creates between 1 and {} pairs",
+            MAX_CREATE_PAIRS_BATCH
+        ),
+    );
+    // the same pair in either order
+    let reversed = [pair(2)[1].clone(), pair(2)[0].clone()];
+    assert_rejected(
+        handle(
+            &mut deps,
+            env_at("addr0000", 2000),
+            create_pairs(vec![pair(2), pair(3), reversed]),
+        ),
+        "Pair asset0002-asset0000 appears twice in the batch",
+    );
+    assert_rejected(
+        handle(
+            &mut deps,
+            env_at("addr0000", 2000),
+            create_pairs(vec![pair(2), pair(1)]),
+        ),
+        "Pair asset0000-asset0001 already exists",
+    );
+    // nothing of a rejected batch is stored
+    assert_eq!(query_pair_list(&deps).total, 1);
+
+    let res = handle(
+        &mut deps,
+        env_at("addr0000", 2000),
+        create_pairs(vec![pair(2), pair(3), pair(4)]),
+    )
+    .unwrap();
+    let labels: Vec<String> = res
+        .messages
+        .iter()
+        .map(|msg| match msg {
+            CosmosMsg::Wasm(WasmMsg::Instantiate { label, msg, .. }) => {
+                let pair_init_msg: PairInitMsg = from_binary(msg).unwrap();
+                assert_eq!(
+                    pair_init_msg.init_hook.unwrap().msg,
+                    to_binary(&HandleMsg::Register {
+                        asset_infos: pair_init_msg.asset_infos,
+                    })
+                    .unwrap()
+                );
+                label.clone()
+            }
+            _ => panic!("Must instantiate the pairs"),
+        })
+        .collect();
+    let response: CreatePairsResponse = from_binary(&res.data.unwrap()).unwrap();
+    assert_eq!(response.labels, labels);
+    assert_eq!(
+        labels[0],
+        format!("asset0000-asset0002-pair-{}-321", MOCK_CONTRACT_ADDR)
+    );
+
+    // each pair shows up once its init hook registered it
+    for i in 1..5 {
+        register_pair(&mut deps, &pair(i), &format!("pair{:04}", i));
+    }
+    let pairs = query_pair_list(&deps);
+    assert_eq!(pairs.total, 4);
+    assert_eq!(
+        pairs
+            .pairs
+            .iter()
+            .map(|pair| pair.contract_addr.clone())
+            .collect::<Vec<_>>(),
+        vec![
+            HumanAddr::from("pair0001"),
+            HumanAddr::from("pair0002"),
+            HumanAddr::from("pair0003"),
+            HumanAddr::from("pair0004"),
+        ]
+    );
+}
+
+#[test]
+fn set_noise_config() {
+    let mut deps = mock_dependencies(20, &[]);
+    init_factory(&mut deps);
+
+    let pair_settings = |deps: &Extern<_, _, _>| {
+        let msg = QueryMsg::PairSettings {
+            pair: Some(HumanAddr::from("pair0000")),
+        };
+        let settings: PairSettings = from_binary(&query(deps, msg).unwrap()).unwrap();
+        settings
+    };
+    let settings = pair_settings(&deps);
+    assert_eq!(settings.noise_bps_max, None);
+    assert!(!settings.noise_per_block);
+    assert!(!settings.private_logs);
+
+    let msg = HandleMsg::SetNoiseConfig {
+        noise_bps_max: Some(MAX_NOISE_BPS + 1),
+        noise_per_block: true,
+        private_logs: true,
+    };
+    match handle(&mut deps, env_at("addr0000", 1000), msg) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            format!("noise_bps_max must not exceed {}", MAX_NOISE_BPS)
+        ),
+        _ => panic!("Must return generic error"),
+    }
+
+    let msg = HandleMsg::SetNoiseConfig {
+        noise_bps_max: Some(500),
+        noise_per_block: true,
+        private_logs: true,
+    };
+    match handle(&mut deps, env_at("addr0001", 1000), msg.clone()) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    let _res = handle(&mut deps, env_at("addr0000", 1000), msg).unwrap();
+
+    // queued behind the timelock
+    let res = query(&deps, QueryMsg::PendingSettings {}).unwrap();
+    let pending: Option<PendingSettingsResponse> = from_binary(&res).unwrap();
+    assert_eq!(
+        pending.unwrap().effective_at_height,
+        1000 + SETTINGS_TIMELOCK_BLOCKS
+    );
+    assert_eq!(pair_settings(&deps).noise_bps_max, None);
+
+    let ready = 1000 + SETTINGS_TIMELOCK_BLOCKS;
+    let _res = handle(
+        &mut deps,
+        env_at("addr0002", ready),
+        HandleMsg::ApplySettings {},
+    )
+    .unwrap();
+
+    // every pair picks it up with its settings
+    let settings = pair_settings(&deps);
+    assert_eq!(settings.noise_bps_max, Some(500));
+    assert!(settings.noise_per_block);
+    assert!(settings.private_logs);
+
+    // ProposeSettings can't bypass the timelock either
+    let msg = HandleMsg::ProposeSettings {
+        settings: PairSettings {
+            private_logs: false,
+            ..settings
+        },
+        effective_at_height: ready + 1,
+    };
+    match handle(&mut deps, env_at("addr0000", ready), msg) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            format!(
+                "A noise or private_logs change can take effect {} blocks from now at the earliest",
+                SETTINGS_TIMELOCK_BLOCKS
+            )
+        ),
+        _ => panic!("Must return generic error"),
+    }
+}
+
+#[test]
+fn pricing_changes_wait_for_the_timelock() {
+    let mut deps = mock_dependencies(20, &[]);
+    init_factory(&mut deps);
+
+    let config: ConfigResponse = from_binary(&query(&deps, QueryMsg::Config {}).unwrap()).unwrap();
+    let current = config.pair_settings;
+    let changes = vec![
+        PairSettings {
+            swap_fee: Fee {
+                commission_rate_nom: Uint128(5),
+                ..current.swap_fee.clone()
+            },
+            ..current.clone()
+        },
+        PairSettings {
+            curve: CurveType::Stable { amp: 100 },
+            ..current.clone()
+        },
+        PairSettings {
+            fee_on_input: true,
+            ..current.clone()
+        },
+        PairSettings {
+            price_oracle: Some(PriceOracle {
+                contract: HumanAddr::from("oracle0000"),
+                code_hash: "oraclehash".to_string(),
+                max_deviation_bps: 100,
+                fail_open: false,
+            }),
+            ..current.clone()
+        },
+        PairSettings {
+            reserve_floor_bps: 5_000,
+            ..current.clone()
+        },
+        PairSettings {
+            max_block_volume_bps: Some(1_000),
+            ..current.clone()
+        },
+    ];
+    let too_soon = format!(
+        "A pricing or endpoint change can take effect {} blocks from now at the earliest",
+        SETTINGS_TIMELOCK_BLOCKS
+    );
+    for settings in changes {
+        let msg = HandleMsg::ProposeSettings {
+            settings: settings.clone(),
+            effective_at_height: 1000 + SETTINGS_TIMELOCK_BLOCKS - 1,
+        };
+        match handle(&mut deps, env_at("addr0000", 1000), msg) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, too_soon),
+            _ => panic!("Must return generic error"),
+        }
+
+        let msg = HandleMsg::ProposeSettings {
+            settings,
+            effective_at_height: 1000 + SETTINGS_TIMELOCK_BLOCKS,
+        };
+        let _res = handle(&mut deps, env_at("addr0000", 1000), msg).unwrap();
+    }
+
+    // settings that don't price swaps can apply right away
+    let msg = HandleMsg::ProposeSettings {
+        settings: PairSettings {
+            min_reserve: Some(Uint128(1000u128)),
+            ..current
+        },
+        effective_at_height: 1000,
+    };
+    let _res = handle(&mut deps, env_at("addr0000", 1000), msg).unwrap();
+    let _res = handle(
+        &mut deps,
+        env_at("addr0002", 1000),
+        HandleMsg::ApplySettings {},
+    )
+    .unwrap();
+    let config: ConfigResponse = from_binary(&query(&deps, QueryMsg::Config {}).unwrap()).unwrap();
+    assert_eq!(config.pair_settings.min_reserve, Some(Uint128(1000u128)));
+}
@@ -162,6 +162,8 @@ fn create_pair() {
     let msg = HandleMsg::CreatePair {
         asset_infos: asset_infos.clone(),
         init_hook: None,
+        restricted: false,
+        curve: None,
     };
 
     let env = mock_env("addr0000", &[]);
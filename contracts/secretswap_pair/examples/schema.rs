@@ -1,12 +1,17 @@
 use std::env::current_dir;
 use std::fs::create_dir_all;
+use std::path::Path;
 
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 use cosmwasm_std::HandleResponse;
 
-use secretswap::{PairInfo, PairInitMsg};
+use secretswap::{AssetInfo, PairInfo, PairInitMsg, SwapDataEndpointMsg};
 use secretswap_pair::msg::{
-    Cw20HookMsg, HandleMsg, PoolResponse, QueryMsg, ReverseSimulationResponse, SimulationResponse,
+    Cw20HookMsg, DcaResponse, DebugNoiseResponse, EstimateLpMintResponse, HandleMsg,
+    HasAssetResponse, IntegrityResponse, OrderResponse, PoolResponse, ProvideLiquidityResponse,
+    QueryMsg, ReverseLiquidityResponse, ReverseSimulationResponse, SimulationResponse,
+    SnapshotResponse, SpotPriceResponse, StakingHookMsg, SwapDataPreviewResponse, SwapResponse,
+    WhitelistResponse, WithdrawHookMsg,
 };
 
 fn main() {
@@ -15,13 +20,37 @@ fn main() {
     create_dir_all(&out_dir).unwrap();
     remove_schemas(&out_dir).unwrap();
 
-    export_schema(&schema_for!(PairInitMsg), &out_dir);
-    export_schema(&schema_for!(HandleMsg), &out_dir);
-    export_schema(&schema_for!(Cw20HookMsg), &out_dir);
-    export_schema(&schema_for!(HandleResponse), &out_dir);
-    export_schema(&schema_for!(QueryMsg), &out_dir);
-    export_schema(&schema_for!(PairInfo), &out_dir);
-    export_schema(&schema_for!(PoolResponse), &out_dir);
-    export_schema(&schema_for!(ReverseSimulationResponse), &out_dir);
-    export_schema(&schema_for!(SimulationResponse), &out_dir);
+    export_schemas(&out_dir);
+}
+
+/// Every message the pair takes or sends out, and every response it gives
+pub fn export_schemas(out_dir: &Path) {
+    let out_dir = &out_dir.to_path_buf();
+
+    export_schema(&schema_for!(PairInitMsg), out_dir);
+    export_schema(&schema_for!(HandleMsg), out_dir);
+    export_schema(&schema_for!(Cw20HookMsg), out_dir);
+    export_schema(&schema_for!(HandleResponse), out_dir);
+    export_schema(&schema_for!(QueryMsg), out_dir);
+    export_schema(&schema_for!(StakingHookMsg), out_dir);
+    export_schema(&schema_for!(WithdrawHookMsg), out_dir);
+    export_schema(&schema_for!(SwapDataEndpointMsg), out_dir);
+    export_schema(&schema_for!(PairInfo), out_dir);
+    export_schema(&schema_for!(AssetInfo), out_dir);
+    export_schema(&schema_for!(PoolResponse), out_dir);
+    export_schema(&schema_for!(ReverseSimulationResponse), out_dir);
+    export_schema(&schema_for!(SimulationResponse), out_dir);
+    export_schema(&schema_for!(SpotPriceResponse), out_dir);
+    export_schema(&schema_for!(HasAssetResponse), out_dir);
+    export_schema(&schema_for!(SnapshotResponse), out_dir);
+    export_schema(&schema_for!(ReverseLiquidityResponse), out_dir);
+    export_schema(&schema_for!(DebugNoiseResponse), out_dir);
+    export_schema(&schema_for!(SwapDataPreviewResponse), out_dir);
+    export_schema(&schema_for!(ProvideLiquidityResponse), out_dir);
+    export_schema(&schema_for!(SwapResponse), out_dir);
+    export_schema(&schema_for!(WhitelistResponse), out_dir);
+    export_schema(&schema_for!(EstimateLpMintResponse), out_dir);
+    export_schema(&schema_for!(OrderResponse), out_dir);
+    export_schema(&schema_for!(DcaResponse), out_dir);
+    export_schema(&schema_for!(IntegrityResponse), out_dir);
 }
@@ -5,43 +5,175 @@ use std::{
 };
 
 use cosmwasm_std::{
-    debug_print, from_binary, log, to_binary, Api, Binary, CanonicalAddr, CosmosMsg, Decimal, Env,
-    Extern, HandleResponse, HandleResult, HumanAddr, InitResponse, Querier, StdError, StdResult,
-    Storage, Uint128, WasmMsg,
+    debug_print, from_binary, log, to_binary, Api, BankMsg, Binary, CanonicalAddr, Coin, CosmosMsg,
+    Decimal, Env, Extern, HandleResponse, HandleResult, HumanAddr, InitResponse, LogAttribute,
+    Querier, StdError, StdResult, Storage, Uint128, WasmMsg,
 };
 use primitive_types::U256;
+use sha2::{Digest, Sha256};
 //use ::{Cw20HandleMsg, Cw20ReceiveMsg, MinterResponse};
 use secret_toolkit::snip20;
 
 use secretswap::{
-    query_supply, Asset, AssetInfo, AssetInfoRaw, Factory, InitHook, PairInfo, PairInfoRaw,
-    PairInitMsg, TokenInitMsg,
+    query_supply, Asset, AssetInfo, AssetInfoRaw, CurveType, Factory, InitHook, PairInfo,
+    PairInitMsg, PairSettings, SwapDataEndpoint, TokenInitMsg, DEFAULT_NOISE_BPS_MAX, MAX_AMP,
+    MAX_NOISE_BPS,
 };
 
 use crate::{
-    math::{decimal_multiplication, decimal_subtraction, reverse_decimal},
+    error::PairError,
+    math::{
+        decimal_multiplication, decimal_subtraction, decimal_to_u256_atomics, reverse_decimal,
+        u256_ratio_to_decimal, u256_to_uint128,
+    },
     msg::{
-        Cw20HookMsg, HandleMsg, PoolResponse, QueryMsg, ReverseSimulationResponse,
-        SimulationResponse,
+        AutoStake, Cw20HookMsg, DcaResponse, DebugNoiseResponse, EstimateLpMintResponse,
+        FactoryHandleMsg, HandleMsg, HasAssetResponse, IntegrityResponse, OrderResponse,
+        PoolResponse, ProvideLiquidityResponse, QueryMsg, ReverseLiquidityResponse,
+        ReverseSimulationResponse, SimulationResponse, SnapshotResponse, Snip20HandleMsg,
+        SpotPriceResponse, StakingContract, StakingHookMsg, SwapDataPreviewResponse, SwapResponse,
+        WhitelistResponse, WithdrawHookMsg,
+    },
+    state::{
+        get_block_random_number, get_random_bytes, get_random_number, refresh_block_noise_seed,
+        supply_more_entropy,
     },
-    state::{get_random_number, supply_more_entropy},
     u256_math::*,
 };
 
-use crate::querier::query_pair_settings;
-use crate::state::{read_pair_info, store_pair_info};
+use crate::querier::{assert_valid_commission, query_pair_settings, query_reference_price};
+use crate::state::{
+    add_to_whitelist, is_initialized, is_whitelisted, migrate_addresses, migrate_pair_info,
+    next_batch_swap_id, next_dca_id, next_order_id, read_accounting, read_addresses, read_batch,
+    read_batch_swap, read_block_volume, read_breaker_tripped, read_config, read_dca,
+    read_debug_key_hash, read_emergency_withdrawn, read_escrowed, read_expected_lp_token,
+    read_full_exit, read_order, read_pair_curve, read_restricted, read_settings_cache,
+    read_settings_ttl_blocks, read_staking_contract, read_stats, read_swap_data_endpoint,
+    read_viewing_key_hash, read_whitelist, remove_batch_swap, remove_dca, remove_from_whitelist,
+    remove_order, store_accounting, store_addresses, store_batch, store_batch_swap,
+    store_block_volume, store_breaker_tripped, store_config, store_dca, store_debug_key_hash,
+    store_emergency_withdrawn, store_escrowed, store_expected_lp_token, store_full_exit,
+    store_order, store_pair_curve, store_restricted, store_settings_cache,
+    store_settings_ttl_blocks, store_staking_contract, store_stats, store_swap_data_endpoint,
+    store_viewing_key_hash, Accounting, Addresses, AmpRamp, Batch, BatchSwap, BlockVolume,
+    CachedAddr, Config, Dca, ExpectedLpToken, Order, PairCurve, SettingsCache, Stats,
+    DEFAULT_SETTINGS_TTL_BLOCKS, MAX_SETTINGS_AGE_BLOCKS,
+};
+
+/// Fewest blocks an amp ramp can take, about a day, like Curve's `MIN_RAMP_TIME`
+pub const MIN_AMP_RAMP_BLOCKS: u64 = 14_400;
+
+/// Most a single ramp can multiply or divide the amp by, Curve's `MAX_A_CHANGE`
+pub const MAX_AMP_CHANGE: u64 = 10;
+
+/// Maximum number of donated bytes accepted by `AddEntropy`
+pub const MAX_ENTROPY_DONATION_BYTES: usize = 256;
+
+/// Bytes of each serialized handle message mixed into the entropy pool
+pub const MAX_ENTROPY_MSG_BYTES: usize = 1024;
+
+/// Log keys carrying amounts or reserves, dropped when `private_logs` is set
+const PRIVATE_LOG_KEYS: &[&str] = &[
+    "assets",
+    "share",
+    "entry_reserves",
+    "entry_price",
+    "withdrawn_share",
+    "refund_assets",
+    "returned_share",
+    "swapped_amount",
+    "received_amount",
+    "offer_amount",
+    "return_amount",
+    "spread_amount",
+    "commission_amount",
+];
+
+/// Decimals of native denoms such as uscrt
+pub const NATIVE_TOKEN_DECIMALS: u8 = 6;
+
+/// Native denom that a wrap config deposits into sSCRT
+pub const WRAPPED_DENOM: &str = "uscrt";
+
+/// Commission rates from this percentage up can't be reverse simulated, the
+/// amount before commission grows without bound as the rate approaches 100%
+pub const MAX_REVERSE_COMMISSION_PERCENT: u128 = 99;
+
+/// Share of each DCA tranche's return, in basis points, paid to the keeper running it
+pub const DCA_BOUNTY_BPS: u16 = 10;
+
+/// Reserves and LP supplies under this many units are reported without noise. At
+/// the default 99 bps the noise moves them by less than a unit, so all it could do
+/// is round dust down to zero, or make a pool look like it has liquidity.
+pub const MIN_NOISED_AMOUNT: u128 = 100;
+
+/// Most swaps a block's batch queues, so that settling it stays bounded
+pub const MAX_BATCH_SWAPS: usize = 50;
+
+/// Most queued swaps a single handle pays out
+pub const BATCH_SETTLE_CHUNK: usize = 10;
 
 pub fn init<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
     msg: PairInitMsg,
 ) -> StdResult<InitResponse> {
+    if is_initialized(&deps.storage) {
+        return Err(PairError::state("Pair is already initialized").into());
+    }
+
     // create viewing key
     let assets_viewing_key = String::from("SecretSwap"); // TODO make it private
 
+    if msg.asset_infos[0].equal(&msg.asset_infos[1]) {
+        return Err(PairError::invalid("identical assets").into());
+    }
+
     let mut asset0 = msg.asset_infos[0].to_raw(&deps)?;
     let mut asset1 = msg.asset_infos[1].to_raw(&deps)?;
 
+    // reserves are measured in sSCRT only, never mixed with native uscrt
+    if let Some(wrap_config) = &msg.wrap_config {
+        if msg.asset_infos.iter().any(|info| info.is_native_token()) {
+            return Err(
+                PairError::invalid("A pair with a wrap_config cannot hold native coins").into(),
+            );
+        }
+        if !msg.asset_infos.iter().any(|info| match info {
+            AssetInfo::Token { contract_addr, .. } => contract_addr == &wrap_config.sscrt_addr,
+            AssetInfo::NativeToken { .. } => false,
+        }) {
+            return Err(PairError::invalid(
+                "The wrap_config sSCRT must be one of the pair's assets",
+            )
+            .into());
+        }
+    }
+    let curve = match (msg.curve.clone(), msg.weights) {
+        (Some(_), Some(_)) => {
+            return Err(
+                PairError::invalid("A pair takes either a curve or weights, not both").into(),
+            )
+        }
+        (None, Some(weights)) => Some(CurveType::Weighted { weights }),
+        (curve, None) => curve,
+    };
+    match &curve {
+        Some(CurveType::Stable { amp }) => assert_valid_amp(*amp)?,
+        Some(CurveType::Weighted { weights }) => assert_valid_weights(weights)?,
+        _ => {}
+    }
+    let settings_ttl_blocks = msg
+        .settings_ttl_blocks
+        .unwrap_or(DEFAULT_SETTINGS_TTL_BLOCKS);
+    if settings_ttl_blocks >= MAX_SETTINGS_AGE_BLOCKS {
+        return Err(PairError::invalid(format!(
+            "settings_ttl_blocks must be below {}",
+            MAX_SETTINGS_AGE_BLOCKS
+        ))
+        .into());
+    }
+
     // append set viewing key messages and store viewing keys
     let mut messages = vec![];
     match &msg.asset_infos[0] {
@@ -70,7 +202,7 @@ pub fn init<S: Storage, A: Api, Q: Querier>(
                 viewing_key: assets_viewing_key.clone(),
             };
         }
-        _ => {}
+        AssetInfo::NativeToken { .. } => {}
     }
     match &msg.asset_infos[1] {
         AssetInfo::Token {
@@ -98,71 +230,290 @@ pub fn init<S: Storage, A: Api, Q: Querier>(
                 viewing_key: assets_viewing_key.clone(),
             };
         }
-        _ => {}
+        AssetInfo::NativeToken { .. } => {}
+    }
+
+    // also proves each token contract answers with the given code hash
+    let mut asset_decimals = [0u8; 2];
+    let mut asset_symbols = [String::new(), String::new()];
+    for (i, asset_info) in msg.asset_infos.iter().enumerate() {
+        let (decimals, symbol) = query_asset_metadata(&deps, asset_info)
+            .map_err(|err| PairError::invalid(format!("asset {} unreachable: {}", i, err)))?;
+        asset_decimals[i] = decimals;
+        asset_symbols[i] = symbol;
+    }
+
+    // pair with an existing LP token right away, or instantiate one that
+    // registers itself through PostInitialize
+    let (liquidity_token, token_code_hash, expected_lp_token) = match &msg.existing_liquidity_token
+    {
+        Some(existing) => {
+            let total_supply = query_supply(&deps, &existing.address, &existing.code_hash)?;
+            if !total_supply.is_zero() && !existing.allow_nonzero_supply {
+                return Err(PairError::state(
+                    "The existing liquidity token must have a zero total supply",
+                )
+                .into());
+            }
+            messages.push(snip20::register_receive_msg(
+                env.contract_code_hash.clone(),
+                None,
+                256,
+                existing.code_hash.clone(),
+                existing.address.clone(),
+            )?);
+            (
+                deps.api.canonical_address(&existing.address)?,
+                existing.code_hash.clone(),
+                None,
+            )
+        }
+        None => {
+            let (instantiate_msg, expected_lp_token) = instantiate_lp_token(&env, &msg)?;
+            messages.push(instantiate_msg);
+            (
+                CanonicalAddr::default(),
+                msg.token_code_hash.clone(),
+                Some(expected_lp_token),
+            )
+        }
+    };
+
+    let hook = msg
+        .init_hook
+        .ok_or_else(|| PairError::invalid("Must provide the factory as init hook"))?;
+    if let Some(expected_factory) = &msg.expected_factory {
+        if hook.contract_addr != expected_factory.address
+            || hook.code_hash != expected_factory.code_hash
+        {
+            return Err(
+                PairError::invalid("The init hook doesn't target the expected factory").into(),
+            );
+        }
+    }
+    // a factory that can't answer settings queries would leave the pair unable to swap
+    query_pair_settings(
+        &deps,
+        &hook.contract_addr,
+        &hook.code_hash,
+        &env.contract.address,
+    )
+    .map_err(|err| {
+        PairError::invalid(format!(
+            "The factory doesn't answer pair settings queries: {}",
+            err
+        ))
+    })?;
+    let factory_addr = hook.contract_addr.clone();
+
+    messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: hook.contract_addr.clone(),
+        callback_code_hash: hook.code_hash.clone(),
+        msg: hook.msg,
+        send: vec![],
+    }));
+
+    let config = Config {
+        contract_addr: deps.api.canonical_address(&env.contract.address)?,
+        liquidity_token,
+        token_code_hash,
+        asset_infos: [asset0, asset1],
+        factory: Factory {
+            address: hook.contract_addr,
+            code_hash: hook.code_hash,
+        },
+        asset_decimals: Some(asset_decimals),
+        asset_symbols: Some(asset_symbols),
+        wrap_config: msg.wrap_config,
+        admin: match &msg.admin {
+            Some(admin) => Some(deps.api.canonical_address(admin)?),
+            None => None,
+        },
+        created_at_height: Some(env.block.height),
+        created_at_time: Some(env.block.time),
+    };
+
+    // create viewing keys
+
+    store_config(&mut deps.storage, &config)?;
+    store_addresses(
+        &mut deps.storage,
+        &Addresses::from_config(&deps.api, &config)?,
+    )?;
+    store_stats(&mut deps.storage, &Stats::default())?;
+    if let Some(expected_lp_token) = expected_lp_token {
+        store_expected_lp_token(&mut deps.storage, &expected_lp_token)?;
     }
+    store_settings_ttl_blocks(&mut deps.storage, settings_ttl_blocks)?;
+    store_restricted(&mut deps.storage, msg.restricted)?;
+    if let Some(curve) = curve {
+        store_pair_curve(&mut deps.storage, &PairCurve { curve, ramp: None })?;
+    }
+    store_accounting(
+        &mut deps.storage,
+        &Accounting {
+            assets: [asset_id(&msg.asset_infos[0]), asset_id(&msg.asset_infos[1])],
+            balances: [Uint128::zero(); 2],
+        },
+    )?;
+
+    Ok(InitResponse {
+        messages,
+        log: vec![
+            log("status", "success"), // See https://github.com/CosmWasm/wasmd/pull/386
+            log("factory", factory_addr),
+        ],
+    })
+}
+
+/// Instantiate message of the pair's LP token, and what PostInitialize expects
+/// of the token it creates
+fn instantiate_lp_token(env: &Env, msg: &PairInitMsg) -> StdResult<(CosmosMsg, ExpectedLpToken)> {
+    let lp_token_name = match msg.lp_token_name.clone() {
+        Some(name) => name,
+        None if msg.generic_label => {
+            "SecretSwapAnonEdition Liquidity Provider (LP) token".to_string()
+        }
+        None => format!(
+            "SecretSwapAnonEdition Liquidity Provider (LP) token for {}-{}",
+            &msg.asset_infos[0], &msg.asset_infos[1]
+        ),
+    };
+    let lp_token_symbol = msg
+        .lp_token_symbol
+        .clone()
+        .unwrap_or_else(|| "SWAP-ANON-LP".to_string());
+    let lp_token_decimals = msg.lp_token_decimals.unwrap_or(18);
+    validate_lp_token(&lp_token_name, &lp_token_symbol, lp_token_decimals)?;
+
+    let lp_token_label = if msg.generic_label {
+        // seeded, so the label can't be matched against hashes of known pairs
+        let mut hasher = Sha256::new();
+        hasher.update(msg.asset_infos[0].to_string().as_bytes());
+        hasher.update(msg.asset_infos[1].to_string().as_bytes());
+        hasher.update(msg.prng_seed.as_slice());
+        let hash: String = hasher.finalize()[..8]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        format!(
+            "{}-SecretSwapAnon-LP-Token-{}",
+            hash,
+            &env.contract.address.clone()
+        )
+    } else {
+        format!(
+            "{}-{}-SecretSwapAnon-LP-Token-{}",
+            &msg.asset_infos[0],
+            &msg.asset_infos[1],
+            &env.contract.address.clone()
+        )
+    };
 
-    // Create LP token
-    messages.extend(vec![CosmosMsg::Wasm(WasmMsg::Instantiate {
+    let expected_lp_token = ExpectedLpToken {
         code_id: msg.token_code_id,
+        label: lp_token_label,
+        name: lp_token_name,
+        symbol: lp_token_symbol,
+        decimals: lp_token_decimals,
+        replacement: false,
+    };
+    let instantiate_msg = lp_token_instantiate_msg(
+        env,
+        &expected_lp_token,
+        &msg.token_code_hash,
+        &msg.prng_seed,
+    )?;
+
+    Ok((instantiate_msg, expected_lp_token))
+}
+
+fn lp_token_instantiate_msg(
+    env: &Env,
+    lp_token: &ExpectedLpToken,
+    token_code_hash: &str,
+    prng_seed: &Binary,
+) -> StdResult<CosmosMsg> {
+    Ok(CosmosMsg::Wasm(WasmMsg::Instantiate {
+        code_id: lp_token.code_id,
         msg: to_binary(&TokenInitMsg::new(
-            format!(
-                "SecretSwapAnonEdition Liquidity Provider (LP) token for {}-{}",
-                &msg.asset_infos[0], &msg.asset_infos[1]
-            ),
+            lp_token.name.clone(),
             env.contract.address.clone(),
-            "SWAP-ANON-LP".to_string(),
-            18,
-            msg.prng_seed,
+            lp_token.symbol.clone(),
+            lp_token.decimals,
+            lp_token_prng_seed(env, prng_seed),
             InitHook {
                 msg: to_binary(&HandleMsg::PostInitialize {})?,
                 contract_addr: env.contract.address.clone(),
-                code_hash: env.contract_code_hash,
+                code_hash: env.contract_code_hash.clone(),
             },
         ))?,
         send: vec![],
-        label: format!(
-            "{}-{}-SecretSwapAnon-LP-Token-{}",
-            &msg.asset_infos[0],
-            &msg.asset_infos[1],
-            &env.contract.address.clone()
-        ),
-        callback_code_hash: msg.token_code_hash.clone(),
-    })]);
-
-    if let Some(hook) = msg.init_hook {
-        messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
-            contract_addr: hook.contract_addr.clone(),
-            callback_code_hash: hook.code_hash.clone(),
-            msg: hook.msg,
-            send: vec![],
-        }));
-
-        let pair_info: &PairInfoRaw = &PairInfoRaw {
-            contract_addr: deps.api.canonical_address(&env.contract.address)?,
-            liquidity_token: CanonicalAddr::default(),
-            token_code_hash: msg.token_code_hash,
-            asset_infos: [asset0, asset1],
-            asset0_volume: Uint128(0),
-            asset1_volume: Uint128(0),
-            factory: Factory {
-                address: hook.contract_addr,
-                code_hash: hook.code_hash,
-            },
-        };
+        label: lp_token.label.clone(),
+        callback_code_hash: token_code_hash.to_string(),
+    }))
+}
 
-        // create viewing keys
+/// The LP token's prng seed. The caller's `prng_seed` alone could be known in advance,
+/// it's hashed with the pair's address and the block the token is created in, so
+/// the token's viewing keys don't derive from a predictable seed.
+fn lp_token_prng_seed(env: &Env, prng_seed: &Binary) -> Binary {
+    let mut hasher = Sha256::new();
+    hasher.update(prng_seed.as_slice());
+    hasher.update(env.contract.address.as_str().as_bytes());
+    hasher.update(env.block.chain_id.as_bytes());
+    hasher.update(&env.block.height.to_be_bytes());
+    hasher.update(&env.block.time.to_be_bytes());
+    Binary::from(hasher.finalize().to_vec())
+}
 
-        store_pair_info(&mut deps.storage, &pair_info)?;
-    } else {
-        return Err(StdError::generic_err(
-            "Must provide the factory as init hook",
-        ));
+/// Decimals and symbol of an asset, native coins have 6 decimals and go by their denom
+fn query_asset_metadata<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    asset_info: &AssetInfo,
+) -> StdResult<(u8, String)> {
+    match asset_info {
+        AssetInfo::Token {
+            contract_addr,
+            token_code_hash,
+            ..
+        } => {
+            let token_info = snip20::token_info_query(
+                &deps.querier,
+                256,
+                token_code_hash.clone(),
+                contract_addr.clone(),
+            )?;
+            Ok((token_info.decimals, token_info.symbol))
+        }
+        AssetInfo::NativeToken { denom } => Ok((NATIVE_TOKEN_DECIMALS, denom.clone())),
     }
+}
 
-    Ok(InitResponse {
-        messages,
-        log: vec![log("status", "success")], // See https://github.com/CosmWasm/wasmd/pull/386
-    })
+/// Same constraints the SNIP-20 LP token checks at init, so a bad value fails
+/// here with a clear error instead of in the instantiate callback
+fn validate_lp_token(name: &str, symbol: &str, decimals: u8) -> StdResult<()> {
+    if name.len() < 3 || name.len() > 200 {
+        return Err(PairError::invalid(
+            "LP token name is not in the expected format (3-200 UTF-8 bytes)",
+        )
+        .into());
+    }
+    if symbol.len() < 3
+        || symbol.len() > 12
+        || !symbol
+            .bytes()
+            .all(|byte| byte.is_ascii_alphabetic() || byte == b'-')
+    {
+        return Err(PairError::invalid(
+            "LP token symbol is not in expected format [A-Za-z-]{3,12}",
+        )
+        .into());
+    }
+    if decimals > 18 {
+        return Err(PairError::invalid("LP token decimals must not exceed 18").into());
+    }
+    Ok(())
 }
 
 pub fn handle<S: Storage, A: Api, Q: Querier>(
@@ -170,18 +521,151 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
     env: Env,
     msg: HandleMsg,
 ) -> HandleResult {
+    // a hook `msg` can be arbitrarily large, only its head goes into the mixer
     let mut fresh_entropy = to_binary(&msg)?.0;
+    fresh_entropy.truncate(MAX_ENTROPY_MSG_BYTES);
     fresh_entropy.extend(to_binary(&env)?.0);
     supply_more_entropy(&mut deps.storage, fresh_entropy.as_slice())?;
 
-    match msg {
+    migrate_pair_info(&mut deps.storage)?;
+    migrate_addresses(&mut deps.storage, &deps.api)?;
+
+    if read_settings_cache(&deps.storage)?.map_or(false, |cache| cache.settings.noise_per_block) {
+        refresh_block_noise_seed(&mut deps.storage, env.block.height);
+    }
+
+    let received = match &msg {
+        HandleMsg::Receive { amount, .. } => *amount,
+        _ => Uint128::zero(),
+    };
+    let accounting_env = env.clone();
+
+    // a batch queued in an earlier block settles before any swap, which would trade
+    // against pools still holding its returns. Other user handles settle it if they
+    // can. Withdrawals, cancellations and the factory and admin handles never wait
+    // on it, a batch that can't settle mustn't lock funds or block its own fix.
+    let settlement = match &msg {
+        HandleMsg::Swap { .. }
+        | HandleMsg::BatchProvideAndSwap { .. }
+        | HandleMsg::ExecuteOrder { .. }
+        | HandleMsg::ExecuteDca { .. } => settle_due_batch(deps, &env, received)?,
+        HandleMsg::Receive {
+            msg: Some(hook), ..
+        } => match from_binary::<Cw20HookMsg>(hook) {
+            Ok(Cw20HookMsg::Swap { .. }) | Ok(Cw20HookMsg::BatchProvideAndSwap { .. }) => {
+                settle_due_batch(deps, &env, received)?
+            }
+            Ok(Cw20HookMsg::WithdrawLiquidity { .. })
+            | Ok(Cw20HookMsg::WithdrawLiquidityTo { .. }) => None,
+            _ => settle_due_batch(deps, &env, received).ok().flatten(),
+        },
+        HandleMsg::Receive { .. }
+        | HandleMsg::ProvideLiquidity { .. }
+        | HandleMsg::AddEntropy { .. }
+        | HandleMsg::SetViewingKey { .. } => settle_due_batch(deps, &env, received).ok().flatten(),
+        _ => None,
+    };
+
+    let mut res = match msg {
         HandleMsg::Receive { amount, msg, from } => receive_cw20(deps, env, from, amount, msg),
+        HandleMsg::Swap {
+            offer_asset,
+            expected_return,
+            belief_price,
+            max_spread,
+            max_price_impact,
+            batch,
+            to,
+            unwrap,
+        } => try_native_swap(
+            deps,
+            env,
+            offer_asset,
+            expected_return,
+            belief_price,
+            max_spread,
+            max_price_impact,
+            batch,
+            to,
+            unwrap,
+        ),
         HandleMsg::PostInitialize {} => try_post_initialize(deps, env),
         HandleMsg::ProvideLiquidity {
             assets,
             slippage_tolerance,
-        } => try_provide_liquidity(deps, env, assets, slippage_tolerance),
+            auto_stake,
+            receiver,
+        } => try_provide_liquidity(deps, env, assets, slippage_tolerance, auto_stake, receiver),
+        HandleMsg::WithdrawLiquidity {
+            amount,
+            min_assets,
+            to,
+        } => try_withdraw_liquidity_from(deps, env, amount, min_assets, to),
+        HandleMsg::Compound { lp_amount } => try_compound(deps, env, lp_amount),
+        HandleMsg::BatchProvideAndSwap {
+            offer_asset,
+            min_share,
+            receiver,
+        } => try_native_batch_provide_and_swap(deps, env, offer_asset, min_share, receiver),
+        HandleMsg::AddEntropy { entropy } => try_add_entropy(deps, env, entropy),
+        HandleMsg::RefreshSettings {} => try_refresh_settings(deps, env),
+        HandleMsg::PushSettings { settings } => try_push_settings(deps, env, settings),
+        HandleMsg::SetAdmin { new_admin } => try_set_admin(deps, env, new_admin),
+        HandleMsg::SyncTokenMetadata {} => try_sync_token_metadata(deps),
+        HandleMsg::ForceSetLiquidityToken { liquidity_token } => {
+            try_force_set_liquidity_token(deps, env, liquidity_token)
+        }
+        HandleMsg::SetStakingContract { staking_contract } => {
+            try_set_staking_contract(deps, env, staking_contract)
+        }
+        HandleMsg::EmergencyWithdraw { holder, amount } => {
+            try_emergency_withdraw(deps, env, holder, amount)
+        }
+        HandleMsg::UpdateLiquidityToken {
+            token_code_id,
+            token_code_hash,
+            prng_seed,
+        } => try_update_liquidity_token(deps, env, token_code_id, token_code_hash, prng_seed),
+        HandleMsg::SetFullExit { enabled } => try_set_full_exit(deps, env, enabled),
+        HandleMsg::SetSwapDataEndpoint { endpoint } => {
+            try_set_swap_data_endpoint(deps, env, endpoint)
+        }
+        HandleMsg::RampAmp {
+            target_amp,
+            end_height,
+        } => try_ramp_amp(deps, env, target_amp, end_height),
+        HandleMsg::SetDebugKey { key } => try_set_debug_key(deps, env, key),
+        HandleMsg::RotateViewingKeys {} => try_rotate_viewing_keys(deps, env),
+        HandleMsg::AddToWhitelist { addresses } => try_add_to_whitelist(deps, env, addresses),
+        HandleMsg::ResetCircuitBreaker {} => try_reset_circuit_breaker(deps, env),
+        HandleMsg::RemoveFromWhitelist { addresses } => {
+            try_remove_from_whitelist(deps, env, addresses)
+        }
+        HandleMsg::ExecuteOrder { order_id } => try_execute_order(deps, env, order_id),
+        HandleMsg::CancelOrder { order_id } => try_cancel_order(deps, env, order_id),
+        HandleMsg::SetViewingKey { key } => try_set_viewing_key(deps, env, key),
+        HandleMsg::ExecuteDca { id } => try_execute_dca(deps, env, id),
+        HandleMsg::CancelDca { id } => try_cancel_dca(deps, env, id),
+        HandleMsg::SettleBatch {} => try_settle_batch(deps, env),
+        HandleMsg::CancelBatchSwap { id } => try_cancel_batch_swap(deps, env, id),
+    }?;
+
+    if let Some((messages, logs)) = settlement {
+        res.messages.splice(0..0, messages);
+        res.log.extend(logs);
     }
+    account_balances(deps, &accounting_env, received, &res.messages)?;
+
+    // read after the handle ran, so it follows settings the handle just fetched
+    if read_settings_cache(&deps.storage)?.map_or(false, |cache| cache.settings.private_logs) {
+        res.log.retain(is_public_log);
+    }
+
+    Ok(res)
+}
+
+fn is_public_log(attr: &LogAttribute) -> bool {
+    !PRIVATE_LOG_KEYS.contains(&attr.key.as_str())
 }
 
 pub fn receive_cw20<S: Storage, A: Api, Q: Querier>(
@@ -191,429 +675,3587 @@ pub fn receive_cw20<S: Storage, A: Api, Q: Querier>(
     amount: Uint128,
     msg: Option<Binary>,
 ) -> HandleResult {
-    let contract_addr = env.message.sender.clone();
     if let Some(bin_msg) = msg {
+        // every hook acts for the user that sent the tokens
+        assert_whitelisted(deps, &from)?;
+
         match from_binary(&bin_msg)? {
             Cw20HookMsg::Swap {
                 expected_return,
                 belief_price,
                 max_spread,
+                max_price_impact,
+                batch,
                 to,
+                unwrap,
+                to_code_hash,
+                to_msg,
+                ask_asset_info,
             } => {
-                // only asset contract can execute this message
-                let mut authorized: bool = false;
-                let config: PairInfoRaw = read_pair_info(&deps.storage)?;
-                let pools: [Asset; 2] = config.query_pools(deps, &env.contract.address)?;
-                for pool in pools.iter() {
-                    let AssetInfo::Token { contract_addr, .. } = &pool.info;
-                    if contract_addr == &env.message.sender {
-                        authorized = true;
-                    }
+                if to_msg.is_some() && to.is_none() {
+                    return Err(PairError::invalid("to_msg requires a to address").into());
                 }
 
-                if !authorized {
-                    return Err(StdError::unauthorized());
+                // only asset contract can execute this message
+                let config: Config = read_config(&deps.storage)?;
+                let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+                let offer_asset_info: AssetInfo = addresses
+                    .asset_infos(&config)
+                    .iter()
+                    .find(|info| match info {
+                        AssetInfo::Token { contract_addr, .. } => {
+                            contract_addr == &env.message.sender
+                        }
+                        AssetInfo::NativeToken { .. } => false,
+                    })
+                    .cloned()
+                    .ok_or_else(StdError::unauthorized)?;
+
+                // the ask pool is implied, routers can assert which one they buy
+                if let Some(ask_asset_info) = ask_asset_info {
+                    let is_ask_pool = |info: &AssetInfo| {
+                        !info.equal(&offer_asset_info) && info.equal(&ask_asset_info)
+                    };
+                    if !addresses.asset_infos(&config).iter().any(is_ask_pool) {
+                        return Err(PairError::invalid(format!(
+                            "ask_asset_info {} doesn't match the ask pool of this pair",
+                            ask_asset_info
+                        ))
+                        .into());
+                    }
                 }
 
                 try_swap(
                     deps,
                     env,
+                    &config,
+                    &addresses,
                     from,
                     Asset {
-                        info: AssetInfo::Token {
-                            contract_addr,
-                            token_code_hash: Default::default(),
-                            viewing_key: Default::default(),
-                        },
+                        info: offer_asset_info,
                         amount,
                     },
                     expected_return,
                     belief_price,
                     max_spread,
+                    max_price_impact,
+                    batch,
                     to,
+                    amount,
+                    unwrap,
+                    to_code_hash.zip(to_msg),
+                )
+            }
+            Cw20HookMsg::WithdrawLiquidity {
+                unwrap,
+                to,
+                to_code_hash,
+                to_msg,
+            } => {
+                let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+                if env.message.sender != addresses.liquidity_token.human {
+                    return Err(StdError::unauthorized());
+                }
+                if to_msg.is_some() && to.is_none() {
+                    return Err(PairError::invalid("to_msg requires a to address").into());
+                }
+
+                try_withdraw_liquidity(
+                    deps,
+                    env,
+                    from,
+                    amount,
+                    unwrap,
+                    to,
+                    to_code_hash.zip(to_msg),
                 )
             }
-            Cw20HookMsg::WithdrawLiquidity {} => {
-                let config: PairInfoRaw = read_pair_info(&deps.storage)?;
-                if deps.api.canonical_address(&env.message.sender)? != config.liquidity_token {
+            Cw20HookMsg::WithdrawLiquidityTo {
+                asset_info,
+                min_received,
+            } => {
+                let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+                if env.message.sender != addresses.liquidity_token.human {
                     return Err(StdError::unauthorized());
                 }
 
-                try_withdraw_liquidity(deps, env, from, amount)
+                try_withdraw_liquidity_to(deps, env, from, amount, asset_info, min_received)
+            }
+            Cw20HookMsg::BatchProvideAndSwap {
+                min_share,
+                receiver,
+            } => {
+                // only asset contract can execute this message
+                let config: Config = read_config(&deps.storage)?;
+                let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+                let offer_asset_info: AssetInfo = addresses
+                    .asset_infos(&config)
+                    .iter()
+                    .find(|info| match info {
+                        AssetInfo::Token { contract_addr, .. } => {
+                            contract_addr == &env.message.sender
+                        }
+                        AssetInfo::NativeToken { .. } => false,
+                    })
+                    .cloned()
+                    .ok_or_else(StdError::unauthorized)?;
+
+                try_batch_provide_and_swap(
+                    deps,
+                    env,
+                    &config,
+                    &addresses,
+                    Asset {
+                        info: offer_asset_info,
+                        amount,
+                    },
+                    amount,
+                    min_share,
+                    receiver.unwrap_or(from),
+                )
+            }
+            Cw20HookMsg::PlaceOrder {
+                ask_min_price,
+                expires_at,
+                bounty_bps,
+            } => {
+                // only asset contract can execute this message
+                let config: Config = read_config(&deps.storage)?;
+                let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+                let offer_index = addresses
+                    .asset_infos(&config)
+                    .iter()
+                    .position(|info| match info {
+                        AssetInfo::Token { contract_addr, .. } => {
+                            contract_addr == &env.message.sender
+                        }
+                        AssetInfo::NativeToken { .. } => false,
+                    })
+                    .ok_or_else(StdError::unauthorized)?;
+
+                try_place_order(
+                    deps,
+                    env,
+                    from,
+                    offer_index,
+                    amount,
+                    ask_min_price,
+                    expires_at,
+                    bounty_bps,
+                )
+            }
+            Cw20HookMsg::PlaceDca {
+                per_interval_amount,
+                interval_seconds,
+                min_price,
+            } => {
+                // only asset contract can execute this message
+                let config: Config = read_config(&deps.storage)?;
+                let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+                let offer_index = addresses
+                    .asset_infos(&config)
+                    .iter()
+                    .position(|info| match info {
+                        AssetInfo::Token { contract_addr, .. } => {
+                            contract_addr == &env.message.sender
+                        }
+                        AssetInfo::NativeToken { .. } => false,
+                    })
+                    .ok_or_else(StdError::unauthorized)?;
+
+                try_place_dca(
+                    deps,
+                    env,
+                    from,
+                    offer_index,
+                    amount,
+                    per_interval_amount,
+                    interval_seconds,
+                    min_price,
+                )
             }
         }
     } else {
-        Err(StdError::generic_err("data should be given"))
+        Err(PairError::invalid("data should be given").into())
     }
 }
 
-// Must token contract execute it
-pub fn try_post_initialize<S: Storage, A: Api, Q: Querier>(
+// Anyone can execute it
+pub fn try_add_entropy<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    entropy: Binary,
+) -> HandleResult {
+    if entropy.len() > MAX_ENTROPY_DONATION_BYTES {
+        return Err(PairError::invalid(format!(
+            "Entropy donation must not exceed {} bytes",
+            MAX_ENTROPY_DONATION_BYTES
+        ))
+        .into());
+    }
+
+    // Mix in values the donor doesn't control, so adversarial entropy can
+    // never make the pool more predictable than it already is
+    let mut donation = entropy.0;
+    donation.extend_from_slice(&env.block.time.to_be_bytes());
+    donation.extend_from_slice(&env.block.height.to_be_bytes());
+    donation.extend_from_slice(deps.api.canonical_address(&env.message.sender)?.as_slice());
+    supply_more_entropy(&mut deps.storage, donation.as_slice())?;
+
+    // Intentionally no logs, they could reveal the donated bytes
+    Ok(HandleResponse::default())
+}
+
+// Anyone can execute it
+pub fn try_refresh_settings<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
 ) -> HandleResult {
-    let config: PairInfoRaw = read_pair_info(&deps.storage)?;
+    let config: Config = read_config(&deps.storage)?;
+    let settings = query_pair_settings(
+        &deps,
+        &config.factory.address,
+        &config.factory.code_hash,
+        &env.contract.address,
+    )?;
 
-    // permission check
-    if config.liquidity_token != CanonicalAddr::default() {
+    store_settings_cache(
+        &mut deps.storage,
+        &SettingsCache {
+            settings,
+            fetched_at_height: env.block.height,
+        },
+    )?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "refresh_settings")],
+        data: None,
+    })
+}
+
+// Only factory can execute it
+pub fn try_push_settings<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    settings: PairSettings,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    if env.message.sender != config.factory.address {
         return Err(StdError::unauthorized());
     }
+    assert_valid_commission(&settings)?;
 
-    store_pair_info(
+    store_settings_cache(
         &mut deps.storage,
-        &PairInfoRaw {
-            liquidity_token: deps.api.canonical_address(&env.message.sender)?,
-            ..config.clone()
+        &SettingsCache {
+            settings,
+            fetched_at_height: env.block.height,
         },
     )?;
 
     Ok(HandleResponse {
-        messages: vec![snip20::register_receive_msg(
-            env.contract_code_hash,
-            None,
-            256,
-            config.token_code_hash,
-            env.message.sender.clone(),
-        )?],
-        log: vec![log("liquidity_token_addr", env.message.sender.as_str())],
+        messages: vec![],
+        log: vec![log("action", "push_settings")],
         data: None,
     })
 }
 
-/// CONTRACT - should approve contract to use the amount of token
-pub fn try_provide_liquidity<S: Storage, A: Api, Q: Querier>(
+// Only factory can execute it
+pub fn try_rotate_viewing_keys<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
-    assets: [Asset; 2],
-    slippage_tolerance: Option<Decimal>,
 ) -> HandleResult {
-    for asset in assets.iter() {
-        asset.assert_sent_native_token_balance(&env)?;
+    let mut config: Config = read_config(&deps.storage)?;
+    if env.message.sender != config.factory.address {
+        return Err(StdError::unauthorized());
     }
 
-    // Note: pair info + viewing keys are read from storage, therefore the input
-    // viewing keys to this function are not used
-    let pair_info: PairInfoRaw = read_pair_info(&deps.storage)?;
-    let mut pools: [Asset; 2] = pair_info.query_pools(deps, &env.contract.address)?;
-    let deposits: [Uint128; 2] = [
-        assets
-            .iter()
-            .find(|a| a.info.equal(&pools[0].info))
-            .map(|a| a.amount)
-            .expect("Wrong asset info is given"),
-        assets
-            .iter()
-            .find(|a| a.info.equal(&pools[1].info))
-            .map(|a| a.amount)
-            .expect("Wrong asset info is given"),
-    ];
+    // the pool was just mixed with this message and env in `handle`
+    let random = get_random_bytes(&deps.storage, 64);
 
-    let mut i = 0;
-    let mut messages: Vec<CosmosMsg> = vec![];
-    for pool in pools.iter_mut() {
-        // If the pool is token contract, then we need to execute TransferFrom msg to receive funds
-        if let AssetInfo::Token {
+    let mut messages = vec![];
+    for (i, asset_info) in config.asset_infos.iter_mut().enumerate() {
+        if let AssetInfoRaw::Token {
             contract_addr,
             token_code_hash,
-            ..
-        } = &pool.info
+            viewing_key,
+        } = asset_info
         {
-            messages.push(snip20::transfer_from_msg(
-                env.message.sender.clone(),
-                env.contract.address.clone(),
-                deposits[i],
+            let new_key = Binary::from(&random[i * 32..(i + 1) * 32]).to_base64();
+            messages.push(snip20::set_viewing_key_msg(
+                new_key.clone(),
                 None,
                 256,
                 token_code_hash.clone(),
-                contract_addr.clone(),
+                deps.api.human_address(contract_addr)?,
             )?);
-        } else {
-            // If the asset is native token, balance is already increased
-            // To calculated properly we should subtract user deposit from the pool
-            pool.amount = (pool.amount - deposits[i])?;
+            *viewing_key = new_key;
         }
-
-        i += 1;
     }
+    store_config(&mut deps.storage, &config)?;
 
-    // assert slippage tolerance
-    assert_slippage_tolerance(&slippage_tolerance, &deposits, &pools)?;
+    Ok(HandleResponse {
+        messages,
+        log: vec![log("action", "rotate_viewing_keys")],
+        data: None,
+    })
+}
 
-    let liquidity_token = deps.api.human_address(&pair_info.liquidity_token)?;
-    let total_share = query_supply(&deps, &liquidity_token, &pair_info.token_code_hash)?;
-    let share = if total_share == Uint128::zero() {
-        // Initial share = collateral amount
-        let deposit_0 = U256::from(deposits[0].u128());
-        let deposit_1 = U256::from(deposits[1].u128());
+// Only factory or admin can execute it
+pub fn try_set_debug_key<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    key: Option<String>,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    assert_factory_or_admin(&deps.api, &env, &config)?;
 
-        let sqrt = mul(Some(deposit_0), Some(deposit_1))
-            .and_then(|prod| u256_sqrt(prod))
-            .ok_or_else(|| {
-                StdError::generic_err(format!(
-                    "Cannot calculate sqrt(deposit_0 {} * deposit_1 {})",
-                    deposit_0, deposit_1
-                ))
-            })?;
+    let key_hash = key.map(|key| Sha256::digest(key.as_bytes()).to_vec());
+    store_debug_key_hash(&mut deps.storage, &key_hash)?;
 
-        Uint128(sqrt.low_u128())
-    } else {
-        // min(1, 2)
-        // 1. sqrt(deposit_0 * exchange_rate_0_to_1 * deposit_0) * (total_share / sqrt(pool_0 * pool_1))
-        // == deposit_0 * total_share / pool_0
-        // 2. sqrt(deposit_1 * exchange_rate_1_to_0 * deposit_1) * (total_share / sqrt(pool_1 * pool_1))
-        // == deposit_1 * total_share / pool_1
+    // the key itself is never logged
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "set_debug_key")],
+        data: None,
+    })
+}
 
-        // This was:
-        // std::cmp::min(
-        //   deposits[0].multiply_ratio(total_share, pools[0].amount),
-        //   deposits[1].multiply_ratio(total_share, pools[1].amount),
-        // )
+// Only factory can execute it
+pub fn try_set_full_exit<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    enabled: bool,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    if env.message.sender != config.factory.address {
+        return Err(StdError::unauthorized());
+    }
 
-        let total_share = Some(U256::from(total_share.u128()));
+    store_full_exit(&mut deps.storage, enabled)?;
 
-        let deposit0 = Some(U256::from(deposits[0].u128()));
-        let pools0_amount = Some(U256::from(pools[0].amount.u128()));
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "set_full_exit"), log("enabled", enabled)],
+        data: None,
+    })
+}
 
-        let share0 = div(mul(deposit0, total_share), pools0_amount).ok_or_else(|| {
-            StdError::generic_err(format!(
-                "Cannot calculate deposits[0] {} * total_share {} / pools[0].amount {}",
-                deposit0.unwrap(),
-                total_share.unwrap(),
-                pools0_amount.unwrap()
-            ))
-        })?;
+// Only factory or admin can execute it
+pub fn try_set_swap_data_endpoint<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    endpoint: Option<SwapDataEndpoint>,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    assert_factory_or_admin(&deps.api, &env, &config)?;
 
-        let deposit1 = Some(U256::from(deposits[1].u128()));
-        let pools1_amount = Some(U256::from(pools[1].amount.u128()));
+    let address = endpoint
+        .as_ref()
+        .map_or_else(String::new, |endpoint| endpoint.address.to_string());
+    store_swap_data_endpoint(&mut deps.storage, &endpoint)?;
 
-        let share1 = div(mul(deposit1, total_share), pools1_amount).ok_or_else(|| {
-            StdError::generic_err(format!(
-                "Cannot calculate deposits[1] {} * total_share {} / pools[1].amount {}",
-                deposit1.unwrap(),
-                total_share.unwrap(),
-                pools1_amount.unwrap()
-            ))
-        })?;
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "set_swap_data_endpoint"),
+            log("endpoint", address),
+        ],
+        data: None,
+    })
+}
+
+// Only factory can execute it
+pub fn try_ramp_amp<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    target_amp: u64,
+    end_height: u64,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    if env.message.sender != config.factory.address {
+        return Err(StdError::unauthorized());
+    }
 
-        Uint128(std::cmp::min(share0, share1).low_u128())
+    // a ramp starts from wherever the amp is now, even halfway through another ramp
+    let initial_amp = match load_pair_settings(deps, &env, &config)?.curve {
+        CurveType::Stable { amp } => amp,
+        CurveType::ConstantProduct | CurveType::Weighted { .. } => {
+            return Err(PairError::unsupported("Only a stable pair has an amp to ramp").into())
+        }
     };
+    assert_valid_amp(target_amp)?;
+    if end_height < env.block.height.saturating_add(MIN_AMP_RAMP_BLOCKS) {
+        return Err(PairError::invalid(format!(
+            "An amp ramp must take at least {} blocks",
+            MIN_AMP_RAMP_BLOCKS
+        ))
+        .into());
+    }
+    if target_amp > initial_amp.saturating_mul(MAX_AMP_CHANGE)
+        || initial_amp > target_amp.saturating_mul(MAX_AMP_CHANGE)
+    {
+        return Err(PairError::invalid(format!(
+            "A ramp can't change the amp of {} by more than {}x",
+            initial_amp, MAX_AMP_CHANGE
+        ))
+        .into());
+    }
 
-    messages.push(snip20::mint_msg(
-        env.message.sender,
-        share,
-        None,
-        256,
-        pair_info.token_code_hash,
-        deps.api.human_address(&pair_info.liquidity_token)?,
-    )?);
+    store_pair_curve(
+        &mut deps.storage,
+        &PairCurve {
+            curve: CurveType::Stable { amp: initial_amp },
+            ramp: Some(AmpRamp {
+                initial_amp,
+                target_amp,
+                start_height: env.block.height,
+                end_height,
+            }),
+        },
+    )?;
 
     Ok(HandleResponse {
-        messages,
+        messages: vec![],
         log: vec![
-            log("action", "provide_liquidity"),
-            log("assets", format!("{}, {}", assets[0], assets[1])),
-            log("share", &share),
+            log("action", "ramp_amp"),
+            log("initial_amp", initial_amp),
+            log("target_amp", target_amp),
+            log("end_height", end_height),
         ],
         data: None,
     })
 }
 
-pub fn try_withdraw_liquidity<S: Storage, A: Api, Q: Querier>(
+fn assert_valid_amp(amp: u64) -> StdResult<()> {
+    if amp == 0 || amp > MAX_AMP {
+        return Err(PairError::invalid(format!("amp must be between 1 and {}", MAX_AMP)).into());
+    }
+    Ok(())
+}
+
+fn assert_valid_weights(weights: &[u8; 2]) -> StdResult<()> {
+    if weights[0] == 0 || weights[1] == 0 || u16::from(weights[0]) + u16::from(weights[1]) != 100 {
+        return Err(PairError::invalid("weights must be positive and sum to 100").into());
+    }
+    Ok(())
+}
+
+// Only factory or admin can execute it
+pub fn try_set_staking_contract<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
-    sender: HumanAddr,
-    amount: Uint128,
+    staking_contract: Option<StakingContract>,
 ) -> HandleResult {
-    let pair_info: PairInfoRaw = read_pair_info(&deps.storage)?;
-    let liquidity_addr: HumanAddr = deps.api.human_address(&pair_info.liquidity_token)?;
+    let config: Config = read_config(&deps.storage)?;
+    assert_factory_or_admin(&deps.api, &env, &config)?;
 
-    let pools: [Asset; 2] = pair_info.query_pools(&deps, &env.contract.address)?;
-    let total_share: Uint128 = query_supply(&deps, &liquidity_addr, &pair_info.token_code_hash)?;
+    store_staking_contract(&mut deps.storage, &staking_contract)?;
 
-    let refund_assets: Vec<Asset> = pools
-        .iter()
-        .map(|a| {
-            // withdrawn_asset_amount = a.amount * amount / total_share
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "set_staking_contract")],
+        data: None,
+    })
+}
 
-            let current_pool_amount = Some(U256::from(a.amount.u128()));
-            let withdrawn_share_amount = Some(U256::from(amount.u128()));
-            let total_share = Some(U256::from(total_share.u128()));
+/// Fills in the asset decimals and symbols of pairs created before they were
+/// stored at init
+pub fn try_sync_token_metadata<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    if config.asset_decimals.is_some() && config.asset_symbols.is_some() {
+        return Err(PairError::state("Token metadata is already synced").into());
+    }
 
-            let withdrawn_asset_amount = div(
-                mul(current_pool_amount, withdrawn_share_amount),
-                total_share,
-            )
-                .ok_or_else(|| {
-                    StdError::generic_err(format!(
-                    "Cannot calculate current_pool_amount {} * withdrawn_share_amount {} / total_share {}",
-                    a.amount,
-                    amount,
-                    total_share.unwrap()
-                    ))
-                })?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    let asset_infos = addresses.asset_infos(&config);
+    let (decimals0, symbol0) = query_asset_metadata(&deps, &asset_infos[0])?;
+    let (decimals1, symbol1) = query_asset_metadata(&deps, &asset_infos[1])?;
 
-            Ok(Asset {
-                info: a.info.clone(),
-                amount: Uint128(withdrawn_asset_amount.low_u128()),
-            })
-        })
-        .collect::<StdResult<Vec<Asset>>>()?;
+    store_config(
+        &mut deps.storage,
+        &Config {
+            asset_decimals: Some([decimals0, decimals1]),
+            asset_symbols: Some([symbol0, symbol1]),
+            ..config
+        },
+    )?;
 
-    // update pool info
     Ok(HandleResponse {
-        messages: vec![
-            // refund asset tokens
-            refund_assets[0].clone().into_msg(
-                deps,
-                env.contract.address.clone(),
-                sender.clone(),
-            )?,
-            refund_assets[1].clone().into_msg(
-                deps,
-                env.contract.address.clone(),
-                sender.clone(),
-            )?,
-            // burn liquidity token
-            snip20::burn_msg(
-                amount,
-                None,
-                256,
-                pair_info.token_code_hash,
-                deps.api.human_address(&pair_info.liquidity_token)?,
-            )?,
-        ],
-        log: vec![
-            log("action", "withdraw_liquidity"),
-            log("withdrawn_share", &amount.to_string()),
-            log(
-                "refund_assets",
-                format!("{}, {}", refund_assets[0].clone(), refund_assets[1].clone()),
-            ),
-        ],
+        messages: vec![],
+        log: vec![log("action", "sync_token_metadata")],
         data: None,
     })
 }
 
-// CONTRACT - a user must do token approval
-pub fn try_swap<S: Storage, A: Api, Q: Querier>(
+// Only factory or admin can execute it
+pub fn try_set_admin<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
-    sender: HumanAddr,
-    offer_asset: Asset,
-    expected_return: Option<Uint128>,
-    belief_price: Option<Decimal>,
-    max_spread: Option<Decimal>,
-    to: Option<HumanAddr>,
+    new_admin: Option<HumanAddr>,
 ) -> HandleResult {
-    offer_asset.assert_sent_native_token_balance(&env)?;
+    let config: Config = read_config(&deps.storage)?;
+    assert_factory_or_admin(&deps.api, &env, &config)?;
 
-    let mut pair_info: PairInfoRaw = read_pair_info(&deps.storage)?;
+    let admin = match &new_admin {
+        Some(new_admin) => Some(deps.api.canonical_address(new_admin)?),
+        None => None,
+    };
+    store_config(&mut deps.storage, &Config { admin, ..config })?;
 
-    let pools: [Asset; 2] = pair_info.query_pools(&deps, &env.contract.address)?;
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "set_admin")],
+        data: None,
+    })
+}
 
-    let offer_pool: Asset;
-    let ask_pool: Asset;
+/// Operational commands may come from the factory or the pair's admin
+fn assert_factory_or_admin<A: Api>(api: &A, env: &Env, config: &Config) -> StdResult<()> {
+    if env.message.sender == config.factory.address {
+        return Ok(());
+    }
+    match &config.admin {
+        Some(admin) if api.canonical_address(&env.message.sender)? == *admin => Ok(()),
+        _ => Err(StdError::unauthorized()),
+    }
+}
 
-    // If the asset balance is already increased
-    // To calculated properly we should subtract user deposit from the pool
-    if offer_asset.info.equal(&pools[0].info) {
-        let pool_amount = U256::from(pools[0].amount.u128());
-        let offer_amount = U256::from(offer_asset.amount.u128());
+/// The factory settings with the pair's own curve and endpoint, see `load_factory_settings`
+fn load_pair_settings<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    config: &Config,
+) -> StdResult<PairSettings> {
+    Ok(load_pair_settings_with_fallback(deps, env, config)?.0)
+}
 
-        let amount = pool_amount.checked_sub(offer_amount).ok_or_else(|| {
-            StdError::generic_err("offer_amount larger than pool_amount + offer_amount")
-        })?;
+/// Same as `load_pair_settings`, also telling whether the cache stood in for a
+/// factory that couldn't be queried
+fn load_pair_settings_with_fallback<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    config: &Config,
+) -> StdResult<(PairSettings, bool)> {
+    let (mut settings, fallback) = load_factory_settings(deps, env, config)?;
+    apply_pair_overrides(&deps.storage, &mut settings, env.block.height)?;
+    Ok((settings, fallback))
+}
 
-        offer_pool = Asset {
-            amount: Uint128(amount.low_u128()),
-            info: pools[0].info.clone(),
+/// Replaces the factory's curve in `settings` by the one the pair was created with,
+/// at the amp its ramp has reached by `height`, and the factory's swap data endpoint
+/// by the pair's own
+fn apply_pair_overrides<S: Storage>(
+    storage: &S,
+    settings: &mut PairSettings,
+    height: u64,
+) -> StdResult<()> {
+    if let Some(endpoint) = read_swap_data_endpoint(storage)? {
+        settings.swap_data_endpoint = Some(endpoint);
+    }
+    if let Some(pair_curve) = read_pair_curve(storage)? {
+        settings.curve = match (pair_curve.curve, pair_curve.ramp) {
+            (CurveType::Stable { .. }, Some(ramp)) => CurveType::Stable {
+                amp: ramp.amp_at(height),
+            },
+            (curve, _) => curve,
         };
-        ask_pool = pools[1].clone();
-
-        pair_info.asset0_volume = pair_info.asset0_volume.add(offer_asset.amount);
-    } else if offer_asset.info.equal(&pools[1].info) {
-        let pool_amount = U256::from(pools[1].amount.u128());
-        let offer_amount = U256::from(offer_asset.amount.u128());
-
-        let amount = pool_amount.checked_sub(offer_amount).ok_or_else(|| {
-            StdError::generic_err("offer_amount larger than pool_amount + offer_amount")
-        })?;
+    }
+    Ok(())
+}
 
-        offer_pool = Asset {
-            amount: Uint128(amount.low_u128()),
-            info: pools[1].info.clone(),
-        };
-        ask_pool = pools[0].clone();
+/// Settings for queries, which have no block height: an amp ramp is quoted as of
+/// the pair's last swap. If the factory can't be queried, the last cached settings
+/// are quoted whatever their age, the swap itself still checks them.
+fn query_settings<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    config: &Config,
+    addresses: &Addresses,
+) -> StdResult<PairSettings> {
+    let mut settings = match query_pair_settings(
+        &deps,
+        &config.factory.address,
+        &config.factory.code_hash,
+        &addresses.contract_addr.human,
+    ) {
+        Ok(settings) => settings,
+        Err(err) => read_settings_cache(&deps.storage)?
+            .map(|cache| cache.settings)
+            .ok_or(err)?,
+    };
+    let height = read_stats(&deps.storage)?
+        .last_swap_at_height
+        .unwrap_or_default();
+    apply_pair_overrides(&deps.storage, &mut settings, height)?;
+    Ok(settings)
+}
 
-        pair_info.asset1_volume = pair_info.asset1_volume.add(offer_asset.amount);
-    } else {
-        return Err(StdError::generic_err("Wrong asset info is given"));
+/// Returns the factory settings, served from the local cache while it's younger
+/// than `settings_ttl_blocks`. A stale cache is refreshed from the factory, and is
+/// still used as a fallback if the factory can't be queried, which the returned flag
+/// tells. The fallback doesn't apply to settings older than `MAX_SETTINGS_AGE_BLOCKS`.
+fn load_factory_settings<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    config: &Config,
+) -> StdResult<(PairSettings, bool)> {
+    let cache = read_settings_cache(&deps.storage)?;
+    if let Some(cache) = &cache {
+        let ttl_blocks = read_settings_ttl_blocks(&deps.storage)?;
+        if env.block.height < cache.fetched_at_height.saturating_add(ttl_blocks) {
+            return Ok((cache.settings.clone(), false));
+        }
     }
 
-    store_pair_info(&mut deps.storage, &pair_info)?;
-
-    let pair_settings = query_pair_settings(
+    match query_pair_settings(
         &deps,
-        &pair_info.factory.address,
-        &pair_info.factory.code_hash,
-    )?;
+        &config.factory.address,
+        &config.factory.code_hash,
+        &env.contract.address,
+    ) {
+        Ok(settings) => {
+            store_settings_cache(
+                &mut deps.storage,
+                &SettingsCache {
+                    settings: settings.clone(),
+                    fetched_at_height: env.block.height,
+                },
+            )?;
+            Ok((settings, false))
+        }
+        // an old cache could still say `paused: false`
+        Err(err) => cache
+            .filter(|cache| {
+                env.block.height
+                    < cache
+                        .fetched_at_height
+                        .saturating_add(MAX_SETTINGS_AGE_BLOCKS)
+            })
+            .map(|cache| (cache.settings, true))
+            .ok_or(err),
+    }
+}
 
-    let offer_amount = offer_asset.amount;
-    let (return_amount, spread_amount, commission_amount) = compute_swap(
-        offer_pool.amount,
-        ask_pool.amount,
-        offer_amount,
-        pair_settings.swap_fee.commission_rate_nom,
-        pair_settings.swap_fee.commission_rate_denom,
-    )?;
+/// Swaps can't take the ask pool below the `min_reserve` floor
+fn assert_min_reserve(
+    pair_settings: &PairSettings,
+    ask_pool: &Asset,
+    return_amount: Uint128,
+) -> StdResult<()> {
+    if let Some(min_reserve) = pair_settings.min_reserve {
+        let remaining = (ask_pool.amount - return_amount).unwrap_or_else(|_| Uint128::zero());
+        if remaining < min_reserve {
+            return Err(PairError::insufficient_liquidity(format!(
+                "The swap would leave {} in the {} pool, below the min_reserve of {}",
+                remaining, ask_pool.info, min_reserve
+            ))
+            .into());
+        }
+    }
+    Ok(())
+}
 
-    // check max spread limit if exist
-    assert_max_spread(
-        belief_price,
-        max_spread,
-        expected_return,
+/// Adds `offer_amount` to the current block's offer volume of the `offer_index` pool.
+/// Fails once that volume would go past `max_block_volume_bps` of the pool's reserve
+/// before the block's first swap. `reserves` are the pools before this swap, and the
+/// first swap of a block records them.
+fn record_block_volume<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    pair_settings: &PairSettings,
+    reserves: [Uint128; 2],
+    offer_index: usize,
+    offer_amount: Uint128,
+) -> StdResult<()> {
+    match add_block_volume(
+        read_block_volume(&deps.storage)?,
+        env,
+        pair_settings,
+        reserves,
+        offer_index,
         offer_amount,
-        return_amount,
-        commission_amount,
-        spread_amount,
-    )?;
+    )? {
+        Some(block_volume) => store_block_volume(&mut deps.storage, &block_volume),
+        None => Ok(()),
+    }
+}
 
-    let return_asset = Asset {
-        info: ask_pool.info.clone(),
-        amount: return_amount,
+/// `block_volume` with `offer_amount` added, see `record_block_volume`. None when
+/// there's no `max_block_volume_bps` to record it for.
+fn add_block_volume(
+    block_volume: Option<BlockVolume>,
+    env: &Env,
+    pair_settings: &PairSettings,
+    reserves: [Uint128; 2],
+    offer_index: usize,
+    offer_amount: Uint128,
+) -> StdResult<Option<BlockVolume>> {
+    let max_block_volume_bps = match pair_settings.max_block_volume_bps {
+        Some(max_block_volume_bps) => max_block_volume_bps,
+        None => return Ok(None),
     };
 
-    let mut messages = Vec::<CosmosMsg>::new();
-    messages.push(return_asset.clone().into_msg(
-        &deps,
+    let mut block_volume = match block_volume {
+        Some(block_volume) if block_volume.height == env.block.height => block_volume,
+        _ => BlockVolume {
+            height: env.block.height,
+            reserves,
+            volumes: [Uint128::zero(), Uint128::zero()],
+        },
+    };
+
+    let volume = block_volume.volumes[offer_index]
+        .u128()
+        .saturating_add(offer_amount.u128());
+    let max_volume = U256::from(block_volume.reserves[offer_index].u128())
+        * U256::from(max_block_volume_bps)
+        / U256::from(10_000u64);
+    if U256::from(volume) > max_volume {
+        return Err(PairError::paused("volume circuit breaker tripped").into());
+    }
+
+    block_volume.volumes[offer_index] = Uint128(volume);
+    Ok(Some(block_volume))
+}
+
+/// The part of `amount` LP shares that can be withdrawn without taking either pool
+/// below `min_reserve`. Withdrawals don't depend on the factory, so without
+/// settings at hand, or once the factory allowed a full exit, nothing is capped.
+fn cap_withdrawal<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    config: &Config,
+    pools: &[Asset; 2],
+    amount: Uint128,
+    total_share: Uint128,
+) -> StdResult<Uint128> {
+    if read_full_exit(&deps.storage)? {
+        return Ok(amount);
+    }
+    let min_reserve = match load_pair_settings(deps, env, config)
+        .ok()
+        .and_then(|settings| settings.min_reserve)
+    {
+        Some(min_reserve) => U256::from(min_reserve.u128()),
+        None => return Ok(amount),
+    };
+
+    let mut capped = U256::from(amount.u128());
+    for pool in pools.iter() {
+        let pool_amount = U256::from(pool.amount.u128());
+        if pool_amount.is_zero() {
+            continue;
+        }
+        // refund = pool * share / total_share <= pool - min_reserve
+        let max_share =
+            pool_amount.saturating_sub(min_reserve) * U256::from(total_share.u128()) / pool_amount;
+        capped = capped.min(max_share);
+    }
+    if capped.is_zero() {
+        return Err(PairError::insufficient_liquidity(format!(
+            "The pools are at the min_reserve of {}, no liquidity can be withdrawn",
+            min_reserve
+        ))
+        .into());
+    }
+    u256_to_uint128(capped)
+}
+
+/// Swaps and deposits stop while the factory pauses trading, withdrawals don't
+fn assert_not_paused<S: Storage>(storage: &S, pair_settings: &PairSettings) -> StdResult<()> {
+    if pair_settings.paused {
+        return Err(PairError::paused("trading is globally paused").into());
+    }
+    if read_breaker_tripped(storage)? {
+        return Err(PairError::paused("trading is paused by the reserve circuit breaker").into());
+    }
+    Ok(())
+}
+
+/// Whether paying out `return_amount` would take `ask_pool` below `reserve_floor_bps`
+/// of what it holds now
+fn breaks_reserve_floor(
+    pair_settings: &PairSettings,
+    ask_pool: &Asset,
+    return_amount: Uint128,
+) -> bool {
+    if pair_settings.reserve_floor_bps == 0 {
+        return false;
+    }
+    let remaining =
+        U256::from(ask_pool.amount.u128()).saturating_sub(U256::from(return_amount.u128()));
+    remaining * U256::from(10_000u64)
+        < U256::from(ask_pool.amount.u128()) * U256::from(pair_settings.reserve_floor_bps)
+}
+
+/// Scales a pool price `pools[1] / pools[0]` to the spot price of the curve, which is
+/// `weights[0] / weights[1]` times it on a weighted pair
+fn price_weights(curve: &CurveType) -> [u8; 2] {
+    match curve {
+        CurveType::Weighted { weights } => *weights,
+        _ => [1, 1],
+    }
+}
+
+/// Fails when the pool price after a swap, `pools[1] / pools[0]` at the pool weights,
+/// would be more than `max_deviation_bps` away from the `price_oracle` reference price
+fn assert_oracle_price<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    pair_settings: &PairSettings,
+    pools_after: &[Asset; 2],
+) -> StdResult<()> {
+    let price_oracle = match &pair_settings.price_oracle {
+        Some(price_oracle) => price_oracle,
+        None => return Ok(()),
+    };
+
+    let reference_price = match query_reference_price(
+        deps,
+        price_oracle,
+        [pools_after[0].info.clone(), pools_after[1].info.clone()],
+    ) {
+        Ok(price) if !price.is_zero() => price,
+        _ if price_oracle.fail_open => return Ok(()),
+        _ => return Err(PairError::oracle("The price oracle is unavailable").into()),
+    };
+
+    // both prices scaled by 10^18 and by pools[0] * weights[1], so nothing is divided
+    let weights = price_weights(&pair_settings.curve);
+    let reference_price = decimal_to_u256_atomics(reference_price)?
+        * U256::from(pools_after[0].amount.u128())
+        * U256::from(weights[1]);
+    let pool_price = U256::from(pools_after[1].amount.u128())
+        * U256::from(1_000_000_000_000_000_000u128)
+        * U256::from(weights[0]);
+    let deviation = if pool_price > reference_price {
+        pool_price - reference_price
+    } else {
+        reference_price - pool_price
+    };
+
+    let deviation_bps = deviation * U256::from(10_000u64) / reference_price;
+    if deviation_bps > U256::from(price_oracle.max_deviation_bps) {
+        return Err(PairError::oracle(format!(
+            "The pool price would move {} bps from the oracle price, over the {} bps allowed",
+            deviation_bps, price_oracle.max_deviation_bps
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+// Must token contract execute it
+pub fn try_post_initialize<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+
+    if config.liquidity_token != CanonicalAddr::default() {
+        return Err(PairError::state(format!(
+            "The LP token is already registered: {}",
+            read_addresses(&deps.storage, &deps.api)?
+                .liquidity_token
+                .human
+        ))
+        .into());
+    }
+
+    // anyone could call first, only accept a token that runs the expected code
+    // and looks like the one instantiated at init. Secret only answers a query
+    // sent with the callee's code hash, so this doubles as the code hash check.
+    let token_info = snip20::token_info_query(
+        &deps.querier,
+        256,
+        config.token_code_hash.clone(),
+        env.message.sender.clone(),
+    )
+    .map_err(|_| StdError::unauthorized())?;
+    let expected_lp_token = read_expected_lp_token(&deps.storage)?;
+    if let Some(expected) = &expected_lp_token {
+        if token_info.name != expected.name
+            || token_info.symbol != expected.symbol
+            || token_info.decimals != expected.decimals
+        {
+            return Err(StdError::unauthorized());
+        }
+    }
+
+    let token_code_hash = config.token_code_hash.clone();
+    let factory = config.factory.clone();
+    // the factory answers queries with these, so they go without the viewing keys
+    let asset_infos = read_addresses(&deps.storage, &deps.api)?.asset_infos(&config);
+    let liquidity_token = env.message.sender.clone();
+    let mut res = set_liquidity_token(deps, env, config, liquidity_token)?;
+    res.log.push(log("verified_code_hash", token_code_hash));
+
+    // a replacement token also has to reach the factory's registry
+    if expected_lp_token.map_or(false, |expected| expected.replacement) {
+        res.messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: factory.address,
+            callback_code_hash: factory.code_hash,
+            msg: to_binary(&FactoryHandleMsg::Register { asset_infos })?,
+            send: vec![],
+        }));
+    }
+    Ok(res)
+}
+
+/// Lets the factory move a pair without liquidity to a new LP token code. The pair
+/// instantiates a fresh token, which registers through PostInitialize like at init.
+pub fn try_update_liquidity_token<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    token_code_id: u64,
+    token_code_hash: String,
+    prng_seed: Binary,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    if env.message.sender != config.factory.address {
+        return Err(StdError::unauthorized());
+    }
+
+    // shares of the old token would no longer redeem anything
+    if config.liquidity_token != CanonicalAddr::default() {
+        let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+        let total_share = query_supply(
+            &deps,
+            &addresses.liquidity_token.human,
+            &config.token_code_hash,
+        )?;
+        if !total_share.is_zero() {
+            return Err(PairError::state(
+                "The LP token can only be replaced while its supply is zero",
+            )
+            .into());
+        }
+    }
+
+    let expected = read_expected_lp_token(&deps.storage)?.ok_or_else(|| {
+        PairError::unsupported("The pair didn't instantiate its LP token, it can't replace it")
+    })?;
+    let lp_token = ExpectedLpToken {
+        code_id: token_code_id,
+        // labels are unique per chain
+        label: format!("{}-{}", expected.label, token_code_id),
+        replacement: true,
+        ..expected
+    };
+    let instantiate_msg = lp_token_instantiate_msg(&env, &lp_token, &token_code_hash, &prng_seed)?;
+
+    let config = Config {
+        liquidity_token: CanonicalAddr::default(),
+        token_code_hash,
+        ..config
+    };
+    store_config(&mut deps.storage, &config)?;
+    store_addresses(
+        &mut deps.storage,
+        &Addresses::from_config(&deps.api, &config)?,
+    )?;
+    store_expected_lp_token(&mut deps.storage, &lp_token)?;
+
+    Ok(HandleResponse {
+        messages: vec![instantiate_msg],
+        log: vec![
+            log("action", "update_liquidity_token"),
+            log("token_code_id", token_code_id),
+        ],
+        data: None,
+    })
+}
+
+/// Lets the factory pair the LP token by hand when PostInitialize was squatted
+/// or never ran
+pub fn try_force_set_liquidity_token<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    liquidity_token: HumanAddr,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    if env.message.sender != config.factory.address {
+        return Err(StdError::unauthorized());
+    }
+
+    set_liquidity_token(deps, env, config, liquidity_token)
+}
+
+/// Stores the LP token and registers the pair as its receiver
+fn set_liquidity_token<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    config: Config,
+    liquidity_token: HumanAddr,
+) -> HandleResult {
+    let liquidity_token = CachedAddr {
+        canonical: deps.api.canonical_address(&liquidity_token)?,
+        human: liquidity_token,
+    };
+    store_config(
+        &mut deps.storage,
+        &Config {
+            liquidity_token: liquidity_token.canonical.clone(),
+            ..config.clone()
+        },
+    )?;
+    store_addresses(
+        &mut deps.storage,
+        &Addresses {
+            liquidity_token: liquidity_token.clone(),
+            ..read_addresses(&deps.storage, &deps.api)?
+        },
+    )?;
+
+    Ok(HandleResponse {
+        messages: vec![snip20::register_receive_msg(
+            env.contract_code_hash,
+            None,
+            256,
+            config.token_code_hash,
+            liquidity_token.human.clone(),
+        )?],
+        log: vec![log("liquidity_token_addr", liquidity_token.human.as_str())],
+        data: None,
+    })
+}
+
+/// CONTRACT - should approve contract to use the amount of token
+pub fn try_provide_liquidity<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    assets: [Asset; 2],
+    slippage_tolerance: Option<Decimal>,
+    auto_stake: Option<AutoStake>,
+    receiver: Option<HumanAddr>,
+) -> HandleResult {
+    assert_whitelisted(deps, &env.message.sender)?;
+    if slippage_tolerance > Some(Decimal::one()) {
+        return Err(PairError::invalid("slippage tolerance must be between 0 and 1").into());
+    }
+    let refunds: Vec<Coin> = collect_native_funds(&env, &assets)?;
+    let staking_contract: Option<StakingContract> = match auto_stake {
+        None => None,
+        Some(AutoStake::Default {}) => Some(
+            read_staking_contract(&deps.storage)?
+                .ok_or_else(|| PairError::state("No default staking contract is registered"))?,
+        ),
+        Some(AutoStake::Contract {
+            staking_contract,
+            code_hash,
+        }) => Some(StakingContract {
+            staking_contract,
+            code_hash,
+        }),
+    };
+
+    // Note: pair info + viewing keys are read from storage, therefore the input
+    // viewing keys to this function are not used
+    let config: Config = read_config(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+
+    let pair_settings = load_pair_settings(deps, &env, &config)?;
+    assert_not_paused(&deps.storage, &pair_settings)?;
+
+    // native uscrt is deposited into sSCRT instead of being pulled with TransferFrom
+    let mut wraps_deposit = false;
+    let assets: Vec<Asset> = assets
+        .iter()
+        .map(|asset| match wrap_native(&config, &addresses, asset) {
+            Some(wrapped_asset) => {
+                wraps_deposit = true;
+                wrapped_asset
+            }
+            None => asset.clone(),
+        })
+        .collect();
+
+    let mut pools: [Asset; 2] = query_pools(deps, &config, &addresses)?;
+    let deposits: [Uint128; 2] = [
+        assets
+            .iter()
+            .find(|a| a.info.equal(&pools[0].info))
+            .map(|a| a.amount)
+            .ok_or_else(|| PairError::asset_not_in_pair(&pools[0].info))?,
+        assets
+            .iter()
+            .find(|a| a.info.equal(&pools[1].info))
+            .map(|a| a.amount)
+            .ok_or_else(|| PairError::asset_not_in_pair(&pools[1].info))?,
+    ];
+
+    let mut i = 0;
+    let mut messages: Vec<CosmosMsg> = vec![];
+    for pool in pools.iter_mut() {
+        // If the pool is token contract, then we need to execute TransferFrom msg to receive funds
+        if let AssetInfo::Token {
+            contract_addr,
+            token_code_hash,
+            ..
+        } = &pool.info
+        {
+            match &config.wrap_config {
+                Some(wrap_config) if wraps_deposit && contract_addr == &wrap_config.sscrt_addr => {
+                    messages.push(snip20::deposit_msg(
+                        deposits[i],
+                        None,
+                        256,
+                        token_code_hash.clone(),
+                        contract_addr.clone(),
+                    )?);
+                }
+                _ => messages.push(snip20::transfer_from_msg(
+                    env.message.sender.clone(),
+                    env.contract.address.clone(),
+                    deposits[i],
+                    None,
+                    256,
+                    token_code_hash.clone(),
+                    contract_addr.clone(),
+                )?),
+            }
+        } else {
+            // If the asset is native token, balance is already increased
+            // To calculated properly we should subtract user deposit from the pool
+            pool.amount = (pool.amount - sent_native_amount(&env, &pool.info))?;
+        }
+
+        i += 1;
+    }
+
+    if !refunds.is_empty() {
+        messages.push(CosmosMsg::Bank(BankMsg::Send {
+            from_address: env.contract.address.clone(),
+            to_address: env.message.sender.clone(),
+            amount: refunds.clone(),
+        }));
+    }
+
+    // assert slippage tolerance
+    assert_slippage_tolerance(&slippage_tolerance, &deposits, &pools)?;
+
+    // pools don't include the deposit at this point
+    let entry_price = if pools[0].amount.is_zero() {
+        None
+    } else {
+        Some(u256_ratio_to_decimal(
+            U256::from(pools[1].amount.u128()),
+            U256::from(pools[0].amount.u128()),
+        )?)
+    };
+
+    let liquidity_token = &addresses.liquidity_token.human;
+    let total_share = query_supply(&deps, liquidity_token, &config.token_code_hash)?;
+    let share = compute_share(&deposits, &pools, total_share, &pair_settings)?;
+
+    let receiver = receiver.unwrap_or_else(|| env.message.sender.clone());
+    match staking_contract {
+        // the pair holds the minted shares just long enough to stake them
+        Some(staking_contract) => {
+            messages.push(snip20::mint_msg(
+                env.contract.address.clone(),
+                share,
+                None,
+                256,
+                config.token_code_hash.clone(),
+                addresses.liquidity_token.human.clone(),
+            )?);
+            messages.push(snip20::send_msg(
+                staking_contract.staking_contract,
+                share,
+                Some(to_binary(&StakingHookMsg::StakeFor {
+                    staker: receiver.clone(),
+                })?),
+                None,
+                256,
+                config.token_code_hash,
+                addresses.liquidity_token.human,
+            )?);
+        }
+        None => messages.push(snip20::mint_msg(
+            receiver.clone(),
+            share,
+            None,
+            256,
+            config.token_code_hash,
+            addresses.liquidity_token.human,
+        )?),
+    }
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            log("action", "provide_liquidity"),
+            log("assets", format!("{}, {}", assets[0], assets[1])),
+            log("share", &share),
+            log("receiver", receiver.as_str()),
+            log("entry_reserves", format!("{}, {}", pools[0], pools[1])),
+            log(
+                "entry_price",
+                entry_price.map_or_else(String::new, |price| price.to_string()),
+            ),
+        ],
+        data: Some(to_binary(&ProvideLiquidityResponse {
+            share,
+            entry_reserves: pools,
+            entry_price,
+            refunds,
+        })?),
+    })
+}
+
+/// LP shares minted for `deposits` into `pools`, which don't include them yet. Past
+/// the first deposit a weighted pair mints pro rata as well: a deposit at the pool
+/// ratio keeps the weights, like the pro rata refunds of a withdrawal. Shares round
+/// down so a share never redeems for more than was deposited for it.
+pub fn compute_share(
+    deposits: &[Uint128; 2],
+    pools: &[Asset; 2],
+    total_share: Uint128,
+    pair_settings: &PairSettings,
+) -> StdResult<Uint128> {
+    if let CurveType::Stable { amp } = pair_settings.curve {
+        return compute_share_stable(deposits, pools, total_share, pair_settings, amp);
+    }
+
+    let initial_share_multiplier = pair_settings.initial_share_multiplier;
+    if total_share == Uint128::zero() {
+        // Initial share = collateral amount
+        let deposit_0 = U256::from(deposits[0].u128());
+        let deposit_1 = U256::from(deposits[1].u128());
+
+        // a weighted pair takes the weighted geometric mean instead of the sqrt
+        let sqrt = match pair_settings.curve {
+            CurveType::Weighted { weights } => {
+                weighted_geometric_mean(deposit_0, deposit_1, weights[0].into(), weights[1].into())
+            }
+            _ => mul(Some(deposit_0), Some(deposit_1)).and_then(|prod| u256_sqrt(prod)),
+        }
+        .ok_or_else(|| {
+            PairError::overflow(format!(
+                "sqrt(deposit_0 {} * deposit_1 {})",
+                deposit_0, deposit_1
+            ))
+        })?;
+
+        // later shares are minted pro rata to the supply, so scaling it here
+        // doesn't change what a share redeems for
+        let multiplier = initial_share_multiplier.unwrap_or_else(|| Uint128(1));
+        let initial_share =
+            mul(Some(sqrt), Some(U256::from(multiplier.u128()))).ok_or_else(|| {
+                PairError::overflow(format!(
+                    "sqrt {} * initial_share_multiplier {}",
+                    sqrt, multiplier
+                ))
+            })?;
+
+        u256_to_uint128(initial_share)
+    } else {
+        // min(1, 2)
+        // 1. sqrt(deposit_0 * exchange_rate_0_to_1 * deposit_0) * (total_share / sqrt(pool_0 * pool_1))
+        // == deposit_0 * total_share / pool_0
+        // 2. sqrt(deposit_1 * exchange_rate_1_to_0 * deposit_1) * (total_share / sqrt(pool_1 * pool_1))
+        // == deposit_1 * total_share / pool_1
+
+        // This was:
+        // std::cmp::min(
+        //   deposits[0].multiply_ratio(total_share, pools[0].amount),
+        //   deposits[1].multiply_ratio(total_share, pools[1].amount),
+        // )
+
+        let total_share = Some(U256::from(total_share.u128()));
+
+        let deposit0 = Some(U256::from(deposits[0].u128()));
+        let pools0_amount = Some(U256::from(pools[0].amount.u128()));
+
+        let share0 = div(mul(deposit0, total_share), pools0_amount).ok_or_else(|| {
+            PairError::overflow(format!(
+                "deposits[0] {} * total_share {} / pools[0].amount {}",
+                deposit0.unwrap(),
+                total_share.unwrap(),
+                pools0_amount.unwrap()
+            ))
+        })?;
+
+        let deposit1 = Some(U256::from(deposits[1].u128()));
+        let pools1_amount = Some(U256::from(pools[1].amount.u128()));
+
+        let share1 = div(mul(deposit1, total_share), pools1_amount).ok_or_else(|| {
+            PairError::overflow(format!(
+                "deposits[1] {} * total_share {} / pools[1].amount {}",
+                deposit1.unwrap(),
+                total_share.unwrap(),
+                pools1_amount.unwrap()
+            ))
+        })?;
+
+        u256_to_uint128(std::cmp::min(share0, share1))
+    }
+}
+
+/// Same as `compute_share` on the stableswap invariant, shares follow the growth of D.
+/// Like on Curve, the part of a deposit that unbalances the pools pays half the swap
+/// commission, or depositing one asset and withdrawing both would be a free swap.
+fn compute_share_stable(
+    deposits: &[Uint128; 2],
+    pools: &[Asset; 2],
+    total_share: Uint128,
+    pair_settings: &PairSettings,
+    amp: u64,
+) -> StdResult<Uint128> {
+    if amp == 0 {
+        return Err(PairError::invalid("amp must be positive").into());
+    }
+    let amp = U256::from(amp);
+    let invariant = |balances: [U256; 2]| {
+        stable_invariant(balances[0], balances[1], amp).ok_or_else(|| {
+            PairError::overflow(format!(
+                "the stable invariant of {} and {}",
+                balances[0], balances[1]
+            ))
+        })
+    };
+
+    let old_balances = [
+        U256::from(pools[0].amount.u128()),
+        U256::from(pools[1].amount.u128()),
+    ];
+    let new_balances = [
+        old_balances[0] + U256::from(deposits[0].u128()),
+        old_balances[1] + U256::from(deposits[1].u128()),
+    ];
+
+    if total_share.is_zero() {
+        // later shares are minted pro rata to D, so the multiplier only scales them
+        let multiplier = pair_settings
+            .initial_share_multiplier
+            .unwrap_or_else(|| Uint128(1));
+        let initial_share = mul(
+            Some(invariant(new_balances)?),
+            Some(U256::from(multiplier.u128())),
+        )
+        .ok_or_else(|| PairError::overflow("the initial share"))?;
+        return u256_to_uint128(initial_share);
+    }
+
+    let d0 = invariant(old_balances)?;
+    if d0.is_zero() {
+        return Err(PairError::insufficient_liquidity(
+            "Cannot mint shares of a pair with empty pools",
+        )
+        .into());
+    }
+    let d1 = invariant(new_balances)?;
+
+    // commission / 2 on how far each balance lands from where a balanced deposit puts it
+    let fee_nom = U256::from(pair_settings.swap_fee.commission_rate_nom.u128());
+    let fee_denom = U256::from(pair_settings.swap_fee.commission_rate_denom.u128()) * 2;
+    let mut charged_balances = new_balances;
+    for (charged, (old, new)) in charged_balances
+        .iter_mut()
+        .zip(old_balances.iter().zip(new_balances.iter()))
+    {
+        let ideal = d1 * *old / d0;
+        let imbalance = if ideal > *new {
+            ideal - *new
+        } else {
+            *new - ideal
+        };
+        // the fee rounds up, the minted shares round down below
+        let fee = div_ceil(Some(imbalance * fee_nom), Some(fee_denom))
+            .ok_or_else(|| PairError::overflow("the imbalance fee"))?;
+        *charged = new.saturating_sub(fee);
+    }
+    let d2 = invariant(charged_balances)?;
+
+    u256_to_uint128(U256::from(total_share.u128()) * d2.saturating_sub(d0) / d0)
+}
+
+pub fn try_withdraw_liquidity<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    sender: HumanAddr,
+    amount: Uint128,
+    unwrap: bool,
+    to: Option<HumanAddr>,
+    to_callback: Option<(String, Binary)>,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    let liquidity_addr: HumanAddr = addresses.liquidity_token.human.clone();
+    let recipient: HumanAddr = to.unwrap_or_else(|| sender.clone());
+
+    let pools: [Asset; 2] = query_pools(&deps, &config, &addresses)?;
+    let total_share: Uint128 = outstanding_share(&deps, &config, &liquidity_addr)?;
+    let requested_amount = amount;
+    let amount = cap_withdrawal(deps, &env, &config, &pools, amount, total_share)?;
+    let refund_assets: Vec<Asset> = compute_refund_assets(&pools, amount, total_share)?;
+
+    // update pool info
+    // Messages are executed in order, so the LP burn goes before any external
+    // transfer. A refund token calling back into the pair will then already see
+    // the reduced supply (checks-effects-interactions).
+    let mut messages = vec![
+        // burn liquidity token
+        snip20::burn_msg(
+            amount,
+            None,
+            256,
+            config.token_code_hash.clone(),
+            liquidity_addr.clone(),
+        )?,
+    ];
+    // shares past the cap go back to the holder
+    let returned_amount = (requested_amount - amount)?;
+    if !returned_amount.is_zero() {
+        messages.push(snip20::transfer_msg(
+            sender,
+            returned_amount,
+            None,
+            256,
+            config.token_code_hash.clone(),
+            liquidity_addr,
+        )?);
+    }
+    // refund asset tokens
+    for refund_asset in refund_assets.iter() {
+        match &to_callback {
+            // only reachable with a `to` address
+            Some((to_code_hash, to_msg)) => {
+                if unwrap {
+                    return Err(PairError::invalid(
+                        "A withdrawal can't both unwrap and deliver a to_msg",
+                    )
+                    .into());
+                }
+                let hook_msg = to_binary(&WithdrawHookMsg::LiquidityRefund {
+                    asset: refund_asset.clone(),
+                    msg: to_msg.clone(),
+                })?;
+
+                if refund_asset.is_native_token() {
+                    messages.push(refund_asset.clone().into_msg(
+                        &deps,
+                        env.contract.address.clone(),
+                        recipient.clone(),
+                    )?);
+                    messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+                        contract_addr: recipient.clone(),
+                        callback_code_hash: to_code_hash.clone(),
+                        msg: hook_msg,
+                        send: vec![],
+                    }));
+                } else {
+                    messages.push(refund_asset.clone().into_send_msg(
+                        &deps,
+                        recipient.clone(),
+                        to_code_hash.clone(),
+                        hook_msg,
+                    )?);
+                }
+            }
+            None => messages.extend(payout_msgs(
+                deps,
+                &config,
+                refund_asset.clone(),
+                env.contract.address.clone(),
+                recipient.clone(),
+                unwrap,
+            )?),
+        }
+    }
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            log("action", "withdraw_liquidity"),
+            log("withdrawn_share", &amount.to_string()),
+            log(
+                "refund_assets",
+                format!("{}, {}", refund_assets[0].clone(), refund_assets[1].clone()),
+            ),
+            log("returned_share", &returned_amount.to_string()),
+        ],
+        data: None,
+    })
+}
+
+/// Withdraws `amount` LP shares in a single asset. The refund of the other asset
+/// is swapped into `asset_info` against the reserves left after the withdrawal,
+/// paying the usual swap fee, so the pool ends up as if the holder had withdrawn
+/// and then swapped.
+pub fn try_withdraw_liquidity_to<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    sender: HumanAddr,
+    amount: Uint128,
+    asset_info: AssetInfo,
+    min_received: Option<Uint128>,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    let liquidity_addr: HumanAddr = addresses.liquidity_token.human.clone();
+
+    let pools: [Asset; 2] = query_pools(&deps, &config, &addresses)?;
+    let (ask_index, offer_index) = if asset_info.equal(&pools[0].info) {
+        (0, 1)
+    } else if asset_info.equal(&pools[1].info) {
+        (1, 0)
+    } else {
+        return Err(PairError::asset_not_in_pair(&asset_info).into());
+    };
+
+    let pair_settings = load_pair_settings(deps, &env, &config)?;
+    assert_not_paused(&deps.storage, &pair_settings)?;
+
+    let total_share: Uint128 = outstanding_share(&deps, &config, &liquidity_addr)?;
+    let refund_assets: Vec<Asset> = compute_refund_assets(&pools, amount, total_share)?;
+
+    // the swap is priced on the pools without the withdrawn amounts
+    let offer_amount = refund_assets[offer_index].amount;
+    let (return_amount, commission_amount) = if offer_amount.is_zero() {
+        (Uint128::zero(), Uint128::zero())
+    } else {
+        let (return_amount, _, commission_amount) = compute_swap_on_curve(
+            &pair_settings.curve,
+            offer_index,
+            (pools[offer_index].amount - offer_amount)?,
+            (pools[ask_index].amount - refund_assets[ask_index].amount)?,
+            offer_amount,
+            pair_settings.swap_fee.commission_rate_nom,
+            pair_settings.swap_fee.commission_rate_denom,
+            pair_settings.swap_fee.min_commission,
+            pair_settings.fee_on_input,
+        )?;
+
+        let mut stats: Stats = read_stats(&deps.storage)?;
+        let volume = if offer_index == 0 {
+            &mut stats.asset0_volume
+        } else {
+            &mut stats.asset1_volume
+        };
+        *volume = Uint128(volume.u128().saturating_add(offer_amount.u128()));
+        stats.swap_count = stats.swap_count.saturating_add(1);
+        stats.last_swap_at_height = Some(env.block.height);
+        stats.last_swap_at_time = Some(env.block.time);
+        store_stats(&mut deps.storage, &stats)?;
+
+        (return_amount, commission_amount)
+    };
+
+    let received = refund_assets[ask_index].amount + return_amount;
+    assert_min_reserve(&pair_settings, &pools[ask_index], received)?;
+    if let Some(min_received) = min_received {
+        if received < min_received {
+            return Err(PairError::slippage(format!(
+                "Operation exceeds max slippage: received {} is less than min_received {}",
+                received, min_received
+            ))
+            .into());
+        }
+    }
+
+    // the burn goes first, same as in try_withdraw_liquidity
+    let mut messages = vec![snip20::burn_msg(
+        amount,
+        None,
+        256,
+        config.token_code_hash.clone(),
+        liquidity_addr,
+    )?];
+    messages.extend(payout_msgs(
+        deps,
+        &config,
+        Asset {
+            info: asset_info.clone(),
+            amount: received,
+        },
+        env.contract.address.clone(),
+        sender,
+        false,
+    )?);
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            log("action", "withdraw_liquidity_to"),
+            log("withdrawn_share", &amount.to_string()),
+            log("ask_asset", asset_info.to_string()),
+            log("swapped_amount", offer_amount.to_string()),
+            log("commission_amount", commission_amount.to_string()),
+            log("received_amount", received.to_string()),
+        ],
+        data: None,
+    })
+}
+
+/// BatchProvideAndSwap with a native offer, native uscrt is deposited into sSCRT
+/// first when the pair wraps it
+pub fn try_native_batch_provide_and_swap<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    offer_asset: Asset,
+    min_share: Option<Uint128>,
+    receiver: Option<HumanAddr>,
+) -> HandleResult {
+    // tokens must be sent through Receive, so the pair knows they arrived
+    if !offer_asset.is_native_token() {
+        return Err(StdError::unauthorized());
+    }
+    assert_whitelisted(deps, &env.message.sender)?;
+    let refunds: Vec<Coin> = collect_native_funds(&env, &[offer_asset.clone()])?;
+
+    let config: Config = read_config(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    let sender = env.message.sender.clone();
+    let contract_addr = env.contract.address.clone();
+    let receiver = receiver.unwrap_or_else(|| sender.clone());
+    let sent_amount = sent_native_amount(&env, &offer_asset.info);
+
+    let mut res = match (
+        &config.wrap_config,
+        wrap_native(&config, &addresses, &offer_asset),
+    ) {
+        (Some(wrap_config), Some(wrapped_asset)) => {
+            let deposit = snip20::deposit_msg(
+                offer_asset.amount,
+                None,
+                256,
+                wrap_config.sscrt_code_hash.clone(),
+                wrap_config.sscrt_addr.clone(),
+            )?;
+
+            let mut res = try_batch_provide_and_swap(
+                deps,
+                env,
+                &config,
+                &addresses,
+                wrapped_asset,
+                Uint128::zero(),
+                min_share,
+                receiver,
+            )?;
+            res.messages.insert(0, deposit);
+            res
+        }
+        _ => try_batch_provide_and_swap(
+            deps,
+            env,
+            &config,
+            &addresses,
+            offer_asset,
+            sent_amount,
+            min_share,
+            receiver,
+        )?,
+    };
+
+    if !refunds.is_empty() {
+        res.messages.push(CosmosMsg::Bank(BankMsg::Send {
+            from_address: contract_addr,
+            to_address: sender,
+            amount: refunds.clone(),
+        }));
+
+        let mut receipt: ProvideLiquidityResponse = from_binary(
+            res.data
+                .as_ref()
+                .ok_or_else(|| PairError::state("Missing provide liquidity receipt"))?,
+        )?;
+        receipt.refunds = refunds;
+        res.data = Some(to_binary(&receipt)?);
+    }
+
+    Ok(res)
+}
+
+/// Provides liquidity from `offer_asset` alone. The swap never leaves the pair: the
+/// swapped part stays in the offer pool and its return is deposited right back, so
+/// only the LP tokens are minted. `offer_amount_in_pool` is the part of the offer
+/// already counted in the pool balance.
+pub fn try_batch_provide_and_swap<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    config: &Config,
+    addresses: &Addresses,
+    offer_asset: Asset,
+    offer_amount_in_pool: Uint128,
+    min_share: Option<Uint128>,
+    receiver: HumanAddr,
+) -> HandleResult {
+    if offer_asset.amount.is_zero() {
+        return Err(PairError::ZeroAmount.into());
+    }
+
+    let pair_settings = load_pair_settings(deps, &env, config)?;
+    assert_not_paused(&deps.storage, &pair_settings)?;
+
+    let mut pools: [Asset; 2] = query_pools(&deps, config, addresses)?;
+    let (offer_index, ask_index) = if offer_asset.info.equal(&pools[0].info) {
+        (0, 1)
+    } else if offer_asset.info.equal(&pools[1].info) {
+        (1, 0)
+    } else {
+        return Err(PairError::asset_not_in_pair(&offer_asset.info).into());
+    };
+    pools[offer_index].amount = (pools[offer_index].amount - offer_amount_in_pool)?;
+
+    let liquidity_token = &addresses.liquidity_token.human;
+    let total_share = query_supply(&deps, liquidity_token, &config.token_code_hash)?;
+    if total_share.is_zero() || pools[0].amount.is_zero() || pools[1].amount.is_zero() {
+        return Err(PairError::insufficient_liquidity(
+            "The pool is empty, the first deposit must provide both assets",
+        )
+        .into());
+    }
+
+    let (swap_amount, return_amount, commission_amount, share) = compute_zap_in(
+        &pair_settings,
+        offer_index,
+        pools[offer_index].amount,
+        pools[ask_index].amount,
+        offer_asset.amount,
+        total_share,
+    )?;
+    assert_min_reserve(&pair_settings, &pools[ask_index], return_amount)?;
+    record_block_volume(
+        deps,
+        &env,
+        &pair_settings,
+        [pools[0].amount, pools[1].amount],
+        offer_index,
+        swap_amount,
+    )?;
+    if let Some(min_share) = min_share {
+        if share < min_share {
+            return Err(PairError::slippage(format!(
+                "Operation exceeds max slippage: share {} is less than min_share {}",
+                share, min_share
+            ))
+            .into());
+        }
+    }
+
+    if !swap_amount.is_zero() {
+        let mut stats: Stats = read_stats(&deps.storage)?;
+        let volume = if offer_index == 0 {
+            &mut stats.asset0_volume
+        } else {
+            &mut stats.asset1_volume
+        };
+        *volume = Uint128(volume.u128().saturating_add(swap_amount.u128()));
+        stats.swap_count = stats.swap_count.saturating_add(1);
+        stats.last_swap_at_height = Some(env.block.height);
+        stats.last_swap_at_time = Some(env.block.time);
+        store_stats(&mut deps.storage, &stats)?;
+    }
+
+    // pools don't include the deposit at this point
+    let entry_price = Some(u256_ratio_to_decimal(
+        U256::from(pools[1].amount.u128()),
+        U256::from(pools[0].amount.u128()),
+    )?);
+
+    Ok(HandleResponse {
+        messages: vec![snip20::mint_msg(
+            receiver.clone(),
+            share,
+            None,
+            256,
+            config.token_code_hash.clone(),
+            liquidity_token.clone(),
+        )?],
+        log: vec![
+            log("action", "batch_provide_and_swap"),
+            log("offer_asset", offer_asset.info.to_string()),
+            log("offer_amount", offer_asset.amount.to_string()),
+            log("swapped_amount", swap_amount.to_string()),
+            log("return_amount", return_amount.to_string()),
+            log("commission_amount", commission_amount.to_string()),
+            log("share", &share),
+            log("receiver", receiver.as_str()),
+        ],
+        data: Some(to_binary(&ProvideLiquidityResponse {
+            share,
+            entry_reserves: pools,
+            entry_price,
+            refunds: vec![],
+        })?),
+    })
+}
+
+/// Splits a single-asset deposit of `amount` into the part swapped for the ask asset
+/// and the part deposited as it is. Returns the swapped amount, its return and
+/// commission, and the share minted. The share is the smaller of what each side mints
+/// against the pools after the swap; the offer side shrinks and the ask side grows
+/// with the swapped amount, so the search looks for where they meet.
+fn compute_zap_in(
+    pair_settings: &PairSettings,
+    offer_index: usize,
+    offer_pool: Uint128,
+    ask_pool: Uint128,
+    amount: Uint128,
+    total_share: Uint128,
+) -> StdResult<(Uint128, Uint128, Uint128, Uint128)> {
+    let total_share = U256::from(total_share.u128());
+    let split = |swap_amount: u128| -> StdResult<(U256, U256, Uint128, Uint128)> {
+        // a swap too small to pay the minimum commission returns nothing
+        let (return_amount, _, commission_amount) = if swap_amount == 0 {
+            (Uint128::zero(), Uint128::zero(), Uint128::zero())
+        } else {
+            compute_swap_on_curve(
+                &pair_settings.curve,
+                offer_index,
+                offer_pool,
+                ask_pool,
+                Uint128(swap_amount),
+                pair_settings.swap_fee.commission_rate_nom,
+                pair_settings.swap_fee.commission_rate_denom,
+                pair_settings.swap_fee.min_commission,
+                pair_settings.fee_on_input,
+            )
+            .unwrap_or((Uint128::zero(), Uint128::zero(), Uint128::zero()))
+        };
+
+        // the commission stays in the ask pool
+        let offer_share = U256::from(amount.u128() - swap_amount) * total_share
+            / (U256::from(offer_pool.u128()) + U256::from(swap_amount));
+        let ask_share = U256::from(return_amount.u128()) * total_share
+            / U256::from((ask_pool - return_amount)?.u128());
+        Ok((offer_share, ask_share, return_amount, commission_amount))
+    };
+
+    // the largest swap that leaves the offer side at least as large as the ask side
+    let (mut low, mut high) = (0u128, amount.u128());
+    while low < high {
+        let mid = low + (high - low + 1) / 2;
+        let (offer_share, ask_share, _, _) = split(mid)?;
+        if offer_share >= ask_share {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+
+    // the optimum is on either side of where the shares cross
+    let mut best = (
+        Uint128::zero(),
+        Uint128::zero(),
+        Uint128::zero(),
+        U256::zero(),
+    );
+    for swap_amount in [low, low.saturating_add(1).min(amount.u128())].iter() {
+        let (offer_share, ask_share, return_amount, commission_amount) = split(*swap_amount)?;
+        let share = offer_share.min(ask_share);
+        if share > best.3 {
+            best = (
+                Uint128(*swap_amount),
+                return_amount,
+                commission_amount,
+                share,
+            );
+        }
+    }
+    if best.3.is_zero() {
+        return Err(PairError::slippage("The deposit is too small to mint a share").into());
+    }
+
+    Ok((best.0, best.1, best.2, u256_to_uint128(best.3)?))
+}
+
+/// Withdraws with the sender's LP allowance instead of a Send to the pair. The LP
+/// token has no BurnFrom, so the shares are pulled with TransferFrom and burned.
+/// Both go before the refunds, so a missing allowance reverts the whole tx.
+pub fn try_withdraw_liquidity_from<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    amount: Uint128,
+    min_assets: Option<Vec<Asset>>,
+    to: Option<HumanAddr>,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    let liquidity_addr: HumanAddr = addresses.liquidity_token.human.clone();
+    let sender: HumanAddr = env.message.sender.clone();
+    assert_whitelisted(deps, &sender)?;
+    let recipient: HumanAddr = to.unwrap_or_else(|| sender.clone());
+
+    let pools: [Asset; 2] = query_pools(&deps, &config, &addresses)?;
+    let total_share: Uint128 = outstanding_share(&deps, &config, &liquidity_addr)?;
+    // past the cap the shares are simply not pulled
+    let amount = cap_withdrawal(deps, &env, &config, &pools, amount, total_share)?;
+    let refund_assets: Vec<Asset> = compute_refund_assets(&pools, amount, total_share)?;
+
+    for min_asset in min_assets.unwrap_or_default().iter() {
+        let refund_asset = refund_assets
+            .iter()
+            .find(|refund_asset| refund_asset.info.equal(&min_asset.info))
+            .ok_or_else(|| PairError::asset_not_in_pair(&min_asset.info))?;
+        if refund_asset.amount < min_asset.amount {
+            return Err(PairError::slippage(format!(
+                "Refund {} is below min_assets",
+                refund_asset
+            ))
+            .into());
+        }
+    }
+
+    let mut messages = vec![
+        snip20::transfer_from_msg(
+            sender,
+            env.contract.address.clone(),
+            amount,
+            None,
+            256,
+            config.token_code_hash.clone(),
+            liquidity_addr.clone(),
+        )?,
+        snip20::burn_msg(
+            amount,
+            None,
+            256,
+            config.token_code_hash.clone(),
+            liquidity_addr,
+        )?,
+    ];
+    for refund_asset in refund_assets.iter() {
+        messages.extend(payout_msgs(
+            deps,
+            &config,
+            refund_asset.clone(),
+            env.contract.address.clone(),
+            recipient.clone(),
+            false,
+        )?);
+    }
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            log("action", "withdraw_liquidity"),
+            log("withdrawn_share", &amount.to_string()),
+            log(
+                "refund_assets",
+                format!("{}, {}", refund_assets[0].clone(), refund_assets[1].clone()),
+            ),
+        ],
+        data: None,
+    })
+}
+
+/// Withdraws `lp_amount` shares and provides the refund back in the same handle.
+/// The refund is pro rata, so no swap is needed to rebalance it and no fee is
+/// charged. What the rounding keeps out of the new shares stays in the pools.
+pub fn try_compound<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    lp_amount: Uint128,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    let liquidity_addr: HumanAddr = addresses.liquidity_token.human.clone();
+    let sender: HumanAddr = env.message.sender.clone();
+    assert_whitelisted(deps, &sender)?;
+
+    let pair_settings = load_pair_settings(deps, &env, &config)?;
+    assert_not_paused(&deps.storage, &pair_settings)?;
+
+    let pools: [Asset; 2] = query_pools(&deps, &config, &addresses)?;
+    let total_share: Uint128 = outstanding_share(&deps, &config, &liquidity_addr)?;
+    // re-providing into emptied pools would mint an initial share instead
+    if lp_amount.is_zero() || lp_amount >= total_share {
+        return Err(PairError::invalid(
+            "lp_amount must be positive and less than the outstanding LP shares",
+        )
+        .into());
+    }
+    let refund_assets: Vec<Asset> = compute_refund_assets(&pools, lp_amount, total_share)?;
+
+    let deposits = [refund_assets[0].amount, refund_assets[1].amount];
+    let pools_after_withdrawal = [
+        Asset {
+            info: pools[0].info.clone(),
+            amount: (pools[0].amount - deposits[0])?,
+        },
+        Asset {
+            info: pools[1].info.clone(),
+            amount: (pools[1].amount - deposits[1])?,
+        },
+    ];
+    let share = compute_share(
+        &deposits,
+        &pools_after_withdrawal,
+        (total_share - lp_amount)?,
+        &pair_settings,
+    )?;
+
+    // burning `lp_amount` and minting `share` back nets out to burning the difference
+    let burned_share = (lp_amount - share)?;
+    let mut messages = vec![];
+    if !burned_share.is_zero() {
+        messages.push(snip20::transfer_from_msg(
+            sender,
+            env.contract.address.clone(),
+            burned_share,
+            None,
+            256,
+            config.token_code_hash.clone(),
+            liquidity_addr.clone(),
+        )?);
+        messages.push(snip20::burn_msg(
+            burned_share,
+            None,
+            256,
+            config.token_code_hash.clone(),
+            liquidity_addr,
+        )?);
+    }
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            log("action", "compound"),
+            log("withdrawn_share", &lp_amount.to_string()),
+            log(
+                "refund_assets",
+                format!("{}, {}", refund_assets[0].clone(), refund_assets[1].clone()),
+            ),
+            log("share", &share),
+        ],
+        data: None,
+    })
+}
+
+// Only factory can execute it
+pub fn try_emergency_withdraw<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    holder: HumanAddr,
+    amount: Uint128,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    if env.message.sender != config.factory.address {
+        return Err(StdError::unauthorized());
+    }
+
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    let liquidity_addr: HumanAddr = addresses.liquidity_token.human.clone();
+
+    let pools: [Asset; 2] = query_pools(&deps, &config, &addresses)?;
+    let total_share: Uint128 = outstanding_share(&deps, &config, &liquidity_addr)?;
+    if amount > total_share {
+        return Err(PairError::invalid("amount exceeds the outstanding LP shares").into());
+    }
+    let refund_assets: Vec<Asset> = compute_refund_assets(&pools, amount, total_share)?;
+
+    // the shares can't be burned, so they are left out of the supply from now on
+    let emergency_withdrawn = read_emergency_withdrawn(&deps.storage)?;
+    store_emergency_withdrawn(&mut deps.storage, emergency_withdrawn + amount)?;
+
+    let mut messages = vec![];
+    for refund_asset in refund_assets.iter() {
+        messages.push(refund_asset.clone().into_msg(
+            &deps,
+            env.contract.address.clone(),
+            holder.clone(),
+        )?);
+    }
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            log("action", "emergency_withdraw"),
+            log("holder", holder.as_str()),
+            log("withdrawn_share", &amount.to_string()),
+            log(
+                "refund_assets",
+                format!("{}, {}", refund_assets[0].clone(), refund_assets[1].clone()),
+            ),
+        ],
+        data: None,
+    })
+}
+
+/// LP supply minus the shares refunded by EmergencyWithdraw without a burn
+fn outstanding_share<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    config: &Config,
+    liquidity_addr: &HumanAddr,
+) -> StdResult<Uint128> {
+    let total_share: Uint128 = query_supply(&deps, liquidity_addr, &config.token_code_hash)?;
+    Ok(
+        (total_share - read_emergency_withdrawn(&deps.storage)?)
+            .unwrap_or_else(|_| Uint128::zero()),
+    )
+}
+
+/// The share of each pool refunded for `amount` LP shares out of `total_share`, rounded
+/// down so what's left of the pools keeps backing the remaining shares
+pub fn compute_refund_assets(
+    pools: &[Asset; 2],
+    amount: Uint128,
+    total_share: Uint128,
+) -> StdResult<Vec<Asset>> {
+    pools
+        .iter()
+        .map(|a| {
+            // withdrawn_asset_amount = a.amount * amount / total_share
+
+            let current_pool_amount = Some(U256::from(a.amount.u128()));
+            let withdrawn_share_amount = Some(U256::from(amount.u128()));
+            let total_share = Some(U256::from(total_share.u128()));
+
+            let withdrawn_asset_amount = div(
+                mul(current_pool_amount, withdrawn_share_amount),
+                total_share,
+            )
+            .ok_or_else(|| {
+                PairError::overflow(format!(
+                    "current_pool_amount {} * withdrawn_share_amount {} / total_share {}",
+                    a.amount,
+                    amount,
+                    total_share.unwrap()
+                ))
+            })?;
+
+            Ok(Asset {
+                info: a.info.clone(),
+                amount: u256_to_uint128(withdrawn_asset_amount)?,
+            })
+        })
+        .collect::<StdResult<Vec<Asset>>>()
+}
+
+/// Swaps native coins sent along with the message. With a wrap config, uscrt is
+/// deposited into sSCRT first and swapped as the pair's sSCRT asset.
+pub fn try_native_swap<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    offer_asset: Asset,
+    expected_return: Option<Uint128>,
+    belief_price: Option<Decimal>,
+    max_spread: Option<Decimal>,
+    max_price_impact: Option<Decimal>,
+    batch: bool,
+    to: Option<HumanAddr>,
+    unwrap: bool,
+) -> HandleResult {
+    // tokens must be sent through Receive, so the pair knows they arrived
+    if !offer_asset.is_native_token() {
+        return Err(StdError::unauthorized());
+    }
+    assert_whitelisted(deps, &env.message.sender)?;
+    let refunds: Vec<Coin> = collect_native_funds(&env, &[offer_asset.clone()])?;
+
+    let config: Config = read_config(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    let sender = env.message.sender.clone();
+    let contract_addr = env.contract.address.clone();
+    let sent_amount = sent_native_amount(&env, &offer_asset.info);
+
+    let mut res = match (
+        &config.wrap_config,
+        wrap_native(&config, &addresses, &offer_asset),
+    ) {
+        (Some(wrap_config), Some(wrapped_asset)) => {
+            let deposit = snip20::deposit_msg(
+                offer_asset.amount,
+                None,
+                256,
+                wrap_config.sscrt_code_hash.clone(),
+                wrap_config.sscrt_addr.clone(),
+            )?;
+
+            let mut res = try_swap(
+                deps,
+                env,
+                &config,
+                &addresses,
+                sender.clone(),
+                wrapped_asset,
+                expected_return,
+                belief_price,
+                max_spread,
+                max_price_impact,
+                batch,
+                to,
+                Uint128::zero(),
+                unwrap,
+                None,
+            )?;
+            res.messages.insert(0, deposit);
+            res
+        }
+        _ => try_swap(
+            deps,
+            env,
+            &config,
+            &addresses,
+            sender.clone(),
+            offer_asset,
+            expected_return,
+            belief_price,
+            max_spread,
+            max_price_impact,
+            batch,
+            to,
+            sent_amount,
+            unwrap,
+            None,
+        )?,
+    };
+
+    // the refund is only reported in the encrypted data
+    if !refunds.is_empty() {
+        res.messages.push(CosmosMsg::Bank(BankMsg::Send {
+            from_address: contract_addr,
+            to_address: sender,
+            amount: refunds.clone(),
+        }));
+    }
+    res.data = Some(to_binary(&SwapResponse { refunds })?);
+
+    Ok(res)
+}
+
+/// The pair's sSCRT asset for native uscrt sent to a pair with a wrap config
+fn wrap_native(config: &Config, addresses: &Addresses, asset: &Asset) -> Option<Asset> {
+    let wrap_config = config.wrap_config.as_ref()?;
+    match &asset.info {
+        AssetInfo::NativeToken { denom } if denom == WRAPPED_DENOM => addresses
+            .asset_infos(config)
+            .iter()
+            .find(|info| match info {
+                AssetInfo::Token { contract_addr, .. } => contract_addr == &wrap_config.sscrt_addr,
+                AssetInfo::NativeToken { .. } => false,
+            })
+            .map(|info| Asset {
+                info: info.clone(),
+                amount: asset.amount,
+            }),
+        _ => None,
+    }
+}
+
+/// Sends `asset` to `recipient`, redeeming sSCRT for native uscrt first if asked to
+fn payout_msgs<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    config: &Config,
+    asset: Asset,
+    contract_addr: HumanAddr,
+    recipient: HumanAddr,
+    unwrap: bool,
+) -> StdResult<Vec<CosmosMsg>> {
+    if unwrap {
+        let wrap_config = config
+            .wrap_config
+            .as_ref()
+            .ok_or_else(|| PairError::unsupported("This pair doesn't wrap native uscrt"))?;
+
+        if let AssetInfo::Token {
+            contract_addr: token_addr,
+            ..
+        } = &asset.info
+        {
+            if token_addr == &wrap_config.sscrt_addr {
+                return Ok(vec![
+                    snip20::redeem_msg(
+                        asset.amount,
+                        Some(WRAPPED_DENOM.to_string()),
+                        None,
+                        256,
+                        wrap_config.sscrt_code_hash.clone(),
+                        wrap_config.sscrt_addr.clone(),
+                    )?,
+                    CosmosMsg::Bank(BankMsg::Send {
+                        from_address: contract_addr,
+                        to_address: recipient,
+                        amount: vec![Coin {
+                            denom: WRAPPED_DENOM.to_string(),
+                            amount: asset.amount,
+                        }],
+                    }),
+                ]);
+            }
+        }
+    }
+
+    Ok(vec![asset.into_msg(deps, contract_addr, recipient)?])
+}
+
+/// Checks the native coins sent with the message cover the native `assets`, and
+/// returns what was sent beyond that so it can be refunded. Denoms that aren't
+/// part of `assets` are rejected rather than absorbed by the pool.
+fn collect_native_funds(env: &Env, assets: &[Asset]) -> StdResult<Vec<Coin>> {
+    for asset in assets.iter() {
+        if asset.is_native_token() && sent_native_amount(env, &asset.info) < asset.amount {
+            return Err(PairError::funds(
+                "Native token balance mismatch between the argument and the transferred",
+            )
+            .into());
+        }
+    }
+
+    let mut refunds: Vec<Coin> = vec![];
+    for coin in env.message.sent_funds.iter() {
+        let required = assets.iter().find_map(|asset| match &asset.info {
+            AssetInfo::NativeToken { denom } if denom == &coin.denom => Some(asset.amount),
+            _ => None,
+        });
+
+        match required {
+            Some(required) => {
+                if coin.amount > required {
+                    refunds.push(Coin {
+                        denom: coin.denom.clone(),
+                        amount: (coin.amount - required)?,
+                    });
+                }
+            }
+            None => {
+                return Err(PairError::funds(format!(
+                    "Unexpected native funds sent: {}",
+                    coin.denom
+                ))
+                .into());
+            }
+        }
+    }
+
+    Ok(refunds)
+}
+
+/// How much of a native asset was sent with the message
+fn sent_native_amount(env: &Env, asset_info: &AssetInfo) -> Uint128 {
+    match asset_info {
+        AssetInfo::NativeToken { denom } => env
+            .message
+            .sent_funds
+            .iter()
+            .find(|coin| &coin.denom == denom)
+            .map(|coin| coin.amount)
+            .unwrap_or_else(Uint128::zero),
+        AssetInfo::Token { .. } => Uint128::zero(),
+    }
+}
+
+// CONTRACT - a user must do token approval
+/// `config` and `addresses` come from the caller, which already loaded them to
+/// validate the offer, so a swap reads them only once
+pub fn try_swap<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    config: &Config,
+    addresses: &Addresses,
+    sender: HumanAddr,
+    offer_asset: Asset,
+    expected_return: Option<Uint128>,
+    belief_price: Option<Decimal>,
+    max_spread: Option<Decimal>,
+    max_price_impact: Option<Decimal>,
+    batch: bool,
+    to: Option<HumanAddr>,
+    offer_amount_in_pool: Uint128,
+    unwrap: bool,
+    to_callback: Option<(String, Binary)>,
+) -> HandleResult {
+    if offer_asset.amount.is_zero() {
+        return Err(PairError::ZeroAmount.into());
+    }
+
+    if batch {
+        if expected_return.is_some()
+            || belief_price.is_some()
+            || max_spread.is_some()
+            || max_price_impact.is_some()
+            || to_callback.is_some()
+        {
+            return Err(PairError::invalid(
+                "A batched swap settles at the clearing price, it takes no price limits or to_msg",
+            )
+            .into());
+        }
+        return try_queue_batch_swap(
+            deps,
+            &env,
+            config,
+            addresses,
+            sender,
+            offer_asset,
+            to,
+            unwrap,
+        );
+    }
+
+    let mut stats: Stats = read_stats(&deps.storage)?;
+
+    let pools: [Asset; 2] = query_pools(&deps, config, addresses)?;
+
+    let offer_pool: Asset;
+    let ask_pool: Asset;
+
+    // If the asset balance is already increased
+    // To calculated properly we should subtract user deposit from the pool
+    if offer_asset.info.equal(&pools[0].info) {
+        let pool_amount = U256::from(pools[0].amount.u128());
+        let offer_amount = U256::from(offer_amount_in_pool.u128());
+
+        let amount =
+            pool_amount
+                .checked_sub(offer_amount)
+                .ok_or_else(|| PairError::OfferNotReceived {
+                    offered: offer_amount_in_pool,
+                    pool: pools[0].amount,
+                })?;
+
+        offer_pool = Asset {
+            amount: u256_to_uint128(amount)?,
+            info: pools[0].info.clone(),
+        };
+        ask_pool = pools[1].clone();
+
+        // volumes saturate, a full counter must never block swaps
+        stats.asset0_volume = Uint128(
+            stats
+                .asset0_volume
+                .u128()
+                .saturating_add(offer_asset.amount.u128()),
+        );
+    } else if offer_asset.info.equal(&pools[1].info) {
+        let pool_amount = U256::from(pools[1].amount.u128());
+        let offer_amount = U256::from(offer_amount_in_pool.u128());
+
+        let amount =
+            pool_amount
+                .checked_sub(offer_amount)
+                .ok_or_else(|| PairError::OfferNotReceived {
+                    offered: offer_amount_in_pool,
+                    pool: pools[1].amount,
+                })?;
+
+        offer_pool = Asset {
+            amount: u256_to_uint128(amount)?,
+            info: pools[1].info.clone(),
+        };
+        ask_pool = pools[0].clone();
+
+        // volumes saturate, a full counter must never block swaps
+        stats.asset1_volume = Uint128(
+            stats
+                .asset1_volume
+                .u128()
+                .saturating_add(offer_asset.amount.u128()),
+        );
+    } else {
+        return Err(PairError::asset_not_in_pair(&offer_asset.info).into());
+    }
+
+    // init rejects identical assets, this only trips on corrupted state
+    if offer_pool.info.equal(&ask_pool.info) {
+        return Err(
+            PairError::state(format!("Offer and ask pools are both {}", offer_pool.info)).into(),
+        );
+    }
+
+    let (pair_settings, settings_fallback) = load_pair_settings_with_fallback(deps, &env, config)?;
+    assert_not_paused(&deps.storage, &pair_settings)?;
+
+    let offer_index = if offer_pool.info.equal(&pools[0].info) {
+        0
+    } else {
+        1
+    };
+    let offer_amount = offer_asset.amount;
+    let (return_amount, spread_amount, commission_amount) = compute_swap_on_curve(
+        &pair_settings.curve,
+        offer_index,
+        offer_pool.amount,
+        ask_pool.amount,
+        offer_amount,
+        pair_settings.swap_fee.commission_rate_nom,
+        pair_settings.swap_fee.commission_rate_denom,
+        pair_settings.swap_fee.min_commission,
+        pair_settings.fee_on_input,
+    )?;
+
+    if breaks_reserve_floor(&pair_settings, &ask_pool, return_amount) {
+        store_breaker_tripped(&mut deps.storage, true)?;
+
+        // refused without an error, which would revert the pause along with it
+        return Ok(HandleResponse {
+            messages: payout_msgs(
+                &deps,
+                config,
+                offer_asset,
+                env.contract.address.clone(),
+                sender,
+                false,
+            )?,
+            log: vec![
+                log("action", "reserve_breaker_tripped"),
+                log("ask_asset", ask_pool.info.to_string()),
+            ],
+            data: None,
+        });
+    }
+    assert_min_reserve(&pair_settings, &ask_pool, return_amount)?;
+
+    // check max spread limit if exist
+    assert_max_spread(
+        belief_price,
+        max_spread,
+        expected_return,
+        offer_amount,
+        return_amount,
+        commission_amount,
+        spread_amount,
+    )?;
+    assert_max_price_impact(
+        max_price_impact,
+        return_amount,
+        commission_amount,
+        spread_amount,
+    )?;
+
+    // the offer is already in its pool
+    let mut pools_after = pools.clone();
+    pools_after[1 - offer_index].amount = (ask_pool.amount - return_amount)?;
+    assert_oracle_price(deps, &pair_settings, &pools_after)?;
+
+    let mut reserves = [pools[0].amount, pools[1].amount];
+    reserves[offer_index] = offer_pool.amount;
+    record_block_volume(
+        deps,
+        &env,
+        &pair_settings,
+        reserves,
+        offer_index,
+        offer_asset.amount,
+    )?;
+
+    // only the small stats record is rewritten on every swap
+    stats.swap_count = stats.swap_count.saturating_add(1);
+    stats.last_swap_at_height = Some(env.block.height);
+    stats.last_swap_at_time = Some(env.block.time);
+    store_stats(&mut deps.storage, &stats)?;
+
+    let return_asset = Asset {
+        info: ask_pool.info.clone(),
+        amount: return_amount,
+    };
+
+    let mut messages = Vec::<CosmosMsg>::new();
+    match to_callback {
+        // only reachable with a `to` address
+        Some((to_code_hash, to_msg)) => {
+            if unwrap {
+                return Err(
+                    PairError::invalid("A swap can't both unwrap and deliver a to_msg").into(),
+                );
+            }
+            messages.push(return_asset.clone().into_send_msg(
+                &deps,
+                to.clone().unwrap_or(sender.clone()),
+                to_code_hash,
+                to_msg,
+            )?);
+        }
+        None => messages.extend(payout_msgs(
+            &deps,
+            config,
+            return_asset.clone(),
+            env.contract.address.clone(),
+            to.clone().unwrap_or(sender.clone()),
+            unwrap,
+        )?),
+    }
+
+    if let Some(data_endpoint) = pair_settings.swap_data_endpoint {
+        messages.push(data_endpoint.into_msg(
+            offer_asset.clone(),
+            Asset {
+                info: return_asset.info,
+                amount: return_amount + commission_amount,
+            },
+            to.unwrap_or(sender),
+        )?);
+    }
+
+    // the messages only go out if the pools the math implies keep the invariant
+    assert_swap_invariant(
+        &pair_settings.curve,
+        offer_index,
+        offer_pool.amount,
+        ask_pool.amount,
+        offer_amount,
+        return_amount,
+    )?;
+
+    let mut logs = vec![
+        log("action", "swap"),
+        log("offer_asset", offer_asset.info.to_string()),
+        log("ask_asset", ask_pool.info.to_string()),
+        log("offer_amount", offer_amount.to_string()),
+        log("return_amount", return_amount.to_string()),
+        log("spread_amount", spread_amount.to_string()),
+        log("commission_amount", commission_amount.to_string()),
+    ];
+    if settings_fallback {
+        logs.push(log(
+            "warning",
+            "the factory is unavailable, swapped on cached settings",
+        ));
+    }
+
+    // 1. send collateral token from the contract to a user
+    // 2. send inactive commission to collector
+    Ok(HandleResponse {
+        messages,
+        log: logs,
+        data: None,
+    })
+}
+
+/// Escrows `offer_asset` in the batch of the current block, see `settle_due_batch`
+fn try_queue_batch_swap<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    config: &Config,
+    addresses: &Addresses,
+    sender: HumanAddr,
+    offer_asset: Asset,
+    to: Option<HumanAddr>,
+    unwrap: bool,
+) -> HandleResult {
+    let pair_settings = load_pair_settings(deps, env, config)?;
+    assert_not_paused(&deps.storage, &pair_settings)?;
+    if pair_settings.curve != CurveType::ConstantProduct {
+        return Err(PairError::unsupported("Batched swaps need the constant product curve").into());
+    }
+
+    let offer_index = addresses
+        .asset_infos(config)
+        .iter()
+        .position(|info| info.equal(&offer_asset.info))
+        .ok_or_else(|| PairError::asset_not_in_pair(&offer_asset.info))?;
+    let pools: [Asset; 2] = query_pools(&deps, config, addresses)?;
+    if pools[1 - offer_index].amount.is_zero() {
+        return Err(PairError::insufficient_liquidity("The ask pool is empty").into());
+    }
+
+    let mut batch = read_batch(&deps.storage)?;
+    if batch.depths.is_some() || (!batch.swap_ids.is_empty() && batch.height < env.block.height) {
+        return Err(PairError::state(
+            "The previous batch is still settling, call SettleBatch first",
+        )
+        .into());
+    }
+    if batch.swap_ids.len() >= MAX_BATCH_SWAPS {
+        return Err(PairError::state("The batch of this block is full").into());
+    }
+
+    let id = next_batch_swap_id(&mut deps.storage)?;
+    batch.height = env.block.height;
+    batch.swap_ids.push(id);
+    batch.offer_totals[offer_index] += offer_asset.amount;
+    store_batch(&mut deps.storage, &batch)?;
+
+    let mut escrowed = read_escrowed(&deps.storage)?;
+    escrowed[offer_index] += offer_asset.amount;
+    store_escrowed(&mut deps.storage, &escrowed)?;
+
+    store_batch_swap(
+        &mut deps.storage,
+        id,
+        &BatchSwap {
+            owner: deps.api.canonical_address(&sender)?,
+            recipient: deps.api.canonical_address(&to.unwrap_or(sender))?,
+            offer_index: offer_index as u8,
+            offer_amount: offer_asset.amount,
+            unwrap,
+        },
+    )?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "queue_batch_swap"), log("batch_swap_id", id)],
+        data: None,
+    })
+}
+
+/// Pays out the next `BATCH_SETTLE_CHUNK` swaps of a batch queued in an earlier block.
+///
+/// The first chunk fixes the clearing price. On the constant product curve the net
+/// flow of the batch trades against the pools at `(pool1 + offers1) / (pool0 + offers0)`
+/// asset1 per asset0, and every queued swap gets that same price before commission.
+/// The net flow has to pass the safeguards a single swap does, or every queued swap
+/// is refunded instead. `received` is a token amount sent with the running handle,
+/// which is in the balances but not in the pools yet. Returns `None` when no batch is
+/// due or trading is paused, only refunds go on while it is. Nothing is stored unless
+/// it succeeds, so a handle can go on without it.
+fn settle_due_batch<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    received: Uint128,
+) -> StdResult<Option<(Vec<CosmosMsg>, Vec<LogAttribute>)>> {
+    let mut batch = read_batch(&deps.storage)?;
+    if batch.swap_ids.is_empty() || (batch.depths.is_none() && batch.height >= env.block.height) {
+        return Ok(None);
+    }
+
+    let config: Config = read_config(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    let asset_infos = addresses.asset_infos(&config);
+    let mut escrowed = read_escrowed(&deps.storage)?;
+    let mut settled_stats = None;
+    let mut block_volume = None;
+    let mut breaker_tripped = false;
+    let mut logs = vec![];
+
+    let pair_settings = if batch.refunding {
+        None
+    } else {
+        let pair_settings = load_pair_settings(deps, env, &config)?;
+        if assert_not_paused(&deps.storage, &pair_settings).is_err() {
+            return Ok(None);
+        }
+        Some(pair_settings)
+    };
+
+    if let (Some(pair_settings), None) = (&pair_settings, batch.depths) {
+        let pools: [Asset; 2] = query_pools(&deps, &config, &addresses)?;
+        let incoming = incoming_amounts(env, &asset_infos, received);
+        let reserves = [
+            Asset {
+                info: asset_infos[0].clone(),
+                amount: (pools[0].amount - incoming[0])?,
+            },
+            Asset {
+                info: asset_infos[1].clone(),
+                amount: (pools[1].amount - incoming[1])?,
+            },
+        ];
+        let depths = [
+            reserves[0].amount + batch.offer_totals[0],
+            reserves[1].amount + batch.offer_totals[1],
+        ];
+        // the queued offers join the pools, the returns they buy leave them
+        let owed = [
+            batch_return(&depths, 1, batch.offer_totals[1])?,
+            batch_return(&depths, 0, batch.offer_totals[0])?,
+        ];
+        let pools_after = [
+            Asset {
+                info: asset_infos[0].clone(),
+                amount: (depths[0] - owed[0])?,
+            },
+            Asset {
+                info: asset_infos[1].clone(),
+                amount: (depths[1] - owed[1])?,
+            },
+        ];
+
+        let breaks_floor = reserves
+            .iter()
+            .zip(pools_after.iter())
+            .any(|(reserve, after)| {
+                let outflow = (reserve.amount - after.amount).unwrap_or_else(|_| Uint128::zero());
+                breaks_reserve_floor(pair_settings, reserve, outflow)
+            });
+        let refund_reason = if breaks_floor {
+            breaker_tripped = true;
+            Some("reserve_breaker_tripped".to_string())
+        } else {
+            match assert_batch_safeguards(
+                deps,
+                env,
+                pair_settings,
+                &reserves,
+                &pools_after,
+                batch.offer_totals,
+            ) {
+                Ok(volume) => {
+                    block_volume = volume;
+                    None
+                }
+                Err(StdError::GenericErr { msg, .. }) => Some(msg),
+                Err(err) => Some(err.to_string()),
+            }
+        };
+
+        match refund_reason {
+            None => {
+                batch.depths = Some(depths);
+                batch.owed = owed;
+                escrowed = [
+                    (escrowed[0] - batch.offer_totals[0])? + batch.owed[0],
+                    (escrowed[1] - batch.offer_totals[1])? + batch.owed[1],
+                ];
+
+                let mut stats: Stats = read_stats(&deps.storage)?;
+                stats.asset0_volume = Uint128(
+                    stats
+                        .asset0_volume
+                        .u128()
+                        .saturating_add(batch.offer_totals[0].u128()),
+                );
+                stats.asset1_volume = Uint128(
+                    stats
+                        .asset1_volume
+                        .u128()
+                        .saturating_add(batch.offer_totals[1].u128()),
+                );
+                stats.swap_count = stats.swap_count.saturating_add(batch.swap_ids.len() as u64);
+                stats.last_swap_at_height = Some(env.block.height);
+                stats.last_swap_at_time = Some(env.block.time);
+                settled_stats = Some(stats);
+            }
+            Some(reason) => {
+                logs.push(log("batch_refund_reason", reason));
+                batch.refunding = true;
+            }
+        }
+    }
+
+    let chunk = batch.swap_ids.len().min(BATCH_SETTLE_CHUNK);
+    let swap_ids: Vec<u64> = batch.swap_ids.drain(..chunk).collect();
+    let mut messages = vec![];
+    for &id in swap_ids.iter() {
+        let swap = read_batch_swap(&deps.storage, id)?
+            .ok_or_else(|| PairError::NotFound { item: "Batch swap" })?;
+        let offer_index = swap.offer_index as usize;
+        let ask_index = 1 - offer_index;
+        let offer_asset = Asset {
+            info: asset_infos[offer_index].clone(),
+            amount: swap.offer_amount,
+        };
+
+        let (pair_settings, depths) = match (&pair_settings, batch.depths) {
+            (Some(pair_settings), Some(depths)) if !batch.refunding => (pair_settings, depths),
+            _ => {
+                escrowed[offer_index] = (escrowed[offer_index] - swap.offer_amount)?;
+                messages.extend(payout_msgs(
+                    deps,
+                    &config,
+                    offer_asset,
+                    env.contract.address.clone(),
+                    deps.api.human_address(&swap.owner)?,
+                    false,
+                )?);
+                continue;
+            }
+        };
+
+        let gross_return = batch_return(&depths, offer_index, swap.offer_amount)?;
+        // a return too small for the minimum commission goes to the pool entirely.
+        // At one price for the whole batch a commission on the offer would cost the
+        // same, so `fee_on_input` doesn't change the settlement.
+        let (return_amount, _) = deduct_commission(
+            U256::from(gross_return.u128()),
+            pair_settings.swap_fee.commission_rate_nom,
+            pair_settings.swap_fee.commission_rate_denom,
+            pair_settings.swap_fee.min_commission,
+        )
+        .unwrap_or((U256::zero(), U256::zero()));
+        let return_amount = u256_to_uint128(return_amount)?;
+
+        batch.owed[ask_index] = (batch.owed[ask_index] - gross_return)?;
+        escrowed[ask_index] = (escrowed[ask_index] - gross_return)?;
+
+        let recipient = deps.api.human_address(&swap.recipient)?;
+        if !return_amount.is_zero() {
+            messages.extend(payout_msgs(
+                deps,
+                &config,
+                Asset {
+                    info: asset_infos[ask_index].clone(),
+                    amount: return_amount,
+                },
+                env.contract.address.clone(),
+                recipient.clone(),
+                swap.unwrap,
+            )?);
+        }
+        if let Some(data_endpoint) = pair_settings.swap_data_endpoint.clone() {
+            messages.push(data_endpoint.into_msg(
+                offer_asset,
+                Asset {
+                    info: asset_infos[ask_index].clone(),
+                    amount: gross_return,
+                },
+                recipient,
+            )?);
+        }
+    }
+    logs.push(if batch.refunding {
+        log("batch_refunded_swaps", chunk)
+    } else {
+        log("batch_settled_swaps", chunk)
+    });
+
+    if batch.swap_ids.is_empty() {
+        // every return rounds down, the dust left of what was promised stays in the pools
+        escrowed = [
+            (escrowed[0] - batch.owed[0])?,
+            (escrowed[1] - batch.owed[1])?,
+        ];
+        batch = Batch::default();
+    }
+    for id in swap_ids {
+        remove_batch_swap(&mut deps.storage, id);
+    }
+    if let Some(stats) = settled_stats {
+        store_stats(&mut deps.storage, &stats)?;
+    }
+    if let Some(block_volume) = block_volume {
+        store_block_volume(&mut deps.storage, &block_volume)?;
+    }
+    if breaker_tripped {
+        store_breaker_tripped(&mut deps.storage, true)?;
+    }
+    store_escrowed(&mut deps.storage, &escrowed)?;
+    store_batch(&mut deps.storage, &batch)?;
+
+    Ok(Some((messages, logs)))
+}
+
+/// Runs the other safeguards of `try_swap` on the net flow of a batch, the reserve
+/// floor is checked by the caller. `reserves` are the pools before the batch and
+/// `pools_after` what its returns leave in them. The commissions staying in the pools
+/// are left out, which errs on the safe side. Returns the block volume to record.
+fn assert_batch_safeguards<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    env: &Env,
+    pair_settings: &PairSettings,
+    reserves: &[Asset; 2],
+    pools_after: &[Asset; 2],
+    offer_totals: [Uint128; 2],
+) -> StdResult<Option<BlockVolume>> {
+    for (reserve, after) in reserves.iter().zip(pools_after.iter()) {
+        let outflow = (reserve.amount - after.amount).unwrap_or_else(|_| Uint128::zero());
+        assert_min_reserve(pair_settings, reserve, outflow)?;
+    }
+    assert_oracle_price(deps, pair_settings, pools_after)?;
+
+    let reserves = [reserves[0].amount, reserves[1].amount];
+    let mut block_volume = read_block_volume(&deps.storage)?;
+    for (offer_index, offer_total) in offer_totals.iter().enumerate() {
+        block_volume = add_block_volume(
+            block_volume,
+            env,
+            pair_settings,
+            reserves,
+            offer_index,
+            *offer_total,
+        )?;
+    }
+    Ok(block_volume)
+}
+
+/// What the running handle already added to the balance of each asset: the native
+/// coins sent with it, and the `received` tokens of a Receive from an asset token
+fn incoming_amounts(env: &Env, asset_infos: &[AssetInfo; 2], received: Uint128) -> [Uint128; 2] {
+    let incoming = |info: &AssetInfo| match info {
+        AssetInfo::Token { contract_addr, .. } if contract_addr == &env.message.sender => received,
+        _ => sent_native_amount(env, info),
+    };
+    [incoming(&asset_infos[0]), incoming(&asset_infos[1])]
+}
+
+/// Contract address of a token asset, denom of a native one
+fn asset_id(info: &AssetInfo) -> String {
+    match info {
+        AssetInfo::Token { contract_addr, .. } => contract_addr.to_string(),
+        AssetInfo::NativeToken { denom } => denom.clone(),
+    }
+}
+
+/// Moves the accounted balances by what the handle brought in and what its
+/// `messages` will move in and out of the pair
+fn account_balances<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    received: Uint128,
+    messages: &[CosmosMsg],
+) -> StdResult<()> {
+    let mut accounting = match read_accounting(&deps.storage)? {
+        Some(mut accounting) => {
+            for (balance, asset) in accounting.balances.iter_mut().zip(accounting.assets.iter()) {
+                *balance += coins_of(&env.message.sent_funds, asset);
+                if env.message.sender.as_str() == asset {
+                    *balance += received;
+                }
+            }
+            accounting
+        }
+        // pairs from before the accounting start from what they hold
+        None => {
+            let config: Config = read_config(&deps.storage)?;
+            let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+            let asset_infos = addresses.asset_infos_with_keys(&config);
+            Accounting {
+                assets: [asset_id(&asset_infos[0]), asset_id(&asset_infos[1])],
+                balances: [
+                    asset_infos[0].query_pool(deps, &env.contract.address)?,
+                    asset_infos[1].query_pool(deps, &env.contract.address)?,
+                ],
+            }
+        }
+    };
+
+    for msg in messages {
+        for (balance, asset) in accounting.balances.iter_mut().zip(accounting.assets.iter()) {
+            let (inflow, outflow) = asset_flows(env, asset, msg);
+            // a drift below zero is for the integrity report, not an error
+            *balance = Uint128(
+                balance
+                    .u128()
+                    .saturating_add(inflow.u128())
+                    .saturating_sub(outflow.u128()),
+            );
+        }
+    }
+
+    store_accounting(&mut deps.storage, &accounting)
+}
+
+/// How much of `asset` (see `asset_id`) `msg` brings into the pair and pays out of it
+fn asset_flows(env: &Env, asset: &str, msg: &CosmosMsg) -> (Uint128, Uint128) {
+    match msg {
+        CosmosMsg::Bank(BankMsg::Send { amount, .. }) => (Uint128::zero(), coins_of(amount, asset)),
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr,
+            msg,
+            send,
+            ..
+        }) if contract_addr.as_str() == asset => match from_binary(msg) {
+            Ok(Snip20HandleMsg::Transfer { amount, .. })
+            | Ok(Snip20HandleMsg::Send { amount, .. })
+            | Ok(Snip20HandleMsg::Redeem { amount, .. }) => (Uint128::zero(), amount),
+            Ok(Snip20HandleMsg::TransferFrom {
+                recipient, amount, ..
+            }) if recipient == env.contract.address => (amount, Uint128::zero()),
+            // uscrt wrapped into the asset
+            Ok(Snip20HandleMsg::Deposit { .. }) => (coins_of(send, WRAPPED_DENOM), Uint128::zero()),
+            _ => (Uint128::zero(), Uint128::zero()),
+        },
+        CosmosMsg::Wasm(WasmMsg::Execute { send, .. }) => (Uint128::zero(), coins_of(send, asset)),
+        _ => (Uint128::zero(), Uint128::zero()),
+    }
+}
+
+/// Total of the `denom` coins among `coins`
+fn coins_of(coins: &[Coin], denom: &str) -> Uint128 {
+    coins
+        .iter()
+        .filter(|coin| coin.denom == denom)
+        .fold(Uint128::zero(), |total, coin| total + coin.amount)
+}
+
+/// What `offer_amount` of asset `offer_index` returns at the clearing price of `depths`
+fn batch_return(
+    depths: &[Uint128; 2],
+    offer_index: usize,
+    offer_amount: Uint128,
+) -> StdResult<Uint128> {
+    if offer_amount.is_zero() {
+        return Ok(Uint128::zero());
+    }
+
+    let offer_amount = Some(U256::from(offer_amount.u128()));
+    let ask_depth = Some(U256::from(depths[1 - offer_index].u128()));
+    let offer_depth = Some(U256::from(depths[offer_index].u128()));
+    let return_amount = div(mul(offer_amount, ask_depth), offer_depth).ok_or_else(|| {
+        PairError::overflow(format!(
+            "offer_amount {} * ask_depth {} / offer_depth {}",
+            offer_amount.unwrap(),
+            ask_depth.unwrap(),
+            offer_depth.unwrap()
+        ))
+    })?;
+
+    u256_to_uint128(return_amount)
+}
+
+// Anyone can execute it
+pub fn try_settle_batch<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> HandleResult {
+    let (messages, settle_log) = match settle_due_batch(deps, &env, Uint128::zero())? {
+        Some(settled) => settled,
+        None => {
+            let config: Config = read_config(&deps.storage)?;
+            let pair_settings = load_pair_settings(deps, &env, &config)?;
+            assert_not_paused(&deps.storage, &pair_settings)?;
+            return Err(PairError::state("No batch is due for settlement").into());
+        }
+    };
+
+    let mut logs = vec![log("action", "settle_batch")];
+    logs.extend(settle_log);
+    Ok(HandleResponse {
+        messages,
+        log: logs,
+        data: None,
+    })
+}
+
+// Only the owner of the swap can execute it
+pub fn try_cancel_batch_swap<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    id: u64,
+) -> HandleResult {
+    let mut batch = read_batch(&deps.storage)?;
+    if batch.depths.is_some() {
+        return Err(PairError::state("The batch is settling, its swaps can't be cancelled").into());
+    }
+    let swap = read_batch_swap(&deps.storage, id)?
+        .ok_or_else(|| PairError::NotFound { item: "Batch swap" })?;
+    if deps.api.canonical_address(&env.message.sender)? != swap.owner {
+        return Err(StdError::unauthorized());
+    }
+
+    let offer_index = swap.offer_index as usize;
+    batch.swap_ids.retain(|queued_id| *queued_id != id);
+    batch.offer_totals[offer_index] = (batch.offer_totals[offer_index] - swap.offer_amount)?;
+    if batch.swap_ids.is_empty() {
+        batch = Batch::default();
+    }
+    store_batch(&mut deps.storage, &batch)?;
+
+    let mut escrowed = read_escrowed(&deps.storage)?;
+    escrowed[offer_index] = (escrowed[offer_index] - swap.offer_amount)?;
+    store_escrowed(&mut deps.storage, &escrowed)?;
+    remove_batch_swap(&mut deps.storage, id);
+
+    let config: Config = read_config(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    Ok(HandleResponse {
+        messages: payout_msgs(
+            deps,
+            &config,
+            Asset {
+                info: addresses.asset_infos(&config)[offer_index].clone(),
+                amount: swap.offer_amount,
+            },
+            env.contract.address.clone(),
+            env.message.sender,
+            false,
+        )?,
+        log: vec![log("action", "cancel_batch_swap"), log("batch_swap_id", id)],
+        data: None,
+    })
+}
+
+pub fn try_place_order<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    owner: HumanAddr,
+    offer_index: usize,
+    offer_amount: Uint128,
+    ask_min_price: Decimal,
+    expires_at: u64,
+    bounty_bps: u16,
+) -> HandleResult {
+    if offer_amount.is_zero() {
+        return Err(PairError::ZeroAmount.into());
+    }
+    if ask_min_price.is_zero() {
+        return Err(PairError::invalid("ask_min_price must be positive").into());
+    }
+    if expires_at <= env.block.time {
+        return Err(PairError::invalid("expires_at must be in the future").into());
+    }
+    if bounty_bps >= 10_000 {
+        return Err(PairError::invalid("bounty_bps must be below 10000").into());
+    }
+
+    // the offer stays out of the pools until the order is filled
+    let mut escrowed = read_escrowed(&deps.storage)?;
+    escrowed[offer_index] += offer_amount;
+    store_escrowed(&mut deps.storage, &escrowed)?;
+
+    let order_id = next_order_id(&mut deps.storage)?;
+    store_order(
+        &mut deps.storage,
+        order_id,
+        &Order {
+            owner: deps.api.canonical_address(&owner)?,
+            offer_index: offer_index as u8,
+            offer_amount,
+            ask_min_price,
+            expires_at,
+            bounty_bps,
+        },
+    )?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "place_order"), log("order_id", order_id)],
+        data: None,
+    })
+}
+
+// Anyone can execute it
+pub fn try_execute_order<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    order_id: u64,
+) -> HandleResult {
+    let order = read_order(&deps.storage, order_id)?
+        .ok_or_else(|| PairError::NotFound { item: "Order" })?;
+    if env.block.time >= order.expires_at {
+        return Err(PairError::expired("The order has expired, it can only be cancelled").into());
+    }
+
+    let (messages, fill_log) = match fill_escrowed_swap(
+        deps,
+        &env,
+        &order.owner,
+        order.offer_index as usize,
+        order.offer_amount,
+        Some(order.ask_min_price),
+        order.bounty_bps,
+    )? {
+        Fill::Filled(messages, fill_log) => (messages, fill_log),
+        Fill::BreakerTripped(log) => return Ok(breaker_tripped_response(log)),
+    };
+    remove_order(&mut deps.storage, order_id);
+
+    let mut logs = vec![log("action", "execute_order"), log("order_id", order_id)];
+    logs.extend(fill_log);
+    Ok(HandleResponse {
+        messages,
+        log: logs,
+        data: None,
+    })
+}
+
+/// What `fill_escrowed_swap` did with an escrowed offer
+enum Fill {
+    /// Swapped, the messages pay the owner and the keeper
+    Filled(Vec<CosmosMsg>, Vec<LogAttribute>),
+    /// The swap would have broken the reserve floor, the pair is paused and the
+    /// offer stays in escrow
+    BreakerTripped(Vec<LogAttribute>),
+}
+
+/// Answers a fill that tripped the breaker without an error, which would revert the
+/// pause along with it. The order or DCA is left as it was.
+fn breaker_tripped_response(log: Vec<LogAttribute>) -> HandleResponse {
+    HandleResponse {
+        messages: vec![],
+        log,
+        data: None,
+    }
+}
+
+/// Swaps `offer_amount` out of the escrow into its pool, paying the return to `owner`
+/// less a `bounty_bps` bounty for the sender. Used by order and DCA keepers.
+fn fill_escrowed_swap<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    owner: &CanonicalAddr,
+    offer_index: usize,
+    offer_amount: Uint128,
+    min_price: Option<Decimal>,
+    bounty_bps: u16,
+) -> StdResult<Fill> {
+    let config: Config = read_config(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    let pair_settings = load_pair_settings(deps, env, &config)?;
+    assert_not_paused(&deps.storage, &pair_settings)?;
+
+    // the escrowed offer isn't part of the pools yet
+    let pools: [Asset; 2] = query_pools(&deps, &config, &addresses)?;
+    let ask_pool = &pools[1 - offer_index];
+    let (return_amount, _, commission_amount) = compute_swap_on_curve(
+        &pair_settings.curve,
+        offer_index,
+        pools[offer_index].amount,
+        ask_pool.amount,
+        offer_amount,
+        pair_settings.swap_fee.commission_rate_nom,
+        pair_settings.swap_fee.commission_rate_denom,
+        pair_settings.swap_fee.min_commission,
+        pair_settings.fee_on_input,
+    )?;
+    if breaks_reserve_floor(&pair_settings, ask_pool, return_amount) {
+        store_breaker_tripped(&mut deps.storage, true)?;
+        return Ok(Fill::BreakerTripped(vec![
+            log("action", "reserve_breaker_tripped"),
+            log("ask_asset", ask_pool.info.to_string()),
+        ]));
+    }
+    assert_min_reserve(&pair_settings, ask_pool, return_amount)?;
+
+    let bounty = return_amount.multiply_ratio(bounty_bps as u128, 10_000u128);
+    let owner_return = (return_amount - bounty)?;
+
+    // the limit price is left out of the error, only the owner may read it
+    if let Some(min_price) = min_price {
+        if U256::from(owner_return.u128()) * U256::from(1_000_000_000_000_000_000u128)
+            < U256::from(offer_amount.u128()) * decimal_to_u256_atomics(min_price)?
+        {
+            return Err(PairError::state("The swap can't be filled at the current price").into());
+        }
+    }
+
+    assert_oracle_price(
+        deps,
+        &pair_settings,
+        &pools_after_swap(&pools, offer_index, offer_amount, return_amount)?,
+    )?;
+    record_block_volume(
+        deps,
+        env,
+        &pair_settings,
+        [pools[0].amount, pools[1].amount],
+        offer_index,
+        offer_amount,
+    )?;
+
+    let mut stats: Stats = read_stats(&deps.storage)?;
+    let volume = if offer_index == 0 {
+        &mut stats.asset0_volume
+    } else {
+        &mut stats.asset1_volume
+    };
+    *volume = Uint128(volume.u128().saturating_add(offer_amount.u128()));
+    stats.swap_count = stats.swap_count.saturating_add(1);
+    stats.last_swap_at_height = Some(env.block.height);
+    stats.last_swap_at_time = Some(env.block.time);
+    store_stats(&mut deps.storage, &stats)?;
+
+    // the offer joins the pool
+    let mut escrowed = read_escrowed(&deps.storage)?;
+    escrowed[offer_index] = (escrowed[offer_index] - offer_amount)?;
+    store_escrowed(&mut deps.storage, &escrowed)?;
+
+    let mut messages = payout_msgs(
+        deps,
+        &config,
+        Asset {
+            info: ask_pool.info.clone(),
+            amount: owner_return,
+        },
         env.contract.address.clone(),
-        to.clone().unwrap_or(sender.clone()),
-    )?);
+        deps.api.human_address(owner)?,
+        false,
+    )?;
+    if !bounty.is_zero() {
+        messages.extend(payout_msgs(
+            deps,
+            &config,
+            Asset {
+                info: ask_pool.info.clone(),
+                amount: bounty,
+            },
+            env.contract.address.clone(),
+            env.message.sender.clone(),
+            false,
+        )?);
+    }
+
+    Ok(Fill::Filled(
+        messages,
+        vec![
+            log("offer_amount", offer_amount.to_string()),
+            log("return_amount", owner_return.to_string()),
+            log("commission_amount", commission_amount.to_string()),
+        ],
+    ))
+}
+
+// Only the owner of the order can execute it
+pub fn try_cancel_order<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    order_id: u64,
+) -> HandleResult {
+    let order = read_order(&deps.storage, order_id)?
+        .ok_or_else(|| PairError::NotFound { item: "Order" })?;
+    if deps.api.canonical_address(&env.message.sender)? != order.owner {
+        return Err(StdError::unauthorized());
+    }
+
+    let offer_index = order.offer_index as usize;
+    let mut escrowed = read_escrowed(&deps.storage)?;
+    escrowed[offer_index] = (escrowed[offer_index] - order.offer_amount)?;
+    store_escrowed(&mut deps.storage, &escrowed)?;
+    remove_order(&mut deps.storage, order_id);
+
+    let config: Config = read_config(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    Ok(HandleResponse {
+        messages: payout_msgs(
+            deps,
+            &config,
+            Asset {
+                info: addresses.asset_infos(&config)[offer_index].clone(),
+                amount: order.offer_amount,
+            },
+            env.contract.address.clone(),
+            env.message.sender,
+            false,
+        )?,
+        log: vec![log("action", "cancel_order"), log("order_id", order_id)],
+        data: None,
+    })
+}
+
+pub fn try_place_dca<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    owner: HumanAddr,
+    offer_index: usize,
+    budget: Uint128,
+    per_interval_amount: Uint128,
+    interval_seconds: u64,
+    min_price: Option<Decimal>,
+) -> HandleResult {
+    if per_interval_amount.is_zero() || per_interval_amount > budget {
+        return Err(PairError::invalid(
+            "per_interval_amount must be positive and at most the budget sent",
+        )
+        .into());
+    }
+    if interval_seconds == 0 {
+        return Err(PairError::invalid("interval_seconds must be positive").into());
+    }
+
+    // the budget stays out of the pools until its tranches are swapped
+    let mut escrowed = read_escrowed(&deps.storage)?;
+    escrowed[offer_index] += budget;
+    store_escrowed(&mut deps.storage, &escrowed)?;
+
+    let id = next_dca_id(&mut deps.storage)?;
+    store_dca(
+        &mut deps.storage,
+        id,
+        &Dca {
+            owner: deps.api.canonical_address(&owner)?,
+            offer_index: offer_index as u8,
+            remaining: budget,
+            per_interval_amount,
+            interval_seconds,
+            min_price,
+            next_execution_at: env.block.time,
+        },
+    )?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "place_dca"), log("dca_id", id)],
+        data: None,
+    })
+}
+
+// Anyone can execute it
+pub fn try_execute_dca<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    id: u64,
+) -> HandleResult {
+    let mut dca =
+        read_dca(&deps.storage, id)?.ok_or_else(|| PairError::NotFound { item: "DCA" })?;
+    if env.block.time < dca.next_execution_at {
+        return Err(PairError::state(format!(
+            "The next DCA tranche can't run before {}",
+            dca.next_execution_at
+        ))
+        .into());
+    }
+
+    let tranche = if dca.remaining < dca.per_interval_amount {
+        dca.remaining
+    } else {
+        dca.per_interval_amount
+    };
+    let (messages, fill_log) = match fill_escrowed_swap(
+        deps,
+        &env,
+        &dca.owner,
+        dca.offer_index as usize,
+        tranche,
+        dca.min_price,
+        DCA_BOUNTY_BPS,
+    )? {
+        Fill::Filled(messages, fill_log) => (messages, fill_log),
+        Fill::BreakerTripped(log) => return Ok(breaker_tripped_response(log)),
+    };
+
+    dca.remaining = (dca.remaining - tranche)?;
+    if dca.remaining.is_zero() {
+        remove_dca(&mut deps.storage, id);
+    } else {
+        // a late keeper runs a single tranche, missed intervals aren't caught up
+        let missed = (env.block.time - dca.next_execution_at) / dca.interval_seconds;
+        dca.next_execution_at += (missed + 1) * dca.interval_seconds;
+        store_dca(&mut deps.storage, id, &dca)?;
+    }
+
+    let mut logs = vec![log("action", "execute_dca"), log("dca_id", id)];
+    logs.extend(fill_log);
+    Ok(HandleResponse {
+        messages,
+        log: logs,
+        data: None,
+    })
+}
+
+// Only the owner of the DCA can execute it
+pub fn try_cancel_dca<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    id: u64,
+) -> HandleResult {
+    let dca = read_dca(&deps.storage, id)?.ok_or_else(|| PairError::NotFound { item: "DCA" })?;
+    if deps.api.canonical_address(&env.message.sender)? != dca.owner {
+        return Err(StdError::unauthorized());
+    }
+
+    let offer_index = dca.offer_index as usize;
+    let mut escrowed = read_escrowed(&deps.storage)?;
+    escrowed[offer_index] = (escrowed[offer_index] - dca.remaining)?;
+    store_escrowed(&mut deps.storage, &escrowed)?;
+    remove_dca(&mut deps.storage, id);
 
-    if let Some(data_endpoint) = pair_settings.swap_data_endpoint {
-        messages.push(data_endpoint.into_msg(
-            offer_asset.clone(),
+    let config: Config = read_config(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    Ok(HandleResponse {
+        messages: payout_msgs(
+            deps,
+            &config,
             Asset {
-                info: return_asset.info,
-                amount: return_amount + commission_amount,
+                info: addresses.asset_infos(&config)[offer_index].clone(),
+                amount: dca.remaining,
             },
-            to.unwrap_or(sender),
-        )?);
-    }
+            env.contract.address.clone(),
+            env.message.sender,
+            false,
+        )?,
+        log: vec![log("action", "cancel_dca"), log("dca_id", id)],
+        data: None,
+    })
+}
 
-    // 1. send collateral token from the contract to a user
-    // 2. send inactive commission to collector
+pub fn try_set_viewing_key<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    key: String,
+) -> HandleResult {
+    let address = deps.api.canonical_address(&env.message.sender)?;
+    store_viewing_key_hash(&mut deps.storage, &address, &Sha256::digest(key.as_bytes()))?;
+
+    // the key itself is never logged
     Ok(HandleResponse {
-        messages,
-        log: vec![
-            log("action", "swap"),
-            log("offer_asset", offer_asset.info.to_string()),
-            log("ask_asset", ask_pool.info.to_string()),
-            log("offer_amount", offer_amount.to_string()),
-            log("return_amount", return_amount.to_string()),
-            log("spread_amount", spread_amount.to_string()),
-            log("commission_amount", commission_amount.to_string()),
-        ],
+        messages: vec![],
+        log: vec![log("action", "set_viewing_key")],
         data: None,
     })
 }
@@ -624,39 +4266,213 @@ pub fn query<S: Storage, A: Api, Q: Querier>(
 ) -> StdResult<Binary> {
     match msg {
         QueryMsg::Pair {} => to_binary(&query_pair_info(&deps)?),
+        QueryMsg::Assets {} => to_binary(&query_assets(&deps)?),
         QueryMsg::Pool {} => to_binary(&query_pool(&deps)?),
-        QueryMsg::Simulation { offer_asset } => to_binary(&query_simulation(&deps, offer_asset)?),
+        QueryMsg::Simulation {
+            offer_asset,
+            commission_rate_override,
+        } => to_binary(&query_simulation(
+            &deps,
+            offer_asset,
+            commission_rate_override,
+        )?),
+        QueryMsg::SimulationByIndex {
+            offer_index,
+            amount,
+        } => to_binary(&query_simulation_by_index(&deps, offer_index, amount)?),
+        QueryMsg::SimulationAtReserves {
+            offer_asset,
+            reserves,
+        } => to_binary(&query_simulation_at_reserves(&deps, offer_asset, reserves)?),
         QueryMsg::ReverseSimulation { ask_asset } => {
             to_binary(&query_reverse_simulation(&deps, ask_asset)?)
         }
+        QueryMsg::SpotPriceScaled {} => to_binary(&query_spot_price_scaled(&deps)?),
+        QueryMsg::HasAsset { asset_info } => to_binary(&query_has_asset(&deps, asset_info)?),
+        QueryMsg::Snapshot {} => to_binary(&query_snapshot(&deps)?),
+        QueryMsg::ReverseLiquidity { lp_amount } => {
+            to_binary(&query_reverse_liquidity(&deps, lp_amount)?)
+        }
+        QueryMsg::EstimateLpMint {
+            assets,
+            slippage_tolerance,
+        } => to_binary(&query_estimate_lp_mint(&deps, assets, slippage_tolerance)?),
+        QueryMsg::DebugNoise { key } => to_binary(&query_debug_noise(&deps, key)?),
+        QueryMsg::Whitelist {
+            key,
+            start_after,
+            limit,
+        } => to_binary(&query_whitelist(&deps, key, start_after, limit)?),
+        QueryMsg::Order {
+            address,
+            key,
+            order_id,
+        } => to_binary(&query_order(&deps, address, key, order_id)?),
+        QueryMsg::Dca { address, key, id } => to_binary(&query_dca(&deps, address, key, id)?),
+        QueryMsg::Integrity { key } => to_binary(&query_integrity(&deps, key)?),
+        QueryMsg::SwapDataPreview {
+            offer_asset,
+            recipient,
+        } => to_binary(&query_swap_data_preview(&deps, offer_asset, recipient)?),
     }
 }
 
 pub fn query_pair_info<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
 ) -> StdResult<PairInfo> {
-    let pair_info: PairInfoRaw = read_pair_info(&deps.storage)?;
-    pair_info.to_normal(&deps)
+    let config: Config = read_config(&deps.storage)?;
+    let stats: Stats = read_stats(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    Ok(addresses.pair_info(&config, &stats))
+}
+
+pub fn query_assets<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<[AssetInfo; 2]> {
+    let config: Config = read_config(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    Ok(addresses.asset_infos(&config))
+}
+
+pub fn query_has_asset<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    asset_info: AssetInfo,
+) -> StdResult<HasAssetResponse> {
+    let index = query_assets(&deps)?
+        .iter()
+        .position(|info| info.equal(&asset_info))
+        .map(|index| index as u8);
+
+    Ok(HasAssetResponse {
+        has_asset: index.is_some(),
+        index,
+    })
+}
+
+/// Inverts the share formula of `try_provide_liquidity`. Noise isn't needed here,
+/// the answer only depends on the reserves per share which `query_pool` already shows.
+pub fn query_reverse_liquidity<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    lp_amount: Uint128,
+) -> StdResult<ReverseLiquidityResponse> {
+    let config: Config = read_config(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    let pools: [Asset; 2] = query_pools(&deps, &config, &addresses)?;
+
+    let total_share = query_supply(
+        &deps,
+        &addresses.liquidity_token.human,
+        &config.token_code_hash,
+    )?;
+    if total_share.is_zero() {
+        // the first deposit picks the price, any ratio works
+        return Err(PairError::insufficient_liquidity(
+            "The pool is empty, the first deposit sets its ratio",
+        )
+        .into());
+    }
+
+    // share_i = deposit_i * total_share / pool_i is rounded down, so the smallest
+    // deposit reaching lp_amount is ceil(lp_amount * pool_i / total_share)
+    let lp_amount = U256::from(lp_amount.u128());
+    let total = U256::from(total_share.u128());
+    let deposit = |pool: &Asset| -> StdResult<Asset> {
+        let amount = (lp_amount * U256::from(pool.amount.u128()) + total - U256::one()) / total;
+        Ok(Asset {
+            info: pool.info.clone(),
+            amount: u256_to_uint128(amount)?,
+        })
+    };
+
+    Ok(ReverseLiquidityResponse {
+        assets: [deposit(&pools[0])?, deposit(&pools[1])?],
+    })
+}
+
+/// Runs the deposit math of `try_provide_liquidity` without the deposit. Like
+/// ReverseLiquidity it isn't noised, the share follows from what `query_pool` shows.
+pub fn query_estimate_lp_mint<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    assets: [Asset; 2],
+    slippage_tolerance: Option<Decimal>,
+) -> StdResult<EstimateLpMintResponse> {
+    if slippage_tolerance > Some(Decimal::one()) {
+        return Err(PairError::invalid("slippage tolerance must be between 0 and 1").into());
+    }
+
+    let config: Config = read_config(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    let pools: [Asset; 2] = query_pools(&deps, &config, &addresses)?;
+
+    // native uscrt is deposited as the pair's sSCRT
+    let assets: Vec<Asset> = assets
+        .iter()
+        .map(|asset| wrap_native(&config, &addresses, asset).unwrap_or_else(|| asset.clone()))
+        .collect();
+    let deposit = |pool: &Asset| -> StdResult<Uint128> {
+        assets
+            .iter()
+            .find(|a| a.info.equal(&pool.info))
+            .map(|a| a.amount)
+            .ok_or_else(|| PairError::invalid(format!("assets must include {}", pool.info)).into())
+    };
+    let deposits: [Uint128; 2] = [deposit(&pools[0])?, deposit(&pools[1])?];
+
+    let total_share = query_supply(
+        &deps,
+        &addresses.liquidity_token.human,
+        &config.token_code_hash,
+    )?;
+    let pair_settings = query_settings(deps, &config, &addresses)?;
+    let share = compute_share(&deposits, &pools, total_share, &pair_settings)?;
+
+    // the first deposit sets the ratio, so all of it counts
+    let refund = if total_share.is_zero() {
+        None
+    } else {
+        // what the share redeems for, rounded up in favor of the pool
+        let total = U256::from(total_share.u128());
+        let excess = |i: usize| -> StdResult<Uint128> {
+            let used = (U256::from(share.u128()) * U256::from(pools[i].amount.u128()) + total
+                - U256::one())
+                / total;
+            u256_to_uint128(U256::from(deposits[i].u128()).saturating_sub(used))
+        };
+        let excess = [excess(0)?, excess(1)?];
+        if excess[0].is_zero() && excess[1].is_zero() {
+            None
+        } else {
+            Some(excess)
+        }
+    };
+
+    Ok(EstimateLpMintResponse {
+        share,
+        refund,
+        // an empty pool has no price to slip from
+        slippage_ok: total_share.is_zero()
+            || assert_slippage_tolerance(&slippage_tolerance, &deposits, &pools).is_ok(),
+    })
 }
 
 pub fn query_pool<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
 ) -> StdResult<PoolResponse> {
-    let pair_info: PairInfoRaw = read_pair_info(&deps.storage)?;
-    let contract_addr = deps.api.human_address(&pair_info.contract_addr)?;
+    let config: Config = read_config(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
 
-    let mut assets: [Asset; 2] = pair_info.query_pools(&deps, &contract_addr)?;
+    let mut assets: [Asset; 2] = query_pools(&deps, &config, &addresses)?;
 
     let (nom, denom) = get_random_nom_denom(deps)?;
-    assets[0].amount = Uint128(assets[0].amount.0 * nom / denom);
-    assets[1].amount = Uint128(assets[1].amount.0 * nom / denom);
+    assets[0].amount = apply_noise(assets[0].amount, nom, denom);
+    assets[1].amount = apply_noise(assets[1].amount, nom, denom);
 
     let mut total_share: Uint128 = query_supply(
         &deps,
-        &deps.api.human_address(&pair_info.liquidity_token)?,
-        &pair_info.token_code_hash,
+        &addresses.liquidity_token.human,
+        &config.token_code_hash,
     )?;
-    total_share = Uint128(total_share.0 * nom / denom);
+    total_share = apply_noise(total_share, nom, denom);
 
     let resp = PoolResponse {
         assets,
@@ -666,66 +4482,555 @@ pub fn query_pool<S: Storage, A: Api, Q: Querier>(
     Ok(resp)
 }
 
-pub fn query_simulation<S: Storage, A: Api, Q: Querier>(
+// Only factory can execute it
+pub fn try_reset_circuit_breaker<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    if env.message.sender != config.factory.address {
+        return Err(StdError::unauthorized());
+    }
+
+    store_breaker_tripped(&mut deps.storage, false)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "reset_circuit_breaker")],
+        data: None,
+    })
+}
+
+// Only factory can execute it
+pub fn try_add_to_whitelist<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    addresses: Vec<HumanAddr>,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    if env.message.sender != config.factory.address {
+        return Err(StdError::unauthorized());
+    }
+
+    for address in addresses.iter() {
+        let address = deps.api.canonical_address(address)?;
+        add_to_whitelist(&mut deps.storage, &address)?;
+    }
+
+    // the addresses themselves are never logged
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "add_to_whitelist")],
+        data: None,
+    })
+}
+
+// Only factory can execute it
+pub fn try_remove_from_whitelist<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    addresses: Vec<HumanAddr>,
+) -> HandleResult {
+    let config: Config = read_config(&deps.storage)?;
+    if env.message.sender != config.factory.address {
+        return Err(StdError::unauthorized());
+    }
+
+    for address in addresses.iter() {
+        let address = deps.api.canonical_address(address)?;
+        remove_from_whitelist(&mut deps.storage, &address);
+    }
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "remove_from_whitelist")],
+        data: None,
+    })
+}
+
+/// In a restricted pair only whitelisted users may trade or provide liquidity. The
+/// error is the same whether or not the pair is restricted, so it doesn't tell an
+/// outsider anything about the list.
+fn assert_whitelisted<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    user: &HumanAddr,
+) -> StdResult<()> {
+    if read_restricted(&deps.storage)?
+        && !is_whitelisted(&deps.storage, &deps.api.canonical_address(user)?)?
+    {
+        return Err(StdError::unauthorized());
+    }
+    Ok(())
+}
+
+/// `amount` scaled by the reserve noise `nom / denom`. Amounts under
+/// `MIN_NOISED_AMOUNT` stay exact, an empty pool reads as true zeros.
+fn apply_noise(amount: Uint128, nom: u128, denom: u128) -> Uint128 {
+    if amount.u128() < MIN_NOISED_AMOUNT {
+        return amount;
+    }
+    Uint128(amount.0 * nom / denom)
+}
+
+/// The queries of the same block see the same noise, since it only changes with the
+/// entropy pool. Without the key this fails the same way whether a key is set or not.
+pub fn query_debug_noise<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    key: String,
+) -> StdResult<DebugNoiseResponse> {
+    assert_debug_key(deps, &key)?;
+
+    let config: Config = read_config(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    let (nom, denom) = get_random_nom_denom(deps)?;
+
+    Ok(DebugNoiseResponse {
+        nom: Uint128(nom),
+        denom: Uint128(denom),
+        assets: query_pools(&deps, &config, &addresses)?,
+        total_share: query_supply(
+            &deps,
+            &addresses.liquidity_token.human,
+            &config.token_code_hash,
+        )?,
+    })
+}
+
+/// Checks `key` against the one set with SetDebugKey, the admin's key to the pair's
+/// private queries
+fn assert_debug_key<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    key: &str,
+) -> StdResult<()> {
+    let key_hash = Sha256::digest(key.as_bytes()).to_vec();
+    if read_debug_key_hash(&deps.storage)? != Some(key_hash) {
+        return Err(StdError::unauthorized());
+    }
+    Ok(())
+}
+
+/// Fails unless `key` is the viewing key `address` set
+fn assert_viewing_key<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    address: &CanonicalAddr,
+    key: &str,
+) -> StdResult<()> {
+    let key_hash = Sha256::digest(key.as_bytes()).to_vec();
+    if read_viewing_key_hash(&deps.storage, address)? != Some(key_hash) {
+        return Err(StdError::unauthorized());
+    }
+    Ok(())
+}
+
+/// Only answered with the viewing key `address` set, and for its own orders
+pub fn query_order<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    address: HumanAddr,
+    key: String,
+    order_id: u64,
+) -> StdResult<OrderResponse> {
+    let address = deps.api.canonical_address(&address)?;
+    assert_viewing_key(deps, &address, &key)?;
+
+    let order = match read_order(&deps.storage, order_id)? {
+        Some(order) if order.owner == address => order,
+        _ => return Err(PairError::NotFound { item: "Order" }.into()),
+    };
+    let config: Config = read_config(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    Ok(OrderResponse {
+        offer_asset: Asset {
+            info: addresses.asset_infos(&config)[order.offer_index as usize].clone(),
+            amount: order.offer_amount,
+        },
+        ask_min_price: order.ask_min_price,
+        expires_at: order.expires_at,
+        bounty_bps: order.bounty_bps,
+    })
+}
+
+/// Only answered with the viewing key `address` set, and for its own DCAs
+pub fn query_dca<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    address: HumanAddr,
+    key: String,
+    id: u64,
+) -> StdResult<DcaResponse> {
+    let address = deps.api.canonical_address(&address)?;
+    assert_viewing_key(deps, &address, &key)?;
+
+    let dca = match read_dca(&deps.storage, id)? {
+        Some(dca) if dca.owner == address => dca,
+        _ => return Err(PairError::NotFound { item: "DCA" }.into()),
+    };
+    let config: Config = read_config(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    Ok(DcaResponse {
+        offer_asset: Asset {
+            info: addresses.asset_infos(&config)[dca.offer_index as usize].clone(),
+            amount: dca.remaining,
+        },
+        per_interval_amount: dca.per_interval_amount,
+        interval_seconds: dca.interval_seconds,
+        min_price: dca.min_price,
+        next_execution_at: dca.next_execution_at,
+    })
+}
+
+/// Exact balances, gated by the debug key since the pool queries only give noised ones
+pub fn query_integrity<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    key: String,
+) -> StdResult<IntegrityResponse> {
+    assert_debug_key(deps, &key)?;
+
+    let config: Config = read_config(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    let asset_infos = addresses.asset_infos(&config);
+    let keyed_infos = addresses.asset_infos_with_keys(&config);
+    let contract_addr = &addresses.contract_addr.human;
+    let balances = [
+        Asset {
+            amount: keyed_infos[0].query_pool(deps, contract_addr)?,
+            info: asset_infos[0].clone(),
+        },
+        Asset {
+            amount: keyed_infos[1].query_pool(deps, contract_addr)?,
+            info: asset_infos[1].clone(),
+        },
+    ];
+
+    let accounted = read_accounting(&deps.storage)?.map(|accounting| accounting.balances);
+    let difference = |minuend: [Uint128; 2], subtrahend: [Uint128; 2]| {
+        [
+            Uint128(minuend[0].u128().saturating_sub(subtrahend[0].u128())),
+            Uint128(minuend[1].u128().saturating_sub(subtrahend[1].u128())),
+        ]
+    };
+    let reported = [balances[0].amount, balances[1].amount];
+    let (surplus, shortfall) = match accounted {
+        Some(accounted) => (
+            difference(reported, accounted),
+            difference(accounted, reported),
+        ),
+        None => ([Uint128::zero(); 2], [Uint128::zero(); 2]),
+    };
+
+    Ok(IntegrityResponse {
+        balances,
+        accounted,
+        surplus,
+        shortfall,
+    })
+}
+
+/// Whitelisted addresses in the order they were added, gated by the debug key
+pub fn query_whitelist<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    key: String,
+    start_after: Option<HumanAddr>,
+    limit: Option<u32>,
+) -> StdResult<WhitelistResponse> {
+    assert_debug_key(deps, &key)?;
+
+    let start_after = match start_after {
+        Some(address) => Some(deps.api.canonical_address(&address)?),
+        None => None,
+    };
+    let addresses = read_whitelist(&deps.storage, start_after.as_ref(), limit)?
+        .iter()
+        .map(|address| deps.api.human_address(address))
+        .collect::<StdResult<Vec<HumanAddr>>>()?;
+
+    Ok(WhitelistResponse {
+        restricted: read_restricted(&deps.storage)?,
+        addresses,
+    })
+}
+
+/// Everything a pool card shows, noised like `query_pool`
+pub fn query_snapshot<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<SnapshotResponse> {
+    let config: Config = read_config(&deps.storage)?;
+    let stats: Stats = read_stats(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    let pool: PoolResponse = query_pool(&deps)?;
+    let pair_settings = query_pair_settings(
+        &deps,
+        &config.factory.address,
+        &config.factory.code_hash,
+        &addresses.contract_addr.human,
+    )?;
+
+    Ok(SnapshotResponse {
+        assets: pool.assets,
+        total_share: pool.total_share,
+        asset0_volume: stats.asset0_volume,
+        asset1_volume: stats.asset1_volume,
+        swap_count: stats.swap_count,
+        swap_fee: pair_settings.swap_fee,
+        last_swap_at_height: stats.last_swap_at_height,
+        last_swap_at_time: stats.last_swap_at_time,
+    })
+}
+
+pub fn query_spot_price_scaled<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<SpotPriceResponse> {
+    let config: Config = read_config(&deps.storage)?;
+    let decimals = config.asset_decimals.ok_or_else(|| {
+        PairError::state("Token decimals are unknown for this pair, run SyncTokenMetadata")
+    })?;
+
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    let pools: [Asset; 2] = query_pools(&deps, &config, &addresses)?;
+    if pools[0].amount.is_zero() {
+        return Err(PairError::insufficient_liquidity("Pool is empty").into());
+    }
+
+    // price = (pool1 / 10^decimals1 / weight1) / (pool0 / 10^decimals0 / weight0)
+    let weights = match read_pair_curve(&deps.storage)? {
+        Some(pair_curve) => price_weights(&pair_curve.curve),
+        None => [1, 1],
+    };
+    // a ratio doesn't reveal the pool amounts, so the price isn't noised
+    let price_nom = mul(
+        mul(
+            Some(U256::from(pools[1].amount.u128())),
+            Some(U256::from(10).pow(U256::from(decimals[0]))),
+        ),
+        Some(U256::from(weights[0])),
+    );
+    let price_denom = mul(
+        mul(
+            Some(U256::from(pools[0].amount.u128())),
+            Some(U256::from(10).pow(U256::from(decimals[1]))),
+        ),
+        Some(U256::from(weights[1])),
+    );
+
+    let price = match (price_nom, price_denom) {
+        (Some(price_nom), Some(price_denom)) => u256_ratio_to_decimal(price_nom, price_denom)?,
+        _ => return Err(PairError::overflow("spot price").into()),
+    };
+
+    Ok(SpotPriceResponse { price })
+}
+
+pub fn query_simulation<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    offer_asset: Asset,
+    commission_rate_override: Option<Decimal>,
+) -> StdResult<SimulationResponse> {
+    let config: Config = read_config(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    let mut pools: [Asset; 2] = query_pools(&deps, &config, &addresses)?;
+
+    let (nom, denom) = get_random_nom_denom(deps)?;
+    pools[0].amount = apply_noise(pools[0].amount, nom, denom);
+    pools[1].amount = apply_noise(pools[1].amount, nom, denom);
+
+    let offer_pool: Asset;
+    let ask_pool: Asset;
+    if offer_asset.info.equal(&pools[0].info) {
+        offer_pool = pools[0].clone();
+        ask_pool = pools[1].clone();
+    } else if offer_asset.info.equal(&pools[1].info) {
+        offer_pool = pools[1].clone();
+        ask_pool = pools[0].clone();
+    } else {
+        return Err(PairError::asset_not_in_pair(&offer_asset.info).into());
+    }
+
+    // the override only replaces the fee, the pair's curve still applies
+    let pair_settings = query_settings(deps, &config, &addresses)?;
+    let (commission_rate_nom, commission_rate_denom, min_commission) =
+        match commission_rate_override {
+            Some(rate) => {
+                if rate > Decimal::one() {
+                    return Err(
+                        PairError::invalid("commission_rate_override must not exceed 1").into(),
+                    );
+                }
+                let denom = Uint128(1_000_000_000_000_000_000u128);
+                (denom * rate, denom, Uint128::zero())
+            }
+            None => (
+                pair_settings.swap_fee.commission_rate_nom,
+                pair_settings.swap_fee.commission_rate_denom,
+                pair_settings.swap_fee.min_commission,
+            ),
+        };
+
+    let offer_index = if offer_pool.info.equal(&pools[0].info) {
+        0
+    } else {
+        1
+    };
+    let (return_amount, spread_amount, commission_amount) = compute_swap_on_curve(
+        &pair_settings.curve,
+        offer_index,
+        offer_pool.amount,
+        ask_pool.amount,
+        offer_asset.amount,
+        commission_rate_nom,
+        commission_rate_denom,
+        min_commission,
+        pair_settings.fee_on_input,
+    )?;
+    let (lp_commission_amount, protocol_commission_amount) = split_commission(commission_amount);
+
+    assert_oracle_price(
+        deps,
+        &pair_settings,
+        &pools_after_swap(&pools, offer_index, offer_asset.amount, return_amount)?,
+    )?;
+
+    Ok(SimulationResponse {
+        return_amount,
+        spread_amount,
+        commission_amount,
+        lp_commission_amount,
+        protocol_commission_amount,
+    })
+}
+
+/// `pools` once `offer_amount` went into `pools[offer_index]` and `return_amount`
+/// left the other one
+fn pools_after_swap(
+    pools: &[Asset; 2],
+    offer_index: usize,
+    offer_amount: Uint128,
+    return_amount: Uint128,
+) -> StdResult<[Asset; 2]> {
+    let mut pools = pools.clone();
+    pools[offer_index].amount += offer_amount;
+    pools[1 - offer_index].amount = (pools[1 - offer_index].amount - return_amount)?;
+    Ok(pools)
+}
+
+/// Splits a commission into the LP and protocol portions. There is no protocol fee,
+/// swaps leave the whole commission in the pools, so the protocol portion is zero.
+fn split_commission(commission_amount: Uint128) -> (Uint128, Uint128) {
+    (commission_amount, Uint128::zero())
+}
+
+pub fn query_simulation_by_index<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    offer_index: u8,
+    amount: Uint128,
+) -> StdResult<SimulationResponse> {
+    if offer_index > 1 {
+        return Err(PairError::invalid("offer_index must be 0 or 1").into());
+    }
+
+    let config: Config = read_config(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    let asset_infos = addresses.asset_infos(&config);
+
+    query_simulation(
+        deps,
+        Asset {
+            info: asset_infos[offer_index as usize].clone(),
+            amount,
+        },
+        None,
+    )
+}
+
+/// Same as `query_simulation` against `reserves` in the pair's asset order instead of
+/// the current pools. The reserves are the caller's, so there's no noise, and no
+/// oracle check on a price the pools may never reach.
+pub fn query_simulation_at_reserves<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
     offer_asset: Asset,
+    reserves: [Uint128; 2],
 ) -> StdResult<SimulationResponse> {
-    let pair_info: PairInfoRaw = read_pair_info(&deps.storage)?;
+    let config: Config = read_config(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    let asset_infos = addresses.asset_infos(&config);
 
-    let contract_addr = deps.api.human_address(&pair_info.contract_addr)?;
-    let mut pools: [Asset; 2] = pair_info.query_pools(&deps, &contract_addr)?;
-
-    let (nom, denom) = get_random_nom_denom(deps)?;
-    pools[0].amount = Uint128(pools[0].amount.0 * nom / denom);
-    pools[1].amount = Uint128(pools[1].amount.0 * nom / denom);
-
-    let offer_pool: Asset;
-    let ask_pool: Asset;
-    if offer_asset.info.equal(&pools[0].info) {
-        offer_pool = pools[0].clone();
-        ask_pool = pools[1].clone();
-    } else if offer_asset.info.equal(&pools[1].info) {
-        offer_pool = pools[1].clone();
-        ask_pool = pools[0].clone();
+    let offer_index = if offer_asset.info.equal(&asset_infos[0]) {
+        0
+    } else if offer_asset.info.equal(&asset_infos[1]) {
+        1
     } else {
-        return Err(StdError::generic_err(
-            "Given offer asset is not belong to pairs",
-        ));
-    }
-
-    let pair_settings = query_pair_settings(
-        &deps,
-        &pair_info.factory.address,
-        &pair_info.factory.code_hash,
-    )?;
+        return Err(PairError::asset_not_in_pair(&offer_asset.info).into());
+    };
 
-    let (return_amount, spread_amount, commission_amount) = compute_swap(
-        offer_pool.amount,
-        ask_pool.amount,
+    let pair_settings = query_settings(deps, &config, &addresses)?;
+    let (return_amount, spread_amount, commission_amount) = compute_swap_on_curve(
+        &pair_settings.curve,
+        offer_index,
+        reserves[offer_index],
+        reserves[1 - offer_index],
         offer_asset.amount,
         pair_settings.swap_fee.commission_rate_nom,
         pair_settings.swap_fee.commission_rate_denom,
+        pair_settings.swap_fee.min_commission,
+        pair_settings.fee_on_input,
     )?;
+    let (lp_commission_amount, protocol_commission_amount) = split_commission(commission_amount);
 
     Ok(SimulationResponse {
         return_amount,
         spread_amount,
         commission_amount,
+        lp_commission_amount,
+        protocol_commission_amount,
     })
 }
 
+pub fn query_swap_data_preview<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    offer_asset: Asset,
+    recipient: HumanAddr,
+) -> StdResult<Option<SwapDataPreviewResponse>> {
+    let config: Config = read_config(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    let pair_settings = query_settings(deps, &config, &addresses)?;
+    let endpoint = match pair_settings.swap_data_endpoint {
+        Some(endpoint) => endpoint,
+        None => return Ok(None),
+    };
+
+    // the swap reports the pair's own asset infos, whatever the caller passed
+    let asset_infos = addresses.asset_infos(&config);
+    let (offer_info, ask_info) = if offer_asset.info.equal(&asset_infos[0]) {
+        (asset_infos[0].clone(), asset_infos[1].clone())
+    } else if offer_asset.info.equal(&asset_infos[1]) {
+        (asset_infos[1].clone(), asset_infos[0].clone())
+    } else {
+        return Err(PairError::asset_not_in_pair(&offer_asset.info).into());
+    };
+
+    let simulation = query_simulation(deps, offer_asset.clone(), None)?;
+    Ok(Some(SwapDataPreviewResponse {
+        endpoint,
+        asset_in: Asset {
+            info: offer_info,
+            amount: offer_asset.amount,
+        },
+        asset_out: Asset {
+            info: ask_info,
+            amount: simulation.return_amount + simulation.commission_amount,
+        },
+        account: recipient,
+    }))
+}
+
 pub fn query_reverse_simulation<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
     ask_asset: Asset,
 ) -> StdResult<ReverseSimulationResponse> {
-    let pair_info: PairInfoRaw = read_pair_info(&deps.storage)?;
-
-    let contract_addr = deps.api.human_address(&pair_info.contract_addr)?;
-    let mut pools: [Asset; 2] = pair_info.query_pools(&deps, &contract_addr)?;
+    let config: Config = read_config(&deps.storage)?;
+    let addresses: Addresses = read_addresses(&deps.storage, &deps.api)?;
+    let mut pools: [Asset; 2] = query_pools(&deps, &config, &addresses)?;
 
     let (nom, denom) = get_random_nom_denom(deps)?;
-    pools[0].amount = Uint128(pools[0].amount.0 * nom / denom);
-    pools[1].amount = Uint128(pools[1].amount.0 * nom / denom);
+    pools[0].amount = apply_noise(pools[0].amount, nom, denom);
+    pools[1].amount = apply_noise(pools[1].amount, nom, denom);
 
     let offer_pool: Asset;
     let ask_pool: Asset;
@@ -736,23 +5041,32 @@ pub fn query_reverse_simulation<S: Storage, A: Api, Q: Querier>(
         ask_pool = pools[1].clone();
         offer_pool = pools[0].clone();
     } else {
-        return Err(StdError::generic_err(
-            "Given ask asset is not blong to pairs",
-        ));
+        return Err(PairError::asset_not_in_pair(&ask_asset.info).into());
     }
 
-    let pair_settings = query_pair_settings(
-        &deps,
-        &pair_info.factory.address,
-        &pair_info.factory.code_hash,
-    )?;
+    let pair_settings = query_settings(deps, &config, &addresses)?;
 
-    let (offer_amount, spread_amount, commission_amount) = compute_offer_amount(
+    let offer_index = if offer_pool.info.equal(&pools[0].info) {
+        0
+    } else {
+        1
+    };
+    let (offer_amount, spread_amount, commission_amount) = compute_offer_amount_on_curve(
+        &pair_settings.curve,
+        offer_index,
         offer_pool.amount,
         ask_pool.amount,
         ask_asset.amount,
-        pair_settings.swap_fee.commission_rate_nom.0,
-        pair_settings.swap_fee.commission_rate_denom.0,
+        pair_settings.swap_fee.commission_rate_nom,
+        pair_settings.swap_fee.commission_rate_denom,
+        pair_settings.swap_fee.min_commission,
+        pair_settings.fee_on_input,
+    )?;
+
+    assert_oracle_price(
+        deps,
+        &pair_settings,
+        &pools_after_swap(&pools, offer_index, offer_amount, ask_asset.amount)?,
     )?;
 
     Ok(ReverseSimulationResponse {
@@ -762,12 +5076,38 @@ pub fn query_reverse_simulation<S: Storage, A: Api, Q: Querier>(
     })
 }
 
-fn compute_swap(
+/// Same as `PairInfoRaw::query_pools`, using the cached human addresses. Escrowed
+/// order offers are held by the pair but aren't liquidity, they're left out.
+fn query_pools<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    config: &Config,
+    addresses: &Addresses,
+) -> StdResult<[Asset; 2]> {
+    let contract_addr = &addresses.contract_addr.human;
+    let asset_infos = addresses.asset_infos(config);
+    let keyed_infos = addresses.asset_infos_with_keys(config);
+    let escrowed = read_escrowed(&deps.storage)?;
+    Ok([
+        Asset {
+            amount: (keyed_infos[0].query_pool(deps, contract_addr)? - escrowed[0])?,
+            info: asset_infos[0].clone(),
+        },
+        Asset {
+            amount: (keyed_infos[1].query_pool(deps, contract_addr)? - escrowed[1])?,
+            info: asset_infos[1].clone(),
+        },
+    ])
+}
+
+/// Rounds against the trader and in favor of the pool: the return floors and the
+/// commission rounds up, the same way `compute_offer_amount` rounds the offer up
+pub fn compute_swap(
     offer_pool: Uint128,
     ask_pool: Uint128,
     offer_amount: Uint128,
     commission_rate_nom: Uint128,
     commission_rate_denom: Uint128,
+    min_commission: Uint128,
 ) -> StdResult<(Uint128, Uint128, Uint128)> {
     // offer => ask
     let offer_pool = Some(U256::from(offer_pool.u128()));
@@ -777,8 +5117,8 @@ fn compute_swap(
     // cp = offer_pool * ask_pool
     let cp = mul(offer_pool, ask_pool);
     cp.ok_or_else(|| {
-        StdError::generic_err(format!(
-            "Cannot calculate cp = offer_pool {} * ask_pool {}",
+        PairError::overflow(format!(
+            "cp = offer_pool {} * ask_pool {}",
             offer_pool.unwrap(),
             ask_pool.unwrap()
         ))
@@ -786,10 +5126,11 @@ fn compute_swap(
 
     // return_amount = (ask_pool - cp / (offer_pool + offer_amount))
     // ask_amount = return_amount * (1 - commission_rate)
-    let return_amount = sub(ask_pool, div(cp, add(offer_pool, offer_amount)));
+    // the new ask pool rounds up so the return floors, and cp never decreases
+    let return_amount = sub(ask_pool, div_ceil(cp, add(offer_pool, offer_amount)));
     return_amount.ok_or_else(|| {
-        StdError::generic_err(format!(
-            "Cannot calculate return_amount = (ask_pool {} - cp {} / (offer_pool {} + offer_amount {}))",
+        PairError::overflow(format!(
+            "return_amount = (ask_pool {} - cp {} / (offer_pool {} + offer_amount {}))",
             ask_pool.unwrap(),
             cp.unwrap(),
             offer_pool.unwrap(),
@@ -801,8 +5142,8 @@ fn compute_swap(
     // spread = offer_amount * ask_pool / offer_pool - return_amount
     let spread_amount = div(mul(offer_amount, ask_pool), offer_pool)
         .ok_or_else(|| {
-            StdError::generic_err(format!(
-                "Cannot calculate offer_amount {} * ask_pool {} / offer_pool {}",
+            PairError::overflow(format!(
+                "offer_amount {} * ask_pool {} / offer_pool {}",
                 offer_amount.unwrap(),
                 ask_pool.unwrap(),
                 offer_pool.unwrap()
@@ -810,36 +5151,376 @@ fn compute_swap(
         })?
         .saturating_sub(return_amount.unwrap());
 
-    // commission_amount = return_amount * commission_rate_nom / commission_rate_denom
+    let (return_amount, commission_amount) = deduct_commission(
+        return_amount.unwrap(),
+        commission_rate_nom,
+        commission_rate_denom,
+        min_commission,
+    )?;
+
+    Ok((
+        u256_to_uint128(return_amount)?,
+        u256_to_uint128(spread_amount)?,
+        u256_to_uint128(commission_amount)?,
+    ))
+}
+
+/// Same as `compute_swap` on the stableswap invariant, the spread is measured
+/// against the 1:1 peg
+fn compute_swap_stable(
+    offer_pool: Uint128,
+    ask_pool: Uint128,
+    offer_amount: Uint128,
+    commission_rate_nom: Uint128,
+    commission_rate_denom: Uint128,
+    min_commission: Uint128,
+    amp: u64,
+) -> StdResult<(Uint128, Uint128, Uint128)> {
+    if amp == 0 {
+        return Err(PairError::invalid("amp must be positive").into());
+    }
+    let amp = U256::from(amp);
+    let offer_pool = U256::from(offer_pool.u128());
+    let ask_pool = U256::from(ask_pool.u128());
+    let offer_amount = U256::from(offer_amount.u128());
+
+    let d = stable_invariant(offer_pool, ask_pool, amp).ok_or_else(|| {
+        PairError::overflow(format!(
+            "the stable invariant of offer_pool {} and ask_pool {}",
+            offer_pool, ask_pool
+        ))
+    })?;
+    let new_ask_pool = offer_pool
+        .checked_add(offer_amount)
+        .and_then(|new_offer_pool| stable_other_balance(new_offer_pool, d, amp))
+        .ok_or_else(|| {
+            PairError::overflow(format!(
+                "the ask_pool after offering {} to offer_pool {}",
+                offer_amount, offer_pool
+            ))
+        })?;
+
+    // rounds against the trader so the invariant never decreases
+    let return_amount = ask_pool
+        .saturating_sub(new_ask_pool)
+        .saturating_sub(U256::one());
+    let spread_amount = offer_amount.saturating_sub(return_amount);
+
+    let (return_amount, commission_amount) = deduct_commission(
+        return_amount,
+        commission_rate_nom,
+        commission_rate_denom,
+        min_commission,
+    )?;
+
+    Ok((
+        u256_to_uint128(return_amount)?,
+        u256_to_uint128(spread_amount)?,
+        u256_to_uint128(commission_amount)?,
+    ))
+}
+
+/// Same as `compute_swap` on the weighted constant mean invariant
+/// `offer_pool^offer_weight * ask_pool^ask_weight = k`, the spread is measured
+/// against the weighted spot price `(ask_pool / ask_weight) / (offer_pool / offer_weight)`
+fn compute_swap_weighted(
+    offer_pool: Uint128,
+    ask_pool: Uint128,
+    offer_amount: Uint128,
+    commission_rate_nom: Uint128,
+    commission_rate_denom: Uint128,
+    min_commission: Uint128,
+    offer_weight: u8,
+    ask_weight: u8,
+) -> StdResult<(Uint128, Uint128, Uint128)> {
+    let offer_pool = U256::from(offer_pool.u128());
+    let ask_pool = U256::from(ask_pool.u128());
+    let offer_amount = U256::from(offer_amount.u128());
+
+    // new_ask_pool =
+    //     ask_pool * (offer_pool / (offer_pool + offer_amount))^(offer_weight / ask_weight),
+    // rounded up against the trader so the invariant never decreases
+    let one = U256::exp10(POW_DECIMALS);
+    let new_ask_pool = offer_pool
+        .checked_add(offer_amount)
+        .and_then(|new_offer_pool| {
+            weighted_pow(
+                offer_pool,
+                new_offer_pool,
+                offer_weight.into(),
+                ask_weight.into(),
+                true,
+            )
+        })
+        .and_then(|ratio| ask_pool.checked_mul(ratio))
+        .and_then(|scaled| scaled.checked_add(one - U256::one()))
+        .map(|scaled| scaled / one)
+        .ok_or_else(|| {
+            PairError::overflow(format!(
+                "the ask_pool after offering {} to offer_pool {}",
+                offer_amount, offer_pool
+            ))
+        })?;
+    let return_amount = ask_pool.saturating_sub(new_ask_pool);
+
+    // spread = offer_amount * ask_pool * offer_weight / (offer_pool * ask_weight) - return_amount
+    let spread_amount = div(
+        mul(
+            mul(Some(offer_amount), Some(ask_pool)),
+            Some(U256::from(offer_weight)),
+        ),
+        mul(Some(offer_pool), Some(U256::from(ask_weight))),
+    )
+    .ok_or_else(|| {
+        PairError::overflow(format!(
+            "offer_amount {} * ask_pool {} / offer_pool {} at the pool weights",
+            offer_amount, ask_pool, offer_pool
+        ))
+    })?
+    .saturating_sub(return_amount);
+
+    let (return_amount, commission_amount) = deduct_commission(
+        return_amount,
+        commission_rate_nom,
+        commission_rate_denom,
+        min_commission,
+    )?;
+
+    Ok((
+        u256_to_uint128(return_amount)?,
+        u256_to_uint128(spread_amount)?,
+        u256_to_uint128(commission_amount)?,
+    ))
+}
+
+/// Prices a swap with `compute_swap`, `compute_swap_stable` or `compute_swap_weighted`
+/// depending on the curve. `offer_index` picks the offer asset's weight.
+/// `fee_on_input` takes the commission off the offer, see `compute_swap_fee_on_input`.
+pub fn compute_swap_on_curve(
+    curve: &CurveType,
+    offer_index: usize,
+    offer_pool: Uint128,
+    ask_pool: Uint128,
+    offer_amount: Uint128,
+    commission_rate_nom: Uint128,
+    commission_rate_denom: Uint128,
+    min_commission: Uint128,
+    fee_on_input: bool,
+) -> StdResult<(Uint128, Uint128, Uint128)> {
+    if fee_on_input {
+        return compute_swap_fee_on_input(
+            curve,
+            offer_index,
+            offer_pool,
+            ask_pool,
+            offer_amount,
+            commission_rate_nom,
+            commission_rate_denom,
+            min_commission,
+        );
+    }
+
+    match curve {
+        CurveType::ConstantProduct => compute_swap(
+            offer_pool,
+            ask_pool,
+            offer_amount,
+            commission_rate_nom,
+            commission_rate_denom,
+            min_commission,
+        ),
+        CurveType::Stable { amp } => compute_swap_stable(
+            offer_pool,
+            ask_pool,
+            offer_amount,
+            commission_rate_nom,
+            commission_rate_denom,
+            min_commission,
+            *amp,
+        ),
+        CurveType::Weighted { weights } => compute_swap_weighted(
+            offer_pool,
+            ask_pool,
+            offer_amount,
+            commission_rate_nom,
+            commission_rate_denom,
+            min_commission,
+            weights[offer_index],
+            weights[1 - offer_index],
+        ),
+    }
+}
+
+/// Same as `compute_swap_on_curve` with the commission taken off the offer before
+/// the curve instead of off the return. `min_commission` applies to the offer then.
+/// The commission is still reported in the ask asset, as the part of the return the
+/// offer would have bought without it, so the return, commission and spread still
+/// add up to the offer at the current price.
+fn compute_swap_fee_on_input(
+    curve: &CurveType,
+    offer_index: usize,
+    offer_pool: Uint128,
+    ask_pool: Uint128,
+    offer_amount: Uint128,
+    commission_rate_nom: Uint128,
+    commission_rate_denom: Uint128,
+    min_commission: Uint128,
+) -> StdResult<(Uint128, Uint128, Uint128)> {
+    // offer_commission = offer_amount * commission_rate_nom / commission_rate_denom,
+    // rounded up like the commission on the return
+    let offer_commission = div_ceil(
+        mul(
+            Some(U256::from(offer_amount.u128())),
+            Some(U256::from(commission_rate_nom.u128())),
+        ),
+        Some(U256::from(commission_rate_denom.u128())),
+    )
+    .ok_or_else(|| {
+        PairError::overflow(format!(
+            "offer_amount {} * commission_rate_nom {} / commission_rate_denom {}",
+            offer_amount, commission_rate_nom, commission_rate_denom
+        ))
+    })?;
+    let offer_commission = u256_to_uint128(offer_commission)?.max(min_commission);
+    if offer_commission > offer_amount {
+        return Err(PairError::CommissionNotMet.into());
+    }
+
+    let without_commission = |amount: Uint128| {
+        compute_swap_on_curve(
+            curve,
+            offer_index,
+            offer_pool,
+            ask_pool,
+            amount,
+            Uint128::zero(),
+            Uint128(1),
+            Uint128::zero(),
+            false,
+        )
+    };
+    let (full_return, spread_amount, _) = without_commission(offer_amount)?;
+    let (return_amount, _, _) = without_commission((offer_amount - offer_commission)?)?;
+    let commission_amount = (full_return - return_amount).unwrap_or_else(|_| Uint128::zero());
+
+    Ok((return_amount, spread_amount, commission_amount))
+}
+
+/// Prices a reverse swap with `compute_offer_amount`, `compute_offer_amount_stable` or
+/// `compute_offer_amount_weighted` depending on the curve, see `compute_swap_on_curve`
+pub fn compute_offer_amount_on_curve(
+    curve: &CurveType,
+    offer_index: usize,
+    offer_pool: Uint128,
+    ask_pool: Uint128,
+    ask_amount: Uint128,
+    commission_rate_nom: Uint128,
+    commission_rate_denom: Uint128,
+    min_commission: Uint128,
+    fee_on_input: bool,
+) -> StdResult<(Uint128, Uint128, Uint128)> {
+    if fee_on_input {
+        // the offer left after the commission must buy ask_amount without one
+        let (net_offer, _, _) = compute_offer_amount_on_curve(
+            curve,
+            offer_index,
+            offer_pool,
+            ask_pool,
+            ask_amount,
+            Uint128::zero(),
+            Uint128(1),
+            Uint128::zero(),
+            false,
+        )?;
+        let offer_amount = u256_to_uint128(before_commission_deduction(
+            net_offer,
+            commission_rate_nom.u128(),
+            commission_rate_denom.u128(),
+        )?)?
+        .max(net_offer + min_commission);
+
+        let (_, spread_amount, commission_amount) = compute_swap_fee_on_input(
+            curve,
+            offer_index,
+            offer_pool,
+            ask_pool,
+            offer_amount,
+            commission_rate_nom,
+            commission_rate_denom,
+            min_commission,
+        )?;
+        return Ok((offer_amount, spread_amount, commission_amount));
+    }
+
+    match curve {
+        CurveType::ConstantProduct => compute_offer_amount(
+            offer_pool,
+            ask_pool,
+            ask_amount,
+            commission_rate_nom.u128(),
+            commission_rate_denom.u128(),
+        ),
+        CurveType::Stable { amp } => compute_offer_amount_stable(
+            offer_pool,
+            ask_pool,
+            ask_amount,
+            commission_rate_nom.u128(),
+            commission_rate_denom.u128(),
+            *amp,
+        ),
+        CurveType::Weighted { weights } => compute_offer_amount_weighted(
+            offer_pool,
+            ask_pool,
+            ask_amount,
+            commission_rate_nom.u128(),
+            commission_rate_denom.u128(),
+            weights[offer_index],
+            weights[1 - offer_index],
+        ),
+    }
+}
+
+/// Takes the commission out of a return amount, returns what's left and the commission
+fn deduct_commission(
+    return_amount: U256,
+    commission_rate_nom: Uint128,
+    commission_rate_denom: Uint128,
+    min_commission: Uint128,
+) -> StdResult<(U256, U256)> {
+    let return_amount = Some(return_amount);
+
+    // commission_amount = return_amount * commission_rate_nom / commission_rate_denom,
+    // rounded up as it stays in the pool
     let commission_rate_nom = Some(U256::from(commission_rate_nom.u128()));
     let commission_rate_denom = Some(U256::from(commission_rate_denom.u128()));
-    let commission_amount = div(
+    let commission_amount = div_ceil(
         mul(return_amount, commission_rate_nom),
         commission_rate_denom,
     )
     .ok_or_else(|| {
-        StdError::generic_err(format!(
-            "Cannot calculate return_amount {} * commission_rate_nom {} / commission_rate_denom {}",
+        PairError::overflow(format!(
+            "return_amount {} * commission_rate_nom {} / commission_rate_denom {}",
             return_amount.unwrap(),
             commission_rate_nom.unwrap(),
             commission_rate_denom.unwrap()
         ))
     })?;
+    // the rate truncates to zero on dust swaps, charge at least min_commission
+    let commission_amount = commission_amount.max(U256::from(min_commission.u128()));
+    if Some(commission_amount) > return_amount {
+        return Err(PairError::CommissionNotMet.into());
+    }
 
     // commission will be absorbed to pool
     let return_amount = sub(return_amount, Some(commission_amount)).ok_or_else(|| {
-        StdError::generic_err(format!(
-            "Cannot calculate return_amount {} - commission_amount {}",
+        PairError::overflow(format!(
+            "return_amount {} - commission_amount {}",
             return_amount.unwrap(),
             commission_amount
         ))
     })?;
 
-    Ok((
-        Uint128(return_amount.low_u128()),
-        Uint128(spread_amount.low_u128()),
-        Uint128(commission_amount.low_u128()),
-    ))
+    Ok((return_amount, commission_amount))
 }
 
 fn compute_offer_amount(
@@ -853,26 +5534,248 @@ fn compute_offer_amount(
 
     // ask => offer
     // offer_amount = cp / (ask_pool - ask_amount / (1 - commission_rate)) - offer_pool
-    let cp = Uint128(offer_pool.u128() * ask_pool.u128());
-    let one_minus_commission = decimal_subtraction(
-        Decimal::one(),
-        Decimal::from_ratio(commission_rate_nom, commission_rate_denom),
-    )?;
-
-    let offer_amount: Uint128 = (cp.multiply_ratio(
-        1u128,
-        (ask_pool - ask_amount * reverse_decimal(one_minus_commission))?,
-    ) - offer_pool)?;
-
-    let before_commission_deduction = ask_amount * reverse_decimal(one_minus_commission);
-    let spread_amount = (offer_amount * Decimal::from_ratio(ask_pool, offer_pool)
-        - before_commission_deduction)
-        .unwrap_or_else(|_| Uint128::zero());
-    let commission_amount = before_commission_deduction
-        * Decimal::from_ratio(commission_rate_nom, commission_rate_denom);
+    let before_commission_deduction =
+        before_commission_deduction(ask_amount, commission_rate_nom, commission_rate_denom)?;
+    let offer_pool = U256::from(offer_pool.u128());
+    let ask_pool = U256::from(ask_pool.u128());
+
+    let new_ask_pool = ask_pool
+        .checked_sub(before_commission_deduction)
+        .filter(|new_ask_pool| !new_ask_pool.is_zero())
+        .ok_or_else(|| {
+            PairError::insufficient_liquidity(format!(
+                "ask_amount {} before commission exceeds ask_pool {}",
+                before_commission_deduction, ask_pool
+            ))
+        })?;
+    // rounds up, like the commission, so the offer always buys `ask_amount`
+    let offer_amount = div_ceil(mul(Some(offer_pool), Some(ask_pool)), Some(new_ask_pool))
+        .ok_or_else(|| {
+            PairError::overflow(format!(
+                "cp = offer_pool {} * ask_pool {}",
+                offer_pool, ask_pool
+            ))
+        })?
+        .saturating_sub(offer_pool);
+
+    // spread = offer_amount * ask_pool / offer_pool - before_commission_deduction
+    let spread_amount = div(mul(Some(offer_amount), Some(ask_pool)), Some(offer_pool))
+        .ok_or_else(|| {
+            PairError::overflow(format!(
+                "offer_amount {} * ask_pool {} / offer_pool {}",
+                offer_amount, ask_pool, offer_pool
+            ))
+        })?
+        .saturating_sub(before_commission_deduction);
+    let commission_amount =
+        before_commission_deduction.saturating_sub(U256::from(ask_amount.u128()));
+
+    Ok((
+        u256_to_uint128(offer_amount)?,
+        u256_to_uint128(spread_amount)?,
+        u256_to_uint128(commission_amount)?,
+    ))
+}
+
+/// The return amount a swap must produce for `ask_amount` to be left after the
+/// commission, `ask_amount / (1 - commission_rate)` rounded up
+fn before_commission_deduction(
+    ask_amount: Uint128,
+    commission_rate_nom: u128,
+    commission_rate_denom: u128,
+) -> StdResult<U256> {
+    let nom = U256::from(commission_rate_nom);
+    let denom = U256::from(commission_rate_denom);
+    // nom / denom >= MAX_REVERSE_COMMISSION_PERCENT / 100, in U256 so it can't overflow
+    if nom * U256::from(100u128) >= denom * U256::from(MAX_REVERSE_COMMISSION_PERCENT) {
+        return Err(PairError::unsupported(format!(
+            "A commission rate of {}/{} is too high to reverse simulate, the maximum is below {}%",
+            commission_rate_nom, commission_rate_denom, MAX_REVERSE_COMMISSION_PERCENT
+        ))
+        .into());
+    }
+
+    let one_minus_commission = denom - nom;
+    div_ceil(
+        Some(U256::from(ask_amount.u128()) * denom),
+        Some(one_minus_commission),
+    )
+    .ok_or_else(|| StdError::from(PairError::overflow("the return before commission")))
+}
+
+/// Same as `compute_offer_amount` on the stableswap invariant
+fn compute_offer_amount_stable(
+    offer_pool: Uint128,
+    ask_pool: Uint128,
+    ask_amount: Uint128,
+    commission_rate_nom: u128,
+    commission_rate_denom: u128,
+    amp: u64,
+) -> StdResult<(Uint128, Uint128, Uint128)> {
+    if amp == 0 {
+        return Err(PairError::invalid("amp must be positive").into());
+    }
+    let before_commission_deduction = u256_to_uint128(before_commission_deduction(
+        ask_amount,
+        commission_rate_nom,
+        commission_rate_denom,
+    )?)?;
+
+    let amp = U256::from(amp);
+    let d = stable_invariant(
+        U256::from(offer_pool.u128()),
+        U256::from(ask_pool.u128()),
+        amp,
+    )
+    .ok_or_else(|| PairError::overflow("the stable invariant"))?;
+    let new_ask_pool = (ask_pool - before_commission_deduction)?;
+    let new_offer_pool = stable_other_balance(U256::from(new_ask_pool.u128()), d, amp)
+        .ok_or_else(|| PairError::overflow("the offer_pool"))?;
+
+    // rounds against the trader, like `compute_swap_stable`
+    let offer_amount = u256_to_uint128(
+        new_offer_pool
+            .saturating_sub(U256::from(offer_pool.u128()))
+            .saturating_add(U256::one()),
+    )?;
+    let spread_amount =
+        (offer_amount - before_commission_deduction).unwrap_or_else(|_| Uint128::zero());
+    let commission_amount =
+        (before_commission_deduction - ask_amount).unwrap_or_else(|_| Uint128::zero());
     Ok((offer_amount, spread_amount, commission_amount))
 }
 
+/// Same as `compute_offer_amount` on the weighted constant mean invariant, see
+/// `compute_swap_weighted`
+fn compute_offer_amount_weighted(
+    offer_pool: Uint128,
+    ask_pool: Uint128,
+    ask_amount: Uint128,
+    commission_rate_nom: u128,
+    commission_rate_denom: u128,
+    offer_weight: u8,
+    ask_weight: u8,
+) -> StdResult<(Uint128, Uint128, Uint128)> {
+    let before_commission_deduction =
+        before_commission_deduction(ask_amount, commission_rate_nom, commission_rate_denom)?;
+    let offer_pool = U256::from(offer_pool.u128());
+    let ask_pool = U256::from(ask_pool.u128());
+
+    let new_ask_pool = ask_pool
+        .checked_sub(before_commission_deduction)
+        .filter(|new_ask_pool| !new_ask_pool.is_zero())
+        .ok_or_else(|| {
+            PairError::insufficient_liquidity(format!(
+                "ask_amount {} before commission exceeds ask_pool {}",
+                before_commission_deduction, ask_pool
+            ))
+        })?;
+
+    // offer_amount = offer_pool * (1 / ratio - 1) with
+    // ratio = (new_ask_pool / ask_pool)^(ask_weight / offer_weight),
+    // the ratio rounds down and the offer up so the offer always buys `ask_amount`
+    let one = U256::exp10(POW_DECIMALS);
+    let ratio = weighted_pow(
+        new_ask_pool,
+        ask_pool,
+        ask_weight.into(),
+        offer_weight.into(),
+        false,
+    )
+    .filter(|ratio| !ratio.is_zero())
+    .ok_or_else(|| {
+        PairError::insufficient_liquidity(format!(
+            "ask_amount {} is too large for the pool weights",
+            before_commission_deduction
+        ))
+    })?;
+    let offer_amount =
+        div_ceil(mul(Some(offer_pool), Some(one - ratio)), Some(ratio)).ok_or_else(|| {
+            PairError::overflow(format!(
+                "the offer for ask_amount {} from offer_pool {}",
+                before_commission_deduction, offer_pool
+            ))
+        })?;
+
+    // spread = offer_amount * ask_pool * offer_weight / (offer_pool * ask_weight)
+    //     - before_commission_deduction
+    let spread_amount = div(
+        mul(
+            mul(Some(offer_amount), Some(ask_pool)),
+            Some(U256::from(offer_weight)),
+        ),
+        mul(Some(offer_pool), Some(U256::from(ask_weight))),
+    )
+    .ok_or_else(|| {
+        PairError::overflow(format!(
+            "offer_amount {} * ask_pool {} / offer_pool {} at the pool weights",
+            offer_amount, ask_pool, offer_pool
+        ))
+    })?
+    .saturating_sub(before_commission_deduction);
+    let commission_amount =
+        before_commission_deduction.saturating_sub(U256::from(ask_amount.u128()));
+
+    Ok((
+        u256_to_uint128(offer_amount)?,
+        u256_to_uint128(spread_amount)?,
+        u256_to_uint128(commission_amount)?,
+    ))
+}
+
+/// Fails unless a swap of `offer_amount` for `return_amount` leaves the curve's
+/// invariant at least where it was. The whole offer goes into the pool whichever
+/// side the commission is taken from, so the commission only makes it grow. Guards
+/// against regressions in the swap math, a correct swap never trips it.
+pub fn assert_swap_invariant(
+    curve: &CurveType,
+    offer_index: usize,
+    offer_pool: Uint128,
+    ask_pool: Uint128,
+    offer_amount: Uint128,
+    return_amount: Uint128,
+) -> StdResult<()> {
+    let offer_pool = U256::from(offer_pool.u128());
+    let ask_pool = U256::from(ask_pool.u128());
+    let new_offer_pool = offer_pool + U256::from(offer_amount.u128());
+    let new_ask_pool = ask_pool
+        .checked_sub(U256::from(return_amount.u128()))
+        .ok_or_else(|| {
+            PairError::insufficient_liquidity("The swap would return more than the ask pool")
+        })?;
+
+    let holds = match curve {
+        CurveType::ConstantProduct => new_offer_pool * new_ask_pool >= offer_pool * ask_pool,
+        CurveType::Stable { amp } => {
+            let amp = U256::from(*amp);
+            match (
+                stable_invariant(offer_pool, ask_pool, amp),
+                stable_invariant(new_offer_pool, new_ask_pool, amp),
+            ) {
+                // Newton's method stops within a unit of D
+                (Some(d), Some(new_d)) => new_d + U256::one() >= d,
+                _ => false,
+            }
+        }
+        // new_ask_pool / ask_pool >= (offer_pool / new_offer_pool)^(offer_weight / ask_weight),
+        // the power rounded down so only a real decrease fails
+        CurveType::Weighted { weights } => match weighted_pow(
+            offer_pool,
+            new_offer_pool,
+            weights[offer_index].into(),
+            weights[1 - offer_index].into(),
+            false,
+        ) {
+            Some(ratio) => new_ask_pool * U256::exp10(POW_DECIMALS) >= ask_pool * ratio,
+            None => false,
+        },
+    };
+    if !holds {
+        return Err(PairError::invariant("The swap would decrease the pool invariant").into());
+    }
+    Ok(())
+}
+
 /// If `expected_return` is given, we check against `return_amount`
 /// Else if `belief_price` and `max_spread` both are given,
 /// we compute new spread else we just use terraswap
@@ -888,9 +5791,11 @@ pub fn assert_max_spread(
 ) -> StdResult<()> {
     if let Some(expected_return) = expected_return {
         if return_amount.lt(&expected_return) {
-            return Err(StdError::generic_err(
-                "Operation fell short of expected_return",
-            ));
+            return Err(PairError::ExpectedReturnNotMet {
+                expected: expected_return,
+                actual: return_amount,
+            }
+            .into());
         }
     } else if let (Some(max_spread), Some(belief_price)) = (max_spread, belief_price) {
         // Note: SecretSwap never goes in here
@@ -900,18 +5805,54 @@ pub fn assert_max_spread(
         let spread_amount =
             (expected_return.sub(return_amount)).unwrap_or_else(|_| Uint128::zero());
 
-        if return_amount.lt(&expected_return)
-            && Decimal::from_ratio(spread_amount, expected_return).gt(&max_spread)
-        {
-            return Err(StdError::generic_err(
-                "Operation exceeds max spread limit with belief_price",
-            ));
+        let spread = Decimal::from_ratio(spread_amount, expected_return);
+        if return_amount.lt(&expected_return) && spread.gt(&max_spread) {
+            return Err(PairError::SpreadLimitExceeded {
+                max: max_spread,
+                actual: spread,
+            }
+            .into());
         }
     } else if let Some(max_spread) = max_spread {
         // Note: SecretSwap never goes in here
         let return_amount = return_amount + commission_amount;
-        if Decimal::from_ratio(spread_amount, return_amount.add(spread_amount)).gt(&max_spread) {
-            return Err(StdError::generic_err("Operation exceeds max spread limit"));
+        let spread = Decimal::from_ratio(spread_amount, return_amount.add(spread_amount));
+        if spread.gt(&max_spread) {
+            return Err(PairError::SpreadLimitExceeded {
+                max: max_spread,
+                actual: spread,
+            }
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+/// The price impact is the spread over the return the swap would have had at the
+/// current price, `return_amount + commission_amount + spread_amount`
+pub fn assert_max_price_impact(
+    max_price_impact: Option<Decimal>,
+    return_amount: Uint128,
+    commission_amount: Uint128,
+    spread_amount: Uint128,
+) -> StdResult<()> {
+    if let Some(max_price_impact) = max_price_impact {
+        let no_impact_return = U256::from(return_amount.u128())
+            + U256::from(commission_amount.u128())
+            + U256::from(spread_amount.u128());
+        if no_impact_return.is_zero() {
+            return Ok(());
+        }
+
+        let price_impact =
+            u256_ratio_to_decimal(U256::from(spread_amount.u128()), no_impact_return)?;
+        if price_impact > max_price_impact {
+            return Err(PairError::PriceImpactExceeded {
+                max: max_price_impact,
+                actual: price_impact,
+            }
+            .into());
         }
     }
 
@@ -937,29 +5878,34 @@ fn assert_slippage_tolerance(
                 one_minus_slippage_tolerance,
             ) > Decimal::from_ratio(pools[1].amount, pools[0].amount)
         {
-            return Err(StdError::generic_err(
-                "Operation exceeds max splippage tolerance",
-            ));
+            return Err(PairError::slippage("Operation exceeds max slippage tolerance").into());
         }
     }
 
     Ok(())
 }
 
+/// Noise factor of the reserve queries, within `noise_bps_max` of 1. Queries can't
+/// fetch settings, so this follows the last ones the pair cached or was pushed.
 fn get_random_nom_denom<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
 ) -> StdResult<(u128, u128)> {
-    let random_number: u64 = get_random_number(&deps.storage);
-    let is_plus = match random_number % 2 {
-        0 => true,
-        1 => false,
-        _ => return Err(StdError::generic_err("Unreacable")),
+    let settings = read_settings_cache(&deps.storage)?.map(|cache| cache.settings);
+    let noise_bps_max = settings
+        .as_ref()
+        .and_then(|settings| settings.noise_bps_max)
+        .unwrap_or(DEFAULT_NOISE_BPS_MAX)
+        .min(MAX_NOISE_BPS);
+    let random_number: u64 = match settings {
+        Some(settings) if settings.noise_per_block => get_block_random_number(&deps.storage),
+        _ => get_random_number(&deps.storage),
     };
+    let is_plus = random_number % 2 == 0;
 
     let nom: u128;
     let denom: u128 = 10_000;
 
-    let nom_noise = random_number as u128 % 100;
+    let nom_noise = random_number as u128 % (noise_bps_max as u128 + 1);
 
     if is_plus {
         nom = denom + nom_noise;
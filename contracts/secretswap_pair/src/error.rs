@@ -0,0 +1,207 @@
+use std::fmt;
+
+use cosmwasm_std::{Decimal, StdError, Uint128};
+
+/// Failures of the pair. Each one becomes a `StdError::GenericErr` whose message starts
+/// with the stable `code()` and a colon, followed by text meant for people. Clients
+/// should match on the code only, the text may change.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PairError {
+    /// Becomes `StdError::Unauthorized`, which is machine readable already
+    Unauthorized,
+    /// A swap, deposit or withdrawal of nothing
+    ZeroAmount,
+    /// An asset that isn't one of the pair's two assets
+    AssetNotInPair { asset: String },
+    /// The pools can't cover the operation
+    InsufficientLiquidity { detail: String },
+    /// The offer was never received, the pool holds less of it than offered
+    OfferNotReceived { offered: Uint128, pool: Uint128 },
+    /// The return is below the `expected_return` of the swap
+    ExpectedReturnNotMet { expected: Uint128, actual: Uint128 },
+    /// The spread is above the `max_spread` of the swap
+    SpreadLimitExceeded { max: Decimal, actual: Decimal },
+    /// The spread over the no impact return is above the `max_price_impact` of the swap
+    PriceImpactExceeded { max: Decimal, actual: Decimal },
+    /// A deposit, zap or withdrawal is outside its slippage limits
+    SlippageExceeded { detail: String },
+    /// The swap can't pay `min_commission`
+    CommissionNotMet,
+    /// The result would break the curve, this is a bug in the pricing
+    InvariantViolated { detail: String },
+    /// An order or a DCA tranche out of its time window
+    ExpiredDeadline { detail: String },
+    /// Trading is stopped
+    Paused { reason: String },
+    /// The pool price would move too far from the oracle price, or the oracle is down
+    OracleRejected { detail: String },
+    /// An order, DCA schedule or batched swap that doesn't exist
+    NotFound { item: &'static str },
+    /// A message field, or a combination of them, that isn't valid
+    InvalidParameter { detail: String },
+    /// Something the pair can't do with its curve or configuration
+    Unsupported { detail: String },
+    /// The pair isn't in a state where the message can run
+    InvalidState { detail: String },
+    /// The native coins sent don't match the message
+    FundsMismatch { detail: String },
+    /// An intermediate amount doesn't fit its type
+    Overflow { op: String },
+}
+
+impl PairError {
+    /// The stable, machine readable prefix of the error message
+    pub fn code(&self) -> &'static str {
+        match self {
+            PairError::Unauthorized => "unauthorized",
+            PairError::ZeroAmount => "zero_amount",
+            PairError::AssetNotInPair { .. } => "asset_not_in_pair",
+            PairError::InsufficientLiquidity { .. } => "insufficient_liquidity",
+            PairError::OfferNotReceived { .. } => "offer_not_received",
+            PairError::ExpectedReturnNotMet { .. } => "expected_return_not_met",
+            PairError::SpreadLimitExceeded { .. } => "spread_limit_exceeded",
+            PairError::PriceImpactExceeded { .. } => "price_impact_exceeded",
+            PairError::SlippageExceeded { .. } => "slippage_exceeded",
+            PairError::CommissionNotMet => "commission_not_met",
+            PairError::InvariantViolated { .. } => "invariant_violated",
+            PairError::ExpiredDeadline { .. } => "expired_deadline",
+            PairError::Paused { .. } => "paused",
+            PairError::OracleRejected { .. } => "oracle_rejected",
+            PairError::NotFound { .. } => "not_found",
+            PairError::InvalidParameter { .. } => "invalid_parameter",
+            PairError::Unsupported { .. } => "unsupported",
+            PairError::InvalidState { .. } => "invalid_state",
+            PairError::FundsMismatch { .. } => "funds_mismatch",
+            PairError::Overflow { .. } => "overflow",
+        }
+    }
+
+    pub fn asset_not_in_pair(info: &impl fmt::Display) -> Self {
+        PairError::AssetNotInPair {
+            asset: info.to_string(),
+        }
+    }
+
+    pub fn insufficient_liquidity(detail: impl Into<String>) -> Self {
+        PairError::InsufficientLiquidity {
+            detail: detail.into(),
+        }
+    }
+
+    pub fn slippage(detail: impl Into<String>) -> Self {
+        PairError::SlippageExceeded {
+            detail: detail.into(),
+        }
+    }
+
+    pub fn invariant(detail: impl Into<String>) -> Self {
+        PairError::InvariantViolated {
+            detail: detail.into(),
+        }
+    }
+
+    pub fn expired(detail: impl Into<String>) -> Self {
+        PairError::ExpiredDeadline {
+            detail: detail.into(),
+        }
+    }
+
+    pub fn paused(reason: impl Into<String>) -> Self {
+        PairError::Paused {
+            reason: reason.into(),
+        }
+    }
+
+    pub fn oracle(detail: impl Into<String>) -> Self {
+        PairError::OracleRejected {
+            detail: detail.into(),
+        }
+    }
+
+    pub fn invalid(detail: impl Into<String>) -> Self {
+        PairError::InvalidParameter {
+            detail: detail.into(),
+        }
+    }
+
+    pub fn unsupported(detail: impl Into<String>) -> Self {
+        PairError::Unsupported {
+            detail: detail.into(),
+        }
+    }
+
+    pub fn state(detail: impl Into<String>) -> Self {
+        PairError::InvalidState {
+            detail: detail.into(),
+        }
+    }
+
+    pub fn funds(detail: impl Into<String>) -> Self {
+        PairError::FundsMismatch {
+            detail: detail.into(),
+        }
+    }
+
+    pub fn overflow(op: impl Into<String>) -> Self {
+        PairError::Overflow { op: op.into() }
+    }
+}
+
+impl fmt::Display for PairError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.code())?;
+        match self {
+            PairError::Unauthorized => write!(f, "Unauthorized"),
+            PairError::ZeroAmount => write!(f, "The amount must be greater than zero"),
+            PairError::AssetNotInPair { asset } => {
+                write!(f, "{} is not one of the pair's assets", asset)
+            }
+            PairError::OfferNotReceived { offered, pool } => write!(
+                f,
+                "The pool holds {} of the offer asset, less than the {} offered",
+                pool, offered
+            ),
+            PairError::ExpectedReturnNotMet { expected, actual } => write!(
+                f,
+                "The return of {} fell short of the expected_return of {}",
+                actual, expected
+            ),
+            PairError::SpreadLimitExceeded { max, actual } => {
+                write!(
+                    f,
+                    "The spread of {} is more than the max_spread of {}",
+                    actual, max
+                )
+            }
+            PairError::PriceImpactExceeded { max, actual } => write!(
+                f,
+                "The price impact of {} is more than the max_price_impact of {}",
+                actual, max
+            ),
+            PairError::CommissionNotMet => {
+                write!(f, "Swap is too small to pay the minimum commission")
+            }
+            PairError::NotFound { item } => write!(f, "{} not found", item),
+            PairError::Overflow { op } => write!(f, "Cannot calculate {}", op),
+            PairError::InsufficientLiquidity { detail }
+            | PairError::SlippageExceeded { detail }
+            | PairError::InvariantViolated { detail }
+            | PairError::ExpiredDeadline { detail }
+            | PairError::OracleRejected { detail }
+            | PairError::InvalidParameter { detail }
+            | PairError::Unsupported { detail }
+            | PairError::InvalidState { detail }
+            | PairError::FundsMismatch { detail } => write!(f, "{}", detail),
+            PairError::Paused { reason } => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl From<PairError> for StdError {
+    fn from(err: PairError) -> Self {
+        match err {
+            PairError::Unauthorized => StdError::unauthorized(),
+            err => StdError::generic_err(err.to_string()),
+        }
+    }
+}
@@ -1,4 +1,9 @@
+use std::str::FromStr;
+
 use cosmwasm_std::{Decimal, StdResult, Uint128};
+use primitive_types::U256;
+
+use crate::error::PairError;
 
 /////////////////////////////////////////////////////////////
 const DECIMAL_FRACTIONAL: Uint128 = Uint128(1_000_000_000u128);
@@ -21,3 +26,43 @@ pub fn decimal_subtraction(a: Decimal, b: Decimal) -> StdResult<Decimal> {
 pub fn decimal_multiplication(a: Decimal, b: Decimal) -> Decimal {
     Decimal::from_ratio(a * DECIMAL_FRACTIONAL * b, DECIMAL_FRACTIONAL)
 }
+
+/// Converts `nom / denom` to a `Decimal` without the u128 overflow of `Decimal::from_ratio`
+pub fn u256_ratio_to_decimal(nom: U256, denom: U256) -> StdResult<Decimal> {
+    let fractional = U256::from(1_000_000_000_000_000_000u128);
+    let atomics = nom
+        .checked_mul(fractional)
+        .and_then(|v| v.checked_div(denom))
+        .ok_or_else(|| PairError::overflow(format!("{} / {} as decimal", nom, denom)))?;
+
+    Decimal::from_str(&format!(
+        "{}.{:0>18}",
+        atomics / fractional,
+        (atomics % fractional).to_string()
+    ))
+}
+
+/// `decimal` scaled by 10^18, the inverse of `u256_ratio_to_decimal`
+pub fn decimal_to_u256_atomics(decimal: Decimal) -> StdResult<U256> {
+    let decimal = decimal.to_string();
+    let (whole, fractional) = match decimal.find('.') {
+        Some(dot) => (&decimal[..dot], &decimal[dot + 1..]),
+        None => (decimal.as_str(), ""),
+    };
+
+    let parse = |digits: &str| {
+        U256::from_dec_str(digits)
+            .map_err(|_| PairError::invalid(format!("Cannot parse {} as decimal", decimal)))
+    };
+    Ok(parse(whole)? * U256::from(1_000_000_000_000_000_000u128)
+        + parse(&format!("{:0<18}", fractional))?)
+}
+
+/// Converts back to `Uint128`, erroring instead of truncating like `.low_u128()` would
+pub fn u256_to_uint128(value: U256) -> StdResult<Uint128> {
+    if value > U256::from(u128::MAX) {
+        return Err(PairError::overflow(format!("{} as a Uint128", value)).into());
+    }
+
+    Ok(Uint128(value.low_u128()))
+}
@@ -1,13 +1,19 @@
+use std::cell::Cell;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use cosmwasm_std::testing::{MockApi, MockQuerier, MockStorage, MOCK_CONTRACT_ADDR};
 use cosmwasm_std::{
-    from_slice, to_binary, Api, CanonicalAddr, Coin, Decimal, Extern, HumanAddr, Querier,
-    QuerierResult, QueryRequest, SystemError, Uint128, WasmQuery,
+    from_slice, to_binary, Api, CanonicalAddr, Coin, Decimal, Empty, Extern, HumanAddr, Querier,
+    QuerierResult, QueryRequest, ReadonlyStorage, StdResult, Storage, SystemError, Uint128,
+    WasmQuery,
 };
 use cosmwasm_storage::to_length_prefixed;
+use schemars::JsonSchema;
+use secret_toolkit::snip20::{Balance, BalanceResponse, TokenInfo, TokenInfoResponse};
+use serde::{Deserialize, Serialize};
 
-//use terra_cosmwasm::{TaxCapResponse, TaxRateResponse, TerraQuery, TerraQueryWrapper, TerraRoute};
+use secretswap::{AssetInfo, CurveType, Fee, PairSettings, ReferencePriceResponse};
 
 /// mock_dependencies is a drop-in replacement for cosmwasm_std::testing::mock_dependencies
 /// this uses our CustomQuerier.
@@ -29,10 +35,153 @@ pub fn mock_dependencies(
     }
 }
 
+/// Same as mock_dependencies, with a Storage that counts config reads and an Api
+/// that counts address conversions
+pub fn mock_dependencies_counting(
+    canonical_length: usize,
+    contract_balance: &[Coin],
+) -> Extern<CountingStorage, CountingApi, WasmMockQuerier> {
+    let deps = mock_dependencies(canonical_length, contract_balance);
+    Extern {
+        storage: CountingStorage::new(deps.storage),
+        api: CountingApi::new(canonical_length),
+        querier: deps.querier,
+    }
+}
+
+/// MockStorage that counts the reads of the pair config
+pub struct CountingStorage {
+    storage: MockStorage,
+    config_key: Vec<u8>,
+    config_reads: Cell<usize>,
+}
+
+impl CountingStorage {
+    pub fn new(storage: MockStorage) -> Self {
+        CountingStorage {
+            storage,
+            // the config is a Singleton, stored under its length prefixed key
+            config_key: to_length_prefixed(b"config"),
+            config_reads: Cell::new(0),
+        }
+    }
+
+    pub fn config_reads(&self) -> usize {
+        self.config_reads.get()
+    }
+
+    pub fn reset_config_reads(&self) {
+        self.config_reads.set(0);
+    }
+}
+
+impl ReadonlyStorage for CountingStorage {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        if key == self.config_key.as_slice() {
+            self.config_reads.set(self.config_reads.get() + 1);
+        }
+        self.storage.get(key)
+    }
+}
+
+impl Storage for CountingStorage {
+    fn set(&mut self, key: &[u8], value: &[u8]) {
+        self.storage.set(key, value)
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        self.storage.remove(key)
+    }
+}
+
+/// MockApi that counts every address conversion. Api must be Copy, so the
+/// counter is leaked to get a 'static reference shared by all copies.
+#[derive(Copy, Clone)]
+pub struct CountingApi {
+    api: MockApi,
+    conversions: &'static AtomicUsize,
+}
+
+impl CountingApi {
+    pub fn new(canonical_length: usize) -> Self {
+        CountingApi {
+            api: MockApi::new(canonical_length),
+            conversions: Box::leak(Box::new(AtomicUsize::new(0))),
+        }
+    }
+
+    pub fn conversions(&self) -> usize {
+        self.conversions.load(Ordering::SeqCst)
+    }
+
+    pub fn reset_conversions(&self) {
+        self.conversions.store(0, Ordering::SeqCst);
+    }
+}
+
+impl Api for CountingApi {
+    fn canonical_address(&self, human: &HumanAddr) -> StdResult<CanonicalAddr> {
+        self.conversions.fetch_add(1, Ordering::SeqCst);
+        self.api.canonical_address(human)
+    }
+
+    fn human_address(&self, canonical: &CanonicalAddr) -> StdResult<HumanAddr> {
+        self.conversions.fetch_add(1, Ordering::SeqCst);
+        self.api.human_address(canonical)
+    }
+}
+
+/// The subset of snip20, factory and oracle queries the pair sends out
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MockQueryMsg {
+    Balance { address: HumanAddr, key: String },
+    TokenInfo {},
+    PairSettings { pair: Option<HumanAddr> },
+    ReferencePrice { asset_infos: [AssetInfo; 2] },
+}
+
+/// What a snip20 answers instead of a balance when the viewing key doesn't match
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MockQueryAnswer {
+    ViewingKeyError { msg: String },
+}
+
+/// What the mock factory answers with unless a test configures otherwise: a 0.3%
+/// commission on the constant product curve, with every safeguard off. Tests change
+/// only the fields they are about, `PairSettings { paused: true, ..test_settings() }`.
+pub fn test_settings() -> PairSettings {
+    PairSettings {
+        swap_fee: Fee {
+            commission_rate_nom: Uint128(3),
+            commission_rate_denom: Uint128(1000),
+            min_commission: Uint128::zero(),
+        },
+        swap_data_endpoint: None,
+        curve: CurveType::ConstantProduct,
+        initial_share_multiplier: None,
+        paused: false,
+        min_reserve: None,
+        noise_bps_max: None,
+        noise_per_block: false,
+        private_logs: false,
+        max_block_volume_bps: None,
+        reserve_floor_bps: 0,
+        price_oracle: None,
+        fee_on_input: false,
+    }
+}
+
 pub struct WasmMockQuerier {
-    base: MockQuerier<TerraQueryWrapper>,
+    base: MockQuerier<Empty>,
     token_querier: TokenQuerier,
     tax_querier: TaxQuerier,
+    pair_settings: Option<PairSettings>,
+    // commission_rate_nom and commission_rate_denom overrides by pair
+    pair_fees: HashMap<HumanAddr, (Uint128, Uint128)>,
+    // the price oracle is unreachable when unset
+    reference_price: Option<Decimal>,
     canonical_length: usize,
 }
 
@@ -40,12 +189,23 @@ pub struct WasmMockQuerier {
 pub struct TokenQuerier {
     // this lets us iterate over all pairs that match the first string
     balances: HashMap<HumanAddr, HashMap<HumanAddr, Uint128>>,
+    decimals: HashMap<HumanAddr, u8>,
+    // name and symbol, tokens default to mAPPL
+    names: HashMap<HumanAddr, (String, String)>,
+    // tokens that reject the pair's viewing key
+    wrong_viewing_key: Vec<HumanAddr>,
+    // contracts that only answer queries sent with this code hash
+    code_hashes: HashMap<HumanAddr, String>,
 }
 
 impl TokenQuerier {
     pub fn new(balances: &[(&HumanAddr, &[(&HumanAddr, &Uint128)])]) -> Self {
         TokenQuerier {
             balances: balances_to_map(balances),
+            decimals: HashMap::new(),
+            names: HashMap::new(),
+            wrong_viewing_key: vec![],
+            code_hashes: HashMap::new(),
         }
     }
 }
@@ -91,8 +251,7 @@ pub(crate) fn caps_to_map(caps: &[(&String, &Uint128)]) -> HashMap<String, Uint1
 
 impl Querier for WasmMockQuerier {
     fn raw_query(&self, bin_request: &[u8]) -> QuerierResult {
-        // MockQuerier doesn't support Custom, so we ignore it completely here
-        let request: QueryRequest<TerraQueryWrapper> = match from_slice(bin_request) {
+        let request: QueryRequest<Empty> = match from_slice(bin_request) {
             Ok(v) => v,
             Err(e) => {
                 return Err(SystemError::InvalidRequest {
@@ -106,103 +265,120 @@ impl Querier for WasmMockQuerier {
 }
 
 impl WasmMockQuerier {
-    pub fn handle_query(&self, request: &QueryRequest<TerraQueryWrapper>) -> QuerierResult {
+    pub fn handle_query(&self, request: &QueryRequest<Empty>) -> QuerierResult {
         match &request {
-            QueryRequest::Custom(TerraQueryWrapper { route, query_data }) => {
-                if route == &TerraRoute::Treasury {
-                    match query_data {
-                        TerraQuery::TaxRate {} => {
-                            let res = TaxRateResponse {
-                                rate: self.tax_querier.rate,
-                            };
-                            Ok(to_binary(&res))
-                        }
-                        TerraQuery::TaxCap { denom } => {
-                            let cap = self
-                                .tax_querier
-                                .caps
-                                .get(denom)
-                                .copied()
-                                .unwrap_or_default();
-                            let res = TaxCapResponse { cap };
-                            Ok(to_binary(&res))
-                        }
-                        _ => panic!("DO NOT ENTER HERE"),
-                    }
-                } else {
-                    panic!("DO NOT ENTER HERE")
-                }
-            }
-            QueryRequest::Wasm(WasmQuery::Raw {
+            QueryRequest::Wasm(WasmQuery::Smart {
                 contract_addr,
-                key,
                 callback_code_hash,
+                msg,
             }) => {
-                let key: &[u8] = key.as_slice();
-
-                let balances: &HashMap<HumanAddr, Uint128> =
-                    match self.token_querier.balances.get(contract_addr) {
-                        Some(balances) => balances,
-                        None => {
-                            return Err(SystemError::InvalidRequest {
-                                error: format!(
-                                    "No balance info exists for the contract {}",
-                                    contract_addr
-                                ),
-                                request: key.into(),
-                            });
-                        }
-                    };
+                if let Some(code_hash) = self.token_querier.code_hashes.get(contract_addr) {
+                    if code_hash != callback_code_hash {
+                        return Err(SystemError::InvalidRequest {
+                            error: format!("Wrong code hash for the contract {}", contract_addr),
+                            request: msg.clone(),
+                        });
+                    }
+                }
 
-                let prefix_token_info = to_length_prefixed(b"token_info").to_vec();
-                let prefix_balance = to_length_prefixed(b"balance").to_vec();
+                // snip20 queries are padded with trailing spaces
+                let query: MockQueryMsg = match from_slice(msg.as_slice()) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        return Err(SystemError::InvalidRequest {
+                            error: format!("Parsing query msg: {:?}", e),
+                            request: msg.clone(),
+                        });
+                    }
+                };
 
-                if key.to_vec() == prefix_token_info {
-                    let mut total_supply = Uint128::zero();
+                match query {
+                    MockQueryMsg::PairSettings { pair } => match &self.pair_settings {
+                        Some(pair_settings) => {
+                            let mut pair_settings = pair_settings.clone();
+                            if let Some((nom, denom)) =
+                                pair.and_then(|pair| self.pair_fees.get(&pair))
+                            {
+                                pair_settings.swap_fee.commission_rate_nom = *nom;
+                                pair_settings.swap_fee.commission_rate_denom = *denom;
+                            }
+                            Ok(to_binary(&pair_settings))
+                        }
+                        None => Err(SystemError::NoSuchContract {
+                            addr: contract_addr.clone(),
+                        }),
+                    },
+                    MockQueryMsg::ReferencePrice { .. } => match self.reference_price {
+                        Some(price) => Ok(to_binary(&ReferencePriceResponse { price })),
+                        None => Err(SystemError::NoSuchContract {
+                            addr: contract_addr.clone(),
+                        }),
+                    },
+                    MockQueryMsg::TokenInfo {} => {
+                        let names = self.token_querier.names.get(contract_addr);
+                        let balances = match self.token_querier.balances.get(contract_addr) {
+                            Some(balances) => balances.clone(),
+                            None if names.is_some() => HashMap::new(),
+                            None => {
+                                return Err(SystemError::InvalidRequest {
+                                    error: format!(
+                                        "No balance info exists for the contract {}",
+                                        contract_addr
+                                    ),
+                                    request: msg.clone(),
+                                });
+                            }
+                        };
 
-                    for balance in balances {
-                        total_supply += *balance.1;
-                    }
+                        let mut total_supply = Uint128::zero();
+                        for balance in balances {
+                            total_supply += balance.1;
+                        }
 
-                    Ok(to_binary(
-                        &to_binary(&secret_toolkit::snip20::TokenInfoResponse {
-                            token_info: secret_toolkit::snip20::TokenInfo {
-                                name: "mAPPL".to_string(),
-                                symbol: "mAPPL".to_string(),
-                                decimals: 6,
+                        let (name, symbol) = names
+                            .cloned()
+                            .unwrap_or_else(|| ("mAPPL".to_string(), "mAPPL".to_string()));
+                        Ok(to_binary(&TokenInfoResponse {
+                            token_info: TokenInfo {
+                                name,
+                                symbol,
+                                decimals: self
+                                    .token_querier
+                                    .decimals
+                                    .get(contract_addr)
+                                    .copied()
+                                    .unwrap_or(6),
                                 total_supply: Some(total_supply),
                             },
-                        })
-                        .unwrap(),
-                    ))
-                } else if key[..prefix_balance.len()].to_vec() == prefix_balance {
-                    let key_address: &[u8] = &key[prefix_balance.len()..];
-                    let address_raw: CanonicalAddr = CanonicalAddr::from(key_address);
-
-                    let api: MockApi = MockApi::new(self.canonical_length);
-                    let address: HumanAddr = match api.human_address(&address_raw) {
-                        Ok(v) => v,
-                        Err(e) => {
-                            return Err(SystemError::InvalidRequest {
-                                error: format!("Parsing query request: {:?}", e),
-                                request: key.into(),
-                            });
-                        }
-                    };
-
-                    let balance = match balances.get(&address) {
-                        Some(v) => v,
-                        None => {
-                            return Err(SystemError::InvalidRequest {
-                                error: "Balance not found".to_string(),
-                                request: key.into(),
-                            });
+                        }))
+                    }
+                    MockQueryMsg::Balance { address, .. } => {
+                        if self.token_querier.wrong_viewing_key.contains(contract_addr) {
+                            return Ok(to_binary(&MockQueryAnswer::ViewingKeyError {
+                                msg: "Wrong viewing key for this address or viewing key not set"
+                                    .to_string(),
+                            }));
                         }
-                    };
 
-                    Ok(to_binary(&to_binary(&balance).unwrap()))
-                } else {
-                    panic!("DO NOT ENTER HERE")
+                        let amount = match self
+                            .token_querier
+                            .balances
+                            .get(contract_addr)
+                            .and_then(|balances| balances.get(&address))
+                        {
+                            Some(v) => *v,
+                            None => {
+                                return Err(SystemError::InvalidRequest {
+                                    error: "Balance not found".to_string(),
+                                    request: msg.clone(),
+                                });
+                            }
+                        };
+
+                        Ok(to_binary(&BalanceResponse {
+                            balance: Balance { amount },
+                        }))
+                    }
                 }
             }
             _ => self.base.handle_query(request),
@@ -211,22 +387,71 @@ impl WasmMockQuerier {
 }
 
 impl WasmMockQuerier {
-    pub fn new<A: Api>(
-        base: MockQuerier<TerraQueryWrapper>,
-        canonical_length: usize,
-        _api: A,
-    ) -> Self {
+    pub fn new<A: Api>(base: MockQuerier<Empty>, canonical_length: usize, _api: A) -> Self {
         WasmMockQuerier {
             base,
             token_querier: TokenQuerier::default(),
             tax_querier: TaxQuerier::default(),
+            pair_settings: Some(test_settings()),
+            pair_fees: HashMap::new(),
+            reference_price: None,
             canonical_length,
         }
     }
 
     // configure the mint whitelist mock querier
     pub fn with_token_balances(&mut self, balances: &[(&HumanAddr, &[(&HumanAddr, &Uint128)])]) {
-        self.token_querier = TokenQuerier::new(balances);
+        let decimals = self.token_querier.decimals.clone();
+        let names = self.token_querier.names.clone();
+        let wrong_viewing_key = self.token_querier.wrong_viewing_key.clone();
+        self.token_querier = TokenQuerier {
+            decimals,
+            names,
+            wrong_viewing_key,
+            ..TokenQuerier::new(balances)
+        };
+    }
+
+    // configure token decimals, tokens default to 6
+    pub fn with_token_decimals(&mut self, decimals: &[(&HumanAddr, u8)]) {
+        for (contract_addr, decimals) in decimals {
+            self.token_querier
+                .decimals
+                .insert(HumanAddr::from(*contract_addr), *decimals);
+        }
+    }
+
+    // configure what a token answers to token_info
+    pub fn with_token_info(
+        &mut self,
+        contract_addr: &HumanAddr,
+        name: &str,
+        symbol: &str,
+        decimals: u8,
+    ) {
+        self.token_querier.names.insert(
+            contract_addr.clone(),
+            (name.to_string(), symbol.to_string()),
+        );
+        self.token_querier
+            .decimals
+            .insert(contract_addr.clone(), decimals);
+    }
+
+    // make the contract reject queries sent with any other code hash
+    pub fn with_code_hash(&mut self, contract_addr: &HumanAddr, code_hash: &str) {
+        self.token_querier
+            .code_hashes
+            .insert(contract_addr.clone(), code_hash.to_string());
+    }
+
+    // make the given tokens answer balance queries with a viewing key error
+    pub fn with_wrong_viewing_key(&mut self, tokens: &[&HumanAddr]) {
+        for token in tokens {
+            self.token_querier
+                .wrong_viewing_key
+                .push(HumanAddr::from(*token));
+        }
     }
 
     // configure the token owner mock querier
@@ -234,6 +459,26 @@ impl WasmMockQuerier {
         self.tax_querier = TaxQuerier::new(rate, caps);
     }
 
+    // configure the settings the mock factory answers with
+    pub fn with_pair_settings(&mut self, pair_settings: PairSettings) {
+        self.pair_settings = Some(pair_settings);
+    }
+
+    // configure the price the mock oracle answers with, None takes it offline
+    pub fn with_reference_price(&mut self, reference_price: Option<Decimal>) {
+        self.reference_price = reference_price;
+    }
+
+    // configure a factory fee override for one pair
+    pub fn with_pair_fee(&mut self, pair: &HumanAddr, nom: Uint128, denom: Uint128) {
+        self.pair_fees.insert(pair.clone(), (nom, denom));
+    }
+
+    // make the mock factory fail every settings query
+    pub fn with_factory_unavailable(&mut self) {
+        self.pair_settings = None;
+    }
+
     pub fn with_balance(&mut self, balances: &[(&HumanAddr, &[Coin])]) {
         for (addr, balance) in balances {
             self.base.update_balance(addr, balance.to_vec());
@@ -1,8 +1,8 @@
-use cosmwasm_std::{Binary, Decimal, HumanAddr, Uint128};
+use cosmwasm_std::{Binary, Coin, Decimal, HumanAddr, Uint128};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use secretswap::Asset;
+use secretswap::{Asset, AssetInfo, Fee, PairSettings, SwapDataEndpoint};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
@@ -12,13 +12,143 @@ pub enum HandleMsg {
         msg: Option<Binary>,
         amount: Uint128,
     },
+    /// Swap a native offer asset sent along with the message, tokens are swapped through Receive.
+    /// With a wrap config, native uscrt is deposited into sSCRT and swapped as sSCRT.
+    Swap {
+        offer_asset: Asset,
+        expected_return: Option<Uint128>,
+        belief_price: Option<Decimal>,
+        max_spread: Option<Decimal>,
+        /// Fail if the spread is more than this fraction of what the swap would
+        /// return without moving the price
+        #[serde(default)]
+        max_price_impact: Option<Decimal>,
+        /// Queue the swap for its block's batch, settled at a single clearing price
+        /// from the next block on. A batched swap takes no price limits or to_msg, and
+        /// is refunded if the batch fails a swap safeguard.
+        #[serde(default)]
+        batch: bool,
+        to: Option<HumanAddr>,
+        /// Redeem a returned sSCRT for native uscrt
+        #[serde(default)]
+        unwrap: bool,
+    },
     /// Post initize step to allow user to set controlled contract address after creating it
     PostInitialize {},
     /// ProvideLiquidity a user provides pool liquidity
     ProvideLiquidity {
         assets: [Asset; 2],
         slippage_tolerance: Option<Decimal>,
+        /// Stake the minted LP tokens for the sender instead of minting them to it
+        #[serde(default)]
+        auto_stake: Option<AutoStake>,
+        /// Mints (or stakes) the LP tokens for this address instead of the sender,
+        /// the deposits are still pulled from the sender
+        receiver: Option<HumanAddr>,
+    },
+    /// BatchProvideAndSwap provides liquidity from a single native asset. The part of
+    /// `offer_asset` that balances the deposit is swapped inside the pair, then both
+    /// sides are provided and the LP tokens minted. Tokens zap in through Receive.
+    BatchProvideAndSwap {
+        offer_asset: Asset,
+        /// The deposit fails if it would mint less than this
+        min_share: Option<Uint128>,
+        /// Mints the LP tokens for this address instead of the sender
+        receiver: Option<HumanAddr>,
     },
+    /// WithdrawLiquidity burns `amount` LP tokens of the sender without a Send to
+    /// the pair. The sender must first approve the pair with IncreaseAllowance on
+    /// the LP token for at least `amount`.
+    WithdrawLiquidity {
+        amount: Uint128,
+        /// Fail unless at least these amounts are refunded
+        min_assets: Option<Vec<Asset>>,
+        to: Option<HumanAddr>,
+    },
+    /// Compound withdraws `lp_amount` LP tokens of the sender and provides the refund
+    /// right back, minting the resulting shares to the sender. Only the net LP
+    /// difference is pulled and burned, so the same allowance as WithdrawLiquidity
+    /// is needed.
+    Compound { lp_amount: Uint128 },
+    /// SettleBatch pays out the next chunk of a batch queued in an earlier block.
+    /// Swaps settle a chunk first as well, deposits do if they can. Withdrawals,
+    /// cancellations and the factory and admin handles never do.
+    SettleBatch {},
+    /// CancelBatchSwap refunds a swap of the sender queued with `batch: true`,
+    /// until its batch starts settling
+    CancelBatchSwap { id: u64 },
+    /// AddEntropy lets anyone donate extra randomness to the entropy pool
+    AddEntropy { entropy: Binary },
+    /// RefreshSettings re-queries the factory settings into the local cache
+    RefreshSettings {},
+    /// PushSettings lets the factory propagate new settings immediately
+    PushSettings { settings: PairSettings },
+    /// ForceSetLiquidityToken lets the factory register the LP token when
+    /// PostInitialize was front-run or failed
+    ForceSetLiquidityToken { liquidity_token: HumanAddr },
+    /// SyncTokenMetadata stores the asset decimals and symbols of pairs created
+    /// before they were recorded at init
+    SyncTokenMetadata {},
+    /// SetAdmin transfers the admin role, or removes it with `None`
+    SetAdmin { new_admin: Option<HumanAddr> },
+    /// SetStakingContract lets the factory or admin register the default staking contract
+    /// used by `AutoStake::Default`
+    SetStakingContract {
+        staking_contract: Option<StakingContract>,
+    },
+    /// EmergencyWithdraw lets the factory refund `amount` LP shares of `holder`
+    /// when the LP token is frozen and can't burn. The factory attests the
+    /// balance, the shares are not burned and must not be withdrawn again.
+    EmergencyWithdraw { holder: HumanAddr, amount: Uint128 },
+    /// UpdateLiquidityToken lets the factory replace an LP token without supply by a
+    /// new one of `token_code_id`. The new token registers through PostInitialize,
+    /// then the pair registers it with the factory.
+    UpdateLiquidityToken {
+        token_code_id: u64,
+        token_code_hash: String,
+        prng_seed: Binary,
+    },
+    /// SetFullExit lets the factory lift the `min_reserve` cap on withdrawals, so the
+    /// last liquidity providers can leave a pair that is winding down
+    SetFullExit { enabled: bool },
+    /// SetSwapDataEndpoint lets the factory or admin send this pair's swap data to
+    /// `endpoint` instead of the factory's, `None` goes back to the factory's
+    SetSwapDataEndpoint { endpoint: Option<SwapDataEndpoint> },
+    /// RampAmp lets the factory move the amp of a stable pair to `target_amp`,
+    /// linearly until `end_height`
+    RampAmp { target_amp: u64, end_height: u64 },
+    /// SetDebugKey lets the factory or admin set the key of the DebugNoise query,
+    /// `None` turns the query off
+    SetDebugKey { key: Option<String> },
+    /// RotateViewingKeys lets the factory replace the viewing keys the pair reads
+    /// its token balances with
+    RotateViewingKeys {},
+    /// AddToWhitelist lets the factory approve addresses to trade and provide
+    /// liquidity in a restricted pair
+    AddToWhitelist { addresses: Vec<HumanAddr> },
+    /// RemoveFromWhitelist lets the factory revoke addresses from the whitelist
+    RemoveFromWhitelist { addresses: Vec<HumanAddr> },
+    /// ResetCircuitBreaker lets the factory reopen a pair paused by `reserve_floor_bps`
+    ResetCircuitBreaker {},
+    /// ExecuteOrder fills an order placed with `Cw20HookMsg::PlaceOrder` at the current
+    /// pools, anyone can call it and earns the order's bounty
+    ExecuteOrder { order_id: u64 },
+    /// CancelOrder refunds the whole offer of an order to its owner, expired or not
+    CancelOrder { order_id: u64 },
+    /// SetViewingKey sets the key the sender reads its orders and DCAs with
+    SetViewingKey { key: String },
+    /// ExecuteDca swaps the next tranche of a `Cw20HookMsg::PlaceDca` schedule, at most
+    /// once per interval. Anyone can call it and earns `DCA_BOUNTY_BPS` of the return.
+    ExecuteDca { id: u64 },
+    /// CancelDca refunds the budget a DCA has left to its owner
+    CancelDca { id: u64 },
+}
+
+/// Factory handles a pair calls
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FactoryHandleMsg {
+    Register { asset_infos: [AssetInfo; 2] },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -29,18 +159,215 @@ pub enum Cw20HookMsg {
         expected_return: Option<Uint128>,
         belief_price: Option<Decimal>,
         max_spread: Option<Decimal>,
+        /// Fail if the spread is more than this fraction of what the swap would
+        /// return without moving the price
+        #[serde(default)]
+        max_price_impact: Option<Decimal>,
+        /// Queue the swap for its block's batch, settled at a single clearing price
+        /// from the next block on. A batched swap takes no price limits or to_msg, and
+        /// is refunded if the batch fails a swap safeguard.
+        #[serde(default)]
+        batch: bool,
+        to: Option<HumanAddr>,
+        /// Redeem a returned sSCRT for native uscrt
+        #[serde(default)]
+        unwrap: bool,
+        /// Code hash of the `to` contract, used with `to_msg`
+        to_code_hash: Option<String>,
+        /// Delivered to the `to` contract along with the return asset
+        to_msg: Option<Binary>,
+        /// Asset the sender expects to receive, the swap fails if it isn't the other pool
+        ask_asset_info: Option<AssetInfo>,
+    },
+    WithdrawLiquidity {
+        /// Redeem a refunded sSCRT for native uscrt
+        #[serde(default)]
+        unwrap: bool,
+        /// Refund to this address instead of the LP holder
         to: Option<HumanAddr>,
+        /// Code hash of the `to` contract, used with `to_msg`
+        to_code_hash: Option<String>,
+        /// Delivered to the `to` contract with each refunded asset, wrapped in a
+        /// `WithdrawHookMsg`
+        to_msg: Option<Binary>,
+    },
+    /// Withdraw entirely in `asset_info`, the other side is swapped into it at
+    /// the reserves left after the withdrawal
+    WithdrawLiquidityTo {
+        asset_info: AssetInfo,
+        /// The withdrawal fails if it would pay out less than this
+        min_received: Option<Uint128>,
+    },
+    /// Provide liquidity from the sent token alone, see `HandleMsg::BatchProvideAndSwap`
+    BatchProvideAndSwap {
+        min_share: Option<Uint128>,
+        receiver: Option<HumanAddr>,
+    },
+    /// Escrow the sent tokens until a keeper swaps them with `HandleMsg::ExecuteOrder`
+    PlaceOrder {
+        /// Least ask units to receive per offer unit, after the bounty
+        ask_min_price: Decimal,
+        /// Block time after which the order can only be cancelled
+        expires_at: u64,
+        /// Share of the return, in basis points, paid to the keeper
+        bounty_bps: u16,
+    },
+    /// Escrow the sent tokens as a budget swapped `per_interval_amount` at a time,
+    /// see `HandleMsg::ExecuteDca`. The first tranche can run right away.
+    PlaceDca {
+        per_interval_amount: Uint128,
+        interval_seconds: u64,
+        /// Tranches fail below this many ask units per offer unit, after the bounty
+        min_price: Option<Decimal>,
+    },
+}
+
+/// Where ProvideLiquidity stakes the minted LP tokens
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AutoStake {
+    /// The staking contract registered by the factory
+    Default {},
+    Contract {
+        staking_contract: HumanAddr,
+        code_hash: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct StakingContract {
+    pub staking_contract: HumanAddr,
+    pub code_hash: String,
+}
+
+/// StakingHookMsg is attached to the LP tokens sent to a staking contract
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StakingHookMsg {
+    StakeFor { staker: HumanAddr },
+}
+
+/// WithdrawHookMsg is sent to the `to` contract of a WithdrawLiquidity for each
+/// refunded asset, so it knows which asset arrived
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WithdrawHookMsg {
+    LiquidityRefund { asset: Asset, msg: Binary },
+}
+
+/// The snip20 handles the pair sends that move one of its asset tokens
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Snip20HandleMsg {
+    Transfer {
+        recipient: HumanAddr,
+        amount: Uint128,
+        padding: Option<String>,
+    },
+    Send {
+        recipient: HumanAddr,
+        amount: Uint128,
+        msg: Option<Binary>,
+        padding: Option<String>,
+    },
+    TransferFrom {
+        owner: HumanAddr,
+        recipient: HumanAddr,
+        amount: Uint128,
+        padding: Option<String>,
+    },
+    Redeem {
+        amount: Uint128,
+        denom: Option<String>,
+        padding: Option<String>,
+    },
+    Deposit {
+        padding: Option<String>,
     },
-    WithdrawLiquidity {},
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
     Pair {},
+    /// The two asset infos alone, cheaper than Pair
+    Assets {},
+    /// Reserves and LP supply with noise, amounts under `MIN_NOISED_AMOUNT` are exact
     Pool {},
-    Simulation { offer_asset: Asset },
-    ReverseSimulation { ask_asset: Asset },
+    Simulation {
+        offer_asset: Asset,
+        /// Quote with this commission rate instead of the factory's, e.g. 0.0025 for 0.25%.
+        /// Swaps are always executed at the factory rate.
+        commission_rate_override: Option<Decimal>,
+    },
+    /// Simulation of offering `amount` of the asset at `offer_index`, 0 or 1
+    SimulationByIndex {
+        offer_index: u8,
+        amount: Uint128,
+    },
+    /// Simulation against `reserves`, in the order of the pair's assets, instead of the
+    /// current pools. Returns the exact quote, without noise.
+    SimulationAtReserves {
+        offer_asset: Asset,
+        reserves: [Uint128; 2],
+    },
+    ReverseSimulation {
+        ask_asset: Asset,
+    },
+    /// Price of asset 0 in asset 1, adjusted for the tokens' decimals
+    SpotPriceScaled {},
+    /// Whether the asset is one of the pair's two assets
+    HasAsset {
+        asset_info: AssetInfo,
+    },
+    /// Reserves, volumes and fee in one round trip
+    Snapshot {},
+    /// Smallest deposit of each asset that mints at least `lp_amount` at the current reserves
+    ReverseLiquidity {
+        lp_amount: Uint128,
+    },
+    /// What ProvideLiquidity would mint for `assets` at the current reserves, and
+    /// whether `slippage_tolerance` would let it through
+    EstimateLpMint {
+        assets: [Asset; 2],
+        slippage_tolerance: Option<Decimal>,
+    },
+    /// Noise factor the noised queries currently apply, with the exact reserves.
+    /// Only answered with the key set by SetDebugKey.
+    DebugNoise {
+        key: String,
+    },
+    /// What a swap of `offer_asset` paying out to `recipient` would report to the
+    /// swap data endpoint, from the same noised pools as Simulation. None without
+    /// an endpoint.
+    SwapDataPreview {
+        offer_asset: Asset,
+        recipient: HumanAddr,
+    },
+    /// Addresses whitelisted for a restricted pair, a page of at most 30 after
+    /// `start_after`. Only answered with the key set by SetDebugKey.
+    Whitelist {
+        key: String,
+        start_after: Option<HumanAddr>,
+        limit: Option<u32>,
+    },
+    /// An open order of `address`, only answered with the key it set by SetViewingKey
+    Order {
+        address: HumanAddr,
+        key: String,
+        order_id: u64,
+    },
+    /// A running DCA of `address`, only answered with the key it set by SetViewingKey
+    Dca {
+        address: HumanAddr,
+        key: String,
+        id: u64,
+    },
+    /// Compares the balances the asset contracts report with what the pair accounted
+    /// for. Only answered with the key set by SetDebugKey.
+    Integrity {
+        key: String,
+    },
 }
 
 // We define a custom struct for each query response
@@ -56,6 +383,11 @@ pub struct SimulationResponse {
     pub return_amount: Uint128,
     pub spread_amount: Uint128,
     pub commission_amount: Uint128,
+    /// The part of `commission_amount` kept by the liquidity providers
+    pub lp_commission_amount: Uint128,
+    /// The part of `commission_amount` owed to the protocol, zero as swaps leave the
+    /// whole commission in the pools
+    pub protocol_commission_amount: Uint128,
 }
 
 /// ReverseSimulationResponse returns reverse swap simulation response
@@ -65,3 +397,127 @@ pub struct ReverseSimulationResponse {
     pub spread_amount: Uint128,
     pub commission_amount: Uint128,
 }
+
+/// SnapshotResponse aggregates the pair's mutable state, reserves and total
+/// share are noised like in PoolResponse
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SnapshotResponse {
+    pub assets: [Asset; 2],
+    pub total_share: Uint128,
+    pub asset0_volume: Uint128,
+    pub asset1_volume: Uint128,
+    pub swap_count: u64,
+    pub swap_fee: Fee,
+    /// Block height and time of the last swap, None until the first one
+    pub last_swap_at_height: Option<u64>,
+    pub last_swap_at_time: Option<u64>,
+}
+
+/// ReverseLiquidityResponse returns the deposit required for a target share.
+/// The share can come out slightly above the target when one unit of an asset is
+/// worth more than one share.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ReverseLiquidityResponse {
+    pub assets: [Asset; 2],
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct EstimateLpMintResponse {
+    pub share: Uint128,
+    /// The part of each deposit beyond the pool ratio, which the share doesn't pay
+    /// for. ProvideLiquidity leaves it in the pool instead of sending it back, so
+    /// trim the deposit by it. None when nothing is left over.
+    pub refund: Option<[Uint128; 2]>,
+    pub slippage_ok: bool,
+}
+
+/// The noised queries scale reserves and supply by `nom / denom`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DebugNoiseResponse {
+    pub nom: Uint128,
+    pub denom: Uint128,
+    pub assets: [Asset; 2],
+    pub total_share: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct WhitelistResponse {
+    /// Whether the whitelist is enforced
+    pub restricted: bool,
+    pub addresses: Vec<HumanAddr>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OrderResponse {
+    pub offer_asset: Asset,
+    pub ask_min_price: Decimal,
+    pub expires_at: u64,
+    pub bounty_bps: u16,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DcaResponse {
+    /// The budget left
+    pub offer_asset: Asset,
+    pub per_interval_amount: Uint128,
+    pub interval_seconds: u64,
+    pub min_price: Option<Decimal>,
+    pub next_execution_at: u64,
+}
+
+/// IntegrityResponse compares the balances with the flows the pair accounted for
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct IntegrityResponse {
+    /// What the asset contracts report the pair holds, escrows included
+    pub balances: [Asset; 2],
+    /// Deposits minus payouts of every handle so far. None for a pair instantiated
+    /// before the accounting, until its next handle.
+    pub accounted: Option<[Uint128; 2]>,
+    /// Held beyond the accounted balances, e.g. tokens transferred to the pair
+    pub surplus: [Uint128; 2],
+    /// Missing from the accounted balances, e.g. taken by a token's transfer fee
+    pub shortfall: [Uint128; 2],
+}
+
+/// Payload of the `ReceiveSwapData` a swap sends to `endpoint`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SwapDataPreviewResponse {
+    pub endpoint: SwapDataEndpoint,
+    pub asset_in: Asset,
+    /// Return amount before commission, as the swap reports it
+    pub asset_out: Asset,
+    pub account: HumanAddr,
+}
+
+/// SpotPriceResponse returns the price of asset 0 in units of asset 1
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SpotPriceResponse {
+    pub price: Decimal,
+}
+
+/// HasAssetResponse returns whether the asset belongs to the pair, and its index
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct HasAssetResponse {
+    pub has_asset: bool,
+    /// 0 or 1, none when the asset isn't part of the pair
+    pub index: Option<u8>,
+}
+
+/// ProvideLiquidityResponse is returned as data by ProvideLiquidity, recording the
+/// pool before the deposit so impermanent loss can be computed later
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ProvideLiquidityResponse {
+    pub share: Uint128,
+    pub entry_reserves: [Asset; 2],
+    /// Price of asset 0 in asset 1, none when the pool was empty
+    pub entry_price: Option<Decimal>,
+    /// Native coins sent beyond the deposit and sent back
+    pub refunds: Vec<Coin>,
+}
+
+/// SwapResponse is returned as data by native swaps
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SwapResponse {
+    /// Native coins sent beyond the offer and sent back
+    pub refunds: Vec<Coin>,
+}
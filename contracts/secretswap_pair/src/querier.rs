@@ -1,29 +1,66 @@
 use cosmwasm_std::{
-    to_binary, Api, Extern, HumanAddr, Querier, QueryRequest, StdResult, Storage, WasmQuery,
+    to_binary, Api, Decimal, Extern, HumanAddr, Querier, QueryRequest, StdResult, Storage,
+    WasmQuery,
 };
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use secretswap::PairSettings;
+use secretswap::{
+    AssetInfo, PairSettings, PriceOracle, PriceOracleQueryMsg, ReferencePriceResponse,
+};
+
+use crate::error::PairError;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryFactory {
-    PairSettings {},
+    /// The factory answers with the pair's fee override if it has one
+    PairSettings { pair: Option<HumanAddr> },
 }
 
 pub fn query_pair_settings<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
     contract_addr: &HumanAddr,
     code_hash: &String,
+    pair: &HumanAddr,
 ) -> StdResult<PairSettings> {
     // load price form the oracle
     let pair_settings: PairSettings =
         deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
             callback_code_hash: code_hash.clone(),
             contract_addr: contract_addr.clone(),
-            msg: to_binary(&QueryFactory::PairSettings {})?,
+            msg: to_binary(&QueryFactory::PairSettings {
+                pair: Some(pair.clone()),
+            })?,
         }))?;
+    assert_valid_commission(&pair_settings)?;
 
     Ok(pair_settings)
 }
+
+pub fn query_reference_price<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    price_oracle: &PriceOracle,
+    asset_infos: [AssetInfo; 2],
+) -> StdResult<Decimal> {
+    let res: ReferencePriceResponse =
+        deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+            callback_code_hash: price_oracle.code_hash.clone(),
+            contract_addr: price_oracle.contract.clone(),
+            msg: to_binary(&PriceOracleQueryMsg::ReferencePrice { asset_infos })?,
+        }))?;
+
+    Ok(res.price)
+}
+
+/// Swaps divide by `commission_rate_denom`, catch a zero before it surfaces as
+/// an obscure math error
+pub fn assert_valid_commission(pair_settings: &PairSettings) -> StdResult<()> {
+    if pair_settings.swap_fee.commission_rate_denom.is_zero() {
+        return Err(PairError::state(
+            "Invalid commission configuration: commission_rate_denom is zero",
+        )
+        .into());
+    }
+    Ok(())
+}
@@ -1,31 +1,813 @@
-use cosmwasm_std::{StdResult, Storage};
-use cosmwasm_storage::{ReadonlySingleton, Singleton};
+use cosmwasm_std::{
+    Api, CanonicalAddr, Decimal, HumanAddr, ReadonlyStorage, StdResult, Storage, Uint128,
+};
+use cosmwasm_storage::{to_length_prefixed, Bucket, ReadonlyBucket, ReadonlySingleton, Singleton};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
-use secretswap::PairInfoRaw;
+use secretswap::{
+    AssetInfo, AssetInfoRaw, CurveType, Factory, PairInfo, PairInfoRaw, PairSettings,
+    SwapDataEndpoint, WrapConfig,
+};
+
+use crate::error::PairError;
+use crate::msg::StakingContract;
 
 use rand::{RngCore, SeedableRng};
 use rand_chacha::ChaChaRng;
 use sha2::{Digest, Sha256};
 
+/// Legacy layout, a single `PairInfoRaw` rewritten on every swap
 static KEY_PAIR_INFO: &[u8] = b"pair_info";
+static KEY_CONFIG: &[u8] = b"config";
+static KEY_STATS: &[u8] = b"stats";
+static KEY_ADDRESSES: &[u8] = b"addresses";
 static KEY_ENTROPY_POOL: &[u8] = b"entropy_pool";
+static KEY_SETTINGS_CACHE: &[u8] = b"settings_cache";
+static KEY_SETTINGS_TTL_BLOCKS: &[u8] = b"settings_ttl_blocks";
+static KEY_EMERGENCY_WITHDRAWN: &[u8] = b"emergency_withdrawn";
+static KEY_STAKING_CONTRACT: &[u8] = b"staking_contract";
+static KEY_EXPECTED_LP_TOKEN: &[u8] = b"expected_lp_token";
+static KEY_FULL_EXIT: &[u8] = b"full_exit";
+static KEY_DEBUG_KEY_HASH: &[u8] = b"debug_key_hash";
+static KEY_RESTRICTED: &[u8] = b"restricted";
+static KEY_PAIR_CURVE: &[u8] = b"pair_curve";
+static KEY_SWAP_DATA_ENDPOINT: &[u8] = b"swap_data_endpoint";
+static KEY_BLOCK_VOLUME: &[u8] = b"block_volume";
+static KEY_BREAKER_TRIPPED: &[u8] = b"breaker_tripped";
+static KEY_ORDER_COUNT: &[u8] = b"order_count";
+/// Order offers and batch returns held by the pair, they aren't part of the pools
+static KEY_ESCROWED: &[u8] = b"escrowed";
+static PREFIX_ORDERS: &[u8] = b"orders";
+static KEY_DCA_COUNT: &[u8] = b"dca_count";
+static PREFIX_DCAS: &[u8] = b"dcas";
+static KEY_BATCH: &[u8] = b"batch";
+static KEY_ACCOUNTING: &[u8] = b"accounting";
+static KEY_BATCH_SWAP_COUNT: &[u8] = b"batch_swap_count";
+static PREFIX_BATCH_SWAPS: &[u8] = b"batch_swaps";
+/// sha256 of the viewing key each address set for its order queries
+static PREFIX_VIEWING_KEY_HASHES: &[u8] = b"viewing_key_hashes";
+/// Number of whitelist slots, including the ones of removed addresses
+static KEY_WHITELIST_SLOTS: &[u8] = b"whitelist_slots";
+/// Whitelisted addresses by slot
+static PREFIX_WHITELIST_ADDRS: &[u8] = b"whitelist_addrs";
+/// Slot by whitelisted address
+static PREFIX_WHITELIST_INDEX: &[u8] = b"whitelist_index";
+/// Block height and the entropy pool its first transaction left behind
+static KEY_BLOCK_NOISE_SEED: &[u8] = b"block_noise_seed";
+
+/// Size of the entropy pool, which is all `get_random_number` ever reads
+pub const ENTROPY_POOL_BYTES: usize = 32;
+
+/// How many blocks cached factory settings are used before re-querying the factory
+pub const DEFAULT_SETTINGS_TTL_BLOCKS: u64 = 100;
+
+/// Oldest cached settings a swap or deposit runs on, so a factory pause reaches
+/// every pair within this many blocks. TTLs stay below it, which leaves a stale
+/// cache usable as a fallback while the factory is down.
+pub const MAX_SETTINGS_AGE_BLOCKS: u64 = 600;
+
+/// Last `PairSettings` fetched from (or pushed by) the factory
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SettingsCache {
+    pub settings: PairSettings,
+    pub fetched_at_height: u64,
+}
+
+/// The LP token instantiated at init, the PostInitialize caller must match it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ExpectedLpToken {
+    pub code_id: u64,
+    pub label: String,
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+    /// Replaces an earlier LP token, the pair re-registers with the factory once it's paired
+    #[serde(default)]
+    pub replacement: bool,
+}
+
+/// Static pair configuration, only written at init and on admin changes
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Config {
+    pub asset_infos: [AssetInfoRaw; 2],
+    pub contract_addr: CanonicalAddr,
+    pub liquidity_token: CanonicalAddr,
+    pub token_code_hash: String,
+    pub factory: Factory,
+    /// Decimals of each asset, queried from the tokens at init
+    pub asset_decimals: Option<[u8; 2]>,
+    /// Symbol of each asset, the denom for native coins
+    pub asset_symbols: Option<[String; 2]>,
+    /// Set when native uscrt is wrapped into the pair's sSCRT asset
+    pub wrap_config: Option<WrapConfig>,
+    /// May run operational commands alongside the factory
+    pub admin: Option<CanonicalAddr>,
+    /// Block the pair was created at, unknown for pairs created before it was recorded
+    pub created_at_height: Option<u64>,
+    /// Block time in seconds the pair was created at
+    pub created_at_time: Option<u64>,
+}
+
+/// Hot counters, updated on every swap
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default, JsonSchema)]
+pub struct Stats {
+    pub asset0_volume: Uint128,
+    pub asset1_volume: Uint128,
+    pub swap_count: u64,
+    #[serde(default)]
+    pub last_swap_at_height: Option<u64>,
+    #[serde(default)]
+    pub last_swap_at_time: Option<u64>,
+}
+
+/// Offer volume of the current block, for the `max_block_volume_bps` breaker
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BlockVolume {
+    pub height: u64,
+    /// Reserves before the block's first swap
+    pub reserves: [Uint128; 2],
+    pub volumes: [Uint128; 2],
+}
+
+/// A swap escrowed until a keeper fills it at `ask_min_price` or better
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Order {
+    pub owner: CanonicalAddr,
+    /// Pool the escrowed offer goes into when the order is filled
+    pub offer_index: u8,
+    pub offer_amount: Uint128,
+    /// Least ask units the owner takes per offer unit, after the bounty
+    pub ask_min_price: Decimal,
+    /// Block time after which the order can only be cancelled
+    pub expires_at: u64,
+    /// Share of the return, in basis points, paid to whoever fills the order
+    pub bounty_bps: u16,
+}
+
+/// A budget swapped in tranches of `per_interval_amount`, one per interval
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Dca {
+    pub owner: CanonicalAddr,
+    pub offer_index: u8,
+    /// Budget left in escrow
+    pub remaining: Uint128,
+    pub per_interval_amount: Uint128,
+    pub interval_seconds: u64,
+    /// Least ask units the owner takes per offer unit, after the bounty
+    pub min_price: Option<Decimal>,
+    /// Block time the next tranche can run from
+    pub next_execution_at: u64,
+}
+
+/// Curve the pair was created with, it takes the place of the factory's
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PairCurve {
+    pub curve: CurveType,
+    /// Moves the amp of a stable `curve` over a range of blocks
+    pub ramp: Option<AmpRamp>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AmpRamp {
+    pub initial_amp: u64,
+    pub target_amp: u64,
+    pub start_height: u64,
+    pub end_height: u64,
+}
+
+impl AmpRamp {
+    /// The amp at `height`, linear between the ends of the ramp
+    pub fn amp_at(&self, height: u64) -> u64 {
+        if height >= self.end_height {
+            return self.target_amp;
+        }
+        if height <= self.start_height {
+            return self.initial_amp;
+        }
+
+        let elapsed = u128::from(height - self.start_height);
+        let duration = u128::from(self.end_height - self.start_height);
+        let (initial, target) = (u128::from(self.initial_amp), u128::from(self.target_amp));
+        let amp = if target > initial {
+            initial + (target - initial) * elapsed / duration
+        } else {
+            initial - (initial - target) * elapsed / duration
+        };
+        amp as u64
+    }
+}
 
-pub fn store_pair_info<S: Storage>(storage: &mut S, data: &PairInfoRaw) -> StdResult<()> {
-    Singleton::new(storage, KEY_PAIR_INFO).save(data)
+/// What the pair should hold of each asset, from the deposits and payouts of its handles
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Accounting {
+    /// Contract address of a token asset, denom of a native one. Kept here so that
+    /// every handle can account without loading the config.
+    pub assets: [String; 2],
+    pub balances: [Uint128; 2],
 }
 
+/// A swap queued with `batch: true`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BatchSwap {
+    pub owner: CanonicalAddr,
+    /// Receives the return, the owner unless the swap had a `to`
+    pub recipient: CanonicalAddr,
+    pub offer_index: u8,
+    pub offer_amount: Uint128,
+    pub unwrap: bool,
+}
+
+/// Swaps queued in one block and settled together at a uniform clearing price
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct Batch {
+    /// Block the swaps were queued in, they settle from the next one on
+    pub height: u64,
+    /// Queued swaps, in the order they are paid out
+    pub swap_ids: Vec<u64>,
+    /// Offers queued for each asset
+    pub offer_totals: [Uint128; 2],
+    /// Set when settlement starts: each pool plus the offers queued into it. An
+    /// offer of asset `i` returns `offer * depths[1 - i] / depths[i]`.
+    pub depths: Option<[Uint128; 2]>,
+    /// Returns promised at the clearing price and not paid out yet
+    pub owed: [Uint128; 2],
+    /// Set instead of `depths` when the batch fails a swap safeguard at its clearing
+    /// price, every queued swap then gets its offer back
+    #[serde(default)]
+    pub refunding: bool,
+}
+
+impl Config {
+    pub fn to_pair_info_raw(&self, stats: &Stats) -> PairInfoRaw {
+        PairInfoRaw {
+            asset_infos: self.asset_infos.clone(),
+            contract_addr: self.contract_addr.clone(),
+            liquidity_token: self.liquidity_token.clone(),
+            token_code_hash: self.token_code_hash.clone(),
+            asset0_volume: stats.asset0_volume,
+            asset1_volume: stats.asset1_volume,
+            factory: self.factory.clone(),
+        }
+    }
+}
+
+impl From<PairInfoRaw> for Config {
+    fn from(pair_info: PairInfoRaw) -> Self {
+        Config {
+            asset_infos: pair_info.asset_infos,
+            contract_addr: pair_info.contract_addr,
+            liquidity_token: pair_info.liquidity_token,
+            token_code_hash: pair_info.token_code_hash,
+            factory: pair_info.factory,
+            asset_decimals: None,
+            asset_symbols: None,
+            wrap_config: None,
+            admin: None,
+            created_at_height: None,
+            created_at_time: None,
+        }
+    }
+}
+
+/// Whether init already ran, in either the current or the legacy layout
+pub fn is_initialized<S: ReadonlyStorage>(storage: &S) -> bool {
+    // both are Singletons, stored under their length prefixed keys
+    storage.get(&to_length_prefixed(KEY_CONFIG)).is_some()
+        || storage.get(&to_length_prefixed(KEY_PAIR_INFO)).is_some()
+}
+
+fn read_legacy_pair_info<S: Storage>(storage: &S) -> StdResult<Option<PairInfoRaw>> {
+    ReadonlySingleton::new(storage, KEY_PAIR_INFO).may_load()
+}
+
+pub fn store_config<S: Storage>(storage: &mut S, data: &Config) -> StdResult<()> {
+    Singleton::new(storage, KEY_CONFIG).save(data)
+}
+
+pub fn read_config<S: Storage>(storage: &S) -> StdResult<Config> {
+    match ReadonlySingleton::new(storage, KEY_CONFIG).may_load()? {
+        Some(config) => Ok(config),
+        // not migrated yet
+        None => match read_legacy_pair_info(storage)? {
+            Some(pair_info) => Ok(Config::from(pair_info)),
+            None => ReadonlySingleton::new(storage, KEY_CONFIG).load(),
+        },
+    }
+}
+
+pub fn store_stats<S: Storage>(storage: &mut S, data: &Stats) -> StdResult<()> {
+    Singleton::new(storage, KEY_STATS).save(data)
+}
+
+pub fn read_stats<S: Storage>(storage: &S) -> StdResult<Stats> {
+    match ReadonlySingleton::new(storage, KEY_STATS).may_load()? {
+        Some(stats) => Ok(stats),
+        // not migrated yet
+        None => Ok(read_legacy_pair_info(storage)?
+            .map(|pair_info| Stats {
+                asset0_volume: pair_info.asset0_volume,
+                asset1_volume: pair_info.asset1_volume,
+                swap_count: 0,
+                last_swap_at_height: None,
+                last_swap_at_time: None,
+            })
+            .unwrap_or_default()),
+    }
+}
+
+/// Reconstructs the legacy `PairInfoRaw` view from config and stats
 pub fn read_pair_info<S: Storage>(storage: &S) -> StdResult<PairInfoRaw> {
-    ReadonlySingleton::new(storage, KEY_PAIR_INFO).load()
+    let config = read_config(storage)?;
+    let stats = read_stats(storage)?;
+    Ok(config.to_pair_info_raw(&stats))
+}
+
+/// Moves a legacy `PairInfoRaw` into the config/stats layout.
+/// Returns whether anything was migrated.
+pub fn migrate_pair_info<S: Storage>(storage: &mut S) -> StdResult<bool> {
+    let pair_info = match read_legacy_pair_info(storage)? {
+        Some(pair_info) => pair_info,
+        None => return Ok(false),
+    };
+
+    store_stats(
+        storage,
+        &Stats {
+            asset0_volume: pair_info.asset0_volume,
+            asset1_volume: pair_info.asset1_volume,
+            swap_count: 0,
+            last_swap_at_height: None,
+            last_swap_at_time: None,
+        },
+    )?;
+    store_config(storage, &Config::from(pair_info))?;
+    Singleton::<S, PairInfoRaw>::new(storage, KEY_PAIR_INFO).remove();
+
+    Ok(true)
+}
+
+/// An address kept in both its canonical and human forms
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CachedAddr {
+    pub canonical: CanonicalAddr,
+    pub human: HumanAddr,
+}
+
+impl CachedAddr {
+    pub fn from_canonical<A: Api>(api: &A, canonical: &CanonicalAddr) -> StdResult<Self> {
+        Ok(CachedAddr {
+            canonical: canonical.clone(),
+            // the LP token is unset until PostInitialize
+            human: if canonical.is_empty() {
+                HumanAddr::default()
+            } else {
+                api.human_address(canonical)?
+            },
+        })
+    }
+}
+
+/// Both forms of every address in `Config`, derived when the config is written
+/// so entry points don't convert the same stored values on every call
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Addresses {
+    pub contract_addr: CachedAddr,
+    /// Empty for native assets
+    pub asset_contracts: [CachedAddr; 2],
+    pub liquidity_token: CachedAddr,
+    pub factory: CachedAddr,
+}
+
+impl Addresses {
+    pub fn from_config<A: Api>(api: &A, config: &Config) -> StdResult<Self> {
+        let asset_contract = |info: &AssetInfoRaw| match info {
+            AssetInfoRaw::Token { contract_addr, .. } => {
+                CachedAddr::from_canonical(api, contract_addr)
+            }
+            AssetInfoRaw::NativeToken { .. } => Ok(CachedAddr {
+                canonical: CanonicalAddr::default(),
+                human: HumanAddr::default(),
+            }),
+        };
+
+        Ok(Addresses {
+            contract_addr: CachedAddr::from_canonical(api, &config.contract_addr)?,
+            asset_contracts: [
+                asset_contract(&config.asset_infos[0])?,
+                asset_contract(&config.asset_infos[1])?,
+            ],
+            liquidity_token: CachedAddr::from_canonical(api, &config.liquidity_token)?,
+            factory: CachedAddr {
+                canonical: api.canonical_address(&config.factory.address)?,
+                human: config.factory.address.clone(),
+            },
+        })
+    }
+
+    /// The stored asset infos with human addresses, without converting any address.
+    /// The viewing keys are blank, these infos end up in responses anyone can read.
+    pub fn asset_infos(&self, config: &Config) -> [AssetInfo; 2] {
+        let mut asset_infos = self.asset_infos_with_keys(config);
+        for info in asset_infos.iter_mut() {
+            if let AssetInfo::Token { viewing_key, .. } = info {
+                viewing_key.clear();
+            }
+        }
+        asset_infos
+    }
+
+    /// `asset_infos` with the viewing keys the pair reads its token balances with.
+    /// Only for the balance queries, never put these in a response.
+    pub fn asset_infos_with_keys(&self, config: &Config) -> [AssetInfo; 2] {
+        let to_normal = |i: usize| match &config.asset_infos[i] {
+            AssetInfoRaw::Token {
+                token_code_hash,
+                viewing_key,
+                ..
+            } => AssetInfo::Token {
+                contract_addr: self.asset_contracts[i].human.clone(),
+                token_code_hash: token_code_hash.clone(),
+                viewing_key: viewing_key.clone(),
+            },
+            AssetInfoRaw::NativeToken { denom } => AssetInfo::NativeToken {
+                denom: denom.clone(),
+            },
+        };
+
+        [to_normal(0), to_normal(1)]
+    }
+
+    /// Same as `PairInfoRaw::to_normal`, without converting any address
+    pub fn pair_info(&self, config: &Config, stats: &Stats) -> PairInfo {
+        PairInfo {
+            asset_infos: self.asset_infos(config),
+            contract_addr: self.contract_addr.human.clone(),
+            liquidity_token: self.liquidity_token.human.clone(),
+            token_code_hash: config.token_code_hash.clone(),
+            asset0_volume: stats.asset0_volume,
+            asset1_volume: stats.asset1_volume,
+            factory: config.factory.clone(),
+            asset_decimals: config.asset_decimals,
+            asset_symbols: config.asset_symbols.clone(),
+            created_at_height: config.created_at_height,
+            created_at_time: config.created_at_time,
+        }
+    }
+}
+
+pub fn store_addresses<S: Storage>(storage: &mut S, data: &Addresses) -> StdResult<()> {
+    Singleton::new(storage, KEY_ADDRESSES).save(data)
+}
+
+/// Reads the cached addresses, deriving them from the config if they weren't stored yet
+pub fn read_addresses<S: Storage, A: Api>(storage: &S, api: &A) -> StdResult<Addresses> {
+    match ReadonlySingleton::new(storage, KEY_ADDRESSES).may_load()? {
+        Some(addresses) => Ok(addresses),
+        None => Addresses::from_config(api, &read_config(storage)?),
+    }
+}
+
+/// Stores the cached addresses for pairs created before they existed.
+/// Returns whether anything was migrated.
+pub fn migrate_addresses<S: Storage, A: Api>(storage: &mut S, api: &A) -> StdResult<bool> {
+    if ReadonlySingleton::<S, Addresses>::new(storage, KEY_ADDRESSES)
+        .may_load()?
+        .is_some()
+    {
+        return Ok(false);
+    }
+    let config: Config = match ReadonlySingleton::new(storage, KEY_CONFIG).may_load()? {
+        Some(config) => config,
+        None => return Ok(false),
+    };
+
+    let addresses = Addresses::from_config(api, &config)?;
+    store_addresses(storage, &addresses)?;
+
+    Ok(true)
+}
+
+pub fn store_settings_cache<S: Storage>(storage: &mut S, data: &SettingsCache) -> StdResult<()> {
+    Singleton::new(storage, KEY_SETTINGS_CACHE).save(data)
+}
+
+pub fn read_settings_cache<S: Storage>(storage: &S) -> StdResult<Option<SettingsCache>> {
+    ReadonlySingleton::new(storage, KEY_SETTINGS_CACHE).may_load()
+}
+
+pub fn store_settings_ttl_blocks<S: Storage>(storage: &mut S, data: u64) -> StdResult<()> {
+    Singleton::new(storage, KEY_SETTINGS_TTL_BLOCKS).save(&data)
+}
+
+pub fn read_settings_ttl_blocks<S: Storage>(storage: &S) -> StdResult<u64> {
+    Ok(ReadonlySingleton::new(storage, KEY_SETTINGS_TTL_BLOCKS)
+        .may_load()?
+        .unwrap_or(DEFAULT_SETTINGS_TTL_BLOCKS))
+}
+
+/// LP shares refunded by EmergencyWithdraw, which are never burned
+pub fn store_emergency_withdrawn<S: Storage>(storage: &mut S, data: Uint128) -> StdResult<()> {
+    Singleton::new(storage, KEY_EMERGENCY_WITHDRAWN).save(&data)
+}
+
+pub fn read_emergency_withdrawn<S: Storage>(storage: &S) -> StdResult<Uint128> {
+    Ok(ReadonlySingleton::new(storage, KEY_EMERGENCY_WITHDRAWN)
+        .may_load()?
+        .unwrap_or_else(Uint128::zero))
+}
+
+pub fn store_full_exit<S: Storage>(storage: &mut S, data: bool) -> StdResult<()> {
+    Singleton::new(storage, KEY_FULL_EXIT).save(&data)
+}
+
+/// Whether withdrawals may take the pools below `min_reserve`
+pub fn read_full_exit<S: Storage>(storage: &S) -> StdResult<bool> {
+    Ok(ReadonlySingleton::new(storage, KEY_FULL_EXIT)
+        .may_load()?
+        .unwrap_or_default())
+}
+
+/// Only the sha256 of the debug key is stored, `None` disables the debug query
+pub fn store_debug_key_hash<S: Storage>(storage: &mut S, data: &Option<Vec<u8>>) -> StdResult<()> {
+    Singleton::new(storage, KEY_DEBUG_KEY_HASH).save(data)
+}
+
+pub fn read_debug_key_hash<S: Storage>(storage: &S) -> StdResult<Option<Vec<u8>>> {
+    Ok(ReadonlySingleton::new(storage, KEY_DEBUG_KEY_HASH)
+        .may_load()?
+        .flatten())
+}
+
+pub fn store_block_volume<S: Storage>(storage: &mut S, data: &BlockVolume) -> StdResult<()> {
+    Singleton::new(storage, KEY_BLOCK_VOLUME).save(data)
+}
+
+pub fn read_block_volume<S: Storage>(storage: &S) -> StdResult<Option<BlockVolume>> {
+    ReadonlySingleton::new(storage, KEY_BLOCK_VOLUME).may_load()
+}
+
+pub fn store_breaker_tripped<S: Storage>(storage: &mut S, data: bool) -> StdResult<()> {
+    Singleton::new(storage, KEY_BREAKER_TRIPPED).save(&data)
+}
+
+/// Set when a swap would have broken `reserve_floor_bps`, until the factory resets it
+pub fn read_breaker_tripped<S: Storage>(storage: &S) -> StdResult<bool> {
+    Ok(ReadonlySingleton::new(storage, KEY_BREAKER_TRIPPED)
+        .may_load()?
+        .unwrap_or_default())
+}
+
+/// Ids start at 0 and are never reused
+pub fn next_order_id<S: Storage>(storage: &mut S) -> StdResult<u64> {
+    let id: u64 = ReadonlySingleton::new(storage, KEY_ORDER_COUNT)
+        .may_load()?
+        .unwrap_or_default();
+    Singleton::new(storage, KEY_ORDER_COUNT).save(&(id + 1))?;
+    Ok(id)
+}
+
+pub fn store_order<S: Storage>(storage: &mut S, id: u64, order: &Order) -> StdResult<()> {
+    Bucket::new(PREFIX_ORDERS, storage).save(&id.to_be_bytes(), order)
+}
+
+pub fn read_order<S: Storage>(storage: &S, id: u64) -> StdResult<Option<Order>> {
+    ReadonlyBucket::new(PREFIX_ORDERS, storage).may_load(&id.to_be_bytes())
+}
+
+/// Filled and cancelled orders are removed
+pub fn remove_order<S: Storage>(storage: &mut S, id: u64) {
+    Bucket::<S, Order>::new(PREFIX_ORDERS, storage).remove(&id.to_be_bytes());
+}
+
+/// Ids start at 0 and are never reused
+pub fn next_dca_id<S: Storage>(storage: &mut S) -> StdResult<u64> {
+    let id: u64 = ReadonlySingleton::new(storage, KEY_DCA_COUNT)
+        .may_load()?
+        .unwrap_or_default();
+    Singleton::new(storage, KEY_DCA_COUNT).save(&(id + 1))?;
+    Ok(id)
+}
+
+pub fn store_dca<S: Storage>(storage: &mut S, id: u64, dca: &Dca) -> StdResult<()> {
+    Bucket::new(PREFIX_DCAS, storage).save(&id.to_be_bytes(), dca)
+}
+
+pub fn read_dca<S: Storage>(storage: &S, id: u64) -> StdResult<Option<Dca>> {
+    ReadonlyBucket::new(PREFIX_DCAS, storage).may_load(&id.to_be_bytes())
+}
+
+/// Spent and cancelled schedules are removed
+pub fn remove_dca<S: Storage>(storage: &mut S, id: u64) {
+    Bucket::<S, Dca>::new(PREFIX_DCAS, storage).remove(&id.to_be_bytes());
+}
+
+pub fn store_escrowed<S: Storage>(storage: &mut S, data: &[Uint128; 2]) -> StdResult<()> {
+    Singleton::new(storage, KEY_ESCROWED).save(data)
+}
+
+pub fn read_escrowed<S: Storage>(storage: &S) -> StdResult<[Uint128; 2]> {
+    Ok(ReadonlySingleton::new(storage, KEY_ESCROWED)
+        .may_load()?
+        .unwrap_or_default())
+}
+
+pub fn store_accounting<S: Storage>(storage: &mut S, data: &Accounting) -> StdResult<()> {
+    Singleton::new(storage, KEY_ACCOUNTING).save(data)
+}
+
+/// None for pairs instantiated before the accounting, until their next handle
+pub fn read_accounting<S: Storage>(storage: &S) -> StdResult<Option<Accounting>> {
+    ReadonlySingleton::new(storage, KEY_ACCOUNTING).may_load()
+}
+
+pub fn store_batch<S: Storage>(storage: &mut S, data: &Batch) -> StdResult<()> {
+    Singleton::new(storage, KEY_BATCH).save(data)
 }
 
-fn get_current_entropy_pool<S: Storage>(storage: &S) -> [u8; 32] {
-    ReadonlySingleton::new(storage, KEY_ENTROPY_POOL)
-        .load()
-        .or::<[u8; 32]>(Ok([
-            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-            0, 0, 0,
-        ]))
-        .unwrap()
+pub fn read_batch<S: Storage>(storage: &S) -> StdResult<Batch> {
+    Ok(ReadonlySingleton::new(storage, KEY_BATCH)
+        .may_load()?
+        .unwrap_or_default())
+}
+
+/// Ids start at 0 and are never reused
+pub fn next_batch_swap_id<S: Storage>(storage: &mut S) -> StdResult<u64> {
+    let id: u64 = ReadonlySingleton::new(storage, KEY_BATCH_SWAP_COUNT)
+        .may_load()?
+        .unwrap_or_default();
+    Singleton::new(storage, KEY_BATCH_SWAP_COUNT).save(&(id + 1))?;
+    Ok(id)
+}
+
+pub fn store_batch_swap<S: Storage>(storage: &mut S, id: u64, swap: &BatchSwap) -> StdResult<()> {
+    Bucket::new(PREFIX_BATCH_SWAPS, storage).save(&id.to_be_bytes(), swap)
+}
+
+pub fn read_batch_swap<S: Storage>(storage: &S, id: u64) -> StdResult<Option<BatchSwap>> {
+    ReadonlyBucket::new(PREFIX_BATCH_SWAPS, storage).may_load(&id.to_be_bytes())
+}
+
+/// Settled and cancelled swaps are removed
+pub fn remove_batch_swap<S: Storage>(storage: &mut S, id: u64) {
+    Bucket::<S, BatchSwap>::new(PREFIX_BATCH_SWAPS, storage).remove(&id.to_be_bytes());
+}
+
+pub fn store_viewing_key_hash<S: Storage>(
+    storage: &mut S,
+    address: &CanonicalAddr,
+    key_hash: &[u8],
+) -> StdResult<()> {
+    Bucket::new(PREFIX_VIEWING_KEY_HASHES, storage).save(address.as_slice(), &key_hash.to_vec())
+}
+
+pub fn read_viewing_key_hash<S: Storage>(
+    storage: &S,
+    address: &CanonicalAddr,
+) -> StdResult<Option<Vec<u8>>> {
+    ReadonlyBucket::new(PREFIX_VIEWING_KEY_HASHES, storage).may_load(address.as_slice())
+}
+
+pub fn store_restricted<S: Storage>(storage: &mut S, data: bool) -> StdResult<()> {
+    Singleton::new(storage, KEY_RESTRICTED).save(&data)
+}
+
+/// Whether only whitelisted addresses may trade and provide liquidity
+pub fn read_restricted<S: Storage>(storage: &S) -> StdResult<bool> {
+    Ok(ReadonlySingleton::new(storage, KEY_RESTRICTED)
+        .may_load()?
+        .unwrap_or_default())
+}
+
+pub fn store_pair_curve<S: Storage>(storage: &mut S, data: &PairCurve) -> StdResult<()> {
+    Singleton::new(storage, KEY_PAIR_CURVE).save(data)
+}
+
+/// None when the pair swaps on the factory's curve
+pub fn read_pair_curve<S: Storage>(storage: &S) -> StdResult<Option<PairCurve>> {
+    ReadonlySingleton::new(storage, KEY_PAIR_CURVE).may_load()
+}
+
+pub fn store_swap_data_endpoint<S: Storage>(
+    storage: &mut S,
+    data: &Option<SwapDataEndpoint>,
+) -> StdResult<()> {
+    Singleton::new(storage, KEY_SWAP_DATA_ENDPOINT).save(data)
+}
+
+/// The pair's own endpoint, None when it sends swap data to the factory's
+pub fn read_swap_data_endpoint<S: Storage>(storage: &S) -> StdResult<Option<SwapDataEndpoint>> {
+    Ok(ReadonlySingleton::new(storage, KEY_SWAP_DATA_ENDPOINT)
+        .may_load()?
+        .flatten())
+}
+
+fn read_whitelist_slots<S: Storage>(storage: &S) -> StdResult<u64> {
+    Ok(ReadonlySingleton::new(storage, KEY_WHITELIST_SLOTS)
+        .may_load()?
+        .unwrap_or_default())
+}
+
+fn read_whitelist_index<S: Storage>(
+    storage: &S,
+    address: &CanonicalAddr,
+) -> StdResult<Option<u64>> {
+    ReadonlyBucket::new(PREFIX_WHITELIST_INDEX, storage).may_load(address.as_slice())
+}
+
+pub fn is_whitelisted<S: Storage>(storage: &S, address: &CanonicalAddr) -> StdResult<bool> {
+    Ok(read_whitelist_index(storage, address)?.is_some())
+}
+
+/// Appends `address` to the whitelist, an address already on it keeps its slot
+pub fn add_to_whitelist<S: Storage>(storage: &mut S, address: &CanonicalAddr) -> StdResult<()> {
+    if is_whitelisted(storage, address)? {
+        return Ok(());
+    }
+    let index = read_whitelist_slots(storage)?;
+    Bucket::new(PREFIX_WHITELIST_ADDRS, storage).save(&index.to_be_bytes(), address)?;
+    Bucket::new(PREFIX_WHITELIST_INDEX, storage).save(address.as_slice(), &index)?;
+    Singleton::new(storage, KEY_WHITELIST_SLOTS).save(&(index + 1))
+}
+
+/// The slot of a removed address is left behind and skipped by `read_whitelist`
+pub fn remove_from_whitelist<S: Storage>(storage: &mut S, address: &CanonicalAddr) {
+    Bucket::<S, u64>::new(PREFIX_WHITELIST_INDEX, storage).remove(address.as_slice());
+}
+
+const MAX_LIMIT: u32 = 30;
+const DEFAULT_LIMIT: u32 = 10;
+
+/// Whitelisted addresses in the order they were added
+pub fn read_whitelist<S: Storage>(
+    storage: &S,
+    start_after: Option<&CanonicalAddr>,
+    limit: Option<u32>,
+) -> StdResult<Vec<CanonicalAddr>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as u64;
+    let start = match start_after {
+        Some(address) => read_whitelist_index(storage, address)?
+            .ok_or_else(|| PairError::invalid("start_after is not whitelisted"))?
+            .saturating_add(1),
+        None => 0,
+    };
+    let slots = read_whitelist_slots(storage)?;
+
+    let mut addresses = vec![];
+    for index in start..slots {
+        if addresses.len() as u64 >= limit {
+            break;
+        }
+        let address: CanonicalAddr =
+            ReadonlyBucket::new(PREFIX_WHITELIST_ADDRS, storage).load(&index.to_be_bytes())?;
+        // the address was removed, or added again under a later slot
+        if read_whitelist_index(storage, &address)? != Some(index) {
+            continue;
+        }
+        addresses.push(address);
+    }
+    Ok(addresses)
+}
+
+pub fn store_staking_contract<S: Storage>(
+    storage: &mut S,
+    data: &Option<StakingContract>,
+) -> StdResult<()> {
+    Singleton::new(storage, KEY_STAKING_CONTRACT).save(data)
+}
+
+pub fn read_staking_contract<S: Storage>(storage: &S) -> StdResult<Option<StakingContract>> {
+    Ok(ReadonlySingleton::new(storage, KEY_STAKING_CONTRACT)
+        .may_load()?
+        .flatten())
+}
+
+pub fn store_expected_lp_token<S: Storage>(
+    storage: &mut S,
+    data: &ExpectedLpToken,
+) -> StdResult<()> {
+    Singleton::new(storage, KEY_EXPECTED_LP_TOKEN).save(data)
+}
+
+/// `None` for pairs created before the LP token was recorded
+pub fn read_expected_lp_token<S: Storage>(storage: &S) -> StdResult<Option<ExpectedLpToken>> {
+    ReadonlySingleton::new(storage, KEY_EXPECTED_LP_TOKEN).may_load()
+}
+
+/// The pool is stored as its raw 32 bytes, so reading it costs the same no
+/// matter how much entropy has been mixed in before
+fn get_current_entropy_pool<S: ReadonlyStorage>(storage: &S) -> [u8; ENTROPY_POOL_BYTES] {
+    let mut entropy_pool = [0u8; ENTROPY_POOL_BYTES];
+    match storage.get(KEY_ENTROPY_POOL) {
+        Some(raw) if raw.len() == ENTROPY_POOL_BYTES => entropy_pool.copy_from_slice(&raw),
+        // pools saved before the raw encoding are in a Singleton under the same name
+        _ => {
+            if let Ok(Some(legacy)) = ReadonlySingleton::new(storage, KEY_ENTROPY_POOL).may_load() {
+                entropy_pool = legacy;
+            }
+        }
+    }
+    entropy_pool
 }
 
 pub fn supply_more_entropy<S: Storage>(
@@ -37,12 +819,72 @@ pub fn supply_more_entropy<S: Storage>(
     let mut new_entropy_source = Vec::from(current_entropy_pool);
     new_entropy_source.extend(additional_entropy);
 
-    let new_entropy_pool: [u8; 32] = Sha256::digest(&new_entropy_source).into();
+    let new_entropy_pool: [u8; ENTROPY_POOL_BYTES] = Sha256::digest(&new_entropy_source).into();
+
+    storage.set(KEY_ENTROPY_POOL, &new_entropy_pool);
+    // the legacy pool is folded in now
+    Singleton::<S, [u8; ENTROPY_POOL_BYTES]>::new(storage, KEY_ENTROPY_POOL).remove();
+    Ok(())
+}
+
+/// `len` bytes drawn from the entropy pool, for secrets such as viewing keys
+pub fn get_random_bytes<S: ReadonlyStorage>(storage: &S, len: usize) -> Vec<u8> {
+    let entropy_pool = get_current_entropy_pool(storage);
+
+    let mut rng = ChaChaRng::from_seed(entropy_pool);
+    let mut bytes = vec![0u8; len];
+    rng.fill_bytes(&mut bytes);
+
+    bytes
+}
+
+/// Keeps the entropy pool as of the first transaction of `height`, later
+/// transactions of the same block leave the seed alone
+pub fn refresh_block_noise_seed<S: Storage>(storage: &mut S, height: u64) {
+    if let Some(raw) = storage.get(KEY_BLOCK_NOISE_SEED) {
+        if raw.len() == 8 + ENTROPY_POOL_BYTES && raw[..8] == height.to_be_bytes() {
+            return;
+        }
+    }
+
+    let mut raw = height.to_be_bytes().to_vec();
+    raw.extend_from_slice(&get_current_entropy_pool(storage));
+    storage.set(KEY_BLOCK_NOISE_SEED, &raw);
+}
+
+/// Same as `get_random_number`, but drawn from the seed of the last block that
+/// had a transaction. Falls back to the entropy pool until a seed is stored.
+pub fn get_block_random_number<S: ReadonlyStorage>(storage: &S) -> u64 {
+    #[cfg(test)]
+    if let Some(pinned) = PINNED_RANDOM_NUMBER.with(|pinned| pinned.get()) {
+        return pinned;
+    }
 
-    Singleton::new(storage, KEY_ENTROPY_POOL).save(&new_entropy_pool)
+    let mut seed = get_current_entropy_pool(storage);
+    if let Some(raw) = storage.get(KEY_BLOCK_NOISE_SEED) {
+        if raw.len() == 8 + ENTROPY_POOL_BYTES {
+            seed.copy_from_slice(&raw[8..]);
+        }
+    }
+
+    let mut rng = ChaChaRng::from_seed(seed);
+
+    rng.next_u64()
 }
 
-pub fn get_random_number<S: Storage>(storage: &S) -> u64 {
+#[cfg(test)]
+thread_local! {
+    /// Replaces the entropy driven random number on the current thread, so tests
+    /// can assert exact noised amounts
+    pub static PINNED_RANDOM_NUMBER: std::cell::Cell<Option<u64>> = std::cell::Cell::new(None);
+}
+
+pub fn get_random_number<S: ReadonlyStorage>(storage: &S) -> u64 {
+    #[cfg(test)]
+    if let Some(pinned) = PINNED_RANDOM_NUMBER.with(|pinned| pinned.get()) {
+        return pinned;
+    }
+
     let entropy_pool = get_current_entropy_pool(storage);
 
     let mut rng = ChaChaRng::from_seed(entropy_pool);
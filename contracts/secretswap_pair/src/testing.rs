@@ -1,75 +1,80 @@
-use cosmwasm_std::testing::{mock_env, MOCK_CONTRACT_ADDR};
+use cosmwasm_std::testing::{mock_env, MockApi, MockStorage, MOCK_CONTRACT_ADDR};
 use cosmwasm_std::{
-    log, to_binary, BankMsg, BlockInfo, Coin, CosmosMsg, Decimal, Env, HandleResponse, HumanAddr,
-    StdError, Uint128, WasmMsg,
+    from_binary, log, to_binary, Api, BankMsg, Binary, BlockInfo, Coin, CosmosMsg, Decimal, Env,
+    Extern, HandleResponse, HandleResult, HumanAddr, InitResponse, ReadonlyStorage, StdError,
+    StdResult, Storage, Uint128, WasmMsg,
 };
 
-use cw20::{Cw20HandleMsg, Cw20ReceiveMsg, MinterResponse};
-use secretswap::{Asset, AssetInfo, InitHook, PairInfo, PairInitMsg, TokenInitMsg};
+use cosmwasm_storage::{to_length_prefixed, ReadonlySingleton, Singleton};
+use primitive_types::U256;
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaChaRng;
+use secret_toolkit::snip20;
+use secretswap::{
+    Asset, AssetInfo, AssetInfoRaw, CurveType, ExistingLiquidityToken, Factory, Fee, InitHook,
+    PairInfo, PairInfoRaw, PairInitMsg, PairSettings, PriceOracle, SwapDataEndpoint, TokenInitMsg,
+    WrapConfig, DEFAULT_NOISE_BPS_MAX,
+};
+use sha2::{Digest, Sha256};
 
 use crate::contract::{
-    assert_max_spread, handle, init, query_pair_info, query_pool, query_reverse_simulation,
-    query_simulation,
+    assert_max_spread, assert_swap_invariant, compute_offer_amount_on_curve, compute_refund_assets,
+    compute_share, compute_swap, compute_swap_on_curve, handle, init, query, query_has_asset,
+    query_pair_info, query_pool, query_reverse_simulation, query_simulation,
+    query_simulation_by_index, query_spot_price_scaled, MIN_NOISED_AMOUNT,
+};
+use crate::error::PairError;
+use crate::math::u256_to_uint128;
+use crate::mock_querier::{
+    mock_dependencies, mock_dependencies_counting, test_settings, WasmMockQuerier,
 };
-use crate::math::{decimal_multiplication, reverse_decimal};
-use crate::mock_querier::mock_dependencies;
 use crate::msg::{
-    Cw20HookMsg, HandleMsg, PoolResponse, ReverseSimulationResponse, SimulationResponse,
+    AutoStake, Cw20HookMsg, DcaResponse, DebugNoiseResponse, EstimateLpMintResponse,
+    FactoryHandleMsg, HandleMsg, HasAssetResponse, IntegrityResponse, OrderResponse, PoolResponse,
+    ProvideLiquidityResponse, QueryMsg, ReverseLiquidityResponse, SimulationResponse,
+    SnapshotResponse, Snip20HandleMsg, StakingContract, StakingHookMsg, SwapDataPreviewResponse,
+    SwapResponse, WhitelistResponse, WithdrawHookMsg,
+};
+use crate::state::{
+    get_random_number, read_addresses, read_block_volume, read_config, read_pair_info,
+    read_settings_cache, read_stats, store_addresses, store_config, store_stats,
+    supply_more_entropy, Addresses, Config, Stats, DEFAULT_SETTINGS_TTL_BLOCKS, ENTROPY_POOL_BYTES,
+    MAX_SETTINGS_AGE_BLOCKS, PINNED_RANDOM_NUMBER,
 };
 
 #[test]
 fn proper_initialization() {
     let mut deps = mock_dependencies(20, &[]);
 
-    let msg = PairInitMsg {
-        asset_infos: [
-            AssetInfo::NativeToken {
-                denom: "uusd".to_string(),
-            },
-            AssetInfo::Token {
-                contract_addr: HumanAddr::from("asset0000"),
-            },
-        ],
-        token_code_id: 10u64,
-        init_hook: Some(InitHook {
-            contract_addr: HumanAddr::from("factory0000"),
-            msg: to_binary(&Uint128(1000000u128)).unwrap(),
-        }),
-    };
+    let msg = pair_init_msg([
+        AssetInfo::NativeToken {
+            denom: "uusd".to_string(),
+        },
+        token_asset_info("asset0000"),
+    ]);
 
     // we can just call .unwrap() to assert this was a success
     let env = mock_env("addr0000", &[]);
     let res = init(&mut deps, env, msg).unwrap();
+    let lp_token = res.messages.iter().find_map(|msg| match msg {
+        CosmosMsg::Wasm(WasmMsg::Instantiate {
+            code_id,
+            callback_code_hash,
+            ..
+        }) => Some((*code_id, callback_code_hash.as_str())),
+        _ => None,
+    });
+    assert_eq!(lp_token, Some((10u64, "lptokenhash")));
     assert_eq!(
-        res.messages,
-        vec![
-            CosmosMsg::Wasm(WasmMsg::Instantiate {
-                code_id: 10u64,
-                msg: to_binary(&TokenInitMsg {
-                    name: "secretswap liquidity token".to_string(),
-                    symbol: "uLP".to_string(),
-                    decimals: 6,
-                    initial_balances: vec![],
-                    mint: Some(MinterResponse {
-                        minter: HumanAddr::from(MOCK_CONTRACT_ADDR),
-                        cap: None,
-                    }),
-                    init_hook: Some(InitHook {
-                        msg: to_binary(&HandleMsg::PostInitialize {}).unwrap(),
-                        contract_addr: HumanAddr::from(MOCK_CONTRACT_ADDR),
-                    }),
-                })
-                .unwrap(),
-                send: vec![],
-                label: None,
-            }),
-            CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: HumanAddr::from("factory0000"),
-                msg: to_binary(&Uint128(1000000u128)).unwrap(),
-                send: vec![],
-            })
-        ]
+        res.messages.last(),
+        Some(&CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: HumanAddr::from("factory0000"),
+            callback_code_hash: "factoryhash".to_string(),
+            msg: to_binary(&Uint128(1000000u128)).unwrap(),
+            send: vec![],
+        }))
     );
+    mock_lp_token(&mut deps, &res.messages, "liquidity0000");
 
     // post initalize
     let msg = HandleMsg::PostInitialize {};
@@ -81,7 +86,7 @@ fn proper_initialization() {
     let env = mock_env("liquidity0001", &[]);
     let _res = handle(&mut deps, env, msg).unwrap_err();
 
-    // // it worked, let's query the state
+    // it worked, let's query the state
     let pair_info: PairInfo = query_pair_info(&deps).unwrap();
     assert_eq!("liquidity0000", pair_info.liquidity_token.as_str());
     assert_eq!(
@@ -90,9 +95,7 @@ fn proper_initialization() {
             AssetInfo::NativeToken {
                 denom: "uusd".to_string(),
             },
-            AssetInfo::Token {
-                contract_addr: HumanAddr::from("asset0000")
-            }
+            token_asset_info("asset0000"),
         ]
     );
 }
@@ -107,40 +110,32 @@ fn provide_liquidity() {
         }],
     );
 
-    deps.querier.with_token_balances(&[(
-        &HumanAddr::from("liquidity0000"),
-        &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0))],
-    )]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0))],
+        ),
+    ]);
 
-    let msg = PairInitMsg {
-        asset_infos: [
+    init_pair(
+        &mut deps,
+        [
             AssetInfo::NativeToken {
                 denom: "uusd".to_string(),
             },
-            AssetInfo::Token {
-                contract_addr: HumanAddr::from("asset0000"),
-            },
+            token_asset_info("asset0000"),
         ],
-        token_code_id: 10u64,
-        init_hook: None,
-    };
-
-    let env = mock_env("addr0000", &[]);
-    // we can just call .unwrap() to assert this was a success
-    let _res = init(&mut deps, env, msg).unwrap();
-
-    // post initalize
-    let msg = HandleMsg::PostInitialize {};
-    let env = mock_env("liquidity0000", &[]);
-    let _res = handle(&mut deps, env, msg).unwrap();
+    );
 
     // successfully provide liquidity for the exist pool
     let msg = HandleMsg::ProvideLiquidity {
         assets: [
             Asset {
-                info: AssetInfo::Token {
-                    contract_addr: HumanAddr::from("asset0000".to_string()),
-                },
+                info: token_asset_info("asset0000"),
                 amount: Uint128::from(100u128),
             },
             Asset {
@@ -151,6 +146,8 @@ fn provide_liquidity() {
             },
         ],
         slippage_tolerance: None,
+        auto_stake: None,
+        receiver: None,
     };
 
     let env = mock_env(
@@ -161,32 +158,29 @@ fn provide_liquidity() {
         }],
     );
     let res = handle(&mut deps, env, msg).unwrap();
-    let transfer_from_msg = res.messages.get(0).expect("no message");
-    let mint_msg = res.messages.get(1).expect("no message");
-    assert_eq!(
-        transfer_from_msg,
-        &CosmosMsg::Wasm(WasmMsg::Execute {
-            contract_addr: HumanAddr::from("asset0000"),
-            msg: to_binary(&Cw20HandleMsg::TransferFrom {
-                owner: HumanAddr::from("addr0000"),
-                recipient: HumanAddr::from(MOCK_CONTRACT_ADDR),
-                amount: Uint128::from(100u128),
-            })
-            .unwrap(),
-            send: vec![],
-        })
-    );
     assert_eq!(
-        mint_msg,
-        &CosmosMsg::Wasm(WasmMsg::Execute {
-            contract_addr: HumanAddr::from("liquidity0000"),
-            msg: to_binary(&Cw20HandleMsg::Mint {
-                recipient: HumanAddr::from("addr0000"),
-                amount: Uint128::from(100u128),
-            })
+        res.messages,
+        vec![
+            snip20::transfer_from_msg(
+                HumanAddr::from("addr0000"),
+                HumanAddr::from(MOCK_CONTRACT_ADDR),
+                Uint128::from(100u128),
+                None,
+                256,
+                "tokenhash".to_string(),
+                HumanAddr::from("asset0000"),
+            )
             .unwrap(),
-            send: vec![],
-        })
+            snip20::mint_msg(
+                HumanAddr::from("addr0000"),
+                Uint128::from(100u128),
+                None,
+                256,
+                "lptokenhash".to_string(),
+                HumanAddr::from("liquidity0000"),
+            )
+            .unwrap(),
+        ]
     );
 
     // provide more liquidity 1:2, which is not propotional to 1:1,
@@ -213,9 +207,7 @@ fn provide_liquidity() {
     let msg = HandleMsg::ProvideLiquidity {
         assets: [
             Asset {
-                info: AssetInfo::Token {
-                    contract_addr: HumanAddr::from("asset0000".to_string()),
-                },
+                info: token_asset_info("asset0000"),
                 amount: Uint128::from(100u128),
             },
             Asset {
@@ -226,6 +218,8 @@ fn provide_liquidity() {
             },
         ],
         slippage_tolerance: None,
+        auto_stake: None,
+        receiver: None,
     };
 
     let env = mock_env_with_block_time(
@@ -239,65 +233,63 @@ fn provide_liquidity() {
 
     // only accept 100, then 50 share will be generated with 100 * (100 / 200)
     let res: HandleResponse = handle(&mut deps, env, msg).unwrap();
-    let transfer_from_msg = res.messages.get(0).expect("no message");
-    let mint_msg = res.messages.get(1).expect("no message");
-    assert_eq!(
-        transfer_from_msg,
-        &CosmosMsg::Wasm(WasmMsg::Execute {
-            contract_addr: HumanAddr::from("asset0000"),
-            msg: to_binary(&Cw20HandleMsg::TransferFrom {
-                owner: HumanAddr::from("addr0000"),
-                recipient: HumanAddr::from(MOCK_CONTRACT_ADDR),
-                amount: Uint128::from(100u128),
-            })
-            .unwrap(),
-            send: vec![],
-        })
-    );
     assert_eq!(
-        mint_msg,
-        &CosmosMsg::Wasm(WasmMsg::Execute {
-            contract_addr: HumanAddr::from("liquidity0000"),
-            msg: to_binary(&Cw20HandleMsg::Mint {
-                recipient: HumanAddr::from("addr0000"),
-                amount: Uint128::from(50u128),
-            })
+        res.messages,
+        vec![
+            snip20::transfer_from_msg(
+                HumanAddr::from("addr0000"),
+                HumanAddr::from(MOCK_CONTRACT_ADDR),
+                Uint128::from(100u128),
+                None,
+                256,
+                "tokenhash".to_string(),
+                HumanAddr::from("asset0000"),
+            )
             .unwrap(),
-            send: vec![],
-        })
+            snip20::mint_msg(
+                HumanAddr::from("addr0000"),
+                Uint128::from(50u128),
+                None,
+                256,
+                "lptokenhash".to_string(),
+                HumanAddr::from("liquidity0000"),
+            )
+            .unwrap(),
+        ]
     );
 
-    // check wrong argument
+    // check wrong argument, sending less than the argument is rejected (sending
+    // more is refunded, see native_overpayment_is_refunded)
     let msg = HandleMsg::ProvideLiquidity {
         assets: [
             Asset {
-                info: AssetInfo::Token {
-                    contract_addr: HumanAddr::from("asset0000".to_string()),
-                },
+                info: token_asset_info("asset0000"),
                 amount: Uint128::from(100u128),
             },
             Asset {
                 info: AssetInfo::NativeToken {
                     denom: "uusd".to_string(),
                 },
-                amount: Uint128::from(50u128),
+                amount: Uint128::from(100u128),
             },
         ],
         slippage_tolerance: None,
+        auto_stake: None,
+        receiver: None,
     };
 
     let env = mock_env(
         "addr0000",
         &[Coin {
             denom: "uusd".to_string(),
-            amount: Uint128::from(100u128),
+            amount: Uint128::from(50u128),
         }],
     );
     let res = handle(&mut deps, env, msg).unwrap_err();
     match res {
         StdError::GenericErr { msg, .. } => assert_eq!(
             msg,
-            "Native token balance missmatch between the argument and the transferred".to_string()
+            "funds_mismatch: Native token balance mismatch between the argument and the transferred"
         ),
         _ => panic!("Must return generic error"),
     }
@@ -326,9 +318,7 @@ fn provide_liquidity() {
     let msg = HandleMsg::ProvideLiquidity {
         assets: [
             Asset {
-                info: AssetInfo::Token {
-                    contract_addr: HumanAddr::from("asset0000".to_string()),
-                },
+                info: token_asset_info("asset0000"),
                 amount: Uint128::from(98u128),
             },
             Asset {
@@ -339,6 +329,8 @@ fn provide_liquidity() {
             },
         ],
         slippage_tolerance: Some(Decimal::percent(1)),
+        auto_stake: None,
+        receiver: None,
     };
 
     let env = mock_env_with_block_time(
@@ -351,9 +343,10 @@ fn provide_liquidity() {
     );
     let res = handle(&mut deps, env, msg).unwrap_err();
     match res {
-        StdError::GenericErr { msg, .. } => {
-            assert_eq!(msg, "Operation exceeds max splippage tolerance")
-        }
+        StdError::GenericErr { msg, .. } => assert_eq!(
+            msg,
+            "slippage_exceeded: Operation exceeds max slippage tolerance"
+        ),
         _ => panic!("DO NOT ENTER HERE"),
     }
 
@@ -370,9 +363,7 @@ fn provide_liquidity() {
     let msg = HandleMsg::ProvideLiquidity {
         assets: [
             Asset {
-                info: AssetInfo::Token {
-                    contract_addr: HumanAddr::from("asset0000".to_string()),
-                },
+                info: token_asset_info("asset0000"),
                 amount: Uint128::from(100u128),
             },
             Asset {
@@ -383,6 +374,8 @@ fn provide_liquidity() {
             },
         ],
         slippage_tolerance: Some(Decimal::percent(1)),
+        auto_stake: None,
+        receiver: None,
     };
 
     let env = mock_env_with_block_time(
@@ -395,9 +388,10 @@ fn provide_liquidity() {
     );
     let res = handle(&mut deps, env, msg).unwrap_err();
     match res {
-        StdError::GenericErr { msg, .. } => {
-            assert_eq!(msg, "Operation exceeds max splippage tolerance")
-        }
+        StdError::GenericErr { msg, .. } => assert_eq!(
+            msg,
+            "slippage_exceeded: Operation exceeds max slippage tolerance"
+        ),
         _ => panic!("DO NOT ENTER HERE"),
     }
 
@@ -414,9 +408,7 @@ fn provide_liquidity() {
     let msg = HandleMsg::ProvideLiquidity {
         assets: [
             Asset {
-                info: AssetInfo::Token {
-                    contract_addr: HumanAddr::from("asset0000".to_string()),
-                },
+                info: token_asset_info("asset0000"),
                 amount: Uint128::from(99u128),
             },
             Asset {
@@ -427,6 +419,8 @@ fn provide_liquidity() {
             },
         ],
         slippage_tolerance: Some(Decimal::percent(1)),
+        auto_stake: None,
+        receiver: None,
     };
 
     let env = mock_env_with_block_time(
@@ -452,9 +446,7 @@ fn provide_liquidity() {
     let msg = HandleMsg::ProvideLiquidity {
         assets: [
             Asset {
-                info: AssetInfo::Token {
-                    contract_addr: HumanAddr::from("asset0000".to_string()),
-                },
+                info: token_asset_info("asset0000"),
                 amount: Uint128::from(100u128),
             },
             Asset {
@@ -465,6 +457,8 @@ fn provide_liquidity() {
             },
         ],
         slippage_tolerance: Some(Decimal::percent(1)),
+        auto_stake: None,
+        receiver: None,
     };
 
     let env = mock_env_with_block_time(
@@ -488,10 +482,6 @@ fn withdraw_liquidity() {
         }],
     );
 
-    deps.querier.with_tax(
-        Decimal::zero(),
-        &[(&"uusd".to_string(), &Uint128::from(1000000u128))],
-    );
     deps.querier.with_token_balances(&[
         (
             &HumanAddr::from("liquidity0000"),
@@ -503,42 +493,49 @@ fn withdraw_liquidity() {
         ),
     ]);
 
-    let msg = PairInitMsg {
-        asset_infos: [
+    init_pair(
+        &mut deps,
+        [
             AssetInfo::NativeToken {
                 denom: "uusd".to_string(),
             },
-            AssetInfo::Token {
-                contract_addr: HumanAddr::from("asset0000"),
-            },
+            token_asset_info("asset0000"),
         ],
-        token_code_id: 10u64,
-        init_hook: None,
-    };
-
-    let env = mock_env("addr0000", &[]);
-    // we can just call .unwrap() to assert this was a success
-    let _res = init(&mut deps, env, msg).unwrap();
-
-    // post initalize
-    let msg = HandleMsg::PostInitialize {};
-    let env = mock_env("liquidity0000", &[]);
-    let _res = handle(&mut deps, env, msg).unwrap();
+    );
 
     // withdraw liquidity
-    let msg = HandleMsg::Receive(Cw20ReceiveMsg {
-        sender: HumanAddr::from("addr0000"),
-        msg: Some(to_binary(&Cw20HookMsg::WithdrawLiquidity {}).unwrap()),
+    let msg = HandleMsg::Receive {
+        from: HumanAddr::from("addr0000"),
+        msg: Some(
+            to_binary(&Cw20HookMsg::WithdrawLiquidity {
+                unwrap: false,
+                to: None,
+                to_code_hash: None,
+                to_msg: None,
+            })
+            .unwrap(),
+        ),
         amount: Uint128(100u128),
-    });
+    };
 
     let env = mock_env("liquidity0000", &[]);
     let res = handle(&mut deps, env, msg).unwrap();
     let log_withdrawn_share = res.log.get(1).expect("no log");
     let log_refund_assets = res.log.get(2).expect("no log");
-    let msg_refund_0 = res.messages.get(0).expect("no message");
-    let msg_refund_1 = res.messages.get(1).expect("no message");
-    let msg_burn_liquidity = res.messages.get(2).expect("no message");
+    let msg_burn_liquidity = res.messages.get(0).expect("no message");
+    let msg_refund_0 = res.messages.get(1).expect("no message");
+    let msg_refund_1 = res.messages.get(2).expect("no message");
+    assert_eq!(
+        msg_burn_liquidity,
+        &snip20::burn_msg(
+            Uint128::from(100u128),
+            None,
+            256,
+            "lptokenhash".to_string(),
+            HumanAddr::from("liquidity0000"),
+        )
+        .unwrap()
+    );
     assert_eq!(
         msg_refund_0,
         &CosmosMsg::Bank(BankMsg::Send {
@@ -552,26 +549,16 @@ fn withdraw_liquidity() {
     );
     assert_eq!(
         msg_refund_1,
-        &CosmosMsg::Wasm(WasmMsg::Execute {
-            contract_addr: HumanAddr::from("asset0000"),
-            msg: to_binary(&Cw20HandleMsg::Transfer {
-                recipient: HumanAddr::from("addr0000"),
-                amount: Uint128::from(100u128),
-            })
-            .unwrap(),
-            send: vec![],
-        })
-    );
-    assert_eq!(
-        msg_burn_liquidity,
-        &CosmosMsg::Wasm(WasmMsg::Execute {
-            contract_addr: HumanAddr::from("liquidity0000"),
-            msg: to_binary(&Cw20HandleMsg::Burn {
-                amount: Uint128::from(100u128),
-            })
-            .unwrap(),
-            send: vec![],
-        })
+        &Asset {
+            info: token_asset_info("asset0000"),
+            amount: Uint128::from(100u128),
+        }
+        .into_msg(
+            &deps,
+            HumanAddr::from(MOCK_CONTRACT_ADDR),
+            HumanAddr::from("addr0000"),
+        )
+        .unwrap()
     );
 
     assert_eq!(
@@ -586,11 +573,10 @@ fn withdraw_liquidity() {
 
 #[test]
 fn try_native_to_token() {
+    PINNED_RANDOM_NUMBER.with(|pinned| pinned.set(Some(0)));
     let total_share = Uint128(30000000000u128);
     let asset_pool_amount = Uint128(20000000000u128);
     let collateral_pool_amount = Uint128(30000000000u128);
-    let price = Decimal::from_ratio(collateral_pool_amount, asset_pool_amount);
-    let exchange_rate = reverse_decimal(price);
     let offer_amount = Uint128(1500000000u128);
 
     let mut deps = mock_dependencies(
@@ -602,11 +588,6 @@ fn try_native_to_token() {
         }],
     );
 
-    deps.querier.with_tax(
-        Decimal::zero(),
-        &[(&"uusd".to_string(), &Uint128::from(1000000u128))],
-    );
-
     deps.querier.with_token_balances(&[
         (
             &HumanAddr::from("liquidity0000"),
@@ -618,27 +599,15 @@ fn try_native_to_token() {
         ),
     ]);
 
-    let msg = PairInitMsg {
-        asset_infos: [
+    init_pair(
+        &mut deps,
+        [
             AssetInfo::NativeToken {
                 denom: "uusd".to_string(),
             },
-            AssetInfo::Token {
-                contract_addr: HumanAddr::from("asset0000"),
-            },
+            token_asset_info("asset0000"),
         ],
-        token_code_id: 10u64,
-        init_hook: None,
-    };
-
-    let env = mock_env("addr0000", &[]);
-    // we can just call .unwrap() to assert this was a success
-    let _res = init(&mut deps, env, msg).unwrap();
-
-    // post initalize
-    let msg = HandleMsg::PostInitialize {};
-    let env = mock_env("liquidity0000", &[]);
-    let _res = handle(&mut deps, env, msg).unwrap();
+    );
 
     // normal swap
     let msg = HandleMsg::Swap {
@@ -648,9 +617,13 @@ fn try_native_to_token() {
             },
             amount: offer_amount,
         },
+        expected_return: None,
         belief_price: None,
         max_spread: None,
+        max_price_impact: None,
+        batch: false,
         to: None,
+        unwrap: false,
     };
     let env = mock_env_with_block_time(
         "addr0000",
@@ -665,12 +638,12 @@ fn try_native_to_token() {
     let msg_transfer = res.messages.get(0).expect("no message");
 
     // current price is 1.5, so expected return without spread is 1000
-    // 952.380953 = 20000 - 20000 * 30000 / (30000 + 1500)
-    let expected_ret_amount = Uint128(952_380_953u128);
-    let expected_spread_amount = (offer_amount * exchange_rate - expected_ret_amount).unwrap();
-    let expected_commission_amount = expected_ret_amount.multiply_ratio(3u128, 1000u128); // 0.3%
+    // 952.380952 = 20000 - ceil(20000 * 30000 / (30000 + 1500)), the return floors
+    let expected_ret_amount = Uint128(952_380_952u128);
+    let expected_spread_amount = (Uint128(1_000_000_000u128) - expected_ret_amount).unwrap();
+    // 0.3%, rounded up as it stays in the pool
+    let expected_commission_amount = Uint128(2_857_143u128);
     let expected_return_amount = (expected_ret_amount - expected_commission_amount).unwrap();
-    let expected_tax_amount = Uint128::zero(); // no tax for token
 
     // check simulation res
     deps.querier.with_balance(&[(
@@ -682,7 +655,7 @@ fn try_native_to_token() {
         }],
     )]);
 
-    let simulation_res: SimulationResponse = query_simulation(
+    let simulation_res = query_simulation(
         &deps,
         Asset {
             info: AssetInfo::NativeToken {
@@ -690,42 +663,28 @@ fn try_native_to_token() {
             },
             amount: offer_amount,
         },
+        None,
     )
     .unwrap();
     assert_eq!(expected_return_amount, simulation_res.return_amount);
     assert_eq!(expected_commission_amount, simulation_res.commission_amount);
     assert_eq!(expected_spread_amount, simulation_res.spread_amount);
 
-    // check reverse simulation res
-    let reverse_simulation_res: ReverseSimulationResponse = query_reverse_simulation(
+    // check reverse simulation res, the offer rounds up so it buys the return exactly
+    let reverse_simulation_res = query_reverse_simulation(
         &deps,
         Asset {
-            info: AssetInfo::Token {
-                contract_addr: HumanAddr::from("asset0000"),
-            },
+            info: token_asset_info("asset0000"),
             amount: expected_return_amount,
         },
     )
     .unwrap();
+    assert_eq!(offer_amount, reverse_simulation_res.offer_amount);
     assert_eq!(
-        (offer_amount.u128() as i128 - reverse_simulation_res.offer_amount.u128() as i128).abs()
-            < 5i128,
-        true
-    );
-    assert_eq!(
-        (expected_commission_amount.u128() as i128
-            - reverse_simulation_res.commission_amount.u128() as i128)
-            .abs()
-            < 5i128,
-        true
-    );
-    assert_eq!(
-        (expected_spread_amount.u128() as i128
-            - reverse_simulation_res.spread_amount.u128() as i128)
-            .abs()
-            < 5i128,
-        true
+        expected_commission_amount,
+        reverse_simulation_res.commission_amount
     );
+    assert_eq!(expected_spread_amount, reverse_simulation_res.spread_amount);
 
     assert_eq!(
         res.log,
@@ -735,33 +694,32 @@ fn try_native_to_token() {
             log("ask_asset", "asset0000"),
             log("offer_amount", offer_amount.to_string()),
             log("return_amount", expected_return_amount.to_string()),
-            log("tax_amount", expected_tax_amount.to_string()),
             log("spread_amount", expected_spread_amount.to_string()),
             log("commission_amount", expected_commission_amount.to_string()),
         ]
     );
 
     assert_eq!(
-        &CosmosMsg::Wasm(WasmMsg::Execute {
-            contract_addr: HumanAddr::from("asset0000"),
-            msg: to_binary(&Cw20HandleMsg::Transfer {
-                recipient: HumanAddr::from("addr0000"),
-                amount: Uint128::from(expected_return_amount),
-            })
-            .unwrap(),
-            send: vec![],
-        }),
+        &Asset {
+            info: token_asset_info("asset0000"),
+            amount: expected_return_amount,
+        }
+        .into_msg(
+            &deps,
+            HumanAddr::from(MOCK_CONTRACT_ADDR),
+            HumanAddr::from("addr0000"),
+        )
+        .unwrap(),
         msg_transfer,
     );
 }
 
 #[test]
 fn try_token_to_native() {
+    PINNED_RANDOM_NUMBER.with(|pinned| pinned.set(Some(0)));
     let total_share = Uint128(20000000000u128);
     let asset_pool_amount = Uint128(30000000000u128);
     let collateral_pool_amount = Uint128(20000000000u128);
-    let price = Decimal::from_ratio(collateral_pool_amount, asset_pool_amount);
-    let exchange_rate = decimal_multiplication(price, Decimal::one());
     let offer_amount = Uint128(1500000000u128);
 
     let mut deps = mock_dependencies(
@@ -771,10 +729,6 @@ fn try_token_to_native() {
             amount: collateral_pool_amount,
         }],
     );
-    deps.querier.with_tax(
-        Decimal::percent(1),
-        &[(&"uusd".to_string(), &Uint128::from(1000000u128))],
-    );
     deps.querier.with_token_balances(&[
         (
             &HumanAddr::from("liquidity0000"),
@@ -789,39 +743,29 @@ fn try_token_to_native() {
         ),
     ]);
 
-    let msg = PairInitMsg {
-        asset_infos: [
+    init_pair(
+        &mut deps,
+        [
             AssetInfo::NativeToken {
                 denom: "uusd".to_string(),
             },
-            AssetInfo::Token {
-                contract_addr: HumanAddr::from("asset0000"),
-            },
+            token_asset_info("asset0000"),
         ],
-        token_code_id: 10u64,
-        init_hook: None,
-    };
-
-    let env = mock_env("addr0000", &[]);
-    // we can just call .unwrap() to assert this was a success
-    let _res = init(&mut deps, env, msg).unwrap();
-
-    // post initalize
-    let msg = HandleMsg::PostInitialize {};
-    let env = mock_env("liquidity0000", &[]);
-    let _res = handle(&mut deps, env, msg).unwrap();
+    );
 
     // unauthorized access; can not execute swap directy for token swap
     let msg = HandleMsg::Swap {
         offer_asset: Asset {
-            info: AssetInfo::Token {
-                contract_addr: HumanAddr::from("asset0000"),
-            },
+            info: token_asset_info("asset0000"),
             amount: offer_amount,
         },
+        expected_return: None,
         belief_price: None,
         max_spread: None,
+        max_price_impact: None,
+        batch: false,
         to: None,
+        unwrap: false,
     };
     let env = mock_env_with_block_time("addr0000", &[], 1000);
     let res = handle(&mut deps, env, msg).unwrap_err();
@@ -831,35 +775,18 @@ fn try_token_to_native() {
     }
 
     // normal sell
-    let msg = HandleMsg::Receive(Cw20ReceiveMsg {
-        sender: HumanAddr::from("addr0000"),
-        amount: offer_amount,
-        msg: Some(
-            to_binary(&Cw20HookMsg::Swap {
-                belief_price: None,
-                max_spread: None,
-                to: None,
-            })
-            .unwrap(),
-        ),
-    });
     let env = mock_env_with_block_time("asset0000", &[], 1000);
-
-    let res = handle(&mut deps, env, msg).unwrap();
+    let res = handle(&mut deps, env, swap_msg(offer_amount)).unwrap();
     let msg_transfer = res.messages.get(0).expect("no message");
 
     // current price is 1.5, so expected return without spread is 1000
-    // 952.380953 = 20000 - 20000 * 30000 / (30000 + 1500)
-    let expected_ret_amount = Uint128(952_380_953u128);
-    let expected_spread_amount = (offer_amount * exchange_rate - expected_ret_amount).unwrap();
-    let expected_commission_amount = expected_ret_amount.multiply_ratio(3u128, 1000u128); // 0.3%
+    // 952.380952 = 20000 - ceil(20000 * 30000 / (30000 + 1500)), the return floors
+    let expected_ret_amount = Uint128(952_380_952u128);
+    let expected_spread_amount = (Uint128(1_000_000_000u128) - expected_ret_amount).unwrap();
+    // 0.3%, rounded up as it stays in the pool
+    let expected_commission_amount = Uint128(2_857_143u128);
     let expected_return_amount = (expected_ret_amount - expected_commission_amount).unwrap();
-    let expected_tax_amount = std::cmp::min(
-        Uint128(1000000u128),
-        (expected_return_amount
-            - expected_return_amount.multiply_ratio(Uint128(100u128), Uint128(101u128)))
-        .unwrap(),
-    );
+
     // check simulation res
     // return asset token balance as normal
     deps.querier.with_token_balances(&[
@@ -873,22 +800,21 @@ fn try_token_to_native() {
         ),
     ]);
 
-    let simulation_res: SimulationResponse = query_simulation(
+    let simulation_res = query_simulation(
         &deps,
         Asset {
             amount: offer_amount,
-            info: AssetInfo::Token {
-                contract_addr: HumanAddr::from("asset0000"),
-            },
+            info: token_asset_info("asset0000"),
         },
+        None,
     )
     .unwrap();
     assert_eq!(expected_return_amount, simulation_res.return_amount);
     assert_eq!(expected_commission_amount, simulation_res.commission_amount);
     assert_eq!(expected_spread_amount, simulation_res.spread_amount);
 
-    // check reverse simulation res
-    let reverse_simulation_res: ReverseSimulationResponse = query_reverse_simulation(
+    // check reverse simulation res, the offer rounds up so it buys the return exactly
+    let reverse_simulation_res = query_reverse_simulation(
         &deps,
         Asset {
             amount: expected_return_amount,
@@ -898,25 +824,12 @@ fn try_token_to_native() {
         },
     )
     .unwrap();
+    assert_eq!(offer_amount, reverse_simulation_res.offer_amount);
     assert_eq!(
-        (offer_amount.u128() as i128 - reverse_simulation_res.offer_amount.u128() as i128).abs()
-            < 5i128,
-        true
-    );
-    assert_eq!(
-        (expected_commission_amount.u128() as i128
-            - reverse_simulation_res.commission_amount.u128() as i128)
-            .abs()
-            < 5i128,
-        true
-    );
-    assert_eq!(
-        (expected_spread_amount.u128() as i128
-            - reverse_simulation_res.spread_amount.u128() as i128)
-            .abs()
-            < 5i128,
-        true
+        expected_commission_amount,
+        reverse_simulation_res.commission_amount
     );
+    assert_eq!(expected_spread_amount, reverse_simulation_res.spread_amount);
 
     assert_eq!(
         res.log,
@@ -926,39 +839,27 @@ fn try_token_to_native() {
             log("ask_asset", "uusd"),
             log("offer_amount", offer_amount.to_string()),
             log("return_amount", expected_return_amount.to_string()),
-            log("tax_amount", expected_tax_amount.to_string()),
             log("spread_amount", expected_spread_amount.to_string()),
             log("commission_amount", expected_commission_amount.to_string()),
         ]
     );
 
+    // no tax on Secret, the whole return is sent
     assert_eq!(
         &CosmosMsg::Bank(BankMsg::Send {
             from_address: HumanAddr::from(MOCK_CONTRACT_ADDR),
             to_address: HumanAddr::from("addr0000"),
             amount: vec![Coin {
                 denom: "uusd".to_string(),
-                amount: (expected_return_amount - expected_tax_amount).unwrap(),
+                amount: expected_return_amount,
             }],
         }),
         msg_transfer,
     );
 
     // failed due to non asset token contract try to execute sell
-    let msg = HandleMsg::Receive(Cw20ReceiveMsg {
-        sender: HumanAddr::from("addr0000"),
-        amount: offer_amount,
-        msg: Some(
-            to_binary(&Cw20HookMsg::Swap {
-                belief_price: None,
-                max_spread: None,
-                to: None,
-            })
-            .unwrap(),
-        ),
-    });
     let env = mock_env_with_block_time("liquidtity0000", &[], 1000);
-    let res = handle(&mut deps, env, msg).unwrap_err();
+    let res = handle(&mut deps, env, swap_msg(offer_amount)).unwrap_err();
     match res {
         StdError::Unauthorized { .. } => (),
         _ => panic!("DO NOT ENTER HERE"),
@@ -970,26 +871,32 @@ fn test_max_spread() {
     assert_max_spread(
         Some(Decimal::from_ratio(1200u128, 1u128)),
         Some(Decimal::percent(1)),
+        None,
         Uint128::from(1200000000u128),
         Uint128::from(989999u128),
         Uint128::zero(),
+        Uint128::zero(),
     )
     .unwrap_err();
 
     assert_max_spread(
         Some(Decimal::from_ratio(1200u128, 1u128)),
         Some(Decimal::percent(1)),
+        None,
         Uint128::from(1200000000u128),
         Uint128::from(990000u128),
         Uint128::zero(),
+        Uint128::zero(),
     )
     .unwrap();
 
     assert_max_spread(
         None,
         Some(Decimal::percent(1)),
+        None,
         Uint128::zero(),
         Uint128::from(989999u128),
+        Uint128::zero(),
         Uint128::from(10001u128),
     )
     .unwrap_err();
@@ -997,8 +904,10 @@ fn test_max_spread() {
     assert_max_spread(
         None,
         Some(Decimal::percent(1)),
+        None,
         Uint128::zero(),
         Uint128::from(990000u128),
+        Uint128::zero(),
         Uint128::from(10000u128),
     )
     .unwrap();
@@ -1035,6 +944,7 @@ fn test_deduct() {
 
 #[test]
 fn test_query_pool() {
+    PINNED_RANDOM_NUMBER.with(|pinned| pinned.set(Some(0)));
     let total_share_amount = Uint128::from(111u128);
     let asset_0_amount = Uint128::from(222u128);
     let asset_1_amount = Uint128::from(333u128);
@@ -1057,29 +967,18 @@ fn test_query_pool() {
         ),
     ]);
 
-    let msg = PairInitMsg {
-        asset_infos: [
+    init_pair(
+        &mut deps,
+        [
             AssetInfo::NativeToken {
                 denom: "uusd".to_string(),
             },
-            AssetInfo::Token {
-                contract_addr: HumanAddr::from("asset0000"),
-            },
+            token_asset_info("asset0000"),
         ],
-        token_code_id: 10u64,
-        init_hook: None,
-    };
+    );
 
-    let env = mock_env("addr0000", &[]);
-    // we can just call .unwrap() to assert this was a success
-    let _res = init(&mut deps, env, msg).unwrap();
-
-    // post initalize
-    let msg = HandleMsg::PostInitialize {};
-    let env = mock_env("liquidity0000", &[]);
-    let _res = handle(&mut deps, env, msg).unwrap();
-
-    let res: PoolResponse = query_pool(&deps).unwrap();
+    // the pinned random number leaves the amounts without noise
+    let res: PoolResponse = query_pool(&deps).unwrap();
 
     assert_eq!(
         res.assets,
@@ -1091,9 +990,7 @@ fn test_query_pool() {
                 amount: asset_0_amount,
             },
             Asset {
-                info: AssetInfo::Token {
-                    contract_addr: HumanAddr::from("asset0000"),
-                },
+                info: token_asset_info("asset0000"),
                 amount: asset_1_amount,
             }
         ]
@@ -1104,12 +1001,7365 @@ fn test_query_pool() {
 fn mock_env_with_block_time<U: Into<HumanAddr>>(sender: U, sent: &[Coin], time: u64) -> Env {
     let env = mock_env(sender, sent);
     // register time
-    return Env {
+    Env {
         block: BlockInfo {
             height: 1,
             time,
             chain_id: "columbus".to_string(),
         },
         ..env
+    }
+}
+
+#[test]
+fn add_entropy() {
+    let mut deps = mock_dependencies(20, &[]);
+
+    let noise_before = get_random_number(&deps.storage);
+
+    let msg = HandleMsg::AddEntropy {
+        entropy: Binary::from(b"some random bytes".to_vec()),
+    };
+    let env = mock_env_with_block_time("addr0000", &[], 1000);
+    let res = handle(&mut deps, env, msg).unwrap();
+    assert_eq!(res.messages.len(), 0);
+    assert_eq!(res.log.len(), 0);
+
+    let noise_after = get_random_number(&deps.storage);
+    assert_ne!(noise_before, noise_after);
+
+    // same donation from another sender still changes the pool
+    let msg = HandleMsg::AddEntropy {
+        entropy: Binary::from(b"some random bytes".to_vec()),
+    };
+    let env = mock_env_with_block_time("addr0001", &[], 1000);
+    let _res = handle(&mut deps, env, msg).unwrap();
+    assert_ne!(noise_after, get_random_number(&deps.storage));
+
+    // oversized donations are rejected
+    let msg = HandleMsg::AddEntropy {
+        entropy: Binary::from(vec![7u8; 257]),
+    };
+    let env = mock_env_with_block_time("addr0000", &[], 1000);
+    let res = handle(&mut deps, env, msg).unwrap_err();
+    match res {
+        StdError::GenericErr { msg, .. } => {
+            assert_eq!(
+                msg,
+                "invalid_parameter: Entropy donation must not exceed 256 bytes"
+            )
+        }
+        _ => panic!("DO NOT ENTER HERE"),
+    }
+}
+
+fn token_asset_info(contract_addr: &str) -> AssetInfo {
+    AssetInfo::Token {
+        contract_addr: HumanAddr::from(contract_addr),
+        token_code_hash: "tokenhash".to_string(),
+        viewing_key: "".to_string(),
+    }
+}
+
+/// Initializes an asset0000/asset0001 pair with liquidity0000 as its LP token
+fn init_token_pair<S: Storage, A: Api>(deps: &mut Extern<S, A, WasmMockQuerier>) {
+    init_pair(
+        deps,
+        [token_asset_info("asset0000"), token_asset_info("asset0001")],
+    );
+}
+
+/// Initializes a pair of the given assets with liquidity0000 as its LP token
+fn init_pair<S: Storage, A: Api>(
+    deps: &mut Extern<S, A, WasmMockQuerier>,
+    asset_infos: [AssetInfo; 2],
+) {
+    init_pair_with(deps, pair_init_msg(asset_infos));
+}
+
+fn pair_init_msg(asset_infos: [AssetInfo; 2]) -> PairInitMsg {
+    PairInitMsg {
+        asset_infos,
+        token_code_id: 10u64,
+        token_code_hash: "lptokenhash".to_string(),
+        init_hook: Some(InitHook {
+            msg: to_binary(&Uint128(1000000u128)).unwrap(),
+            contract_addr: HumanAddr::from("factory0000"),
+            code_hash: "factoryhash".to_string(),
+        }),
+        prng_seed: Binary::from(b"seed".to_vec()),
+        settings_ttl_blocks: None,
+        wrap_config: None,
+        lp_token_name: None,
+        lp_token_symbol: None,
+        lp_token_decimals: None,
+        generic_label: false,
+        admin: None,
+        expected_factory: None,
+        existing_liquidity_token: None,
+        restricted: false,
+        curve: None,
+        weights: None,
+    }
+}
+
+/// Runs init and PostInitialize from liquidity0000
+fn init_pair_with<S: Storage, A: Api>(deps: &mut Extern<S, A, WasmMockQuerier>, msg: PairInitMsg) {
+    let env = mock_env("addr0000", &[]);
+    let res = init(deps, env, msg).unwrap();
+    mock_lp_token(deps, &res.messages, "liquidity0000");
+
+    // post initalize
+    let msg = HandleMsg::PostInitialize {};
+    let env = mock_env("liquidity0000", &[]);
+    let _res = handle(deps, env, msg).unwrap();
+}
+
+/// Makes `lp_token` answer token_info like the LP token instantiated by `messages`
+fn mock_lp_token<S: Storage, A: Api>(
+    deps: &mut Extern<S, A, WasmMockQuerier>,
+    messages: &[CosmosMsg],
+    lp_token: &str,
+) {
+    let token_init_msg: TokenInitMsg = messages
+        .iter()
+        .find_map(|msg| match msg {
+            CosmosMsg::Wasm(WasmMsg::Instantiate { msg, .. }) => from_binary(msg).ok(),
+            _ => None,
+        })
+        .expect("no LP token instantiated");
+    deps.querier.with_token_info(
+        &HumanAddr::from(lp_token),
+        &token_init_msg.name,
+        &token_init_msg.symbol,
+        token_init_msg.decimals,
+    );
+}
+
+#[test]
+fn withdraw_liquidity_burns_before_refunds() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(100u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(100u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(200u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    let msg = HandleMsg::Receive {
+        from: HumanAddr::from("addr0000"),
+        msg: Some(
+            to_binary(&Cw20HookMsg::WithdrawLiquidity {
+                unwrap: false,
+                to: None,
+                to_code_hash: None,
+                to_msg: None,
+            })
+            .unwrap(),
+        ),
+        amount: Uint128(100u128),
+    };
+    let env = mock_env("liquidity0000", &[]);
+    let res = handle(&mut deps, env, msg).unwrap();
+
+    assert_eq!(
+        res.messages,
+        vec![
+            snip20::burn_msg(
+                Uint128(100u128),
+                None,
+                256,
+                "lptokenhash".to_string(),
+                HumanAddr::from("liquidity0000"),
+            )
+            .unwrap(),
+            Asset {
+                info: token_asset_info("asset0000"),
+                amount: Uint128(100u128),
+            }
+            .into_msg(
+                &deps,
+                HumanAddr::from(MOCK_CONTRACT_ADDR),
+                HumanAddr::from("addr0000"),
+            )
+            .unwrap(),
+            Asset {
+                info: token_asset_info("asset0001"),
+                amount: Uint128(200u128),
+            }
+            .into_msg(
+                &deps,
+                HumanAddr::from(MOCK_CONTRACT_ADDR),
+                HumanAddr::from("addr0000"),
+            )
+            .unwrap(),
+        ]
+    );
+
+    // a malicious refund token re-entering the pair from its hook can't withdraw again
+    let msg = HandleMsg::Receive {
+        from: HumanAddr::from("addr0000"),
+        msg: Some(
+            to_binary(&Cw20HookMsg::WithdrawLiquidity {
+                unwrap: false,
+                to: None,
+                to_code_hash: None,
+                to_msg: None,
+            })
+            .unwrap(),
+        ),
+        amount: Uint128(100u128),
+    };
+    let env = mock_env("asset0000", &[]);
+    let res = handle(&mut deps, env, msg).unwrap_err();
+    match res {
+        StdError::Unauthorized { .. } => (),
+        _ => panic!("DO NOT ENTER HERE"),
+    }
+}
+
+fn mock_env_with_block_height<U: Into<HumanAddr>>(sender: U, sent: &[Coin], height: u64) -> Env {
+    let env = mock_env(sender, sent);
+    Env {
+        block: BlockInfo {
+            height,
+            ..env.block
+        },
+        ..env
+    }
+}
+
+fn swap_msg(offer_amount: Uint128) -> HandleMsg {
+    HandleMsg::Receive {
+        from: HumanAddr::from("addr0000"),
+        amount: offer_amount,
+        msg: Some(
+            to_binary(&Cw20HookMsg::Swap {
+                expected_return: None,
+                belief_price: None,
+                max_spread: None,
+                max_price_impact: None,
+                batch: false,
+                to: None,
+                unwrap: false,
+                to_code_hash: None,
+                to_msg: None,
+                ask_asset_info: None,
+            })
+            .unwrap(),
+        ),
+    }
+}
+
+fn commission_log(res: &HandleResponse) -> String {
+    res.log
+        .iter()
+        .find(|l| l.key == "commission_amount")
+        .expect("no commission log")
+        .value
+        .clone()
+}
+
+#[test]
+fn settings_cache() {
+    let offer_amount = Uint128(1000u128);
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1000000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &(Uint128(1000000u128) + offer_amount),
+            )],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    // no cache and the factory is down, so swaps can't be priced
+    deps.querier.with_factory_unavailable();
+    let env = mock_env_with_block_height("asset0000", &[], 100);
+    handle(&mut deps, env, swap_msg(offer_amount)).unwrap_err();
+
+    // first swap fills the cache (0.3%)
+    deps.querier.with_pair_settings(test_settings());
+    let env = mock_env_with_block_height("asset0000", &[], 100);
+    let res = handle(&mut deps, env, swap_msg(offer_amount)).unwrap();
+    assert_eq!(commission_log(&res), "3");
+
+    // a fresh cache is used even though the factory changed its fee to 1%
+    deps.querier.with_pair_settings(PairSettings {
+        swap_fee: Fee {
+            commission_rate_nom: Uint128(1),
+            commission_rate_denom: Uint128(100),
+            ..test_settings().swap_fee
+        },
+        ..test_settings()
+    });
+    let env = mock_env_with_block_height("asset0000", &[], 150);
+    let res = handle(&mut deps, env, swap_msg(offer_amount)).unwrap();
+    assert_eq!(commission_log(&res), "3");
+
+    // a stale cache older than MAX_SETTINGS_AGE_BLOCKS is no fallback when the
+    // factory is down, it could hide a pause
+    deps.querier.with_factory_unavailable();
+    let env = mock_env_with_block_height("asset0000", &[], 1000);
+    handle(&mut deps, env, swap_msg(offer_amount)).unwrap_err();
+
+    // only the factory can push settings
+    let settings = PairSettings {
+        swap_fee: Fee {
+            commission_rate_nom: Uint128(1),
+            commission_rate_denom: Uint128(100),
+            ..test_settings().swap_fee
+        },
+        ..test_settings()
+    };
+    let env = mock_env_with_block_height("addr0000", &[], 1000);
+    let res = handle(
+        &mut deps,
+        env,
+        HandleMsg::PushSettings {
+            settings: settings.clone(),
+        },
+    )
+    .unwrap_err();
+    match res {
+        StdError::Unauthorized { .. } => (),
+        _ => panic!("DO NOT ENTER HERE"),
+    }
+
+    let env = mock_env_with_block_height("factory0000", &[], 1000);
+    let _res = handle(&mut deps, env, HandleMsg::PushSettings { settings }).unwrap();
+
+    let env = mock_env_with_block_height("asset0000", &[], 1001);
+    let res = handle(&mut deps, env, swap_msg(offer_amount)).unwrap();
+    assert_eq!(commission_log(&res), "10");
+
+    // a manual refresh can't succeed while the factory is down
+    let env = mock_env_with_block_height("addr0000", &[], 1001);
+    handle(&mut deps, env, HandleMsg::RefreshSettings {}).unwrap_err();
+}
+
+#[test]
+fn factory_outage_falls_back_to_cached_settings() {
+    PINNED_RANDOM_NUMBER.with(|pinned| pinned.set(Some(0)));
+    let offer_amount = Uint128(1000u128);
+    let mut deps = mock_dependencies(20, &[]);
+    with_offer_in_pool(&mut deps, offer_amount.u128());
+    init_pair_with(
+        &mut deps,
+        PairInitMsg {
+            settings_ttl_blocks: Some(10),
+            ..pair_init_msg([token_asset_info("asset0000"), token_asset_info("asset0001")])
+        },
+    );
+    let offer_asset = Asset {
+        info: token_asset_info("asset0000"),
+        amount: offer_amount,
+    };
+    let ask_asset = Asset {
+        info: token_asset_info("asset0001"),
+        amount: Uint128(900u128),
+    };
+    let warning = log(
+        "warning",
+        "the factory is unavailable, swapped on cached settings",
+    );
+
+    // nothing is cached yet, so quotes fail with the factory
+    deps.querier.with_factory_unavailable();
+    query_simulation(&deps, offer_asset.clone(), None).unwrap_err();
+    query_reverse_simulation(&deps, ask_asset.clone()).unwrap_err();
+
+    // a swap caches the factory settings
+    deps.querier.with_pair_settings(test_settings());
+    let simulation = query_simulation(&deps, offer_asset.clone(), None).unwrap();
+    let reverse_simulation = query_reverse_simulation(&deps, ask_asset.clone()).unwrap();
+    let env = mock_env_with_block_height("asset0000", &[], 20_000);
+    let res = handle(&mut deps, env, swap_msg(offer_amount)).unwrap();
+    assert_eq!(commission_log(&res), "3");
+    assert!(!res.log.contains(&warning));
+
+    // past the TTL the factory is down, swaps and quotes run on the cache
+    deps.querier.with_factory_unavailable();
+    let env = mock_env_with_block_height("asset0000", &[], 20_050);
+    let res = handle(&mut deps, env, swap_msg(offer_amount)).unwrap();
+    assert_eq!(commission_log(&res), "3");
+    assert!(res.log.contains(&warning));
+    assert_eq!(
+        query_simulation(&deps, offer_asset.clone(), None).unwrap(),
+        simulation
+    );
+    assert_eq!(
+        query_reverse_simulation(&deps, ask_asset.clone()).unwrap(),
+        reverse_simulation
+    );
+
+    // swaps stop at MAX_SETTINGS_AGE_BLOCKS so a pause can't be missed, quotes go on
+    let env = mock_env_with_block_height("asset0000", &[], 20_000 + MAX_SETTINGS_AGE_BLOCKS);
+    handle(&mut deps, env, swap_msg(offer_amount)).unwrap_err();
+    assert_eq!(
+        query_simulation(&deps, offer_asset, None).unwrap(),
+        simulation
+    );
+}
+
+#[test]
+fn factory_outage_falls_back_on_the_default_ttl() {
+    PINNED_RANDOM_NUMBER.with(|pinned| pinned.set(Some(0)));
+    let offer_amount = Uint128(1000u128);
+    let mut deps = mock_dependencies(20, &[]);
+    with_offer_in_pool(&mut deps, offer_amount.u128());
+    // no settings_ttl_blocks, like the pairs the factory creates
+    init_token_pair(&mut deps);
+    let warning = log(
+        "warning",
+        "the factory is unavailable, swapped on cached settings",
+    );
+
+    let env = mock_env_with_block_height("asset0000", &[], 20_000);
+    handle(&mut deps, env, swap_msg(offer_amount)).unwrap();
+
+    // the cache is stale once the TTL is over, but still a fallback
+    deps.querier.with_factory_unavailable();
+    let height = 20_000 + DEFAULT_SETTINGS_TTL_BLOCKS;
+    let env = mock_env_with_block_height("asset0000", &[], height);
+    let res = handle(&mut deps, env, swap_msg(offer_amount)).unwrap();
+    assert_eq!(commission_log(&res), "3");
+    assert!(res.log.contains(&warning));
+
+    let height = 20_000 + MAX_SETTINGS_AGE_BLOCKS - 1;
+    let env = mock_env_with_block_height("asset0000", &[], height);
+    let res = handle(&mut deps, env, swap_msg(offer_amount)).unwrap();
+    assert!(res.log.contains(&warning));
+
+    let height = 20_000 + MAX_SETTINGS_AGE_BLOCKS;
+    let env = mock_env_with_block_height("asset0000", &[], height);
+    handle(&mut deps, env, swap_msg(offer_amount)).unwrap_err();
+}
+
+#[test]
+fn pair_query_reports_volumes() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1000000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1001000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000500u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    let pair_info: PairInfo = query_pair_info(&deps).unwrap();
+    assert_eq!(pair_info.asset0_volume, Uint128::zero());
+    assert_eq!(pair_info.asset1_volume, Uint128::zero());
+
+    let env = mock_env("asset0000", &[]);
+    let _res = handle(&mut deps, env, swap_msg(Uint128(1000u128))).unwrap();
+    let env = mock_env("asset0000", &[]);
+    let _res = handle(&mut deps, env, swap_msg(Uint128(1000u128))).unwrap();
+    let env = mock_env("asset0001", &[]);
+    let _res = handle(&mut deps, env, swap_msg(Uint128(500u128))).unwrap();
+
+    let pair_info: PairInfo = query_pair_info(&deps).unwrap();
+    assert_eq!(pair_info.asset0_volume, Uint128(2000u128));
+    assert_eq!(pair_info.asset1_volume, Uint128(500u128));
+}
+
+#[test]
+fn migrate_legacy_pair_info() {
+    let mut deps = mock_dependencies(20, &[]);
+
+    let legacy = PairInfoRaw {
+        asset_infos: [
+            token_asset_info("asset0000").to_raw(&deps).unwrap(),
+            token_asset_info("asset0001").to_raw(&deps).unwrap(),
+        ],
+        contract_addr: deps
+            .api
+            .canonical_address(&HumanAddr::from(MOCK_CONTRACT_ADDR))
+            .unwrap(),
+        liquidity_token: deps
+            .api
+            .canonical_address(&HumanAddr::from("liquidity0000"))
+            .unwrap(),
+        token_code_hash: "lptokenhash".to_string(),
+        asset0_volume: Uint128(123u128),
+        asset1_volume: Uint128(456u128),
+        factory: Factory {
+            address: HumanAddr::from("factory0000"),
+            code_hash: "factoryhash".to_string(),
+        },
+    };
+    Singleton::new(&mut deps.storage, b"pair_info")
+        .save(&legacy)
+        .unwrap();
+
+    // the legacy layout is readable before migrating
+    assert_eq!(read_pair_info(&deps.storage).unwrap(), legacy);
+
+    // any handle migrates it
+    let msg = HandleMsg::AddEntropy {
+        entropy: Binary::from(b"x".to_vec()),
+    };
+    let env = mock_env("addr0000", &[]);
+    let _res = handle(&mut deps, env, msg.clone()).unwrap();
+
+    let legacy_left: Option<PairInfoRaw> = ReadonlySingleton::new(&deps.storage, b"pair_info")
+        .may_load()
+        .unwrap();
+    assert_eq!(legacy_left, None);
+    assert_eq!(read_pair_info(&deps.storage).unwrap(), legacy);
+    assert_eq!(
+        read_config(&deps.storage).unwrap().liquidity_token,
+        legacy.liquidity_token
+    );
+    let stats = read_stats(&deps.storage).unwrap();
+    assert_eq!(stats.asset0_volume, Uint128(123u128));
+    assert_eq!(stats.asset1_volume, Uint128(456u128));
+    assert_eq!(stats.swap_count, 0);
+
+    // later handles run on the migrated layout, the legacy value is gone for good
+    let mut config = read_config(&deps.storage).unwrap();
+    config.token_code_hash = "newlptokenhash".to_string();
+    store_config(&mut deps.storage, &config).unwrap();
+    let stats = Stats {
+        swap_count: 7,
+        ..read_stats(&deps.storage).unwrap()
     };
+    store_stats(&mut deps.storage, &stats).unwrap();
+    let env = mock_env("addr0000", &[]);
+    let _res = handle(&mut deps, env, msg).unwrap();
+    assert_eq!(read_config(&deps.storage).unwrap(), config);
+    assert_eq!(read_stats(&deps.storage).unwrap(), stats);
+}
+
+#[test]
+fn swap_only_rewrites_stats() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1000000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1001000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    let config_before = deps.storage.get(&to_length_prefixed(b"config"));
+    assert!(config_before.is_some());
+    let env = mock_env("asset0000", &[]);
+    let _res = handle(&mut deps, env, swap_msg(Uint128(1000u128))).unwrap();
+
+    assert_eq!(
+        deps.storage.get(&to_length_prefixed(b"config")),
+        config_before
+    );
+    let stats = read_stats(&deps.storage).unwrap();
+    assert_eq!(stats.asset0_volume, Uint128(1000u128));
+    assert_eq!(stats.swap_count, 1);
+}
+
+#[test]
+fn spot_price_scaled() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_decimals(&[
+        (&HumanAddr::from("asset0000"), 6),
+        (&HumanAddr::from("asset0001"), 18),
+    ]);
+    // 1,000 asset0000 (6 decimals) against 2,000 asset0001 (18 decimals)
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(1_000_000_000u128),
+            )],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(2_000_000_000_000_000_000_000u128),
+            )],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    assert_eq!(
+        read_config(&deps.storage).unwrap().asset_decimals,
+        Some([6u8, 18u8])
+    );
+
+    // the unscaled ratio would be 2e12, the scaled price is 2 without any noise
+    let res = query_spot_price_scaled(&deps).unwrap();
+    assert_eq!(res.price, Decimal::from_ratio(2u128, 1u128));
+}
+
+#[test]
+fn sync_token_metadata() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_decimals(&[
+        (&HumanAddr::from("asset0000"), 6),
+        (&HumanAddr::from("asset0001"), 18),
+    ]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(1_000_000_000u128),
+            )],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(2_000_000_000_000_000_000_000u128),
+            )],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    // a pair created before the metadata was stored at init
+    let config = read_config(&deps.storage).unwrap();
+    store_config(
+        &mut deps.storage,
+        &Config {
+            asset_decimals: None,
+            asset_symbols: None,
+            ..config
+        },
+    )
+    .unwrap();
+    match query_spot_price_scaled(&deps) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            "invalid_state: Token decimals are unknown for this pair, run SyncTokenMetadata"
+        ),
+        _ => panic!("Must return generic error"),
+    }
+    let pair_info: PairInfo = query_pair_info(&deps).unwrap();
+    assert_eq!(pair_info.asset_decimals, None);
+
+    // anyone can sync it
+    let env = mock_env("addr0000", &[]);
+    handle(&mut deps, env.clone(), HandleMsg::SyncTokenMetadata {}).unwrap();
+
+    let pair_info: PairInfo = query_pair_info(&deps).unwrap();
+    assert_eq!(pair_info.asset_decimals, Some([6u8, 18u8]));
+    assert_eq!(
+        pair_info.asset_symbols,
+        Some(["mAPPL".to_string(), "mAPPL".to_string()])
+    );
+
+    let res = query_spot_price_scaled(&deps).unwrap();
+    assert_eq!(res.price, Decimal::from_ratio(2u128, 1u128));
+
+    match handle(&mut deps, env, HandleMsg::SyncTokenMetadata {}) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "invalid_state: Token metadata is already synced")
+        }
+        _ => panic!("Must return generic error"),
+    }
+}
+
+#[test]
+fn pool_query_with_wrong_viewing_key() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    // a genuinely empty reserve is reported as zero
+    let res: PoolResponse = query_pool(&deps).unwrap();
+    assert_eq!(res.assets[0].amount, Uint128::zero());
+
+    // a rejected viewing key is an error, not a zero reserve
+    deps.querier
+        .with_wrong_viewing_key(&[&HumanAddr::from("asset0001")]);
+    match query_pool(&deps) {
+        Err(StdError::GenericErr { msg, .. }) => assert!(msg
+            .starts_with("Failed to query the reserve of asset0001, the viewing key may be wrong")),
+        _ => panic!("Must return generic error"),
+    }
+
+    let env = mock_env("asset0000", &[]);
+    match handle(&mut deps, env, swap_msg(Uint128(1000u128))) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert!(msg.starts_with("Failed to query the reserve of asset0001"))
+        }
+        _ => panic!("Must return generic error"),
+    }
+}
+
+#[test]
+fn receive_swap_from_unknown_token() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1001000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    // a token that isn't one of the pair's assets can't trigger a swap
+    let env = mock_env("asset0002", &[]);
+    match handle(&mut deps, env, swap_msg(Uint128(1000u128))) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    // the LP token can't either
+    let env = mock_env("liquidity0000", &[]);
+    match handle(&mut deps, env, swap_msg(Uint128(1000u128))) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    let env = mock_env("asset0000", &[]);
+    let res = handle(&mut deps, env, swap_msg(Uint128(1000u128))).unwrap();
+    assert_eq!(read_stats(&deps.storage).unwrap().swap_count, 1);
+    match &res.messages[0] {
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr,
+            callback_code_hash,
+            ..
+        }) => {
+            assert_eq!(contract_addr, &HumanAddr::from("asset0001"));
+            assert_eq!(callback_code_hash, "tokenhash");
+        }
+        _ => panic!("Must return the ask asset transfer"),
+    }
+}
+
+#[test]
+fn entropy_pool_read_is_constant_size() {
+    let mut deps = mock_dependencies(20, &[]);
+
+    // storage reads are what queries pay for, so the pool must not grow
+    for i in 0..200u64 {
+        let msg = HandleMsg::AddEntropy {
+            entropy: Binary::from(i.to_be_bytes().to_vec()),
+        };
+        let env = mock_env_with_block_time("addr0000", &[], 1000 + i);
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let raw_pool = deps.storage.get(b"entropy_pool").unwrap();
+        assert_eq!(raw_pool.len(), ENTROPY_POOL_BYTES);
+    }
+
+    // a pool stored in a Singleton before the raw encoding still seeds the numbers
+    let mut deps = mock_dependencies(20, &[]);
+    let legacy: [u8; ENTROPY_POOL_BYTES] = Sha256::digest(b"legacy pool").into();
+    Singleton::new(&mut deps.storage, b"entropy_pool")
+        .save(&legacy)
+        .unwrap();
+    assert!(deps.storage.get(b"entropy_pool").is_none());
+    let noise = ChaChaRng::from_seed(legacy).next_u64();
+    assert_eq!(get_random_number(&deps.storage), noise);
+
+    // and is folded into the raw pool, then removed
+    supply_more_entropy(&mut deps.storage, b"more").unwrap();
+    let mut source = legacy.to_vec();
+    source.extend_from_slice(b"more");
+    let folded: [u8; ENTROPY_POOL_BYTES] = Sha256::digest(&source).into();
+    assert_eq!(deps.storage.get(b"entropy_pool").unwrap(), folded.to_vec());
+    let legacy_left: Option<[u8; ENTROPY_POOL_BYTES]> =
+        ReadonlySingleton::new(&deps.storage, b"entropy_pool")
+            .may_load()
+            .unwrap();
+    assert_eq!(legacy_left, None);
+}
+
+#[test]
+fn large_message_entropy_is_capped() {
+    // a 1 MB hook, of which only the head is mixed in
+    let pool_after = |hook: Vec<u8>| {
+        let mut deps = mock_dependencies(20, &[]);
+        let msg = HandleMsg::Receive {
+            from: HumanAddr::from("addr0000"),
+            amount: Uint128(1u128),
+            msg: Some(Binary::from(hook)),
+        };
+        let env = mock_env_with_block_time("asset0000", &[], 1000);
+        // the pair isn't initialized, the entropy is mixed in before that fails
+        let _res = handle(&mut deps, env, msg);
+        deps.storage.get(b"entropy_pool").unwrap()
+    };
+
+    let hook = vec![7u8; 1_000_000];
+    let pool = pool_after(hook.clone());
+    assert_eq!(pool.len(), ENTROPY_POOL_BYTES);
+
+    let mut other_tail = hook.clone();
+    other_tail[999_999] = 8;
+    assert_eq!(pool_after(other_tail), pool);
+
+    let mut other_head = hook;
+    other_head[0] = 8;
+    assert_ne!(pool_after(other_head), pool);
+}
+
+#[test]
+fn stored_addresses_are_not_converted_again() {
+    let mut deps = mock_dependencies_counting(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1000000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1001000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    let addresses = read_addresses(&deps.storage, &deps.api).unwrap();
+    assert_eq!(
+        addresses.liquidity_token.human,
+        HumanAddr::from("liquidity0000")
+    );
+    assert_eq!(
+        addresses.asset_contracts[1].human,
+        HumanAddr::from("asset0001")
+    );
+
+    deps.api.reset_conversions();
+    deps.storage.reset_config_reads();
+    let env = mock_env("asset0000", &[]);
+    let _res = handle(&mut deps, env, swap_msg(Uint128(1000u128))).unwrap();
+    assert_eq!(deps.api.conversions(), 0);
+    // the config is loaded once and shared by the whole swap
+    assert_eq!(deps.storage.config_reads(), 1);
+
+    let _res = query_pool(&deps).unwrap();
+    let _res = query_pair_info(&deps).unwrap();
+    let _res = query_simulation(
+        &deps,
+        Asset {
+            info: token_asset_info("asset0000"),
+            amount: Uint128(1000u128),
+        },
+        None,
+    )
+    .unwrap();
+    assert_eq!(deps.api.conversions(), 0);
+
+    // pairs created before the cache derive it until the next handle stores it
+    deps.storage.remove(&to_length_prefixed(b"addresses"));
+    assert_eq!(
+        query_pair_info(&deps).unwrap().liquidity_token,
+        HumanAddr::from("liquidity0000")
+    );
+    assert!(deps.api.conversions() > 0);
+
+    let msg = HandleMsg::AddEntropy {
+        entropy: Binary::from(b"x".to_vec()),
+    };
+    let env = mock_env("addr0000", &[]);
+    let _res = handle(&mut deps, env, msg).unwrap();
+    assert_eq!(read_addresses(&deps.storage, &deps.api).unwrap(), addresses);
+
+    deps.api.reset_conversions();
+    let _res = query_pair_info(&deps).unwrap();
+    assert_eq!(deps.api.conversions(), 0);
+}
+
+#[test]
+fn simulation_commission_rate_override() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    let offer_asset = Asset {
+        info: token_asset_info("asset0000"),
+        amount: Uint128(100000u128),
+    };
+
+    // the mock factory charges 0.3%
+    let default = query_simulation(&deps, offer_asset.clone(), None).unwrap();
+    let same = query_simulation(
+        &deps,
+        offer_asset.clone(),
+        Some(Decimal::from_ratio(3u128, 1000u128)),
+    )
+    .unwrap();
+    assert_eq!(same, default);
+
+    // a sub basis point rate quotes a smaller commission and a larger return
+    let cheap = query_simulation(
+        &deps,
+        offer_asset.clone(),
+        Some(Decimal::from_ratio(25u128, 1000000u128)),
+    )
+    .unwrap();
+    assert!(cheap.commission_amount < default.commission_amount);
+    assert_eq!(
+        cheap.return_amount + cheap.commission_amount,
+        default.return_amount + default.commission_amount
+    );
+
+    let free = query_simulation(&deps, offer_asset.clone(), Some(Decimal::zero())).unwrap();
+    assert_eq!(free.commission_amount, Uint128::zero());
+
+    match query_simulation(&deps, offer_asset, Some(Decimal::percent(101))) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(
+                msg,
+                "invalid_parameter: commission_rate_override must not exceed 1"
+            )
+        }
+        _ => panic!("Must return generic error"),
+    }
+}
+
+#[test]
+fn native_uscrt_pair() {
+    let mut deps = mock_dependencies_counting(20, &[]);
+    let uscrt = AssetInfo::NativeToken {
+        denom: "uscrt".to_string(),
+    };
+    deps.querier.with_token_balances(&[
+        (&HumanAddr::from("liquidity0000"), &[]),
+        (
+            &HumanAddr::from("sscrt0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0u128))],
+        ),
+    ]);
+    init_pair(&mut deps, [uscrt.clone(), token_asset_info("sscrt0000")]);
+
+    // no snip20 registration for the native side
+    let config = read_config(&deps.storage).unwrap();
+    assert_eq!(
+        config.asset_infos[0],
+        AssetInfoRaw::NativeToken {
+            denom: "uscrt".to_string()
+        }
+    );
+    assert_eq!(config.asset_decimals, Some([6u8, 6u8]));
+
+    // provide, the native deposit is already in the contract balance
+    deps.querier.with_balance(&[(
+        &HumanAddr::from(MOCK_CONTRACT_ADDR),
+        &[Coin::new(100u128, "uscrt")],
+    )]);
+    let msg = HandleMsg::ProvideLiquidity {
+        assets: [
+            Asset {
+                info: uscrt.clone(),
+                amount: Uint128(100u128),
+            },
+            Asset {
+                info: token_asset_info("sscrt0000"),
+                amount: Uint128(100u128),
+            },
+        ],
+        slippage_tolerance: None,
+        auto_stake: None,
+        receiver: None,
+    };
+    let env = mock_env("addr0000", &[Coin::new(100u128, "uscrt")]);
+    let res = handle(&mut deps, env, msg).unwrap();
+    assert_eq!(
+        res.messages,
+        vec![
+            snip20::transfer_from_msg(
+                HumanAddr::from("addr0000"),
+                HumanAddr::from(MOCK_CONTRACT_ADDR),
+                Uint128(100u128),
+                None,
+                256,
+                "tokenhash".to_string(),
+                HumanAddr::from("sscrt0000"),
+            )
+            .unwrap(),
+            snip20::mint_msg(
+                HumanAddr::from("addr0000"),
+                Uint128(100u128),
+                None,
+                256,
+                "lptokenhash".to_string(),
+                HumanAddr::from("liquidity0000"),
+            )
+            .unwrap(),
+        ]
+    );
+
+    // swap uscrt -> sscrt, funds arrive with the message
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1000u128))],
+        ),
+        (
+            &HumanAddr::from("sscrt0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+    ]);
+    deps.querier.with_balance(&[(
+        &HumanAddr::from(MOCK_CONTRACT_ADDR),
+        &[Coin::new(1001000u128, "uscrt")],
+    )]);
+    let native_swap = |amount: u128| HandleMsg::Swap {
+        offer_asset: Asset {
+            info: uscrt.clone(),
+            amount: Uint128(amount),
+        },
+        expected_return: None,
+        belief_price: None,
+        max_spread: None,
+        max_price_impact: None,
+        batch: false,
+        to: None,
+        unwrap: false,
+    };
+
+    let env = mock_env("addr0000", &[Coin::new(999u128, "uscrt")]);
+    match handle(&mut deps, env, native_swap(1000)) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            "funds_mismatch: Native token balance mismatch between the argument and the transferred"
+        ),
+        _ => panic!("Must return generic error"),
+    }
+
+    let env = mock_env("addr0000", &[Coin::new(1000u128, "uscrt")]);
+    deps.storage.reset_config_reads();
+    let res = handle(&mut deps, env, native_swap(1000)).unwrap();
+    assert_eq!(deps.storage.config_reads(), 1);
+    match &res.messages[0] {
+        CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, .. }) => {
+            assert_eq!(contract_addr, &HumanAddr::from("sscrt0000"))
+        }
+        _ => panic!("Must return the sscrt transfer"),
+    }
+
+    // tokens can't be swapped without being sent
+    let msg = HandleMsg::Swap {
+        offer_asset: Asset {
+            info: token_asset_info("sscrt0000"),
+            amount: Uint128(1000u128),
+        },
+        expected_return: None,
+        belief_price: None,
+        max_spread: None,
+        max_price_impact: None,
+        batch: false,
+        to: None,
+        unwrap: false,
+    };
+    let env = mock_env("addr0000", &[]);
+    match handle(&mut deps, env, msg) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    // swap sscrt -> uscrt, paid out with a bank send
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1000u128))],
+        ),
+        (
+            &HumanAddr::from("sscrt0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1001000u128))],
+        ),
+    ]);
+    deps.querier.with_balance(&[(
+        &HumanAddr::from(MOCK_CONTRACT_ADDR),
+        &[Coin::new(1000000u128, "uscrt")],
+    )]);
+    let env = mock_env("sscrt0000", &[]);
+    let res = handle(&mut deps, env, swap_msg(Uint128(1000u128))).unwrap();
+    match &res.messages[0] {
+        CosmosMsg::Bank(BankMsg::Send {
+            to_address, amount, ..
+        }) => {
+            assert_eq!(to_address, &HumanAddr::from("addr0000"));
+            assert_eq!(amount[0].denom, "uscrt");
+        }
+        _ => panic!("Must return a bank send"),
+    }
+
+    // withdraw 10% of the shares
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1000u128))],
+        ),
+        (
+            &HumanAddr::from("sscrt0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(2000u128))],
+        ),
+    ]);
+    deps.querier.with_balance(&[(
+        &HumanAddr::from(MOCK_CONTRACT_ADDR),
+        &[Coin::new(1000u128, "uscrt")],
+    )]);
+    let msg = HandleMsg::Receive {
+        from: HumanAddr::from("addr0000"),
+        msg: Some(
+            to_binary(&Cw20HookMsg::WithdrawLiquidity {
+                unwrap: false,
+                to: None,
+                to_code_hash: None,
+                to_msg: None,
+            })
+            .unwrap(),
+        ),
+        amount: Uint128(100u128),
+    };
+    let env = mock_env("liquidity0000", &[]);
+    let res = handle(&mut deps, env, msg).unwrap();
+    assert_eq!(
+        res.messages[1],
+        CosmosMsg::Bank(BankMsg::Send {
+            from_address: HumanAddr::from(MOCK_CONTRACT_ADDR),
+            to_address: HumanAddr::from("addr0000"),
+            amount: vec![Coin::new(100u128, "uscrt")],
+        })
+    );
+    match &res.messages[2] {
+        CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, .. }) => {
+            assert_eq!(contract_addr, &HumanAddr::from("sscrt0000"))
+        }
+        _ => panic!("Must return the sscrt refund"),
+    }
+}
+
+#[test]
+fn provide_liquidity_receipt() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(2000u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    let msg = HandleMsg::ProvideLiquidity {
+        assets: [
+            Asset {
+                info: token_asset_info("asset0000"),
+                amount: Uint128(100u128),
+            },
+            Asset {
+                info: token_asset_info("asset0001"),
+                amount: Uint128(200u128),
+            },
+        ],
+        slippage_tolerance: None,
+        auto_stake: None,
+        receiver: None,
+    };
+    let env = mock_env("addr0000", &[]);
+    let res = handle(&mut deps, env, msg).unwrap();
+
+    let entry_reserves = [
+        Asset {
+            info: token_asset_info("asset0000"),
+            amount: Uint128(1000u128),
+        },
+        Asset {
+            info: token_asset_info("asset0001"),
+            amount: Uint128(2000u128),
+        },
+    ];
+    let receipt: ProvideLiquidityResponse = from_binary(&res.data.unwrap()).unwrap();
+    assert_eq!(
+        receipt,
+        ProvideLiquidityResponse {
+            share: Uint128(100u128),
+            entry_reserves,
+            entry_price: Some(Decimal::from_ratio(2u128, 1u128)),
+            refunds: vec![],
+        }
+    );
+    assert!(res
+        .log
+        .contains(&log("entry_reserves", "1000asset0000, 2000asset0001")));
+    assert!(res.log.contains(&log("entry_price", "2")));
+}
+
+#[test]
+fn wrap_native_uscrt() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1000u128))],
+        ),
+        (
+            &HumanAddr::from("sscrt0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+    ]);
+    let uscrt = AssetInfo::NativeToken {
+        denom: "uscrt".to_string(),
+    };
+    let wrap_config = WrapConfig {
+        sscrt_addr: HumanAddr::from("sscrt0000"),
+        sscrt_code_hash: "tokenhash".to_string(),
+    };
+
+    // the sSCRT must be one of the assets, and reserves are never native
+    let mut msg = pair_init_msg([token_asset_info("asset0000"), token_asset_info("asset0001")]);
+    msg.wrap_config = Some(wrap_config.clone());
+    match init(&mut deps, mock_env("addr0000", &[]), msg) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(
+                msg,
+                "invalid_parameter: The wrap_config sSCRT must be one of the pair's assets"
+            )
+        }
+        _ => panic!("Must return generic error"),
+    }
+    let mut msg = pair_init_msg([uscrt.clone(), token_asset_info("sscrt0000")]);
+    msg.wrap_config = Some(wrap_config.clone());
+    match init(&mut deps, mock_env("addr0000", &[]), msg) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(
+                msg,
+                "invalid_parameter: A pair with a wrap_config cannot hold native coins"
+            )
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    let mut msg = pair_init_msg([token_asset_info("sscrt0000"), token_asset_info("asset0001")]);
+    msg.wrap_config = Some(wrap_config);
+    init_pair_with(&mut deps, msg);
+
+    // native in, token out: the uscrt is deposited before the swap pays out.
+    // The deposit hasn't landed yet, so the sSCRT pool is used as is.
+    let msg = HandleMsg::Swap {
+        offer_asset: Asset {
+            info: uscrt,
+            amount: Uint128(1000u128),
+        },
+        expected_return: None,
+        belief_price: None,
+        max_spread: None,
+        max_price_impact: None,
+        batch: false,
+        to: None,
+        unwrap: false,
+    };
+    let env = mock_env("addr0000", &[Coin::new(1000u128, "uscrt")]);
+    let res = handle(&mut deps, env, msg).unwrap();
+    assert_eq!(
+        res.messages[0],
+        snip20::deposit_msg(
+            Uint128(1000u128),
+            None,
+            256,
+            "tokenhash".to_string(),
+            HumanAddr::from("sscrt0000"),
+        )
+        .unwrap()
+    );
+    match &res.messages[1] {
+        CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, .. }) => {
+            assert_eq!(contract_addr, &HumanAddr::from("asset0001"))
+        }
+        _ => panic!("Must return the asset0001 transfer"),
+    }
+    assert_eq!(
+        read_stats(&deps.storage).unwrap().asset0_volume,
+        Uint128(1000u128)
+    );
+
+    // unwrap on withdraw redeems the sSCRT refund and sends it as uscrt
+    let msg = HandleMsg::Receive {
+        from: HumanAddr::from("addr0000"),
+        msg: Some(
+            to_binary(&Cw20HookMsg::WithdrawLiquidity {
+                unwrap: true,
+                to: None,
+                to_code_hash: None,
+                to_msg: None,
+            })
+            .unwrap(),
+        ),
+        amount: Uint128(100u128),
+    };
+    let env = mock_env("liquidity0000", &[]);
+    let res = handle(&mut deps, env, msg).unwrap();
+    assert_eq!(
+        res.messages[1..3].to_vec(),
+        vec![
+            snip20::redeem_msg(
+                Uint128(100000u128),
+                Some("uscrt".to_string()),
+                None,
+                256,
+                "tokenhash".to_string(),
+                HumanAddr::from("sscrt0000"),
+            )
+            .unwrap(),
+            CosmosMsg::Bank(BankMsg::Send {
+                from_address: HumanAddr::from(MOCK_CONTRACT_ADDR),
+                to_address: HumanAddr::from("addr0000"),
+                amount: vec![Coin::new(100000u128, "uscrt")],
+            }),
+        ]
+    );
+    match &res.messages[3] {
+        CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, .. }) => {
+            assert_eq!(contract_addr, &HumanAddr::from("asset0001"))
+        }
+        _ => panic!("Must return the asset0001 refund"),
+    }
+}
+
+#[test]
+fn volume_saturates_instead_of_overflowing() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1001000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    store_stats(
+        &mut deps.storage,
+        &Stats {
+            asset0_volume: Uint128(u128::MAX - 10),
+            asset1_volume: Uint128::zero(),
+            swap_count: 7,
+            last_swap_at_height: None,
+            last_swap_at_time: None,
+        },
+    )
+    .unwrap();
+
+    let env = mock_env("asset0000", &[]);
+    let _res = handle(&mut deps, env, swap_msg(Uint128(1000u128))).unwrap();
+
+    let stats = read_stats(&deps.storage).unwrap();
+    assert_eq!(stats.asset0_volume, Uint128(u128::MAX));
+    assert_eq!(stats.swap_count, 8);
+}
+
+#[test]
+fn provide_liquidity_rejects_stray_native_funds() {
+    let mut deps = mock_dependencies(20, &[]);
+    with_offer_in_pool(&mut deps, 0);
+    init_token_pair(&mut deps);
+
+    let msg = HandleMsg::ProvideLiquidity {
+        assets: [
+            Asset {
+                info: token_asset_info("asset0000"),
+                amount: Uint128(1_000u128),
+            },
+            Asset {
+                info: token_asset_info("asset0001"),
+                amount: Uint128(1_000u128),
+            },
+        ],
+        slippage_tolerance: None,
+        auto_stake: None,
+        receiver: None,
+    };
+
+    // a token-token pair has no use for native coins, they would be stranded
+    let env = mock_env("addr0000", &[Coin::new(100u128, "uscrt")]);
+    match handle(&mut deps, env, msg) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "funds_mismatch: Unexpected native funds sent: uscrt")
+        }
+        _ => panic!("Must return generic error"),
+    }
+}
+
+#[test]
+fn native_overpayment_is_refunded() {
+    let mut deps = mock_dependencies(20, &[]);
+    let uscrt = AssetInfo::NativeToken {
+        denom: "uscrt".to_string(),
+    };
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1000u128))],
+        ),
+        (
+            &HumanAddr::from("sscrt0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+    ]);
+    init_pair(&mut deps, [uscrt.clone(), token_asset_info("sscrt0000")]);
+
+    let native_swap = HandleMsg::Swap {
+        offer_asset: Asset {
+            info: uscrt.clone(),
+            amount: Uint128(1000u128),
+        },
+        expected_return: None,
+        belief_price: None,
+        max_spread: None,
+        max_price_impact: None,
+        batch: false,
+        to: None,
+        unwrap: false,
+    };
+
+    // exact payment, nothing to refund
+    deps.querier.with_balance(&[(
+        &HumanAddr::from(MOCK_CONTRACT_ADDR),
+        &[Coin::new(1001000u128, "uscrt")],
+    )]);
+    let env = mock_env("addr0000", &[Coin::new(1000u128, "uscrt")]);
+    let exact = handle(&mut deps, env, native_swap.clone()).unwrap();
+    assert_eq!(exact.messages.len(), 1);
+    let data: SwapResponse = from_binary(&exact.data.unwrap()).unwrap();
+    assert_eq!(data.refunds, vec![]);
+
+    // over payment, the excess is sent back and left out of the pool
+    deps.querier.with_balance(&[(
+        &HumanAddr::from(MOCK_CONTRACT_ADDR),
+        &[Coin::new(1001500u128, "uscrt")],
+    )]);
+    let env = mock_env("addr0000", &[Coin::new(1500u128, "uscrt")]);
+    let over = handle(&mut deps, env, native_swap.clone()).unwrap();
+    assert_eq!(over.messages[0], exact.messages[0]);
+    assert_eq!(
+        over.messages[1],
+        CosmosMsg::Bank(BankMsg::Send {
+            from_address: HumanAddr::from(MOCK_CONTRACT_ADDR),
+            to_address: HumanAddr::from("addr0000"),
+            amount: vec![Coin::new(500u128, "uscrt")],
+        })
+    );
+    let data: SwapResponse = from_binary(&over.data.unwrap()).unwrap();
+    assert_eq!(data.refunds, vec![Coin::new(500u128, "uscrt")]);
+    assert!(!over.log.iter().any(|l| l.value.contains("500uscrt")));
+
+    // under payment
+    let env = mock_env("addr0000", &[Coin::new(999u128, "uscrt")]);
+    match handle(&mut deps, env, native_swap.clone()) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            "funds_mismatch: Native token balance mismatch between the argument and the transferred"
+        ),
+        _ => panic!("Must return generic error"),
+    }
+
+    // unrelated denoms are rejected
+    let env = mock_env(
+        "addr0000",
+        &[Coin::new(1000u128, "uscrt"), Coin::new(1u128, "uatom")],
+    );
+    match handle(&mut deps, env, native_swap) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "funds_mismatch: Unexpected native funds sent: uatom")
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    // provide refunds the excess too
+    let msg = HandleMsg::ProvideLiquidity {
+        assets: [
+            Asset {
+                info: uscrt,
+                amount: Uint128(1000u128),
+            },
+            Asset {
+                info: token_asset_info("sscrt0000"),
+                amount: Uint128(1000u128),
+            },
+        ],
+        slippage_tolerance: None,
+        auto_stake: None,
+        receiver: None,
+    };
+    deps.querier.with_balance(&[(
+        &HumanAddr::from(MOCK_CONTRACT_ADDR),
+        &[Coin::new(1001200u128, "uscrt")],
+    )]);
+    let env = mock_env("addr0000", &[Coin::new(1200u128, "uscrt")]);
+    let res = handle(&mut deps, env, msg).unwrap();
+    assert_eq!(
+        res.messages.last().unwrap(),
+        &CosmosMsg::Bank(BankMsg::Send {
+            from_address: HumanAddr::from(MOCK_CONTRACT_ADDR),
+            to_address: HumanAddr::from("addr0000"),
+            amount: vec![Coin::new(200u128, "uscrt")],
+        })
+    );
+    let receipt: ProvideLiquidityResponse = from_binary(&res.data.unwrap()).unwrap();
+    assert_eq!(receipt.entry_reserves[0].amount, Uint128(1000000u128));
+    assert_eq!(receipt.refunds, vec![Coin::new(200u128, "uscrt")]);
+}
+
+#[test]
+fn assets() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    let res = query(&deps, QueryMsg::Assets {}).unwrap();
+    let assets: [AssetInfo; 2] = from_binary(&res).unwrap();
+    assert!(assets[0].equal(&token_asset_info("asset0000")));
+    assert!(assets[1].equal(&token_asset_info("asset0001")));
+    for asset in assets.iter() {
+        match asset {
+            AssetInfo::Token {
+                token_code_hash, ..
+            } => assert_eq!(token_code_hash, "tokenhash"),
+            _ => panic!("DO NOT ENTER HERE"),
+        }
+    }
+    assert_eq!(assets, query_pair_info(&deps).unwrap().asset_infos);
+}
+
+#[test]
+fn queries_never_return_viewing_keys() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+    let env = mock_env("factory0000", &[]);
+    let _res = handle(&mut deps, env, HandleMsg::RotateViewingKeys {}).unwrap();
+
+    let expected = [token_asset_info("asset0000"), token_asset_info("asset0001")];
+    let pair_info: PairInfo = from_binary(&query(&deps, QueryMsg::Pair {}).unwrap()).unwrap();
+    assert_eq!(pair_info.asset_infos, expected);
+    let assets: [AssetInfo; 2] = from_binary(&query(&deps, QueryMsg::Assets {}).unwrap()).unwrap();
+    assert_eq!(assets, expected);
+    let pool: PoolResponse = from_binary(&query(&deps, QueryMsg::Pool {}).unwrap()).unwrap();
+    assert_eq!(pool.assets[0].info, expected[0]);
+    assert_eq!(pool.assets[1].info, expected[1]);
+}
+
+#[test]
+fn has_asset() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    assert_eq!(
+        query_has_asset(&deps, token_asset_info("asset0000")).unwrap(),
+        HasAssetResponse {
+            has_asset: true,
+            index: Some(0),
+        }
+    );
+    assert_eq!(
+        query_has_asset(&deps, token_asset_info("asset0001")).unwrap(),
+        HasAssetResponse {
+            has_asset: true,
+            index: Some(1),
+        }
+    );
+    assert_eq!(
+        query_has_asset(&deps, token_asset_info("asset0002")).unwrap(),
+        HasAssetResponse {
+            has_asset: false,
+            index: None,
+        }
+    );
+    assert_eq!(
+        query_has_asset(
+            &deps,
+            AssetInfo::NativeToken {
+                denom: "uscrt".to_string(),
+            },
+        )
+        .unwrap(),
+        HasAssetResponse {
+            has_asset: false,
+            index: None,
+        }
+    );
+}
+
+#[test]
+fn swap_to_contract_with_msg() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1001000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    let hook_msg = Binary::from(r#"{"continue":{}}"#.as_bytes());
+    let swap_with_msg = |to: Option<HumanAddr>| HandleMsg::Receive {
+        from: HumanAddr::from("addr0000"),
+        amount: Uint128(1000u128),
+        msg: Some(
+            to_binary(&Cw20HookMsg::Swap {
+                expected_return: None,
+                belief_price: None,
+                max_spread: None,
+                max_price_impact: None,
+                batch: false,
+                to,
+                unwrap: false,
+                to_code_hash: Some("routerhash".to_string()),
+                to_msg: Some(hook_msg.clone()),
+                ask_asset_info: None,
+            })
+            .unwrap(),
+        ),
+    };
+
+    let env = mock_env("asset0000", &[]);
+    match handle(&mut deps, env, swap_with_msg(None)) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "invalid_parameter: to_msg requires a to address")
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    let env = mock_env("asset0000", &[]);
+    let res = handle(
+        &mut deps,
+        env,
+        swap_with_msg(Some(HumanAddr::from("router0000"))),
+    )
+    .unwrap();
+    let return_amount = res
+        .log
+        .iter()
+        .find(|l| l.key == "return_amount")
+        .unwrap()
+        .value
+        .parse::<u128>()
+        .unwrap();
+    assert_eq!(
+        res.messages[0],
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: HumanAddr::from("asset0001"),
+            callback_code_hash: "tokenhash".to_string(),
+            msg: to_binary(&snip20::HandleMsg::Send {
+                recipient: HumanAddr::from("router0000"),
+                amount: Uint128(return_amount),
+                padding: None,
+                msg: Some(hook_msg),
+            })
+            .unwrap(),
+            send: vec![],
+        })
+    );
+}
+
+#[test]
+fn emergency_withdraw() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[
+                (&HumanAddr::from("addr0000"), &Uint128(30u128)),
+                (&HumanAddr::from("addr0001"), &Uint128(70u128)),
+            ],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(2000u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    let msg = HandleMsg::EmergencyWithdraw {
+        holder: HumanAddr::from("addr0000"),
+        amount: Uint128(30u128),
+    };
+    for sender in &["addr0000", "liquidity0000", "asset0000"] {
+        let env = mock_env(*sender, &[]);
+        match handle(&mut deps, env, msg.clone()) {
+            Err(StdError::Unauthorized { .. }) => {}
+            _ => panic!("Must return unauthorized error"),
+        }
+    }
+
+    // the refund is the one a regular withdrawal would pay, without the burn
+    let withdraw = HandleMsg::Receive {
+        from: HumanAddr::from("addr0000"),
+        msg: Some(
+            to_binary(&Cw20HookMsg::WithdrawLiquidity {
+                unwrap: false,
+                to: None,
+                to_code_hash: None,
+                to_msg: None,
+            })
+            .unwrap(),
+        ),
+        amount: Uint128(30u128),
+    };
+    let env = mock_env("liquidity0000", &[]);
+    let regular = handle(&mut deps, env, withdraw).unwrap();
+
+    let env = mock_env("factory0000", &[]);
+    let res = handle(&mut deps, env, msg).unwrap();
+    assert_eq!(res.messages, regular.messages[1..].to_vec());
+    assert_eq!(
+        res.messages[0],
+        Asset {
+            info: token_asset_info("asset0000"),
+            amount: Uint128(300u128),
+        }
+        .into_msg(
+            &deps,
+            HumanAddr::from(MOCK_CONTRACT_ADDR),
+            HumanAddr::from("addr0000"),
+        )
+        .unwrap()
+    );
+
+    // the unburned shares no longer dilute the remaining holders
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[
+                (&HumanAddr::from("addr0000"), &Uint128(30u128)),
+                (&HumanAddr::from("addr0001"), &Uint128(70u128)),
+            ],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(700u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1400u128))],
+        ),
+    ]);
+    let withdraw = HandleMsg::Receive {
+        from: HumanAddr::from("addr0001"),
+        msg: Some(
+            to_binary(&Cw20HookMsg::WithdrawLiquidity {
+                unwrap: false,
+                to: None,
+                to_code_hash: None,
+                to_msg: None,
+            })
+            .unwrap(),
+        ),
+        amount: Uint128(70u128),
+    };
+    let env = mock_env("liquidity0000", &[]);
+    let res = handle(&mut deps, env, withdraw).unwrap();
+    assert!(res
+        .log
+        .contains(&log("refund_assets", "700asset0000, 1400asset0001")));
+}
+
+#[test]
+fn withdraw_liquidity_to_contract_with_msg() {
+    let mut deps = mock_dependencies(20, &[]);
+    let uscrt = AssetInfo::NativeToken {
+        denom: "uscrt".to_string(),
+    };
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1000u128))],
+        ),
+        (
+            &HumanAddr::from("sscrt0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(2000u128))],
+        ),
+    ]);
+    deps.querier.with_balance(&[(
+        &HumanAddr::from(MOCK_CONTRACT_ADDR),
+        &[Coin::new(1000u128, "uscrt")],
+    )]);
+    init_pair(&mut deps, [uscrt.clone(), token_asset_info("sscrt0000")]);
+
+    let hook_msg = Binary::from(r#"{"unwind":{}}"#.as_bytes());
+    let withdraw = |to: Option<HumanAddr>| HandleMsg::Receive {
+        from: HumanAddr::from("addr0000"),
+        msg: Some(
+            to_binary(&Cw20HookMsg::WithdrawLiquidity {
+                unwrap: false,
+                to,
+                to_code_hash: Some("vaulthash".to_string()),
+                to_msg: Some(hook_msg.clone()),
+            })
+            .unwrap(),
+        ),
+        amount: Uint128(100u128),
+    };
+
+    let env = mock_env("liquidity0000", &[]);
+    match handle(&mut deps, env, withdraw(None)) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "invalid_parameter: to_msg requires a to address")
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    let env = mock_env("liquidity0000", &[]);
+    let res = handle(&mut deps, env, withdraw(Some(HumanAddr::from("vault0000")))).unwrap();
+    let native_refund = Asset {
+        info: uscrt,
+        amount: Uint128(100u128),
+    };
+    let token_refund = Asset {
+        info: token_asset_info("sscrt0000"),
+        amount: Uint128(200u128),
+    };
+    assert_eq!(
+        res.messages[1..].to_vec(),
+        vec![
+            CosmosMsg::Bank(BankMsg::Send {
+                from_address: HumanAddr::from(MOCK_CONTRACT_ADDR),
+                to_address: HumanAddr::from("vault0000"),
+                amount: vec![Coin::new(100u128, "uscrt")],
+            }),
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: HumanAddr::from("vault0000"),
+                callback_code_hash: "vaulthash".to_string(),
+                msg: to_binary(&WithdrawHookMsg::LiquidityRefund {
+                    asset: native_refund,
+                    msg: hook_msg.clone(),
+                })
+                .unwrap(),
+                send: vec![],
+            }),
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: HumanAddr::from("sscrt0000"),
+                callback_code_hash: "tokenhash".to_string(),
+                msg: to_binary(&snip20::HandleMsg::Send {
+                    recipient: HumanAddr::from("vault0000"),
+                    amount: Uint128(200u128),
+                    padding: None,
+                    msg: Some(
+                        to_binary(&WithdrawHookMsg::LiquidityRefund {
+                            asset: token_refund,
+                            msg: hook_msg,
+                        })
+                        .unwrap()
+                    ),
+                })
+                .unwrap(),
+                send: vec![],
+            }),
+        ]
+    );
+}
+
+#[test]
+fn dust_swaps_pay_min_commission() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000010u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    // 0.3% of a 9 return rounds up to a unit
+    let env = mock_env("asset0000", &[]);
+    let res = handle(&mut deps, env, swap_msg(Uint128(10u128))).unwrap();
+    assert_eq!(commission_log(&res), "1");
+    assert!(res.log.contains(&log("return_amount", "8")));
+
+    deps.querier.with_pair_settings(PairSettings {
+        swap_fee: Fee {
+            min_commission: Uint128(2u128),
+            ..test_settings().swap_fee
+        },
+        ..test_settings()
+    });
+    handle(
+        &mut deps,
+        mock_env("addr0000", &[]),
+        HandleMsg::RefreshSettings {},
+    )
+    .unwrap();
+
+    let env = mock_env("asset0000", &[]);
+    let res = handle(&mut deps, env, swap_msg(Uint128(10u128))).unwrap();
+    assert_eq!(commission_log(&res), "2");
+    assert!(res.log.contains(&log("return_amount", "7")));
+
+    // expected_return is checked against the return after the minimum commission
+    let msg = HandleMsg::Receive {
+        from: HumanAddr::from("addr0000"),
+        amount: Uint128(10u128),
+        msg: Some(
+            to_binary(&Cw20HookMsg::Swap {
+                expected_return: Some(Uint128(9u128)),
+                belief_price: None,
+                max_spread: None,
+                max_price_impact: None,
+                batch: false,
+                to: None,
+                unwrap: false,
+                to_code_hash: None,
+                to_msg: None,
+                ask_asset_info: None,
+            })
+            .unwrap(),
+        ),
+    };
+    let env = mock_env("asset0000", &[]);
+    match handle(&mut deps, env, msg) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(
+                msg,
+                "expected_return_not_met: The return of 7 fell short of the expected_return of 9"
+            )
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    // a swap returning less than the minimum commission is rejected
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000001u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+    ]);
+    let env = mock_env("asset0000", &[]);
+    match handle(&mut deps, env, swap_msg(Uint128(1u128))) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(
+                msg,
+                "commission_not_met: Swap is too small to pay the minimum commission"
+            )
+        }
+        _ => panic!("Must return generic error"),
+    }
+}
+
+#[test]
+fn withdraw_liquidity_with_allowance() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(100u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(100u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(200u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    let msg = |min_assets: Option<Vec<Asset>>| HandleMsg::WithdrawLiquidity {
+        amount: Uint128(50u128),
+        min_assets,
+        to: Some(HumanAddr::from("addr0001")),
+    };
+
+    let env = mock_env("addr0000", &[]);
+    match handle(
+        &mut deps,
+        env,
+        msg(Some(vec![Asset {
+            info: token_asset_info("asset0001"),
+            amount: Uint128(101u128),
+        }])),
+    ) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(
+                msg,
+                "slippage_exceeded: Refund 100asset0001 is below min_assets"
+            )
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    // the LP shares are pulled and burned before anything is refunded
+    let env = mock_env("addr0000", &[]);
+    let res = handle(
+        &mut deps,
+        env,
+        msg(Some(vec![Asset {
+            info: token_asset_info("asset0001"),
+            amount: Uint128(100u128),
+        }])),
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages,
+        vec![
+            snip20::transfer_from_msg(
+                HumanAddr::from("addr0000"),
+                HumanAddr::from(MOCK_CONTRACT_ADDR),
+                Uint128(50u128),
+                None,
+                256,
+                "lptokenhash".to_string(),
+                HumanAddr::from("liquidity0000"),
+            )
+            .unwrap(),
+            snip20::burn_msg(
+                Uint128(50u128),
+                None,
+                256,
+                "lptokenhash".to_string(),
+                HumanAddr::from("liquidity0000"),
+            )
+            .unwrap(),
+            Asset {
+                info: token_asset_info("asset0000"),
+                amount: Uint128(50u128),
+            }
+            .into_msg(
+                &deps,
+                HumanAddr::from(MOCK_CONTRACT_ADDR),
+                HumanAddr::from("addr0001"),
+            )
+            .unwrap(),
+            Asset {
+                info: token_asset_info("asset0001"),
+                amount: Uint128(100u128),
+            }
+            .into_msg(
+                &deps,
+                HumanAddr::from(MOCK_CONTRACT_ADDR),
+                HumanAddr::from("addr0001"),
+            )
+            .unwrap(),
+        ]
+    );
+}
+
+#[test]
+fn batch_swaps_settle_at_uniform_price() {
+    fn with_balances(
+        deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>,
+        asset0_balance: u128,
+        asset1_balance: u128,
+    ) {
+        deps.querier.with_token_balances(&[
+            (
+                &HumanAddr::from("liquidity0000"),
+                &[(&HumanAddr::from("addr0000"), &Uint128(1_000_000u128))],
+            ),
+            (
+                &HumanAddr::from("asset0000"),
+                &[(
+                    &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                    &Uint128(asset0_balance),
+                )],
+            ),
+            (
+                &HumanAddr::from("asset0001"),
+                &[(
+                    &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                    &Uint128(asset1_balance),
+                )],
+            ),
+        ]);
+    }
+    let swap =
+        |from: &str, amount: u128, batch: bool, belief_price: Option<Decimal>| HandleMsg::Receive {
+            from: HumanAddr::from(from),
+            amount: Uint128(amount),
+            msg: Some(
+                to_binary(&Cw20HookMsg::Swap {
+                    expected_return: None,
+                    belief_price,
+                    max_spread: None,
+                    max_price_impact: None,
+                    batch,
+                    to: None,
+                    unwrap: false,
+                    to_code_hash: None,
+                    to_msg: None,
+                    ask_asset_info: None,
+                })
+                .unwrap(),
+            ),
+        };
+    let payout = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>,
+                  asset: &str,
+                  amount: u128,
+                  recipient: &str| {
+        Asset {
+            info: token_asset_info(asset),
+            amount: Uint128(amount),
+        }
+        .into_msg(
+            deps,
+            HumanAddr::from(MOCK_CONTRACT_ADDR),
+            HumanAddr::from(recipient),
+        )
+        .unwrap()
+    };
+    let return_log = |res: &HandleResponse| -> u128 {
+        res.log
+            .iter()
+            .find(|l| l.key == "return_amount")
+            .expect("no return log")
+            .value
+            .parse()
+            .unwrap()
+    };
+    let height = mock_env("addr0000", &[]).block.height;
+
+    let mut deps = mock_dependencies(20, &[]);
+    with_balances(&mut deps, 1_000_000, 1_000_000);
+    init_token_pair(&mut deps);
+
+    // both directions queue in the same block, nothing is paid out yet
+    with_balances(&mut deps, 1_010_000, 1_000_000);
+    let env = mock_env("asset0000", &[]);
+    let res = handle(&mut deps, env, swap("addr0000", 10_000, true, None)).unwrap();
+    assert_eq!(res.messages, vec![]);
+    assert_eq!(
+        res.log,
+        vec![log("action", "queue_batch_swap"), log("batch_swap_id", 0)]
+    );
+    with_balances(&mut deps, 1_010_000, 1_005_000);
+    let env = mock_env("asset0001", &[]);
+    let _res = handle(&mut deps, env, swap("addr0001", 5_000, true, None)).unwrap();
+
+    // a batched swap can't carry a price limit
+    let env = mock_env("asset0000", &[]);
+    match handle(
+        &mut deps,
+        env,
+        swap("addr0002", 2_000, true, Some(Decimal::one())),
+    ) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            "invalid_parameter: A batched swap settles at the clearing price, it takes no price limits or to_msg"
+        ),
+        _ => panic!("Must return generic error"),
+    }
+
+    // only the owner cancels, before the batch settles
+    with_balances(&mut deps, 1_012_000, 1_005_000);
+    let env = mock_env("asset0000", &[]);
+    let _res = handle(&mut deps, env, swap("addr0002", 2_000, true, None)).unwrap();
+    let env = mock_env("addr0001", &[]);
+    match handle(&mut deps, env, HandleMsg::CancelBatchSwap { id: 2 }) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    let env = mock_env("addr0002", &[]);
+    let res = handle(&mut deps, env, HandleMsg::CancelBatchSwap { id: 2 }).unwrap();
+    assert_eq!(
+        res.messages,
+        vec![payout(&deps, "asset0000", 2_000, "addr0002")]
+    );
+    with_balances(&mut deps, 1_010_000, 1_005_000);
+
+    let env = mock_env("keeper0000", &[]);
+    match handle(&mut deps, env, HandleMsg::SettleBatch {}) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "invalid_state: No batch is due for settlement")
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    // both sides clear at 1_005_000 asset0001 per 1_010_000 asset0000: 10_000 asset0000
+    // return 9_950 and 5_000 asset0001 return 5_024, less the 0.3% commission
+    let env = mock_env_with_block_height("keeper0000", &[], height + 1);
+    let res = handle(&mut deps, env, HandleMsg::SettleBatch {}).unwrap();
+    assert_eq!(
+        res.messages,
+        vec![
+            payout(&deps, "asset0001", 9_920, "addr0000"),
+            payout(&deps, "asset0000", 5_008, "addr0001"),
+        ]
+    );
+    assert_eq!(
+        res.log,
+        vec![log("action", "settle_batch"), log("batch_settled_swaps", 2),]
+    );
+    let env = mock_env_with_block_height("addr0001", &[], height + 1);
+    match handle(&mut deps, env, HandleMsg::CancelBatchSwap { id: 1 }) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "not_found: Batch swap not found"),
+        _ => panic!("Must return generic error"),
+    }
+
+    // executed one after the other, the first swap moves the price against itself
+    // and in favor of the second
+    let mut deps = mock_dependencies(20, &[]);
+    with_balances(&mut deps, 1_000_000, 1_000_000);
+    init_token_pair(&mut deps);
+    with_balances(&mut deps, 1_010_000, 1_000_000);
+    let env = mock_env("asset0000", &[]);
+    let first = return_log(&handle(&mut deps, env, swap("addr0000", 10_000, false, None)).unwrap());
+    with_balances(&mut deps, 1_010_000, 1_000_000 - first + 5_000);
+    let env = mock_env("asset0001", &[]);
+    let second = return_log(&handle(&mut deps, env, swap("addr0001", 5_000, false, None)).unwrap());
+    assert_eq!((first, second), (9_870, 5_058));
+    assert!(first < 9_920 && 5_008 < second);
+}
+
+#[test]
+fn due_batch_never_blocks_withdrawals() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1_000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(1_010_000u128),
+            )],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(1_000_000u128),
+            )],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+    let height = mock_env("addr0000", &[]).block.height;
+
+    let msg = HandleMsg::Receive {
+        from: HumanAddr::from("addr0001"),
+        amount: Uint128(10_000u128),
+        msg: Some(
+            to_binary(&Cw20HookMsg::Swap {
+                expected_return: None,
+                belief_price: None,
+                max_spread: None,
+                max_price_impact: None,
+                batch: true,
+                to: None,
+                unwrap: false,
+                to_code_hash: None,
+                to_msg: None,
+                ask_asset_info: None,
+            })
+            .unwrap(),
+        ),
+    };
+    let _res = handle(&mut deps, mock_env("asset0000", &[]), msg).unwrap();
+
+    // the batch is due, but without the factory it can't be priced
+    deps.querier.with_factory_unavailable();
+    let height = height + MAX_SETTINGS_AGE_BLOCKS;
+    let env = mock_env_with_block_height("keeper0000", &[], height);
+    handle(&mut deps, env, HandleMsg::SettleBatch {}).unwrap_err();
+
+    let msg = HandleMsg::Receive {
+        from: HumanAddr::from("addr0000"),
+        amount: Uint128(100u128),
+        msg: Some(
+            to_binary(&Cw20HookMsg::WithdrawLiquidity {
+                unwrap: false,
+                to: None,
+                to_code_hash: None,
+                to_msg: None,
+            })
+            .unwrap(),
+        ),
+    };
+    let env = mock_env_with_block_height("liquidity0000", &[], height);
+    let res = handle(&mut deps, env, msg).unwrap();
+    assert_eq!(res.log[0], log("action", "withdraw_liquidity"));
+    assert!(res.log.iter().all(|l| l.key != "batch_settled_swaps"));
+
+    // the queued swap is still there to cancel
+    let env = mock_env_with_block_height("addr0001", &[], height);
+    let res = handle(&mut deps, env, HandleMsg::CancelBatchSwap { id: 0 }).unwrap();
+    assert_eq!(res.log[0], log("action", "cancel_batch_swap"));
+}
+
+#[test]
+fn batch_swaps_run_the_swap_safeguards() {
+    let height = mock_env("addr0000", &[]).block.height;
+    // queues an `offer_amount` asset0000 batch swap on a new pair with 1_000_000 of each
+    let queued = |settings: PairSettings, offer_amount: u128| {
+        let mut deps = mock_dependencies(20, &[]);
+        deps.querier.with_pair_settings(settings);
+        with_offer_in_pool(&mut deps, 0);
+        init_token_pair(&mut deps);
+        with_offer_in_pool(&mut deps, offer_amount);
+        let msg = HandleMsg::Receive {
+            from: HumanAddr::from("addr0000"),
+            amount: Uint128(offer_amount),
+            msg: Some(
+                to_binary(&Cw20HookMsg::Swap {
+                    expected_return: None,
+                    belief_price: None,
+                    max_spread: None,
+                    max_price_impact: None,
+                    batch: true,
+                    to: None,
+                    unwrap: false,
+                    to_code_hash: None,
+                    to_msg: None,
+                    ask_asset_info: None,
+                })
+                .unwrap(),
+            ),
+        };
+        let _res = handle(&mut deps, mock_env("asset0000", &[]), msg).unwrap();
+        deps
+    };
+    let refund = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>, amount: u128| {
+        vec![Asset {
+            info: token_asset_info("asset0000"),
+            amount: Uint128(amount),
+        }
+        .into_msg(
+            deps,
+            HumanAddr::from(MOCK_CONTRACT_ADDR),
+            HumanAddr::from("addr0000"),
+        )
+        .unwrap()]
+    };
+
+    // a batch that would leave 97_290 of the asset0001 reserve is refunded and
+    // pauses the pair, the same as a single swap
+    let settings = PairSettings {
+        reserve_floor_bps: 1_000,
+        ..test_settings()
+    };
+    let mut deps = queued(settings, 9_278_549);
+    let env = mock_env_with_block_height("keeper0000", &[], height + 1);
+    let res = handle(&mut deps, env, HandleMsg::SettleBatch {}).unwrap();
+    assert_eq!(
+        res.log,
+        vec![
+            log("action", "settle_batch"),
+            log("batch_refund_reason", "reserve_breaker_tripped"),
+            log("batch_refunded_swaps", 1),
+        ]
+    );
+    assert_eq!(res.messages, refund(&deps, 9_278_549));
+    with_offer_in_pool(&mut deps, 1_000);
+    let env = mock_env_with_block_height("asset0000", &[], height + 1);
+    match handle(&mut deps, env, swap_msg(Uint128(1_000u128))) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            "paused: trading is paused by the reserve circuit breaker"
+        ),
+        _ => panic!("Must return generic error"),
+    }
+
+    // min_reserve
+    let settings = PairSettings {
+        min_reserve: Some(Uint128(995_000u128)),
+        ..test_settings()
+    };
+    let mut deps = queued(settings, 10_000);
+    let env = mock_env_with_block_height("keeper0000", &[], height + 1);
+    let res = handle(&mut deps, env, HandleMsg::SettleBatch {}).unwrap();
+    assert!(res.log[1].value.starts_with("insufficient_liquidity: "));
+    assert_eq!(res.messages, refund(&deps, 10_000));
+
+    // a refunded batch doesn't count towards the block volume
+    let settings = PairSettings {
+        max_block_volume_bps: Some(100),
+        ..test_settings()
+    };
+    let mut deps = queued(settings, 20_000);
+    let env = mock_env_with_block_height("keeper0000", &[], height + 1);
+    let res = handle(&mut deps, env, HandleMsg::SettleBatch {}).unwrap();
+    assert_eq!(
+        res.log[1],
+        log(
+            "batch_refund_reason",
+            "paused: volume circuit breaker tripped"
+        )
+    );
+    assert_eq!(res.messages, refund(&deps, 20_000));
+    with_offer_in_pool(&mut deps, 10_000);
+    let env = mock_env_with_block_height("asset0000", &[], height + 1);
+    let _res = handle(&mut deps, env, swap_msg(Uint128(10_000u128))).unwrap();
+
+    // every settled swap is reported to the endpoint, with its return before commission
+    let endpoint = SwapDataEndpoint {
+        address: HumanAddr::from("endpoint0000"),
+        code_hash: "endpoint_code_hash".to_string(),
+    };
+    let settings = PairSettings {
+        swap_data_endpoint: Some(endpoint.clone()),
+        ..test_settings()
+    };
+    let mut deps = queued(settings, 10_000);
+    let env = mock_env_with_block_height("keeper0000", &[], height + 1);
+    let res = handle(&mut deps, env, HandleMsg::SettleBatch {}).unwrap();
+    assert_eq!(res.log[1], log("batch_settled_swaps", 1));
+    assert_eq!(
+        res.messages[1],
+        endpoint
+            .into_msg(
+                Asset {
+                    info: token_asset_info("asset0000"),
+                    amount: Uint128(10_000u128),
+                },
+                Asset {
+                    info: token_asset_info("asset0001"),
+                    amount: Uint128(9_900u128),
+                },
+                HumanAddr::from("addr0000"),
+            )
+            .unwrap()
+    );
+}
+
+#[test]
+fn compound_liquidity() {
+    fn with_pools(deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>, asset0_balance: u128) {
+        deps.querier.with_token_balances(&[
+            (
+                &HumanAddr::from("liquidity0000"),
+                &[(&HumanAddr::from("addr0000"), &Uint128(1_000_000u128))],
+            ),
+            (
+                &HumanAddr::from("asset0000"),
+                &[(
+                    &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                    &Uint128(asset0_balance),
+                )],
+            ),
+            (
+                &HumanAddr::from("asset0001"),
+                &[(
+                    &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                    &Uint128(2_000_000u128),
+                )],
+            ),
+        ]);
+    }
+    let compound = |lp_amount: u128| HandleMsg::Compound {
+        lp_amount: Uint128(lp_amount),
+    };
+
+    let mut deps = mock_dependencies(20, &[]);
+    with_pools(&mut deps, 1_000_000);
+    init_token_pair(&mut deps);
+
+    // an exact pro rata refund mints back every share, nothing moves
+    let env = mock_env("addr0000", &[]);
+    let res = handle(&mut deps, env, compound(300_000)).unwrap();
+    assert_eq!(res.messages, vec![]);
+    assert_eq!(
+        res.log,
+        vec![
+            log("action", "compound"),
+            log("withdrawn_share", "300000"),
+            log("refund_assets", "300000asset0000, 600000asset0001"),
+            log("share", "300000"),
+        ]
+    );
+
+    // 333_333 shares refund 333_333 asset0000 and 666_666 asset0001, which only
+    // mint back 333_331 shares into the pools they were taken out of
+    with_pools(&mut deps, 1_000_003);
+    let env = mock_env("addr0000", &[]);
+    let res = handle(&mut deps, env, compound(333_333)).unwrap();
+    assert_eq!(
+        res.messages,
+        vec![
+            snip20::transfer_from_msg(
+                HumanAddr::from("addr0000"),
+                HumanAddr::from(MOCK_CONTRACT_ADDR),
+                Uint128(2u128),
+                None,
+                256,
+                "lptokenhash".to_string(),
+                HumanAddr::from("liquidity0000"),
+            )
+            .unwrap(),
+            snip20::burn_msg(
+                Uint128(2u128),
+                None,
+                256,
+                "lptokenhash".to_string(),
+                HumanAddr::from("liquidity0000"),
+            )
+            .unwrap(),
+        ]
+    );
+    assert_eq!(res.log[3], log("share", "333331"));
+
+    let env = mock_env("addr0000", &[]);
+    match handle(&mut deps, env, compound(1_000_000)) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            "invalid_parameter: lp_amount must be positive and less than the outstanding LP shares"
+        ),
+        _ => panic!("Must return generic error"),
+    }
+}
+
+#[test]
+fn simulation_reports_no_protocol_commission() {
+    PINNED_RANDOM_NUMBER.with(|pinned| pinned.set(Some(0)));
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    let offer_asset = Asset {
+        info: token_asset_info("asset0000"),
+        amount: Uint128(100000u128),
+    };
+
+    // there's no protocol fee, the LPs keep the whole commission
+    let split = query_simulation(&deps, offer_asset.clone(), None).unwrap();
+    assert!(!split.commission_amount.is_zero());
+    assert_eq!(split.lp_commission_amount, split.commission_amount);
+    assert_eq!(split.protocol_commission_amount, Uint128::zero());
+
+    // and the swap agrees with the simulation
+    with_offer_in_pool(&mut deps, offer_asset.amount.u128());
+    let env = mock_env("asset0000", &[]);
+    let res = handle(&mut deps, env, swap_msg(offer_asset.amount)).unwrap();
+    assert_eq!(commission_log(&res), split.lp_commission_amount.to_string());
+}
+
+#[test]
+fn provide_liquidity_auto_stake() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    let provide = |auto_stake: Option<AutoStake>| HandleMsg::ProvideLiquidity {
+        assets: [
+            Asset {
+                info: token_asset_info("asset0000"),
+                amount: Uint128(100u128),
+            },
+            Asset {
+                info: token_asset_info("asset0001"),
+                amount: Uint128(100u128),
+            },
+        ],
+        slippage_tolerance: None,
+        auto_stake,
+        receiver: None,
+    };
+
+    // nothing registered yet
+    let env = mock_env("addr0000", &[]);
+    match handle(&mut deps, env, provide(Some(AutoStake::Default {}))) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(
+                msg,
+                "invalid_state: No default staking contract is registered"
+            )
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    // only the factory registers the default
+    let set_staking = HandleMsg::SetStakingContract {
+        staking_contract: Some(StakingContract {
+            staking_contract: HumanAddr::from("staking0000"),
+            code_hash: "stakinghash".to_string(),
+        }),
+    };
+    let env = mock_env("addr0000", &[]);
+    match handle(&mut deps, env, set_staking.clone()) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    let env = mock_env("factory0000", &[]);
+    handle(&mut deps, env, set_staking).unwrap();
+
+    // deposits, mint to the pair, then send to the staking contract
+    let env = mock_env("addr0000", &[]);
+    let res = handle(&mut deps, env, provide(Some(AutoStake::Default {}))).unwrap();
+    assert_eq!(
+        res.messages,
+        vec![
+            snip20::transfer_from_msg(
+                HumanAddr::from("addr0000"),
+                HumanAddr::from(MOCK_CONTRACT_ADDR),
+                Uint128(100u128),
+                None,
+                256,
+                "tokenhash".to_string(),
+                HumanAddr::from("asset0000"),
+            )
+            .unwrap(),
+            snip20::transfer_from_msg(
+                HumanAddr::from("addr0000"),
+                HumanAddr::from(MOCK_CONTRACT_ADDR),
+                Uint128(100u128),
+                None,
+                256,
+                "tokenhash".to_string(),
+                HumanAddr::from("asset0001"),
+            )
+            .unwrap(),
+            snip20::mint_msg(
+                HumanAddr::from(MOCK_CONTRACT_ADDR),
+                Uint128(100u128),
+                None,
+                256,
+                "lptokenhash".to_string(),
+                HumanAddr::from("liquidity0000"),
+            )
+            .unwrap(),
+            snip20::send_msg(
+                HumanAddr::from("staking0000"),
+                Uint128(100u128),
+                Some(
+                    to_binary(&StakingHookMsg::StakeFor {
+                        staker: HumanAddr::from("addr0000"),
+                    })
+                    .unwrap()
+                ),
+                None,
+                256,
+                "lptokenhash".to_string(),
+                HumanAddr::from("liquidity0000"),
+            )
+            .unwrap(),
+        ]
+    );
+
+    // an explicit contract overrides the default
+    let env = mock_env("addr0000", &[]);
+    let res = handle(
+        &mut deps,
+        env,
+        provide(Some(AutoStake::Contract {
+            staking_contract: HumanAddr::from("staking0001"),
+            code_hash: "stakinghash".to_string(),
+        })),
+    )
+    .unwrap();
+    match &res.messages[3] {
+        CosmosMsg::Wasm(WasmMsg::Execute { msg, .. }) => {
+            assert!(String::from_utf8_lossy(msg.as_slice()).contains("staking0001"))
+        }
+        _ => panic!("Must return the staking send"),
+    }
+
+    // without auto_stake the shares are minted to the sender
+    let env = mock_env("addr0000", &[]);
+    let res = handle(&mut deps, env, provide(None)).unwrap();
+    assert_eq!(res.messages.len(), 3);
+    assert_eq!(
+        res.messages[2],
+        snip20::mint_msg(
+            HumanAddr::from("addr0000"),
+            Uint128(100u128),
+            None,
+            256,
+            "lptokenhash".to_string(),
+            HumanAddr::from("liquidity0000"),
+        )
+        .unwrap()
+    );
+}
+
+#[test]
+fn lp_token_init_params() {
+    // a pair only initializes once, every case gets a fresh one
+    let init_at_height = |msg: PairInitMsg, height: u64| {
+        let mut deps = mock_dependencies(20, &[]);
+        deps.querier.with_token_balances(&[
+            (
+                &HumanAddr::from("asset0000"),
+                &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0u128))],
+            ),
+            (
+                &HumanAddr::from("asset0001"),
+                &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0u128))],
+            ),
+        ]);
+        init(
+            &mut deps,
+            mock_env_with_block_height("addr0000", &[], height),
+            msg,
+        )
+    };
+    let init_fresh = |msg: PairInitMsg| init_at_height(msg, 12_345);
+    let asset_infos = [token_asset_info("asset0000"), token_asset_info("asset0001")];
+    let lp_token_init = |res: &InitResponse| -> (TokenInitMsg, String) {
+        match &res.messages[4] {
+            CosmosMsg::Wasm(WasmMsg::Instantiate { msg, label, .. }) => {
+                (from_binary(msg).unwrap(), label.clone())
+            }
+            _ => panic!("Must instantiate the LP token"),
+        }
+    };
+
+    // defaults
+    let res = init_fresh(pair_init_msg(asset_infos.clone())).unwrap();
+    let (token_init, label) = lp_token_init(&res);
+    assert_eq!(token_init.symbol, "SWAP-ANON-LP");
+    assert_eq!(token_init.decimals, 18);
+    assert!(token_init.name.contains("asset0000"));
+    assert!(label.contains("asset0000"));
+
+    // the token's seed isn't the caller's, it mixes in the pair and the block
+    let prng_seed = pair_init_msg(asset_infos.clone()).prng_seed;
+    assert_ne!(token_init.prng_seed, prng_seed);
+    assert_eq!(token_init.prng_seed.len(), 32);
+    let (later_token_init, _) =
+        lp_token_init(&init_at_height(pair_init_msg(asset_infos.clone()), 12_346).unwrap());
+    assert_ne!(later_token_init.prng_seed, token_init.prng_seed);
+
+    // custom values and a label that hides the assets
+    let res = init_fresh(PairInitMsg {
+        lp_token_name: Some("Anon LP".to_string()),
+        lp_token_symbol: Some("ANON-LP".to_string()),
+        lp_token_decimals: Some(6),
+        generic_label: true,
+        ..pair_init_msg(asset_infos.clone())
+    })
+    .unwrap();
+    let (token_init, label) = lp_token_init(&res);
+    assert_eq!(token_init.name, "Anon LP");
+    assert_eq!(token_init.symbol, "ANON-LP");
+    assert_eq!(token_init.decimals, 6);
+    assert!(!label.contains("asset0000"));
+    assert!(!label.contains("asset0001"));
+
+    // the default name leaves the assets out too
+    let res = init_fresh(PairInitMsg {
+        generic_label: true,
+        ..pair_init_msg(asset_infos.clone())
+    })
+    .unwrap();
+    let (token_init, _) = lp_token_init(&res);
+    assert!(!token_init.name.contains("asset0000"));
+
+    for (msg, err) in vec![
+        (
+            PairInitMsg {
+                lp_token_symbol: Some("LP_1".to_string()),
+                ..pair_init_msg(asset_infos.clone())
+            },
+            "invalid_parameter: LP token symbol is not in expected format [A-Za-z-]{3,12}",
+        ),
+        (
+            PairInitMsg {
+                lp_token_name: Some("LP".to_string()),
+                ..pair_init_msg(asset_infos.clone())
+            },
+            "invalid_parameter: LP token name is not in the expected format (3-200 UTF-8 bytes)",
+        ),
+        (
+            PairInitMsg {
+                lp_token_decimals: Some(19),
+                ..pair_init_msg(asset_infos.clone())
+            },
+            "invalid_parameter: LP token decimals must not exceed 18",
+        ),
+    ] {
+        match init_fresh(msg) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, err),
+            _ => panic!("Must return generic error"),
+        }
+    }
+}
+
+#[test]
+fn provide_liquidity_slippage_tolerance_range() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    let provide = |slippage_tolerance: Decimal| HandleMsg::ProvideLiquidity {
+        assets: [
+            Asset {
+                info: token_asset_info("asset0000"),
+                amount: Uint128(100u128),
+            },
+            Asset {
+                info: token_asset_info("asset0001"),
+                amount: Uint128(100u128),
+            },
+        ],
+        slippage_tolerance: Some(slippage_tolerance),
+        auto_stake: None,
+        receiver: None,
+    };
+
+    let env = mock_env("addr0000", &[]);
+    match handle(&mut deps, env, provide(Decimal::from_ratio(3u128, 2u128))) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(
+                msg,
+                "invalid_parameter: slippage tolerance must be between 0 and 1"
+            )
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    let env = mock_env("addr0000", &[]);
+    handle(&mut deps, env, provide(Decimal::percent(1))).unwrap();
+}
+
+#[test]
+fn admin_transfer() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0u128))],
+        ),
+    ]);
+    init_pair_with(
+        &mut deps,
+        PairInitMsg {
+            admin: Some(HumanAddr::from("admin0000")),
+            ..pair_init_msg([token_asset_info("asset0000"), token_asset_info("asset0001")])
+        },
+    );
+
+    let set_admin = |new_admin: &str| HandleMsg::SetAdmin {
+        new_admin: Some(HumanAddr::from(new_admin)),
+    };
+
+    let env = mock_env("addr0000", &[]);
+    match handle(&mut deps, env, set_admin("addr0000")) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    let env = mock_env("admin0000", &[]);
+    handle(&mut deps, env, set_admin("admin0001")).unwrap();
+    assert_eq!(
+        read_config(&deps.storage).unwrap().admin,
+        Some(
+            deps.api
+                .canonical_address(&HumanAddr::from("admin0001"))
+                .unwrap()
+        )
+    );
+
+    // the previous admin lost its rights, the new one and the factory have them
+    let staking = HandleMsg::SetStakingContract {
+        staking_contract: None,
+    };
+    let env = mock_env("admin0000", &[]);
+    match handle(&mut deps, env, staking.clone()) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    let env = mock_env("admin0001", &[]);
+    handle(&mut deps, env, staking.clone()).unwrap();
+    let env = mock_env("factory0000", &[]);
+    handle(&mut deps, env, set_admin("admin0002")).unwrap();
+    let env = mock_env("admin0001", &[]);
+    match handle(&mut deps, env, staking) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+}
+
+#[test]
+fn init_validates_assets() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[(
+        &HumanAddr::from("asset0000"),
+        &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0u128))],
+    )]);
+
+    let env = mock_env("addr0000", &[]);
+    let msg = pair_init_msg([token_asset_info("asset0000"), token_asset_info("asset0000")]);
+    match init(&mut deps, env, msg) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "invalid_parameter: identical assets")
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    // asset0001 doesn't answer token_info
+    let env = mock_env("addr0000", &[]);
+    let msg = pair_init_msg([token_asset_info("asset0000"), token_asset_info("asset0001")]);
+    match init(&mut deps, env, msg) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert!(msg.starts_with("invalid_parameter: asset 1 unreachable: "))
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    // the token metadata is kept, native coins go by their denom
+    let env = mock_env("addr0000", &[]);
+    let msg = pair_init_msg([
+        AssetInfo::NativeToken {
+            denom: "uscrt".to_string(),
+        },
+        token_asset_info("asset0000"),
+    ]);
+    init(&mut deps, env, msg).unwrap();
+    let config = read_config(&deps.storage).unwrap();
+    assert_eq!(
+        config.asset_symbols,
+        Some(["uscrt".to_string(), "mAPPL".to_string()])
+    );
+    assert_eq!(config.asset_decimals, Some([6u8, 6u8]));
+}
+
+#[test]
+fn init_verifies_factory() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0u128))],
+        ),
+    ]);
+    let asset_infos = [token_asset_info("asset0000"), token_asset_info("asset0001")];
+
+    // the hook targets another contract than the expected factory
+    let env = mock_env("addr0000", &[]);
+    let msg = PairInitMsg {
+        expected_factory: Some(Factory {
+            address: HumanAddr::from("factory0001"),
+            code_hash: "factoryhash".to_string(),
+        }),
+        ..pair_init_msg(asset_infos.clone())
+    };
+    match init(&mut deps, env, msg) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(
+                msg,
+                "invalid_parameter: The init hook doesn't target the expected factory"
+            )
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    // same address but another code hash
+    let env = mock_env("addr0000", &[]);
+    let msg = PairInitMsg {
+        expected_factory: Some(Factory {
+            address: HumanAddr::from("factory0000"),
+            code_hash: "otherhash".to_string(),
+        }),
+        ..pair_init_msg(asset_infos.clone())
+    };
+    match init(&mut deps, env, msg) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(
+                msg,
+                "invalid_parameter: The init hook doesn't target the expected factory"
+            )
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    // the hook target doesn't answer settings queries
+    deps.querier.with_factory_unavailable();
+    let env = mock_env("addr0000", &[]);
+    match init(&mut deps, env, pair_init_msg(asset_infos.clone())) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert!(msg.starts_with(
+                "invalid_parameter: The factory doesn't answer pair settings queries: "
+            ))
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    deps.querier.with_pair_settings(test_settings());
+    let env = mock_env("addr0000", &[]);
+    let msg = PairInitMsg {
+        expected_factory: Some(Factory {
+            address: HumanAddr::from("factory0000"),
+            code_hash: "factoryhash".to_string(),
+        }),
+        ..pair_init_msg(asset_infos)
+    };
+    let res = init(&mut deps, env, msg).unwrap();
+    assert!(res.log.contains(&log("factory", "factory0000")));
+}
+
+#[test]
+fn stable_curve() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(1_001_000_000u128),
+            )],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(1_000_000_000u128),
+            )],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+    let offer_asset = Asset {
+        info: token_asset_info("asset0000"),
+        amount: Uint128(1_000_000u128),
+    };
+    let settings = |curve| PairSettings {
+        curve,
+        ..test_settings()
+    };
+
+    // a near-peg swap loses ~0.1% to slippage on the constant product curve
+    deps.querier
+        .with_pair_settings(settings(CurveType::ConstantProduct));
+    let constant_product = query_simulation(&deps, offer_asset.clone(), None).unwrap();
+    assert!(constant_product.return_amount < Uint128(997_000u128));
+
+    // but next to none on the stable curve
+    deps.querier
+        .with_pair_settings(settings(CurveType::Stable { amp: 100 }));
+    let stable = query_simulation(&deps, offer_asset.clone(), None).unwrap();
+    assert!(stable.return_amount > constant_product.return_amount + Uint128(900u128));
+    assert!(stable.spread_amount < Uint128(10u128));
+
+    // 999,995 out of the invariant, less the 0.3% commission
+    let env = mock_env("asset0000", &[]);
+    let res = handle(&mut deps, env, swap_msg(offer_asset.amount)).unwrap();
+    assert!(res.log.contains(&log("return_amount", "996995")));
+    assert_eq!(commission_log(&res), "3000");
+
+    // the reverse simulation lands back on the offer amount
+    let res = query_reverse_simulation(
+        &deps,
+        Asset {
+            info: token_asset_info("asset0001"),
+            amount: Uint128(996_995u128),
+        },
+    )
+    .unwrap();
+    assert!(res.offer_amount >= Uint128(999_990u128));
+    assert!(res.offer_amount <= Uint128(1_000_010u128));
+
+    // a stable curve without amplification is rejected
+    deps.querier
+        .with_pair_settings(settings(CurveType::Stable { amp: 0 }));
+    match query_simulation(&deps, offer_asset, None) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "invalid_parameter: amp must be positive")
+        }
+        _ => panic!("Must return generic error"),
+    }
+}
+
+#[test]
+fn stable_quotes_beat_constant_product_near_peg() {
+    let mut deps = mock_dependencies(20, &[]);
+    let with_pools = |deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>,
+                      offer_pool: u128| {
+        deps.querier.with_token_balances(&[
+            (
+                &HumanAddr::from("asset0000"),
+                &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(offer_pool))],
+            ),
+            (
+                &HumanAddr::from("asset0001"),
+                &[(
+                    &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                    &Uint128(1_000_000_000u128),
+                )],
+            ),
+        ])
+    };
+    with_pools(&mut deps, 1_000_000_000);
+    init_token_pair(&mut deps);
+    let settings = |curve| PairSettings {
+        curve,
+        ..test_settings()
+    };
+
+    // pools at or off the peg towards the offered asset, trades up to 5% of a pool
+    for offer_pool in [1_000_000_000u128, 1_002_000_000, 1_010_000_000].iter() {
+        with_pools(&mut deps, *offer_pool);
+        for offer_amount in [100_000u128, 1_000_000, 10_000_000, 50_000_000].iter() {
+            let offer_asset = Asset {
+                info: token_asset_info("asset0000"),
+                amount: Uint128(*offer_amount),
+            };
+            deps.querier
+                .with_pair_settings(settings(CurveType::ConstantProduct));
+            let constant_product = query_simulation(&deps, offer_asset.clone(), None).unwrap();
+            deps.querier
+                .with_pair_settings(settings(CurveType::Stable { amp: 100 }));
+            let stable = query_simulation(&deps, offer_asset, None).unwrap();
+
+            assert!(
+                stable.return_amount > constant_product.return_amount,
+                "offer {} into {}: stable {} <= constant product {}",
+                offer_amount,
+                offer_pool,
+                stable.return_amount,
+                constant_product.return_amount
+            );
+        }
+    }
+}
+
+#[test]
+fn stable_pair_curve_and_amp_ramp() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(1_001_000_000u128),
+            )],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(1_000_000_000u128),
+            )],
+        ),
+    ]);
+    let asset_infos = [token_asset_info("asset0000"), token_asset_info("asset0001")];
+
+    let msg = PairInitMsg {
+        curve: Some(CurveType::Stable { amp: 0 }),
+        ..pair_init_msg(asset_infos.clone())
+    };
+    match init(
+        &mut mock_dependencies(20, &[]),
+        mock_env("addr0000", &[]),
+        msg,
+    ) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "invalid_parameter: amp must be between 1 and 1000000")
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    // the pair's own curve replaces the factory's constant product
+    init_pair_with(
+        &mut deps,
+        PairInitMsg {
+            curve: Some(CurveType::Stable { amp: 100 }),
+            ..pair_init_msg(asset_infos.clone())
+        },
+    );
+    let return_at = |deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>, height: u64| {
+        let env = mock_env_with_block_height("asset0000", &[], height);
+        let res = handle(deps, env, swap_msg(Uint128(1_000_000u128))).unwrap();
+        let return_amount = &res
+            .log
+            .iter()
+            .find(|l| l.key == "return_amount")
+            .expect("no return log")
+            .value;
+        return_amount.parse::<u128>().unwrap()
+    };
+    assert_eq!(return_at(&mut deps, 1_000), 996_995);
+
+    let ramp = |target_amp: u64, end_height: u64| HandleMsg::RampAmp {
+        target_amp,
+        end_height,
+    };
+    let res = handle(
+        &mut deps,
+        mock_env_with_block_height("addr0000", &[], 1_000),
+        ramp(1_000, 21_000),
+    );
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    let assert_ramp_err = |res: HandleResult, err: &str| match res {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, err),
+        _ => panic!("Must return generic error"),
+    };
+    assert_ramp_err(
+        handle(
+            &mut deps,
+            mock_env_with_block_height("factory0000", &[], 1_000),
+            ramp(1_000, 15_399),
+        ),
+        "invalid_parameter: An amp ramp must take at least 14400 blocks",
+    );
+    assert_ramp_err(
+        handle(
+            &mut deps,
+            mock_env_with_block_height("factory0000", &[], 1_000),
+            ramp(1_001, 21_000),
+        ),
+        "invalid_parameter: A ramp can't change the amp of 100 by more than 10x",
+    );
+
+    let res = handle(
+        &mut deps,
+        mock_env_with_block_height("factory0000", &[], 1_000),
+        ramp(1_000, 21_000),
+    )
+    .unwrap();
+    assert_eq!(
+        res.log,
+        vec![
+            log("action", "ramp_amp"),
+            log("initial_amp", 100),
+            log("target_amp", 1_000),
+            log("end_height", 21_000),
+        ]
+    );
+
+    // the amp moves with the blocks, a higher amp flattens the curve around the peg
+    let at_start = return_at(&mut deps, 1_000);
+    let halfway = return_at(&mut deps, 11_000);
+    let at_end = return_at(&mut deps, 21_000);
+    assert_eq!(at_start, 996_995);
+    assert!(halfway > at_start);
+    assert!(at_end > halfway);
+    assert_eq!(return_at(&mut deps, 50_000), at_end);
+
+    // a new ramp starts from the amp the last one reached
+    let res = handle(
+        &mut deps,
+        mock_env_with_block_height("factory0000", &[], 50_000),
+        ramp(100, 70_000),
+    )
+    .unwrap();
+    assert!(res.log.contains(&log("initial_amp", 1_000)));
+
+    // a constant product pair has no amp to ramp
+    let mut deps = mock_dependencies(20, &[]);
+    init_token_pair(&mut deps);
+    assert_ramp_err(
+        handle(
+            &mut deps,
+            mock_env_with_block_height("factory0000", &[], 1_000),
+            ramp(1_000, 21_000),
+        ),
+        "Only a stable pair has an amp to ramp",
+    );
+}
+
+#[test]
+fn stable_pair_shares() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (&HumanAddr::from("liquidity0000"), &[]),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128::zero())],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128::zero())],
+        ),
+    ]);
+    init_pair_with(
+        &mut deps,
+        PairInitMsg {
+            curve: Some(CurveType::Stable { amp: 100 }),
+            ..pair_init_msg([token_asset_info("asset0000"), token_asset_info("asset0001")])
+        },
+    );
+    let estimate = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>, amounts: [u128; 2]| {
+        let msg = QueryMsg::EstimateLpMint {
+            assets: [
+                Asset {
+                    info: token_asset_info("asset0000"),
+                    amount: Uint128(amounts[0]),
+                },
+                Asset {
+                    info: token_asset_info("asset0001"),
+                    amount: Uint128(amounts[1]),
+                },
+            ],
+            slippage_tolerance: None,
+        };
+        from_binary::<EstimateLpMintResponse>(&query(deps, msg).unwrap())
+            .unwrap()
+            .share
+            .u128()
+    };
+
+    // the first deposit mints its invariant, the sum of a balanced deposit
+    assert_eq!(estimate(&deps, [1_000_000, 1_000_000]), 2_000_000);
+
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(2_000_000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(1_000_000u128),
+            )],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(1_000_000u128),
+            )],
+        ),
+    ]);
+    let balanced = estimate(&deps, [1_000, 1_000]);
+    assert!(balanced >= 1_999 && balanced <= 2_000);
+
+    // one asset alone still mints on the stable curve, less half the commission
+    // on the part that unbalances the pools
+    let one_sided = estimate(&deps, [2_000, 0]);
+    assert!(one_sided > 1_990 && one_sided < balanced);
+}
+
+#[test]
+fn weighted_pair_at_50_50_matches_constant_product() {
+    PINNED_RANDOM_NUMBER.with(|pinned| pinned.set(Some(0)));
+    let pair = |weights: Option<[u8; 2]>| {
+        let mut deps = mock_dependencies(20, &[]);
+        deps.querier.with_token_balances(&[
+            (
+                &HumanAddr::from("asset0000"),
+                &[(
+                    &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                    &Uint128(1_000_000_000u128),
+                )],
+            ),
+            (
+                &HumanAddr::from("asset0001"),
+                &[(
+                    &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                    &Uint128(3_000_000_000u128),
+                )],
+            ),
+        ]);
+        init_pair_with(
+            &mut deps,
+            PairInitMsg {
+                weights,
+                ..pair_init_msg([token_asset_info("asset0000"), token_asset_info("asset0001")])
+            },
+        );
+        deps
+    };
+    let legacy = pair(None);
+    let weighted = pair(Some([50, 50]));
+
+    // the weighted math rounds each way against the trader, a unit off at most
+    let assert_close = |weighted: Uint128, legacy: Uint128| {
+        let (a, b) = (weighted.u128(), legacy.u128());
+        assert!(a.max(b) - a.min(b) <= 1, "weighted {} legacy {}", a, b);
+    };
+    for amount in [1_000u128, 1_000_000, 100_000_000, 900_000_000].iter() {
+        for offer_index in 0..2u8 {
+            let res = query_simulation_by_index(&weighted, offer_index, Uint128(*amount)).unwrap();
+            let expected =
+                query_simulation_by_index(&legacy, offer_index, Uint128(*amount)).unwrap();
+            assert!(res.return_amount <= expected.return_amount);
+            assert_close(res.return_amount, expected.return_amount);
+            assert_close(res.spread_amount, expected.spread_amount);
+            assert_close(res.commission_amount, expected.commission_amount);
+
+            let ask_asset = Asset {
+                info: token_asset_info(if offer_index == 0 {
+                    "asset0001"
+                } else {
+                    "asset0000"
+                }),
+                amount: Uint128(*amount),
+            };
+            let res = query_reverse_simulation(&weighted, ask_asset.clone()).unwrap();
+            let expected = query_reverse_simulation(&legacy, ask_asset).unwrap();
+            assert!(res.offer_amount >= expected.offer_amount);
+            assert_close(res.offer_amount, expected.offer_amount);
+        }
+    }
+
+    let weighted_price = query_spot_price_scaled(&weighted).unwrap().price;
+    assert_eq!(
+        weighted_price,
+        query_spot_price_scaled(&legacy).unwrap().price
+    );
+}
+
+#[test]
+fn weighted_pair() {
+    PINNED_RANDOM_NUMBER.with(|pinned| pinned.set(Some(0)));
+    let asset_infos = [token_asset_info("asset0000"), token_asset_info("asset0001")];
+    let assert_init_err = |msg: PairInitMsg, err: &str| match init(
+        &mut mock_dependencies(20, &[]),
+        mock_env("addr0000", &[]),
+        msg,
+    ) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, err),
+        _ => panic!("Must return generic error"),
+    };
+    for weights in [[0u8, 100u8], [60, 50]].iter() {
+        assert_init_err(
+            PairInitMsg {
+                weights: Some(*weights),
+                ..pair_init_msg(asset_infos.clone())
+            },
+            "invalid_parameter: weights must be positive and sum to 100",
+        );
+    }
+    assert_init_err(
+        PairInitMsg {
+            curve: Some(CurveType::Stable { amp: 100 }),
+            weights: Some([80, 20]),
+            ..pair_init_msg(asset_infos.clone())
+        },
+        "invalid_parameter: A pair takes either a curve or weights, not both",
+    );
+
+    // the first deposit mints the weighted geometric mean, 1e6^0.8 * 4e6^0.2
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (&HumanAddr::from("liquidity0000"), &[]),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128::zero())],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128::zero())],
+        ),
+    ]);
+    init_pair_with(
+        &mut deps,
+        PairInitMsg {
+            weights: Some([80, 20]),
+            ..pair_init_msg(asset_infos.clone())
+        },
+    );
+    let msg = QueryMsg::EstimateLpMint {
+        assets: [
+            Asset {
+                info: token_asset_info("asset0000"),
+                amount: Uint128(1_000_000u128),
+            },
+            Asset {
+                info: token_asset_info("asset0001"),
+                amount: Uint128(4_000_000u128),
+            },
+        ],
+        slippage_tolerance: None,
+    };
+    let res: EstimateLpMintResponse = from_binary(&query(&deps, msg).unwrap()).unwrap();
+    assert_eq!(res.share, Uint128(1_319_507u128));
+
+    // 80% of the value in asset0000, the pools are at a 1:1 price. The swap's
+    // 10_000 is already in the pool.
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(4_010_000u128),
+            )],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(1_000_000u128),
+            )],
+        ),
+    ]);
+    let price = query_spot_price_scaled(&deps).unwrap().price;
+    assert_eq!(price, Decimal::from_ratio(400u128, 401u128));
+
+    // 1_000_000 * (1 - (4_000_000 / 4_010_000)^(80 / 20)) = 9_937, less 30 commission,
+    // where a 50/50 pool of the same balances returns less than a third of it
+    let env = mock_env("asset0000", &[]);
+    let res = handle(&mut deps, env, swap_msg(Uint128(10_000u128))).unwrap();
+    assert!(res.log.contains(&log("return_amount", 9_907)));
+    assert!(res.log.contains(&log("spread_amount", 63)));
+    assert!(res.log.contains(&log("commission_amount", 30)));
+}
+
+#[test]
+fn fee_on_input() {
+    PINNED_RANDOM_NUMBER.with(|pinned| pinned.set(Some(0)));
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(1_000_000u128),
+            )],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(1_000_000u128),
+            )],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+    let settings = |fee_on_input| PairSettings {
+        fee_on_input,
+        ..test_settings()
+    };
+    let offer_asset = Asset {
+        info: token_asset_info("asset0000"),
+        amount: Uint128(100_000u128),
+    };
+
+    // 1_000_000 - 1e12 / 1_100_000 = 90_909, less 0.3% of it
+    deps.querier.with_pair_settings(settings(false));
+    let on_output = query_simulation(&deps, offer_asset.clone(), None).unwrap();
+    assert_eq!(on_output.return_amount, Uint128(90_636u128));
+    assert_eq!(on_output.commission_amount, Uint128(273u128));
+    assert_eq!(on_output.spread_amount, Uint128(9_091u128));
+
+    // 0.3% off the offer first, 1_000_000 - 1e12 / 1_099_700 = 90_661. The curve
+    // gives the smaller offer a better rate, so the same rate returns more.
+    deps.querier.with_pair_settings(settings(true));
+    let on_input = query_simulation(&deps, offer_asset, None).unwrap();
+    assert_eq!(on_input.return_amount, Uint128(90_661u128));
+    assert_eq!(on_input.commission_amount, Uint128(248u128));
+    assert_eq!(on_input.spread_amount, on_output.spread_amount);
+
+    // either way the parts add up to the offer at the current price
+    for res in [&on_output, &on_input].iter() {
+        assert_eq!(
+            res.return_amount + res.commission_amount + res.spread_amount,
+            Uint128(100_000u128)
+        );
+    }
+
+    // the reverse simulation grosses up the offer the curve needs, and it buys the ask
+    let ask_asset = Asset {
+        info: token_asset_info("asset0001"),
+        amount: Uint128(90_661u128),
+    };
+    let res = query_reverse_simulation(&deps, ask_asset).unwrap();
+    assert_eq!(res.offer_amount, Uint128(100_000u128));
+    let offer_asset = Asset {
+        info: token_asset_info("asset0000"),
+        amount: res.offer_amount,
+    };
+    let res = query_simulation(&deps, offer_asset, None).unwrap();
+    assert!(res.return_amount >= Uint128(90_661u128));
+}
+
+#[test]
+fn swap_invariant_check() {
+    let check = |curve: CurveType, pools: [u128; 2], offer_amount: u128, return_amount: u128| {
+        assert_swap_invariant(
+            &curve,
+            0,
+            Uint128(pools[0]),
+            Uint128(pools[1]),
+            Uint128(offer_amount),
+            Uint128(return_amount),
+        )
+    };
+    let assert_broken = |res: StdResult<()>| match res {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(
+                msg,
+                "invariant_violated: The swap would decrease the pool invariant"
+            )
+        }
+        _ => panic!("Must return generic error"),
+    };
+
+    // 1e12 / 1_010_000 = 990_099.0099, so 9_900 is all a 10_000 offer can take
+    let pools = [1_000_000u128, 1_000_000u128];
+    check(CurveType::ConstantProduct, pools, 10_000, 9_900).unwrap();
+    assert_broken(check(CurveType::ConstantProduct, pools, 10_000, 9_901));
+    // a return that rounded the wrong way, or left the commission out
+    assert_broken(check(CurveType::ConstantProduct, pools, 10_000, 10_000));
+
+    let stable = CurveType::Stable { amp: 100 };
+    check(stable.clone(), pools, 10_000, 9_998).unwrap();
+    assert_broken(check(stable, pools, 10_000, 10_010));
+
+    // 1_000_000 * (1 - (4_000_000 / 4_010_000)^4) = 9_937.6
+    let weighted = CurveType::Weighted { weights: [80, 20] };
+    check(weighted.clone(), [4_000_000, 1_000_000], 10_000, 9_937).unwrap();
+    assert_broken(check(weighted, [4_000_000, 1_000_000], 10_000, 9_940));
+
+    match check(CurveType::ConstantProduct, pools, 10_000, 1_000_001) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(
+                msg,
+                "insufficient_liquidity: The swap would return more than the ask pool"
+            )
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    // a real swap passes it
+    let mut deps = mock_dependencies(20, &[]);
+    with_offer_in_pool(&mut deps, 10_000);
+    init_token_pair(&mut deps);
+    let env = mock_env("asset0000", &[]);
+    let res = handle(&mut deps, env, swap_msg(Uint128(10_000u128))).unwrap();
+    assert!(res.log.contains(&log("return_amount", 9_870)));
+}
+
+#[test]
+fn rounding_favors_the_pool() {
+    let pair_settings = test_settings();
+    let curve = CurveType::ConstantProduct;
+    let pools = |amounts: [u128; 2]| {
+        [
+            Asset {
+                info: token_asset_info("asset0000"),
+                amount: Uint128(amounts[0]),
+            },
+            Asset {
+                info: token_asset_info("asset0001"),
+                amount: Uint128(amounts[1]),
+            },
+        ]
+    };
+
+    for reserve in 1..=1000u128 {
+        let before = [reserve, 1001 - reserve];
+        for amount in 1..=1000u128 {
+            // a provide and withdraw round trip leaves the pools no smaller
+            let total_share = Uint128(reserve);
+            let share = compute_share(
+                &[Uint128(amount), Uint128(amount)],
+                &pools(before),
+                total_share,
+                &pair_settings,
+            )
+            .unwrap();
+            let after = [before[0] + amount, before[1] + amount];
+            let refunds = compute_refund_assets(&pools(after), share, total_share + share).unwrap();
+            for i in 0..2 {
+                assert!(
+                    after[i] - refunds[i].amount.u128() >= before[i],
+                    "{} into {:?} refunds {}",
+                    amount,
+                    before,
+                    refunds[i]
+                );
+            }
+
+            // a swap keeps the invariant even without a commission to make up for it
+            let (offer_pool, ask_pool) = (Uint128(reserve), Uint128(1000u128));
+            let (return_amount, _, _) = compute_swap_on_curve(
+                &curve,
+                0,
+                offer_pool,
+                ask_pool,
+                Uint128(amount),
+                Uint128::zero(),
+                Uint128(1),
+                Uint128::zero(),
+                false,
+            )
+            .unwrap();
+            assert_swap_invariant(
+                &curve,
+                0,
+                offer_pool,
+                ask_pool,
+                Uint128(amount),
+                return_amount,
+            )
+            .unwrap();
+
+            // the commission rounds up, and the offer quoted for a return buys it
+            let (return_amount, _, commission_amount) = compute_swap_on_curve(
+                &curve,
+                0,
+                offer_pool,
+                ask_pool,
+                Uint128(amount),
+                Uint128(3),
+                Uint128(1000),
+                Uint128::zero(),
+                false,
+            )
+            .unwrap();
+            assert!(
+                commission_amount.u128() * 1000 >= (return_amount + commission_amount).u128() * 3
+            );
+            let (offer_amount, _, _) = compute_offer_amount_on_curve(
+                &curve,
+                0,
+                offer_pool,
+                Uint128(2000u128),
+                Uint128(amount),
+                Uint128(3),
+                Uint128(1000),
+                Uint128::zero(),
+                false,
+            )
+            .unwrap();
+            let (return_amount, _, _) = compute_swap_on_curve(
+                &curve,
+                0,
+                offer_pool,
+                Uint128(2000u128),
+                offer_amount,
+                Uint128(3),
+                Uint128(1000),
+                Uint128::zero(),
+                false,
+            )
+            .unwrap();
+            assert!(return_amount >= Uint128(amount));
+        }
+    }
+}
+
+#[test]
+fn post_initialize_squatting() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0u128))],
+        ),
+        (
+            &HumanAddr::from("squatter0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1000u128))],
+        ),
+    ]);
+    let env = mock_env("addr0000", &[]);
+    let res = init(
+        &mut deps,
+        env,
+        pair_init_msg([token_asset_info("asset0000"), token_asset_info("asset0001")]),
+    )
+    .unwrap();
+    mock_lp_token(&mut deps, &res.messages, "liquidity0000");
+
+    // a contract that doesn't answer token_info can't register itself
+    let env = mock_env("addr0000", &[]);
+    match handle(&mut deps, env, HandleMsg::PostInitialize {}) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    // nor can a token other than the one instantiated at init
+    let env = mock_env("squatter0000", &[]);
+    match handle(&mut deps, env, HandleMsg::PostInitialize {}) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    // a squatter copying the LP token metadata still gets in
+    mock_lp_token(&mut deps, &res.messages, "squatter0000");
+    let env = mock_env("squatter0000", &[]);
+    handle(&mut deps, env, HandleMsg::PostInitialize {}).unwrap();
+    let env = mock_env("liquidity0000", &[]);
+    match handle(&mut deps, env, HandleMsg::PostInitialize {}) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(
+                msg,
+                "invalid_state: The LP token is already registered: squatter0000"
+            )
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    // only the factory can repair the pairing
+    let msg = HandleMsg::ForceSetLiquidityToken {
+        liquidity_token: HumanAddr::from("liquidity0000"),
+    };
+    let env = mock_env("addr0000", &[]);
+    match handle(&mut deps, env, msg.clone()) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    let env = mock_env("factory0000", &[]);
+    let res = handle(&mut deps, env, msg).unwrap();
+    match &res.messages[..] {
+        [CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr,
+            callback_code_hash,
+            ..
+        })] => {
+            assert_eq!(contract_addr, &HumanAddr::from("liquidity0000"));
+            assert_eq!(callback_code_hash, "lptokenhash");
+        }
+        _ => panic!("Must register the pair as receiver"),
+    }
+    let pair_info: PairInfo = query_pair_info(&deps).unwrap();
+    assert_eq!(pair_info.liquidity_token, HumanAddr::from("liquidity0000"));
+}
+
+#[test]
+fn post_initialize_checks_code_hash() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0u128))],
+        ),
+    ]);
+    let env = mock_env("addr0000", &[]);
+    let res = init(
+        &mut deps,
+        env,
+        pair_init_msg([token_asset_info("asset0000"), token_asset_info("asset0001")]),
+    )
+    .unwrap();
+
+    // same metadata as the LP token, different code
+    mock_lp_token(&mut deps, &res.messages, "impostor0000");
+    deps.querier
+        .with_code_hash(&HumanAddr::from("impostor0000"), "impostorhash");
+    let env = mock_env("impostor0000", &[]);
+    match handle(&mut deps, env, HandleMsg::PostInitialize {}) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    mock_lp_token(&mut deps, &res.messages, "liquidity0000");
+    deps.querier
+        .with_code_hash(&HumanAddr::from("liquidity0000"), "lptokenhash");
+    let env = mock_env("liquidity0000", &[]);
+    let res = handle(&mut deps, env, HandleMsg::PostInitialize {}).unwrap();
+    assert!(res.log.contains(&log("verified_code_hash", "lptokenhash")));
+    let pair_info: PairInfo = query_pair_info(&deps).unwrap();
+    assert_eq!(pair_info.liquidity_token, HumanAddr::from("liquidity0000"));
+}
+
+#[test]
+fn update_liquidity_token() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    let msg = HandleMsg::UpdateLiquidityToken {
+        token_code_id: 200u64,
+        token_code_hash: "lptokenhash2".to_string(),
+        prng_seed: Binary::from(b"seed".to_vec()),
+    };
+    let env = mock_env("addr0000", &[]);
+    match handle(&mut deps, env, msg.clone()) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    // outstanding shares would be orphaned
+    let env = mock_env("factory0000", &[]);
+    match handle(&mut deps, env, msg.clone()) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            "invalid_state: The LP token can only be replaced while its supply is zero"
+        ),
+        _ => panic!("Must return generic error"),
+    }
+
+    deps.querier.with_token_balances(&[
+        (&HumanAddr::from("liquidity0000"), &[]),
+        (&HumanAddr::from("asset0000"), &[]),
+        (&HumanAddr::from("asset0001"), &[]),
+    ]);
+    let env = mock_env("factory0000", &[]);
+    let res = handle(&mut deps, env, msg).unwrap();
+    match &res.messages[..] {
+        [CosmosMsg::Wasm(WasmMsg::Instantiate {
+            code_id,
+            callback_code_hash,
+            ..
+        })] => {
+            assert_eq!(*code_id, 200u64);
+            assert_eq!(callback_code_hash, "lptokenhash2");
+        }
+        _ => panic!("Must instantiate the new LP token"),
+    }
+    let pair_info: PairInfo = query_pair_info(&deps).unwrap();
+    assert_eq!(pair_info.liquidity_token, HumanAddr::default());
+
+    // the old token can't come back, it runs the old code
+    mock_lp_token(&mut deps, &res.messages, "liquidity0000");
+    deps.querier
+        .with_code_hash(&HumanAddr::from("liquidity0000"), "lptokenhash");
+    let env = mock_env("liquidity0000", &[]);
+    match handle(&mut deps, env, HandleMsg::PostInitialize {}) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    // the new token registers and the pair re-registers with the factory
+    mock_lp_token(&mut deps, &res.messages, "liquidity0001");
+    deps.querier
+        .with_code_hash(&HumanAddr::from("liquidity0001"), "lptokenhash2");
+    let env = mock_env("liquidity0001", &[]);
+    let res = handle(&mut deps, env, HandleMsg::PostInitialize {}).unwrap();
+    assert!(res.messages.iter().any(|msg| match msg {
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr, msg, ..
+        }) => {
+            contract_addr == &HumanAddr::from("factory0000")
+                && from_binary::<FactoryHandleMsg>(msg).is_ok()
+        }
+        _ => false,
+    }));
+    let pair_info: PairInfo = query_pair_info(&deps).unwrap();
+    assert_eq!(pair_info.liquidity_token, HumanAddr::from("liquidity0001"));
+}
+
+#[test]
+fn pair_creation_block() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0u128))],
+        ),
+    ]);
+    let env = mock_env("addr0000", &[]);
+    let env = Env {
+        block: BlockInfo {
+            height: 4_200_000,
+            time: 1_620_000_000,
+            ..env.block
+        },
+        ..env
+    };
+    init(
+        &mut deps,
+        env,
+        pair_init_msg([token_asset_info("asset0000"), token_asset_info("asset0001")]),
+    )
+    .unwrap();
+
+    let pair_info: PairInfo = query_pair_info(&deps).unwrap();
+    assert_eq!(pair_info.created_at_height, Some(4_200_000));
+    assert_eq!(pair_info.created_at_time, Some(1_620_000_000));
+}
+
+#[test]
+fn init_with_existing_liquidity_token() {
+    let mut deps = mock_dependencies(20, &[]);
+    let set_balances = |querier: &mut WasmMockQuerier, lp_supply: u128| {
+        querier.with_token_balances(&[
+            (
+                &HumanAddr::from("asset0000"),
+                &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0u128))],
+            ),
+            (
+                &HumanAddr::from("asset0001"),
+                &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0u128))],
+            ),
+            (
+                &HumanAddr::from("oldlp0000"),
+                &[(&HumanAddr::from("addr0000"), &Uint128(lp_supply))],
+            ),
+        ])
+    };
+    let existing_msg = |allow_nonzero_supply| PairInitMsg {
+        existing_liquidity_token: Some(ExistingLiquidityToken {
+            address: HumanAddr::from("oldlp0000"),
+            code_hash: "oldlphash".to_string(),
+            allow_nonzero_supply,
+        }),
+        ..pair_init_msg([token_asset_info("asset0000"), token_asset_info("asset0001")])
+    };
+
+    // a token that already has holders is rejected by default
+    set_balances(&mut deps.querier, 1000);
+    let env = mock_env("addr0000", &[]);
+    match init(&mut deps, env, existing_msg(false)) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            "invalid_state: The existing liquidity token must have a zero total supply"
+        ),
+        _ => panic!("Must return generic error"),
+    }
+
+    // unless migrating
+    let env = mock_env("addr0000", &[]);
+    init(&mut deps, env, existing_msg(true)).unwrap();
+
+    // an empty token is paired right away, without instantiating a new one
+    let mut deps = mock_dependencies(20, &[]);
+    set_balances(&mut deps.querier, 0);
+    let env = mock_env("addr0000", &[]);
+    let res = init(&mut deps, env, existing_msg(false)).unwrap();
+    assert!(!res.messages.iter().any(|msg| match msg {
+        CosmosMsg::Wasm(WasmMsg::Instantiate { .. }) => true,
+        _ => false,
+    }));
+    assert!(res.messages.iter().any(|msg| match msg {
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr,
+            callback_code_hash,
+            ..
+        }) => contract_addr == &HumanAddr::from("oldlp0000") && callback_code_hash == "oldlphash",
+        _ => false,
+    }));
+
+    let pair_info: PairInfo = query_pair_info(&deps).unwrap();
+    assert_eq!(pair_info.liquidity_token, HumanAddr::from("oldlp0000"));
+    assert_eq!(pair_info.token_code_hash, "oldlphash");
+
+    let env = mock_env("oldlp0000", &[]);
+    match handle(&mut deps, env, HandleMsg::PostInitialize {}) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(
+                msg,
+                "invalid_state: The LP token is already registered: oldlp0000"
+            )
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    // the default path still instantiates the LP token
+    let mut deps = mock_dependencies(20, &[]);
+    set_balances(&mut deps.querier, 0);
+    let env = mock_env("addr0000", &[]);
+    let res = init(
+        &mut deps,
+        env,
+        pair_init_msg([token_asset_info("asset0000"), token_asset_info("asset0001")]),
+    )
+    .unwrap();
+    assert!(res.messages.iter().any(|msg| match msg {
+        CosmosMsg::Wasm(WasmMsg::Instantiate { .. }) => true,
+        _ => false,
+    }));
+    let pair_info: PairInfo = query_pair_info(&deps).unwrap();
+    assert_eq!(pair_info.liquidity_token, HumanAddr::default());
+}
+
+#[test]
+fn zero_offer_swap() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    let env = mock_env("asset0000", &[]);
+    match handle(&mut deps, env, swap_msg(Uint128::zero())) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "zero_amount: The amount must be greater than zero")
+        }
+        _ => panic!("Must return generic error"),
+    }
+    assert_eq!(read_stats(&deps.storage).unwrap(), Stats::default());
+}
+
+#[test]
+fn double_initialization() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    let env = mock_env("addr0000", &[]);
+    match init(
+        &mut deps,
+        env,
+        pair_init_msg([token_asset_info("asset0000"), token_asset_info("asset0001")]),
+    ) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "invalid_state: Pair is already initialized")
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    // the LP token calling back twice learns which token is registered
+    let env = mock_env("liquidity0000", &[]);
+    match handle(&mut deps, env, HandleMsg::PostInitialize {}) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(
+                msg,
+                "invalid_state: The LP token is already registered: liquidity0000"
+            )
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    // pair age is available from the pair info
+    let pair_info: PairInfo = query_pair_info(&deps).unwrap();
+    assert_eq!(
+        pair_info.created_at_height,
+        Some(mock_env("addr0000", &[]).block.height)
+    );
+}
+
+#[test]
+fn provide_liquidity_for_receiver() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    // a vault deposits its own funds and the user gets the shares
+    let env = mock_env("vault0000", &[]);
+    let msg = HandleMsg::ProvideLiquidity {
+        assets: [
+            Asset {
+                info: token_asset_info("asset0000"),
+                amount: Uint128(100u128),
+            },
+            Asset {
+                info: token_asset_info("asset0001"),
+                amount: Uint128(100u128),
+            },
+        ],
+        slippage_tolerance: None,
+        auto_stake: None,
+        receiver: Some(HumanAddr::from("user0000")),
+    };
+    let res = handle(&mut deps, env, msg).unwrap();
+    assert_eq!(
+        res.messages,
+        vec![
+            snip20::transfer_from_msg(
+                HumanAddr::from("vault0000"),
+                HumanAddr::from(MOCK_CONTRACT_ADDR),
+                Uint128(100u128),
+                None,
+                256,
+                "tokenhash".to_string(),
+                HumanAddr::from("asset0000"),
+            )
+            .unwrap(),
+            snip20::transfer_from_msg(
+                HumanAddr::from("vault0000"),
+                HumanAddr::from(MOCK_CONTRACT_ADDR),
+                Uint128(100u128),
+                None,
+                256,
+                "tokenhash".to_string(),
+                HumanAddr::from("asset0001"),
+            )
+            .unwrap(),
+            snip20::mint_msg(
+                HumanAddr::from("user0000"),
+                Uint128(100u128),
+                None,
+                256,
+                "lptokenhash".to_string(),
+                HumanAddr::from("liquidity0000"),
+            )
+            .unwrap(),
+        ]
+    );
+    assert!(res.log.contains(&log("receiver", "user0000")));
+}
+
+#[test]
+fn u256_to_uint128_checked() {
+    assert_eq!(
+        u256_to_uint128(U256::from(u128::MAX)).unwrap(),
+        Uint128(u128::MAX)
+    );
+    assert_eq!(u256_to_uint128(U256::zero()).unwrap(), Uint128::zero());
+
+    // one past u128::MAX used to truncate to zero
+    let too_big = U256::from(u128::MAX) + U256::one();
+    match u256_to_uint128(too_big) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(
+                msg,
+                format!("overflow: Cannot calculate {} as a Uint128", too_big)
+            )
+        }
+        _ => panic!("Must return generic error"),
+    }
+    assert!(u256_to_uint128(U256::max_value()).is_err());
+}
+
+#[test]
+fn provide_liquidity_share_overflow() {
+    // a supply far above the pools, so a modest deposit mints past u128::MAX
+    let total_share = u128::MAX / 2;
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(total_share))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1_000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1_000u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    // up to the pools' size the share still fits
+    assert_eq!(
+        provide_token_liquidity(&mut deps, 1_000, 1_000),
+        Uint128(total_share)
+    );
+
+    let msg = HandleMsg::ProvideLiquidity {
+        assets: [
+            Asset {
+                info: token_asset_info("asset0000"),
+                amount: Uint128(3_000u128),
+            },
+            Asset {
+                info: token_asset_info("asset0001"),
+                amount: Uint128(3_000u128),
+            },
+        ],
+        slippage_tolerance: None,
+        auto_stake: None,
+        receiver: None,
+    };
+    let too_big = U256::from(total_share) * U256::from(3u64);
+    match handle(&mut deps, mock_env("addr0000", &[]), msg) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(
+                msg,
+                format!("overflow: Cannot calculate {} as a Uint128", too_big)
+            )
+        }
+        _ => panic!("Must return generic error"),
+    }
+}
+
+/// Provides both token assets from addr0000 and returns the minted share
+fn provide_token_liquidity<A: Api>(
+    deps: &mut Extern<MockStorage, A, WasmMockQuerier>,
+    amount0: u128,
+    amount1: u128,
+) -> Uint128 {
+    let msg = HandleMsg::ProvideLiquidity {
+        assets: [
+            Asset {
+                info: token_asset_info("asset0000"),
+                amount: Uint128(amount0),
+            },
+            Asset {
+                info: token_asset_info("asset0001"),
+                amount: Uint128(amount1),
+            },
+        ],
+        slippage_tolerance: None,
+        auto_stake: None,
+        receiver: None,
+    };
+    let res = handle(deps, mock_env("addr0000", &[]), msg).unwrap();
+    from_binary::<ProvideLiquidityResponse>(&res.data.unwrap())
+        .unwrap()
+        .share
+}
+
+#[test]
+fn initial_share_multiplier() {
+    // seeds an empty pair with `initial`, then makes a 10% proportional deposit.
+    // Returns the initial share and the part per million of the second deposit
+    // that its share can't redeem.
+    let deposit_loss_ppm = |multiplier: Option<Uint128>, initial: (u128, u128)| {
+        let mut deps = mock_dependencies(20, &[]);
+        deps.querier.with_pair_settings(PairSettings {
+            initial_share_multiplier: multiplier,
+            ..test_settings()
+        });
+        deps.querier.with_token_balances(&[
+            (&HumanAddr::from("liquidity0000"), &[]),
+            (&HumanAddr::from("asset0000"), &[]),
+            (&HumanAddr::from("asset0001"), &[]),
+        ]);
+        init_token_pair(&mut deps);
+
+        let initial_share = provide_token_liquidity(&mut deps, initial.0, initial.1);
+
+        deps.querier.with_token_balances(&[
+            (
+                &HumanAddr::from("liquidity0000"),
+                &[(&HumanAddr::from("addr0000"), &initial_share)],
+            ),
+            (
+                &HumanAddr::from("asset0000"),
+                &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(initial.0))],
+            ),
+            (
+                &HumanAddr::from("asset0001"),
+                &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(initial.1))],
+            ),
+        ]);
+        let second = (initial.0 / 10, initial.1 / 10);
+        let share = provide_token_liquidity(&mut deps, second.0, second.1);
+
+        // asset0 redeemable for `share` once both deposits are in the pool
+        let total_share = U256::from(initial_share.u128() + share.u128());
+        let redeemable_ppm =
+            U256::from(share.u128()) * U256::from(initial.0 + second.0) * U256::from(1_000_000u128)
+                / total_share
+                / U256::from(second.0);
+        (initial_share, 1_000_000u128 - redeemable_ppm.low_u128())
+    };
+
+    // two 0 decimal assets: sqrt(30 * 70) = 45 shares, the 10% deposit is
+    // worth 4.5 of them and gets 4
+    assert_eq!(deposit_loss_ppm(None, (30, 70)), (Uint128(45), 102_041));
+    let (initial_share, loss_ppm) = deposit_loss_ppm(Some(Uint128(1_000_000)), (30, 70));
+    assert_eq!(initial_share, Uint128(45_000_000));
+    assert!(loss_ppm <= 1);
+
+    // 6 and 0 decimals
+    assert_eq!(
+        deposit_loss_ppm(None, (35_000_000, 30)),
+        (Uint128(32_403), 85)
+    );
+    let (initial_share, loss_ppm) = deposit_loss_ppm(Some(Uint128(1_000_000)), (35_000_000, 30));
+    assert_eq!(initial_share, Uint128(32_403_000_000));
+    assert!(loss_ppm <= 1);
+
+    // 18 and 6 decimals already give a comfortable supply, scaling it keeps
+    // the rounding just as fine
+    for multiplier in [None, Some(Uint128(1_000_000))].iter().cloned() {
+        let (_, loss_ppm) = deposit_loss_ppm(multiplier, (7_000_000_000_000_000_000, 3_000_000));
+        assert!(loss_ppm <= 1);
+    }
+}
+
+#[test]
+fn snapshot() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1000000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1001000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    let snapshot: SnapshotResponse =
+        from_binary(&query(&deps, QueryMsg::Snapshot {}).unwrap()).unwrap();
+    assert_eq!(snapshot.swap_count, 0);
+    assert_eq!(snapshot.last_swap_at_height, None);
+    assert_eq!(snapshot.last_swap_at_time, None);
+
+    let env = mock_env("asset0000", &[]);
+    handle(&mut deps, env.clone(), swap_msg(Uint128(1000u128))).unwrap();
+
+    let snapshot: SnapshotResponse =
+        from_binary(&query(&deps, QueryMsg::Snapshot {}).unwrap()).unwrap();
+    let pool = query_pool(&deps).unwrap();
+    assert_eq!(snapshot.assets, pool.assets);
+    assert_eq!(snapshot.total_share, pool.total_share);
+
+    let pair_info = query_pair_info(&deps).unwrap();
+    assert_eq!(snapshot.asset0_volume, pair_info.asset0_volume);
+    assert_eq!(snapshot.asset0_volume, Uint128(1000u128));
+    assert_eq!(snapshot.asset1_volume, pair_info.asset1_volume);
+    assert_eq!(
+        snapshot.swap_count,
+        read_stats(&deps.storage).unwrap().swap_count
+    );
+    assert_eq!(snapshot.swap_count, 1);
+
+    assert_eq!(
+        snapshot.swap_fee,
+        Fee {
+            commission_rate_nom: Uint128(3),
+            commission_rate_denom: Uint128(1000),
+            min_commission: Uint128::zero(),
+        }
+    );
+    assert_eq!(snapshot.last_swap_at_height, Some(env.block.height));
+    assert_eq!(snapshot.last_swap_at_time, Some(env.block.time));
+}
+
+#[test]
+fn zero_commission_denom() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1000000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1001000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    let settings = PairSettings {
+        swap_fee: Fee {
+            commission_rate_denom: Uint128::zero(),
+            ..test_settings().swap_fee
+        },
+        ..test_settings()
+    };
+    deps.querier.with_pair_settings(settings.clone());
+    let assert_friendly_err = |res: StdResult<_>| match res {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            "invalid_state: Invalid commission configuration: commission_rate_denom is zero"
+        ),
+        _ => panic!("Must return generic error"),
+    };
+
+    let env = mock_env("asset0000", &[]);
+    assert_friendly_err(handle(&mut deps, env, swap_msg(Uint128(1000u128))).map(|_| ()));
+    assert_friendly_err(
+        query_simulation(
+            &deps,
+            Asset {
+                info: token_asset_info("asset0000"),
+                amount: Uint128(1000u128),
+            },
+            None,
+        )
+        .map(|_| ()),
+    );
+
+    // the factory can't push it either
+    let env = mock_env("factory0000", &[]);
+    assert_friendly_err(handle(&mut deps, env, HandleMsg::PushSettings { settings }).map(|_| ()));
+}
+
+#[test]
+fn pair_fee_override() {
+    // the same factory overrides the commission of pair0001 only
+    let pair_at = |pair: &str| {
+        let pair = HumanAddr::from(pair);
+        let mut deps = mock_dependencies(20, &[]);
+        deps.querier.with_token_balances(&[
+            (
+                &HumanAddr::from("liquidity0000"),
+                &[(&HumanAddr::from("addr0000"), &Uint128(1000000u128))],
+            ),
+            (
+                &HumanAddr::from("asset0000"),
+                &[(&pair, &Uint128(1000000u128))],
+            ),
+            (
+                &HumanAddr::from("asset0001"),
+                &[(&pair, &Uint128(1000000u128))],
+            ),
+        ]);
+        deps.querier
+            .with_pair_fee(&HumanAddr::from("pair0001"), Uint128(1), Uint128(10000));
+
+        let mut env = mock_env("addr0000", &[]);
+        env.contract.address = pair.clone();
+        let msg = pair_init_msg([token_asset_info("asset0000"), token_asset_info("asset0001")]);
+        let res = init(&mut deps, env, msg).unwrap();
+        mock_lp_token(&mut deps, &res.messages, "liquidity0000");
+        let mut env = mock_env("liquidity0000", &[]);
+        env.contract.address = pair;
+        handle(&mut deps, env, HandleMsg::PostInitialize {}).unwrap();
+        deps
+    };
+    // commission and pre-commission return of a simulation
+    let simulated_rate = |pair: &str| {
+        let res = query_simulation(
+            &pair_at(pair),
+            Asset {
+                info: token_asset_info("asset0000"),
+                amount: Uint128(100000u128),
+            },
+            None,
+        )
+        .unwrap();
+        (
+            res.commission_amount.u128(),
+            res.return_amount.u128() + res.commission_amount.u128(),
+        )
+    };
+
+    // pair0000 keeps the global 0.3%, pair0001 charges its 0.01%, both rounded up
+    let (commission, before_commission) = simulated_rate("pair0000");
+    assert_eq!(commission, (before_commission * 3 + 999) / 1000);
+    let (commission, before_commission) = simulated_rate("pair0001");
+    assert_eq!(commission, (before_commission + 9999) / 10000);
+    assert!(commission > 0);
+}
+
+#[test]
+fn swap_with_ask_asset_info() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1000000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1001000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    let swap_for = |ask_asset_info: AssetInfo| HandleMsg::Receive {
+        from: HumanAddr::from("addr0000"),
+        amount: Uint128(1000u128),
+        msg: Some(
+            to_binary(&Cw20HookMsg::Swap {
+                expected_return: None,
+                belief_price: None,
+                max_spread: None,
+                max_price_impact: None,
+                batch: false,
+                to: None,
+                unwrap: false,
+                to_code_hash: None,
+                to_msg: None,
+                ask_asset_info: Some(ask_asset_info),
+            })
+            .unwrap(),
+        ),
+    };
+
+    let env = mock_env("asset0000", &[]);
+    let res = handle(&mut deps, env, swap_for(token_asset_info("asset0001"))).unwrap();
+    assert!(res.log.contains(&log("ask_asset", "asset0001")));
+
+    // neither the offered asset nor a foreign one is the ask pool
+    for ask_asset_info in [
+        token_asset_info("asset0000"),
+        AssetInfo::NativeToken {
+            denom: "uscrt".to_string(),
+        },
+    ]
+    .iter()
+    {
+        let env = mock_env("asset0000", &[]);
+        match handle(&mut deps, env, swap_for(ask_asset_info.clone())) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+                msg,
+                format!(
+                    "invalid_parameter: ask_asset_info {} doesn't match the ask pool of this pair",
+                    ask_asset_info
+                )
+            ),
+            _ => panic!("Must return generic error"),
+        }
+    }
+}
+
+#[test]
+fn global_pause() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1000000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1001000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+    ]);
+    let settings = |paused| PairSettings {
+        paused,
+        ..test_settings()
+    };
+    let provide_msg = HandleMsg::ProvideLiquidity {
+        assets: [
+            Asset {
+                info: token_asset_info("asset0000"),
+                amount: Uint128(100u128),
+            },
+            Asset {
+                info: token_asset_info("asset0001"),
+                amount: Uint128(100u128),
+            },
+        ],
+        slippage_tolerance: None,
+        auto_stake: None,
+        receiver: None,
+    };
+    let assert_paused = |res: HandleResult| match res {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "paused: trading is globally paused")
+        }
+        _ => panic!("Must return generic error"),
+    };
+    init_token_pair(&mut deps);
+
+    // swapping at height 100 caches `paused: false`
+    let env = mock_env_with_block_height("asset0000", &[], 100);
+    handle(&mut deps, env, swap_msg(Uint128(1000u128))).unwrap();
+
+    // the factory pauses, the cache is only trusted until its TTL
+    deps.querier.with_pair_settings(settings(true));
+    let env = mock_env_with_block_height("asset0000", &[], 150);
+    handle(&mut deps, env, swap_msg(Uint128(1000u128))).unwrap();
+    let env = mock_env_with_block_height("asset0000", &[], 200);
+    assert_paused(handle(&mut deps, env, swap_msg(Uint128(1000u128))));
+    let env = mock_env_with_block_height("addr0000", &[], 200);
+    assert_paused(handle(&mut deps, env, provide_msg.clone()));
+
+    // withdrawals stay open
+    let env = mock_env_with_block_height("liquidity0000", &[], 200);
+    let msg = HandleMsg::Receive {
+        from: HumanAddr::from("addr0000"),
+        amount: Uint128(100u128),
+        msg: Some(
+            to_binary(&Cw20HookMsg::WithdrawLiquidity {
+                unwrap: false,
+                to: None,
+                to_code_hash: None,
+                to_msg: None,
+            })
+            .unwrap(),
+        ),
+    };
+    handle(&mut deps, env, msg).unwrap();
+
+    // the pushed resume applies right away
+    let env = mock_env_with_block_height("factory0000", &[], 201);
+    handle(
+        &mut deps,
+        env,
+        HandleMsg::PushSettings {
+            settings: settings(false),
+        },
+    )
+    .unwrap();
+    let env = mock_env_with_block_height("addr0000", &[], 201);
+    handle(&mut deps, env, provide_msg).unwrap();
+}
+
+#[test]
+fn settings_ttl_capped_for_pause() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1000000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1001000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+    ]);
+    let asset_infos = [token_asset_info("asset0000"), token_asset_info("asset0001")];
+
+    // a TTL has to stay below MAX_SETTINGS_AGE_BLOCKS
+    let msg = PairInitMsg {
+        settings_ttl_blocks: Some(MAX_SETTINGS_AGE_BLOCKS),
+        ..pair_init_msg(asset_infos.clone())
+    };
+    match init(&mut deps, mock_env("addr0000", &[]), msg) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            format!(
+                "invalid_parameter: settings_ttl_blocks must be below {}",
+                MAX_SETTINGS_AGE_BLOCKS
+            )
+        ),
+        _ => panic!("Must return generic error"),
+    }
+    init_pair_with(
+        &mut deps,
+        PairInitMsg {
+            settings_ttl_blocks: Some(MAX_SETTINGS_AGE_BLOCKS - 1),
+            ..pair_init_msg(asset_infos)
+        },
+    );
+
+    let env = mock_env_with_block_height("asset0000", &[], 100);
+    handle(&mut deps, env, swap_msg(Uint128(1000u128))).unwrap();
+
+    // the longest TTL doesn't delay a pause past MAX_SETTINGS_AGE_BLOCKS
+    let mut paused = read_settings_cache(&deps.storage)
+        .unwrap()
+        .unwrap()
+        .settings;
+    paused.paused = true;
+    deps.querier.with_pair_settings(paused);
+    let env = mock_env_with_block_height("asset0000", &[], 100 + MAX_SETTINGS_AGE_BLOCKS - 2);
+    handle(&mut deps, env, swap_msg(Uint128(1000u128))).unwrap();
+    let env = mock_env_with_block_height("asset0000", &[], 100 + MAX_SETTINGS_AGE_BLOCKS - 1);
+    match handle(&mut deps, env, swap_msg(Uint128(1000u128))) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "paused: trading is globally paused")
+        }
+        _ => panic!("Must return generic error"),
+    }
+}
+
+#[test]
+fn reverse_liquidity() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (&HumanAddr::from("liquidity0000"), &[]),
+        (&HumanAddr::from("asset0000"), &[]),
+        (&HumanAddr::from("asset0001"), &[]),
+    ]);
+    init_token_pair(&mut deps);
+
+    // the first deposit sets the ratio, there's nothing to invert
+    let msg = QueryMsg::ReverseLiquidity {
+        lp_amount: Uint128(100u128),
+    };
+    match query(&deps, msg) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(
+                msg,
+                "insufficient_liquidity: The pool is empty, the first deposit sets its ratio"
+            )
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    // one share is worth 3 asset0000 and 0.7 asset0001
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1_000_000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(3_000_000u128),
+            )],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(700_000u128))],
+        ),
+    ]);
+
+    for lp_amount in [1u128, 7, 10, 333_333, 1_000_000].iter() {
+        let msg = QueryMsg::ReverseLiquidity {
+            lp_amount: Uint128(*lp_amount),
+        };
+        let res: ReverseLiquidityResponse = from_binary(&query(&deps, msg).unwrap()).unwrap();
+        assert_eq!(res.assets[0].info, token_asset_info("asset0000"));
+        assert_eq!(res.assets[1].info, token_asset_info("asset0001"));
+        let deposit = (res.assets[0].amount.u128(), res.assets[1].amount.u128());
+
+        // depositing the answer mints the target
+        let share = provide_token_liquidity(&mut deps, deposit.0, deposit.1);
+        assert!(share.u128() >= *lp_amount);
+        assert!(share.u128() - *lp_amount <= 1);
+
+        // and it is the smallest deposit that does
+        let share = provide_token_liquidity(&mut deps, deposit.0 - 1, deposit.1);
+        assert!(share.u128() < *lp_amount);
+        let share = provide_token_liquidity(&mut deps, deposit.0, deposit.1 - 1);
+        assert!(share.u128() < *lp_amount);
+    }
+}
+
+#[test]
+fn data_endpoint_removal() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1000000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1001000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+    ]);
+    let settings = |swap_data_endpoint| PairSettings {
+        swap_data_endpoint,
+        ..test_settings()
+    };
+    let notifies_endpoint = |res: HandleResponse| {
+        res.messages.iter().any(|msg| match msg {
+            CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, .. }) => {
+                contract_addr == &HumanAddr::from("endpoint0000")
+            }
+            _ => false,
+        })
+    };
+    deps.querier
+        .with_pair_settings(settings(Some(SwapDataEndpoint {
+            address: HumanAddr::from("endpoint0000"),
+            code_hash: "endpoint_code_hash".to_string(),
+        })));
+    init_token_pair(&mut deps);
+
+    let env = mock_env_with_block_height("asset0000", &[], 100);
+    let res = handle(&mut deps, env, swap_msg(Uint128(1000u128))).unwrap();
+    assert!(notifies_endpoint(res));
+
+    // the factory removes the endpoint, pairs stop calling it once they refresh
+    deps.querier.with_pair_settings(settings(None));
+    let env = mock_env_with_block_height("asset0000", &[], 100 + MAX_SETTINGS_AGE_BLOCKS);
+    let res = handle(&mut deps, env, swap_msg(Uint128(1000u128))).unwrap();
+    assert!(!notifies_endpoint(res));
+}
+
+#[test]
+fn pair_swap_data_endpoint() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1000000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1001000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+    ]);
+    let endpoint = |address: &str| SwapDataEndpoint {
+        address: HumanAddr::from(address),
+        code_hash: "endpoint_code_hash".to_string(),
+    };
+    let notified = |res: &HandleResponse| -> Vec<HumanAddr> {
+        res.messages
+            .iter()
+            .filter_map(|msg| match msg {
+                CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, .. })
+                    if contract_addr.as_str().starts_with("endpoint") =>
+                {
+                    Some(contract_addr.clone())
+                }
+                _ => None,
+            })
+            .collect()
+    };
+    let preview = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>| {
+        let msg = QueryMsg::SwapDataPreview {
+            offer_asset: Asset {
+                info: token_asset_info("asset0000"),
+                amount: Uint128(1000u128),
+            },
+            recipient: HumanAddr::from("addr0000"),
+        };
+        from_binary::<Option<SwapDataPreviewResponse>>(&query(deps, msg).unwrap())
+            .unwrap()
+            .map(|preview| preview.endpoint)
+    };
+    deps.querier.with_pair_settings(PairSettings {
+        swap_data_endpoint: Some(endpoint("endpoint0000")),
+        ..test_settings()
+    });
+    init_token_pair(&mut deps);
+
+    let msg = HandleMsg::SetSwapDataEndpoint {
+        endpoint: Some(endpoint("endpoint0001")),
+    };
+    match handle(&mut deps, mock_env("addr0001", &[]), msg.clone()) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    let res = handle(&mut deps, mock_env("factory0000", &[]), msg).unwrap();
+    assert_eq!(
+        res.log,
+        vec![
+            log("action", "set_swap_data_endpoint"),
+            log("endpoint", "endpoint0001"),
+        ]
+    );
+
+    // the pair's endpoint takes precedence over the factory's
+    let env = mock_env("asset0000", &[]);
+    let res = handle(&mut deps, env, swap_msg(Uint128(1000u128))).unwrap();
+    assert_eq!(notified(&res), vec![HumanAddr::from("endpoint0001")]);
+    assert_eq!(preview(&deps), Some(endpoint("endpoint0001")));
+
+    // and clearing it goes back to the factory's
+    let msg = HandleMsg::SetSwapDataEndpoint { endpoint: None };
+    let _res = handle(&mut deps, mock_env("factory0000", &[]), msg).unwrap();
+    let env = mock_env("asset0000", &[]);
+    let res = handle(&mut deps, env, swap_msg(Uint128(1000u128))).unwrap();
+    assert_eq!(notified(&res), vec![HumanAddr::from("endpoint0000")]);
+    assert_eq!(preview(&deps), Some(endpoint("endpoint0000")));
+}
+
+#[test]
+fn withdraw_liquidity_to_single_asset() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_pair_settings(test_settings());
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1_000_000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(2_000_000u128),
+            )],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(1_000_000u128),
+            )],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    let withdraw_to = |asset: &str, min_received: Option<u128>| HandleMsg::Receive {
+        from: HumanAddr::from("addr0000"),
+        msg: Some(
+            to_binary(&Cw20HookMsg::WithdrawLiquidityTo {
+                asset_info: token_asset_info(asset),
+                min_received: min_received.map(Uint128),
+            })
+            .unwrap(),
+        ),
+        amount: Uint128(100_000u128),
+    };
+    let burn = snip20::burn_msg(
+        Uint128(100_000u128),
+        None,
+        256,
+        "lptokenhash".to_string(),
+        HumanAddr::from("liquidity0000"),
+    )
+    .unwrap();
+
+    // 10% of the pool is 200_000 asset0000 and 100_000 asset0001. The asset0001
+    // is sold into the 1_800_000 / 900_000 left behind for 180_000 asset0000,
+    // minus a 540 commission.
+    let env = mock_env("liquidity0000", &[]);
+    let res = handle(&mut deps, env, withdraw_to("asset0000", Some(379_460))).unwrap();
+    assert_eq!(
+        res.messages,
+        vec![
+            burn.clone(),
+            Asset {
+                info: token_asset_info("asset0000"),
+                amount: Uint128(379_460u128),
+            }
+            .into_msg(
+                &deps,
+                HumanAddr::from(MOCK_CONTRACT_ADDR),
+                HumanAddr::from("addr0000"),
+            )
+            .unwrap(),
+        ]
+    );
+    assert!(res.log.contains(&log("commission_amount", "540")));
+    let stats = read_stats(&deps.storage).unwrap();
+    assert_eq!(stats.asset1_volume, Uint128(100_000u128));
+    assert_eq!(stats.swap_count, 1);
+
+    // the other way 200_000 asset0000 buy 90_000 asset0001 minus 270
+    let env = mock_env("liquidity0000", &[]);
+    let res = handle(&mut deps, env, withdraw_to("asset0001", None)).unwrap();
+    assert_eq!(
+        res.messages,
+        vec![
+            burn,
+            Asset {
+                info: token_asset_info("asset0001"),
+                amount: Uint128(189_730u128),
+            }
+            .into_msg(
+                &deps,
+                HumanAddr::from(MOCK_CONTRACT_ADDR),
+                HumanAddr::from("addr0000"),
+            )
+            .unwrap(),
+        ]
+    );
+    let stats = read_stats(&deps.storage).unwrap();
+    assert_eq!(stats.asset0_volume, Uint128(200_000u128));
+    assert_eq!(stats.swap_count, 2);
+
+    // slippage protection
+    let env = mock_env("liquidity0000", &[]);
+    match handle(&mut deps, env, withdraw_to("asset0001", Some(189_731))) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            "slippage_exceeded: Operation exceeds max slippage: received 189730 is less than min_received 189731"
+        ),
+        _ => panic!("Must return generic error"),
+    }
+
+    // only the pair's assets can be asked for
+    let env = mock_env("liquidity0000", &[]);
+    match handle(&mut deps, env, withdraw_to("asset0002", None)) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(
+                msg,
+                "asset_not_in_pair: asset0002 is not one of the pair's assets"
+            )
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    // and only the LP token can start it
+    let env = mock_env("asset0000", &[]);
+    match handle(&mut deps, env, withdraw_to("asset0000", None)) {
+        Err(StdError::Unauthorized { .. }) => (),
+        _ => panic!("Must return unauthorized error"),
+    }
+}
+
+#[test]
+fn reverse_simulation_high_commission() {
+    let mut deps = mock_dependencies(20, &[]);
+    let settings = |commission_rate_nom: u128, commission_rate_denom: u128| PairSettings {
+        swap_fee: Fee {
+            commission_rate_nom: Uint128(commission_rate_nom),
+            commission_rate_denom: Uint128(commission_rate_denom),
+            ..test_settings().swap_fee
+        },
+        ..test_settings()
+    };
+    // 18 decimal tokens, the product of the pools doesn't fit in a Uint128
+    let pool = Uint128(100_000_000_000_000_000_000u128);
+    deps.querier.with_token_balances(&[
+        (&HumanAddr::from("liquidity0000"), &[]),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &pool)],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &pool)],
+        ),
+    ]);
+    deps.querier.with_pair_settings(settings(1, 2));
+    init_token_pair(&mut deps);
+
+    // a 50% commission doubles the amount the pool has to give up
+    let ask_asset = Asset {
+        info: token_asset_info("asset0000"),
+        amount: Uint128(1_000_000_000_000_000_000u128),
+    };
+    let res = query_reverse_simulation(&deps, ask_asset.clone()).unwrap();
+    assert_eq!(res.commission_amount, ask_asset.amount);
+    // 2e18 out of a 1e20 pool costs about 2.04e18, give or take the 1% pool noise
+    assert!(res.offer_amount > Uint128(2_000_000_000_000_000_000u128));
+    assert!(res.offer_amount < Uint128(2_100_000_000_000_000_000u128));
+
+    // selling the quoted offer returns at least the asked amount
+    let offer_asset = Asset {
+        info: token_asset_info("asset0001"),
+        amount: res.offer_amount,
+    };
+    let simulation = query_simulation(&deps, offer_asset, None).unwrap();
+    assert!(simulation.return_amount >= ask_asset.amount);
+
+    // past the bound the reverse computation is refused instead of overflowing
+    deps.querier.with_pair_settings(settings(99, 100));
+    match query_reverse_simulation(&deps, ask_asset) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            "unsupported: A commission rate of 99/100 is too high to reverse simulate, the maximum is below 99%"
+        ),
+        _ => panic!("Must return generic error"),
+    }
+}
+
+#[test]
+fn min_reserve() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_pair_settings(PairSettings {
+        min_reserve: Some(Uint128(100u128)),
+        ..test_settings()
+    });
+    let with_pools =
+        |deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>, asset0: u128, asset1: u128| {
+            deps.querier.with_token_balances(&[
+                (
+                    &HumanAddr::from("liquidity0000"),
+                    &[(&HumanAddr::from("addr0000"), &Uint128(1000u128))],
+                ),
+                (
+                    &HumanAddr::from("asset0000"),
+                    &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(asset0))],
+                ),
+                (
+                    &HumanAddr::from("asset0001"),
+                    &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(asset1))],
+                ),
+            ]);
+        };
+    with_pools(&mut deps, 1000, 1000);
+    init_token_pair(&mut deps);
+
+    // 10,000 asset0000 would buy 908 asset0001 and leave 92
+    with_pools(&mut deps, 11_000, 1000);
+    let env = mock_env("asset0000", &[]);
+    match handle(&mut deps, env, swap_msg(Uint128(10_000u128))) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            "insufficient_liquidity: The swap would leave 92 in the asset0001 pool, below the min_reserve of 100"
+        ),
+        _ => panic!("Must return generic error"),
+    }
+    with_pools(&mut deps, 1100, 1000);
+    let env = mock_env("asset0000", &[]);
+    let _res = handle(&mut deps, env, swap_msg(Uint128(100u128))).unwrap();
+
+    // withdrawing 950 of the 1000 shares is capped at 900, the other 50 go back
+    with_pools(&mut deps, 1000, 1000);
+    let withdraw_msg = HandleMsg::Receive {
+        from: HumanAddr::from("addr0000"),
+        msg: Some(
+            to_binary(&Cw20HookMsg::WithdrawLiquidity {
+                unwrap: false,
+                to: None,
+                to_code_hash: None,
+                to_msg: None,
+            })
+            .unwrap(),
+        ),
+        amount: Uint128(950u128),
+    };
+    let refunds = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>, amount: u128| {
+        ["asset0000", "asset0001"]
+            .iter()
+            .map(|asset| {
+                Asset {
+                    info: token_asset_info(asset),
+                    amount: Uint128(amount),
+                }
+                .into_msg(
+                    deps,
+                    HumanAddr::from(MOCK_CONTRACT_ADDR),
+                    HumanAddr::from("addr0000"),
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>()
+    };
+    let burn = |amount: u128| {
+        snip20::burn_msg(
+            Uint128(amount),
+            None,
+            256,
+            "lptokenhash".to_string(),
+            HumanAddr::from("liquidity0000"),
+        )
+        .unwrap()
+    };
+    let env = mock_env("liquidity0000", &[]);
+    let res = handle(&mut deps, env, withdraw_msg.clone()).unwrap();
+    let mut expected = vec![
+        burn(900),
+        snip20::transfer_msg(
+            HumanAddr::from("addr0000"),
+            Uint128(50u128),
+            None,
+            256,
+            "lptokenhash".to_string(),
+            HumanAddr::from("liquidity0000"),
+        )
+        .unwrap(),
+    ];
+    expected.extend(refunds(&deps, 900));
+    assert_eq!(res.messages, expected);
+    assert!(res.log.contains(&log("returned_share", "50")));
+
+    // nothing is left above the floor
+    with_pools(&mut deps, 100, 1000);
+    let env = mock_env("liquidity0000", &[]);
+    match handle(&mut deps, env, withdraw_msg.clone()) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            "insufficient_liquidity: The pools are at the min_reserve of 100, no liquidity can be withdrawn"
+        ),
+        _ => panic!("Must return generic error"),
+    }
+
+    // until the factory opens the pair for a full exit
+    let msg = HandleMsg::SetFullExit { enabled: true };
+    match handle(&mut deps, mock_env("addr0000", &[]), msg.clone()) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    let _res = handle(&mut deps, mock_env("factory0000", &[]), msg).unwrap();
+    with_pools(&mut deps, 1000, 1000);
+    let env = mock_env("liquidity0000", &[]);
+    let res = handle(&mut deps, env, withdraw_msg).unwrap();
+    let mut expected = vec![burn(950)];
+    expected.extend(refunds(&deps, 950));
+    assert_eq!(res.messages, expected);
+}
+
+#[test]
+fn debug_noise() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1_000_000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(3_000_000u128),
+            )],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(700_000u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    let debug_noise = |key: &str| QueryMsg::DebugNoise {
+        key: key.to_string(),
+    };
+    let assert_unauthorized = |res: StdResult<Binary>| match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    };
+
+    // off until a key is set
+    assert_unauthorized(query(&deps, debug_noise("")));
+
+    let msg = HandleMsg::SetDebugKey {
+        key: Some("debugkey".to_string()),
+    };
+    let res = handle(&mut deps, mock_env("addr0001", &[]), msg.clone());
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    let res = handle(&mut deps, mock_env("factory0000", &[]), msg).unwrap();
+    assert_eq!(res.log, vec![log("action", "set_debug_key")]);
+
+    assert_unauthorized(query(&deps, debug_noise("debugke")));
+    let res: DebugNoiseResponse =
+        from_binary(&query(&deps, debug_noise("debugkey")).unwrap()).unwrap();
+    assert_eq!(res.assets[0].amount, Uint128(3_000_000u128));
+    assert_eq!(res.assets[1].amount, Uint128(700_000u128));
+    assert_eq!(res.total_share, Uint128(1_000_000u128));
+
+    // the factor is the one the noised queries apply
+    let pool = query_pool(&deps).unwrap();
+    for (noised, exact) in pool.assets.iter().zip(res.assets.iter()) {
+        assert_eq!(
+            noised.amount,
+            exact.amount.multiply_ratio(res.nom, res.denom)
+        );
+    }
+    assert_eq!(
+        pool.total_share,
+        res.total_share.multiply_ratio(res.nom, res.denom)
+    );
+
+    let msg = HandleMsg::SetDebugKey { key: None };
+    let _res = handle(&mut deps, mock_env("factory0000", &[]), msg).unwrap();
+    assert_unauthorized(query(&deps, debug_noise("debugkey")));
+}
+
+#[test]
+fn rotate_viewing_keys() {
+    let mut deps = mock_dependencies(20, &[]);
+    init_token_pair(&mut deps);
+
+    let stored_keys = |config: &Config| {
+        config
+            .asset_infos
+            .iter()
+            .map(|info| match info {
+                AssetInfoRaw::Token { viewing_key, .. } => viewing_key.clone(),
+                AssetInfoRaw::NativeToken { .. } => panic!("DO NOT ENTER HERE"),
+            })
+            .collect::<Vec<String>>()
+    };
+    let initial_keys = stored_keys(&read_config(&deps.storage).unwrap());
+    assert_eq!(initial_keys, vec!["SecretSwap", "SecretSwap"]);
+
+    let msg = HandleMsg::RotateViewingKeys {};
+    let res = handle(&mut deps, mock_env("addr0000", &[]), msg.clone());
+    match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    let res = handle(&mut deps, mock_env("factory0000", &[]), msg.clone()).unwrap();
+    let keys = stored_keys(&read_config(&deps.storage).unwrap());
+    assert!(keys
+        .iter()
+        .all(|key| key != "SecretSwap" && !key.is_empty()));
+    assert_ne!(keys[0], keys[1]);
+    assert_eq!(
+        res.messages,
+        vec![
+            snip20::set_viewing_key_msg(
+                keys[0].clone(),
+                None,
+                256,
+                "tokenhash".to_string(),
+                HumanAddr::from("asset0000"),
+            )
+            .unwrap(),
+            snip20::set_viewing_key_msg(
+                keys[1].clone(),
+                None,
+                256,
+                "tokenhash".to_string(),
+                HumanAddr::from("asset0001"),
+            )
+            .unwrap(),
+        ]
+    );
+    assert_eq!(res.log, vec![log("action", "rotate_viewing_keys")]);
+
+    // the pair reads its balances with the new keys
+    let addresses = read_addresses(&deps.storage, &deps.api).unwrap();
+    let config = read_config(&deps.storage).unwrap();
+    for (info, key) in addresses
+        .asset_infos_with_keys(&config)
+        .iter()
+        .zip(keys.iter())
+    {
+        match info {
+            AssetInfo::Token { viewing_key, .. } => assert_eq!(viewing_key, key),
+            AssetInfo::NativeToken { .. } => panic!("DO NOT ENTER HERE"),
+        }
+    }
+
+    let _res = handle(&mut deps, mock_env("factory0000", &[]), msg).unwrap();
+    let rotated_again = stored_keys(&read_config(&deps.storage).unwrap());
+    assert_ne!(rotated_again[0], keys[0]);
+    assert_ne!(rotated_again[1], keys[1]);
+}
+
+#[test]
+fn noise_config_from_settings() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(10_000_000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(10_000_000u128),
+            )],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(10_000_000u128),
+            )],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    let push_settings = |deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>,
+                         height: u64,
+                         noise_bps_max: Option<u16>,
+                         noise_per_block: bool,
+                         private_logs: bool| {
+        let msg = HandleMsg::PushSettings {
+            settings: PairSettings {
+                noise_bps_max,
+                noise_per_block,
+                private_logs,
+                ..test_settings()
+            },
+        };
+        let env = mock_env_with_block_height("factory0000", &[], height);
+        handle(deps, env, msg).unwrap();
+    };
+    let add_entropy = |deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>, height: u64| {
+        let msg = HandleMsg::AddEntropy {
+            entropy: Binary::from(height.to_be_bytes().to_vec()),
+        };
+        let env = mock_env_with_block_height("addr0000", &[], height);
+        handle(deps, env, msg).unwrap();
+    };
+    // the reserves are 10_000_000, so each basis point of noise moves them by 1_000
+    let noise_bps = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>| {
+        let pool = query_pool(deps).unwrap();
+        (pool.assets[0].amount.u128() as i128 - 10_000_000) / 1_000
+    };
+
+    // an old factory leaves the fields out
+    assert!(noise_bps(&deps).abs() <= DEFAULT_NOISE_BPS_MAX as i128);
+
+    push_settings(&mut deps, 100, Some(0), false, false);
+    assert_eq!(noise_bps(&deps), 0);
+    assert_eq!(
+        query_pool(&deps).unwrap().total_share,
+        Uint128(10_000_000u128)
+    );
+
+    push_settings(&mut deps, 100, Some(1_000), false, false);
+    let mut widest = 0i128;
+    for height in 101..121 {
+        add_entropy(&mut deps, height);
+        let noise = noise_bps(&deps).abs();
+        assert!(noise <= 1_000);
+        widest = widest.max(noise);
+    }
+    assert!(widest > DEFAULT_NOISE_BPS_MAX as i128);
+
+    // the same noise for the whole block
+    push_settings(&mut deps, 200, Some(1_000), true, false);
+    add_entropy(&mut deps, 201);
+    let block_noise = noise_bps(&deps);
+    add_entropy(&mut deps, 201);
+    assert_eq!(noise_bps(&deps), block_noise);
+    add_entropy(&mut deps, 202);
+    assert_ne!(noise_bps(&deps), block_noise);
+
+    // amounts are left out of the logs
+    push_settings(&mut deps, 300, None, false, true);
+    let env = mock_env_with_block_height("asset0000", &[], 300);
+    let res = handle(&mut deps, env, swap_msg(Uint128(1_000u128))).unwrap();
+    assert_eq!(
+        res.log,
+        vec![
+            log("action", "swap"),
+            log("offer_asset", "asset0000"),
+            log("ask_asset", "asset0001"),
+        ]
+    );
+}
+
+#[test]
+fn swap_data_preview() {
+    let mut deps = mock_dependencies(20, &[]);
+    let balances = |deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>, offered: u128| {
+        deps.querier.with_token_balances(&[
+            (
+                &HumanAddr::from("liquidity0000"),
+                &[(&HumanAddr::from("addr0000"), &Uint128(1_000_000u128))],
+            ),
+            (
+                &HumanAddr::from("asset0000"),
+                &[(
+                    &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                    &Uint128(1_000_000u128 + offered),
+                )],
+            ),
+            (
+                &HumanAddr::from("asset0001"),
+                &[(
+                    &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                    &Uint128(1_000_000u128),
+                )],
+            ),
+        ]);
+    };
+    balances(&mut deps, 0);
+    init_token_pair(&mut deps);
+
+    let preview_msg = QueryMsg::SwapDataPreview {
+        offer_asset: Asset {
+            info: token_asset_info("asset0000"),
+            amount: Uint128(1_000u128),
+        },
+        recipient: HumanAddr::from("addr0000"),
+    };
+    let preview: Option<SwapDataPreviewResponse> =
+        from_binary(&query(&deps, preview_msg.clone()).unwrap()).unwrap();
+    assert_eq!(preview, None);
+
+    // the noise is off so the preview sees the exact pools
+    let settings = PairSettings {
+        swap_data_endpoint: Some(SwapDataEndpoint {
+            address: HumanAddr::from("endpoint0000"),
+            code_hash: "endpointhash".to_string(),
+        }),
+        noise_bps_max: Some(0),
+        ..test_settings()
+    };
+    deps.querier.with_pair_settings(settings.clone());
+    let msg = HandleMsg::PushSettings { settings };
+    let _res = handle(&mut deps, mock_env("factory0000", &[]), msg).unwrap();
+
+    let preview: SwapDataPreviewResponse =
+        from_binary::<Option<_>>(&query(&deps, preview_msg).unwrap())
+            .unwrap()
+            .unwrap();
+    assert_eq!(
+        preview.asset_out.info,
+        query_pair_info(&deps).unwrap().asset_infos[1]
+    );
+
+    balances(&mut deps, 1_000);
+    let res = handle(
+        &mut deps,
+        mock_env("asset0000", &[]),
+        swap_msg(Uint128(1_000u128)),
+    )
+    .unwrap();
+    let endpoint_msg = preview
+        .endpoint
+        .into_msg(preview.asset_in, preview.asset_out, preview.account)
+        .unwrap();
+    assert_eq!(res.messages.last(), Some(&endpoint_msg));
+}
+
+#[test]
+fn simulation_by_index() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1000000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(2000000u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    for (index, asset) in ["asset0000", "asset0001"].iter().enumerate() {
+        let by_index = query_simulation_by_index(&deps, index as u8, Uint128(1000u128)).unwrap();
+        let by_asset = query_simulation(
+            &deps,
+            Asset {
+                info: token_asset_info(asset),
+                amount: Uint128(1000u128),
+            },
+            None,
+        )
+        .unwrap();
+        assert_eq!(by_index, by_asset);
+    }
+
+    match query_simulation_by_index(&deps, 2, Uint128(1000u128)) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "invalid_parameter: offer_index must be 0 or 1")
+        }
+        _ => panic!("Must return generic error"),
+    }
+}
+
+#[test]
+fn simulation_at_reserves() {
+    // the pools hold 1_000_000 of each, the scenarios don't look at them
+    let mut deps = mock_dependencies(20, &[]);
+    with_offer_in_pool(&mut deps, 0);
+    init_token_pair(&mut deps);
+
+    let simulate = |asset: &str, amount: u128, reserves: [u128; 2]| {
+        let msg = QueryMsg::SimulationAtReserves {
+            offer_asset: Asset {
+                info: token_asset_info(asset),
+                amount: Uint128(amount),
+            },
+            reserves: [Uint128(reserves[0]), Uint128(reserves[1])],
+        };
+        query(&deps, msg).map(|res| from_binary::<SimulationResponse>(&res).unwrap())
+    };
+
+    for reserves in [
+        [2_000_000u128, 500_000u128],
+        [1_000, 3_000_000],
+        [1_000_000, 1_000_000],
+    ]
+    .iter()
+    {
+        for amount in [1_000u128, 10_000, 250_000].iter() {
+            let res = simulate("asset0000", *amount, *reserves).unwrap();
+            let expected = compute_swap(
+                Uint128(reserves[0]),
+                Uint128(reserves[1]),
+                Uint128(*amount),
+                Uint128(3),
+                Uint128(1000),
+                Uint128::zero(),
+            )
+            .unwrap();
+            assert_eq!(
+                (res.return_amount, res.spread_amount, res.commission_amount),
+                expected
+            );
+            assert_eq!(res.lp_commission_amount, res.commission_amount);
+
+            // the reserves are in the pair's order, asset0001 offers into the second
+            let res = simulate("asset0001", *amount, *reserves).unwrap();
+            let expected = compute_swap(
+                Uint128(reserves[1]),
+                Uint128(reserves[0]),
+                Uint128(*amount),
+                Uint128(3),
+                Uint128(1000),
+                Uint128::zero(),
+            )
+            .unwrap();
+            assert_eq!(
+                (res.return_amount, res.spread_amount, res.commission_amount),
+                expected
+            );
+        }
+    }
+
+    // at the current reserves it's the simulation without the noise
+    PINNED_RANDOM_NUMBER.with(|pinned| pinned.set(Some(0)));
+    let current = query_simulation(
+        &deps,
+        Asset {
+            info: token_asset_info("asset0000"),
+            amount: Uint128(10_000u128),
+        },
+        None,
+    )
+    .unwrap();
+    assert_eq!(
+        simulate("asset0000", 10_000, [1_000_000, 1_000_000]).unwrap(),
+        current
+    );
+
+    match simulate("asset0002", 10_000, [1_000_000, 1_000_000]) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(
+                msg,
+                "asset_not_in_pair: asset0002 is not one of the pair's assets"
+            )
+        }
+        _ => panic!("Must return generic error"),
+    }
+}
+
+#[test]
+fn batch_provide_and_swap() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_pair_settings(test_settings());
+    // the offer of 100_000 asset0000 is already in the pool balance
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1_000_000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(2_100_000u128),
+            )],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(1_000_000u128),
+            )],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    let zap = |min_share: Option<u128>| HandleMsg::Receive {
+        from: HumanAddr::from("addr0000"),
+        msg: Some(
+            to_binary(&Cw20HookMsg::BatchProvideAndSwap {
+                min_share: min_share.map(Uint128),
+                receiver: None,
+            })
+            .unwrap(),
+        ),
+        amount: Uint128(100_000u128),
+    };
+
+    // 49_468 asset0000 buy 24_136 asset0001 minus a 73 commission, both sides then
+    // mint 24_656 against the 2_049_468 / 975_937 pools
+    let env = mock_env("asset0000", &[]);
+    let res = handle(&mut deps, env, zap(Some(24_656))).unwrap();
+    assert_eq!(
+        res.messages,
+        vec![snip20::mint_msg(
+            HumanAddr::from("addr0000"),
+            Uint128(24_656u128),
+            None,
+            256,
+            "lptokenhash".to_string(),
+            HumanAddr::from("liquidity0000"),
+        )
+        .unwrap()]
+    );
+    assert!(res.log.contains(&log("swapped_amount", "49468")));
+    assert!(res.log.contains(&log("return_amount", "24063")));
+    assert!(res.log.contains(&log("commission_amount", "73")));
+    let receipt: ProvideLiquidityResponse = from_binary(&res.data.unwrap()).unwrap();
+    assert_eq!(receipt.share, Uint128(24_656u128));
+    assert_eq!(receipt.entry_reserves[0].amount, Uint128(2_000_000u128));
+
+    // the optimum of a constant product pool with fee f swaps
+    // s = (sqrt(((2 - f) * R)^2 + 4 * (1 - f) * R * A) - (2 - f) * R) / (2 * (1 - f))
+    let (pool, offer, supply, fee) = (2_000_000f64, 100_000f64, 1_000_000f64, 0.003f64);
+    let optimal_swap =
+        (((2. - fee) * pool).powi(2) + 4. * (1. - fee) * pool * offer).sqrt() - (2. - fee) * pool;
+    let optimal_swap = optimal_swap / (2. * (1. - fee));
+    let optimal_share = (offer - optimal_swap) * supply / (pool + optimal_swap);
+    // the return rounds down and the commission up, each a unit against the deposit
+    assert!((49_468f64 - optimal_swap).abs() < 4.);
+    assert!(optimal_share - 24_656f64 < 2.);
+
+    let stats = read_stats(&deps.storage).unwrap();
+    assert_eq!(stats.asset0_volume, Uint128(49_468u128));
+    assert_eq!(stats.swap_count, 1);
+
+    // slippage protection
+    let env = mock_env("asset0000", &[]);
+    match handle(&mut deps, env, zap(Some(24_657))) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            "slippage_exceeded: Operation exceeds max slippage: share 24656 is less than min_share 24657"
+        ),
+        _ => panic!("Must return generic error"),
+    }
+
+    // only the pair's tokens can zap in
+    let env = mock_env("asset0002", &[]);
+    match handle(&mut deps, env, zap(None)) {
+        Err(StdError::Unauthorized { .. }) => (),
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    // and native assets must be sent along
+    let env = mock_env("addr0000", &[]);
+    let msg = HandleMsg::BatchProvideAndSwap {
+        offer_asset: Asset {
+            info: token_asset_info("asset0000"),
+            amount: Uint128(100_000u128),
+        },
+        min_share: None,
+        receiver: None,
+    };
+    match handle(&mut deps, env, msg) {
+        Err(StdError::Unauthorized { .. }) => (),
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    // an empty pool has no ratio to balance the deposit against
+    deps.querier.with_token_balances(&[
+        (&HumanAddr::from("liquidity0000"), &[]),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(100_000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128::zero())],
+        ),
+    ]);
+    let env = mock_env("asset0000", &[]);
+    match handle(&mut deps, env, zap(None)) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            "insufficient_liquidity: The pool is empty, the first deposit must provide both assets"
+        ),
+        _ => panic!("Must return generic error"),
+    }
+}
+
+#[test]
+fn restricted_pair_whitelist() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1_000_000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(1_000_100u128),
+            )],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(1_000_000u128),
+            )],
+        ),
+    ]);
+    init_pair_with(
+        &mut deps,
+        PairInitMsg {
+            restricted: true,
+            ..pair_init_msg([token_asset_info("asset0000"), token_asset_info("asset0001")])
+        },
+    );
+
+    let assert_unauthorized = |res: HandleResult| match res {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    };
+    let provide = HandleMsg::ProvideLiquidity {
+        assets: [
+            Asset {
+                info: token_asset_info("asset0000"),
+                amount: Uint128(100u128),
+            },
+            Asset {
+                info: token_asset_info("asset0001"),
+                amount: Uint128(100u128),
+            },
+        ],
+        slippage_tolerance: None,
+        auto_stake: None,
+        receiver: None,
+    };
+    let withdraw = HandleMsg::Receive {
+        from: HumanAddr::from("addr0000"),
+        msg: Some(
+            to_binary(&Cw20HookMsg::WithdrawLiquidity {
+                unwrap: false,
+                to: None,
+                to_code_hash: None,
+                to_msg: None,
+            })
+            .unwrap(),
+        ),
+        amount: Uint128(100u128),
+    };
+
+    // nobody is whitelisted yet
+    assert_unauthorized(handle(
+        &mut deps,
+        mock_env("asset0000", &[]),
+        swap_msg(Uint128(100u128)),
+    ));
+    assert_unauthorized(handle(
+        &mut deps,
+        mock_env("addr0000", &[]),
+        provide.clone(),
+    ));
+    assert_unauthorized(handle(
+        &mut deps,
+        mock_env("liquidity0000", &[]),
+        withdraw.clone(),
+    ));
+
+    // only the factory keeps the list
+    let add = |addresses: &[&str]| HandleMsg::AddToWhitelist {
+        addresses: addresses.iter().map(|a| HumanAddr::from(*a)).collect(),
+    };
+    assert_unauthorized(handle(
+        &mut deps,
+        mock_env("addr0000", &[]),
+        add(&["addr0000"]),
+    ));
+    let res = handle(
+        &mut deps,
+        mock_env("factory0000", &[]),
+        add(&["addr0000", "addr0001", "addr0002", "addr0000"]),
+    )
+    .unwrap();
+    assert_eq!(res.log, vec![log("action", "add_to_whitelist")]);
+
+    let _res = handle(
+        &mut deps,
+        mock_env("asset0000", &[]),
+        swap_msg(Uint128(100u128)),
+    )
+    .unwrap();
+    let _res = handle(&mut deps, mock_env("addr0000", &[]), provide.clone()).unwrap();
+    let _res = handle(&mut deps, mock_env("liquidity0000", &[]), withdraw.clone()).unwrap();
+
+    // the list pages in the order addresses were added, behind the debug key
+    let whitelist = |start_after: Option<&str>, limit: Option<u32>| QueryMsg::Whitelist {
+        key: "debugkey".to_string(),
+        start_after: start_after.map(HumanAddr::from),
+        limit,
+    };
+    match query(&deps, whitelist(None, None)) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    let msg = HandleMsg::SetDebugKey {
+        key: Some("debugkey".to_string()),
+    };
+    let _res = handle(&mut deps, mock_env("factory0000", &[]), msg).unwrap();
+
+    let res: WhitelistResponse =
+        from_binary(&query(&deps, whitelist(None, Some(2))).unwrap()).unwrap();
+    assert_eq!(
+        res,
+        WhitelistResponse {
+            restricted: true,
+            addresses: vec![HumanAddr::from("addr0000"), HumanAddr::from("addr0001")],
+        }
+    );
+    let res: WhitelistResponse =
+        from_binary(&query(&deps, whitelist(Some("addr0001"), None)).unwrap()).unwrap();
+    assert_eq!(res.addresses, vec![HumanAddr::from("addr0002")]);
+
+    // removed addresses are locked out again and drop off the list
+    let msg = HandleMsg::RemoveFromWhitelist {
+        addresses: vec![HumanAddr::from("addr0000")],
+    };
+    assert_unauthorized(handle(&mut deps, mock_env("addr0001", &[]), msg.clone()));
+    let _res = handle(&mut deps, mock_env("factory0000", &[]), msg).unwrap();
+    assert_unauthorized(handle(&mut deps, mock_env("addr0000", &[]), provide));
+    assert_unauthorized(handle(&mut deps, mock_env("liquidity0000", &[]), withdraw));
+    let res: WhitelistResponse =
+        from_binary(&query(&deps, whitelist(None, None)).unwrap()).unwrap();
+    assert_eq!(
+        res.addresses,
+        vec![HumanAddr::from("addr0001"), HumanAddr::from("addr0002")]
+    );
+
+    // and come back at the end when added again
+    let _res = handle(&mut deps, mock_env("factory0000", &[]), add(&["addr0000"])).unwrap();
+    let res: WhitelistResponse =
+        from_binary(&query(&deps, whitelist(Some("addr0002"), None)).unwrap()).unwrap();
+    assert_eq!(res.addresses, vec![HumanAddr::from("addr0000")]);
+}
+
+#[test]
+fn swap_rejects_degenerate_pools() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(1_000_100u128),
+            )],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(1_000_000u128),
+            )],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    // both pools point at asset0000, which init never allows
+    let mut config = read_config(&deps.storage).unwrap();
+    config.asset_infos[1] = config.asset_infos[0].clone();
+    store_config(&mut deps.storage, &config).unwrap();
+    let addresses = Addresses::from_config(&deps.api, &config).unwrap();
+    store_addresses(&mut deps.storage, &addresses).unwrap();
+
+    let env = mock_env("asset0000", &[]);
+    match handle(&mut deps, env, swap_msg(Uint128(100u128))) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "invalid_state: Offer and ask pools are both asset0000")
+        }
+        _ => panic!("Must return generic error"),
+    }
+    assert_eq!(read_stats(&deps.storage).unwrap().swap_count, 0);
+}
+
+#[test]
+fn block_volume_circuit_breaker() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_pair_settings(PairSettings {
+        max_block_volume_bps: Some(100),
+        ..test_settings()
+    });
+    // the 4_000 offers are already in the pool balance
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1_000_000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(1_004_000u128),
+            )],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(1_000_000u128),
+            )],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    // 1% of the 1_000_000 reserve may be offered per block, the third 4_000 goes past it
+    for _ in 0..2 {
+        let env = mock_env_with_block_height("asset0000", &[], 100);
+        let _res = handle(&mut deps, env, swap_msg(Uint128(4_000u128))).unwrap();
+    }
+    let env = mock_env_with_block_height("asset0000", &[], 100);
+    match handle(&mut deps, env, swap_msg(Uint128(4_000u128))) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "paused: volume circuit breaker tripped")
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    // withdrawals still go through, even the ones swapping on the way out
+    let withdraw_to = HandleMsg::Receive {
+        from: HumanAddr::from("addr0000"),
+        msg: Some(
+            to_binary(&Cw20HookMsg::WithdrawLiquidityTo {
+                asset_info: token_asset_info("asset0001"),
+                min_received: None,
+            })
+            .unwrap(),
+        ),
+        amount: Uint128(100_000u128),
+    };
+    let env = mock_env_with_block_height("liquidity0000", &[], 100);
+    let _res = handle(&mut deps, env, withdraw_to).unwrap();
+
+    // the next block starts over
+    let env = mock_env_with_block_height("asset0000", &[], 101);
+    let _res = handle(&mut deps, env, swap_msg(Uint128(4_000u128))).unwrap();
+    let block_volume = read_block_volume(&deps.storage).unwrap().unwrap();
+    assert_eq!(block_volume.height, 101);
+    assert_eq!(block_volume.volumes, [Uint128(4_000u128), Uint128::zero()]);
+    assert_eq!(
+        block_volume.reserves,
+        [Uint128(1_000_000u128), Uint128(1_000_000u128)]
+    );
+}
+
+// 1_000_000 of each token in the pool, plus an `offer_amount` of asset0000 already sent in
+fn with_offer_in_pool(
+    deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>,
+    offer_amount: u128,
+) {
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1_000_000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(1_000_000u128 + offer_amount),
+            )],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(1_000_000u128),
+            )],
+        ),
+    ]);
+}
+
+#[test]
+fn reserve_circuit_breaker() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_pair_settings(PairSettings {
+        reserve_floor_bps: 1_000,
+        ..test_settings()
+    });
+    with_offer_in_pool(&mut deps, 0);
+    init_token_pair(&mut deps);
+
+    // leaves exactly 10% of the asset0001 reserve
+    with_offer_in_pool(&mut deps, 9_278_548);
+    let env = mock_env_with_block_height("asset0000", &[], 100);
+    let res = handle(&mut deps, env, swap_msg(Uint128(9_278_548u128))).unwrap();
+    assert_eq!(res.log[0], log("action", "swap"));
+
+    // one more unit leaves 99_999, the offer goes back and the pair pauses
+    with_offer_in_pool(&mut deps, 9_278_549);
+    let env = mock_env_with_block_height("asset0000", &[], 100);
+    let res = handle(&mut deps, env, swap_msg(Uint128(9_278_549u128))).unwrap();
+    assert_eq!(
+        res.log,
+        vec![
+            log("action", "reserve_breaker_tripped"),
+            log("ask_asset", "asset0001"),
+        ]
+    );
+    assert_eq!(
+        res.messages,
+        vec![Asset {
+            info: token_asset_info("asset0000"),
+            amount: Uint128(9_278_549u128),
+        }
+        .into_msg(
+            &deps,
+            HumanAddr::from(MOCK_CONTRACT_ADDR),
+            HumanAddr::from("addr0000"),
+        )
+        .unwrap()]
+    );
+
+    with_offer_in_pool(&mut deps, 1_000);
+    let env = mock_env_with_block_height("asset0000", &[], 101);
+    match handle(&mut deps, env, swap_msg(Uint128(1_000u128))) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(
+                msg,
+                "paused: trading is paused by the reserve circuit breaker"
+            )
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    // liquidity providers can still leave
+    let msg = HandleMsg::Receive {
+        from: HumanAddr::from("addr0000"),
+        msg: Some(
+            to_binary(&Cw20HookMsg::WithdrawLiquidity {
+                unwrap: false,
+                to: None,
+                to_code_hash: None,
+                to_msg: None,
+            })
+            .unwrap(),
+        ),
+        amount: Uint128(100_000u128),
+    };
+    let env = mock_env_with_block_height("liquidity0000", &[], 101);
+    let _res = handle(&mut deps, env, msg).unwrap();
+
+    // only the factory reopens the pair
+    let env = mock_env_with_block_height("addr0000", &[], 101);
+    match handle(&mut deps, env, HandleMsg::ResetCircuitBreaker {}) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    let env = mock_env_with_block_height("factory0000", &[], 101);
+    let _res = handle(&mut deps, env, HandleMsg::ResetCircuitBreaker {}).unwrap();
+
+    let env = mock_env_with_block_height("asset0000", &[], 101);
+    let _res = handle(&mut deps, env, swap_msg(Uint128(1_000u128))).unwrap();
+}
+
+#[test]
+fn reserve_circuit_breaker_on_keeper_fills() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_pair_settings(PairSettings {
+        reserve_floor_bps: 1_000,
+        ..test_settings()
+    });
+    with_offer_in_pool(&mut deps, 0);
+    init_token_pair(&mut deps);
+    let refund = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>| {
+        vec![Asset {
+            info: token_asset_info("asset0000"),
+            amount: Uint128(9_278_549u128),
+        }
+        .into_msg(
+            deps,
+            HumanAddr::from(MOCK_CONTRACT_ADDR),
+            HumanAddr::from("addr0000"),
+        )
+        .unwrap()]
+    };
+    let tripped = vec![
+        log("action", "reserve_breaker_tripped"),
+        log("ask_asset", "asset0001"),
+    ];
+    let paused = |deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>| {
+        let env = mock_env_with_block_height("asset0000", &[], 100);
+        match handle(deps, env, swap_msg(Uint128(1_000u128))) {
+            Err(StdError::GenericErr { msg, .. }) => {
+                assert_eq!(
+                    msg,
+                    "paused: trading is paused by the reserve circuit breaker"
+                )
+            }
+            _ => panic!("Must return generic error"),
+        }
+    };
+
+    // an order that would leave 99_999 of the asset0001 reserve pauses the pair
+    with_offer_in_pool(&mut deps, 9_278_549);
+    let msg = HandleMsg::Receive {
+        from: HumanAddr::from("addr0000"),
+        amount: Uint128(9_278_549u128),
+        msg: Some(
+            to_binary(&Cw20HookMsg::PlaceOrder {
+                ask_min_price: Decimal::percent(1),
+                expires_at: mock_env("addr0000", &[]).block.time + 1_000,
+                bounty_bps: 100,
+            })
+            .unwrap(),
+        ),
+    };
+    let _res = handle(&mut deps, mock_env("asset0000", &[]), msg).unwrap();
+    let env = mock_env_with_block_height("keeper0000", &[], 100);
+    let res = handle(&mut deps, env, HandleMsg::ExecuteOrder { order_id: 0 }).unwrap();
+    assert_eq!(res.log, tripped);
+    assert!(res.messages.is_empty());
+    paused(&mut deps);
+
+    // the order is still there, with its whole offer
+    let env = mock_env("addr0000", &[]);
+    let res = handle(&mut deps, env, HandleMsg::CancelOrder { order_id: 0 }).unwrap();
+    assert_eq!(res.messages, refund(&deps));
+
+    // the same for a DCA tranche
+    let env = mock_env_with_block_height("factory0000", &[], 100);
+    let _res = handle(&mut deps, env, HandleMsg::ResetCircuitBreaker {}).unwrap();
+    let msg = HandleMsg::Receive {
+        from: HumanAddr::from("addr0000"),
+        amount: Uint128(9_278_549u128),
+        msg: Some(
+            to_binary(&Cw20HookMsg::PlaceDca {
+                per_interval_amount: Uint128(9_278_549u128),
+                interval_seconds: 3_600,
+                min_price: None,
+            })
+            .unwrap(),
+        ),
+    };
+    let _res = handle(&mut deps, mock_env("asset0000", &[]), msg).unwrap();
+    let env = mock_env_with_block_height("keeper0000", &[], 100);
+    let res = handle(&mut deps, env, HandleMsg::ExecuteDca { id: 0 }).unwrap();
+    assert_eq!(res.log, tripped);
+    assert!(res.messages.is_empty());
+    paused(&mut deps);
+
+    let env = mock_env("addr0000", &[]);
+    let res = handle(&mut deps, env, HandleMsg::CancelDca { id: 0 }).unwrap();
+    assert_eq!(res.messages, refund(&deps));
+}
+
+#[test]
+fn swap_oracle_deviation() {
+    let settings = |fail_open| PairSettings {
+        price_oracle: Some(PriceOracle {
+            contract: HumanAddr::from("oracle0000"),
+            code_hash: "oraclehash".to_string(),
+            max_deviation_bps: 500,
+            fail_open,
+        }),
+        ..test_settings()
+    };
+
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_pair_settings(settings(false));
+    deps.querier.with_reference_price(Some(Decimal::one()));
+    with_offer_in_pool(&mut deps, 0);
+    init_token_pair(&mut deps);
+
+    // the pool price ends 481 bps under the oracle's
+    with_offer_in_pool(&mut deps, 25_000);
+    let env = mock_env_with_block_height("asset0000", &[], 100);
+    let _res = handle(&mut deps, env, swap_msg(Uint128(25_000u128))).unwrap();
+
+    with_offer_in_pool(&mut deps, 30_000);
+    let env = mock_env_with_block_height("asset0000", &[], 100);
+    match handle(&mut deps, env, swap_msg(Uint128(30_000u128))) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            "oracle_rejected: The pool price would move 573 bps from the oracle price, over the 500 bps allowed"
+        ),
+        _ => panic!("Must return generic error"),
+    }
+
+    // simulations warn ahead of time
+    with_offer_in_pool(&mut deps, 0);
+    let offer_asset = Asset {
+        info: token_asset_info("asset0000"),
+        amount: Uint128(30_000u128),
+    };
+    match query_simulation(&deps, offer_asset.clone(), None) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert!(msg.starts_with("oracle_rejected: The pool price would move"))
+        }
+        _ => panic!("Must return generic error"),
+    }
+    let ask_asset = Asset {
+        info: token_asset_info("asset0001"),
+        amount: Uint128(29_040u128),
+    };
+    match query_reverse_simulation(&deps, ask_asset) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert!(msg.starts_with("oracle_rejected: The pool price would move"))
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    // an unreachable oracle stops swaps unless the pair fails open
+    deps.querier.with_reference_price(None);
+    with_offer_in_pool(&mut deps, 10_000);
+    let env = mock_env_with_block_height("asset0000", &[], 100);
+    match handle(&mut deps, env, swap_msg(Uint128(10_000u128))) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "oracle_rejected: The price oracle is unavailable")
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    deps.querier.with_pair_settings(settings(true));
+    let env = mock_env_with_block_height("addr0000", &[], 100);
+    let _res = handle(&mut deps, env, HandleMsg::RefreshSettings {}).unwrap();
+    let env = mock_env_with_block_height("asset0000", &[], 100);
+    let _res = handle(&mut deps, env, swap_msg(Uint128(10_000u128))).unwrap();
+}
+
+#[test]
+fn swap_max_price_impact() {
+    let swap_with_impact_limit = |offer_amount: u128| HandleMsg::Receive {
+        from: HumanAddr::from("addr0000"),
+        amount: Uint128(offer_amount),
+        msg: Some(
+            to_binary(&Cw20HookMsg::Swap {
+                expected_return: None,
+                belief_price: None,
+                max_spread: None,
+                max_price_impact: Some(Decimal::percent(2)),
+                batch: false,
+                to: None,
+                unwrap: false,
+                to_code_hash: None,
+                to_msg: None,
+                ask_asset_info: None,
+            })
+            .unwrap(),
+        ),
+    };
+
+    let mut deps = mock_dependencies(20, &[]);
+    with_offer_in_pool(&mut deps, 0);
+    init_token_pair(&mut deps);
+
+    // 407 spread on a 20_378 return without impact, 1.997%
+    with_offer_in_pool(&mut deps, 20_378);
+    let env = mock_env("asset0000", &[]);
+    let res = handle(&mut deps, env, swap_with_impact_limit(20_378)).unwrap();
+    assert_eq!(res.log[0], log("action", "swap"));
+
+    // 408 spread on 20_379, just over 2%
+    with_offer_in_pool(&mut deps, 20_379);
+    let env = mock_env("asset0000", &[]);
+    match handle(&mut deps, env, swap_with_impact_limit(20_379)) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            "price_impact_exceeded: The price impact of 0.020020609450905343 is more than the max_price_impact of 0.02"
+        ),
+        _ => panic!("Must return generic error"),
+    }
+}
+
+#[test]
+fn simulation_with_pinned_noise() {
+    let mut deps = mock_dependencies(20, &[]);
+    with_offer_in_pool(&mut deps, 0);
+    init_token_pair(&mut deps);
+
+    let offer_asset = Asset {
+        info: token_asset_info("asset0000"),
+        amount: Uint128(10_000u128),
+    };
+
+    // an even number adds its noise, 42 bps of the 99 allowed
+    PINNED_RANDOM_NUMBER.with(|pinned| pinned.set(Some(42)));
+    let res = query_simulation(&deps, offer_asset.clone(), None).unwrap();
+    assert_eq!(res.return_amount, Uint128(9_871u128));
+    assert_eq!(res.spread_amount, Uint128(99u128));
+    assert_eq!(res.commission_amount, Uint128(30u128));
+
+    // an odd one takes it away
+    PINNED_RANDOM_NUMBER.with(|pinned| pinned.set(Some(43)));
+    let res = query_simulation(&deps, offer_asset, None).unwrap();
+    assert_eq!(res.return_amount, Uint128(9_870u128));
+    assert_eq!(res.spread_amount, Uint128(100u128));
+    assert_eq!(res.commission_amount, Uint128(30u128));
+}
+
+#[test]
+fn pool_query_without_noise_on_dust() {
+    // an odd number takes 43 bps away, 1 * 9_957 / 10_000 would read as zero
+    PINNED_RANDOM_NUMBER.with(|pinned| pinned.set(Some(43)));
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (&HumanAddr::from("liquidity0000"), &[]),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128::zero())],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128::zero())],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    // an empty pool reads as true zeros
+    let res = query_pool(&deps).unwrap();
+    assert_eq!(res.assets[0].amount, Uint128::zero());
+    assert_eq!(res.assets[1].amount, Uint128::zero());
+    assert_eq!(res.total_share, Uint128::zero());
+
+    // dust is exact, the noise would only round it down
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(7u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(1u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(99u128))],
+        ),
+    ]);
+    let res = query_pool(&deps).unwrap();
+    assert_eq!(res.assets[0].amount, Uint128(1u128));
+    assert_eq!(res.assets[1].amount, Uint128(99u128));
+    assert_eq!(res.total_share, Uint128(7u128));
+
+    // from MIN_NOISED_AMOUNT up the noise applies
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1_000_000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(MIN_NOISED_AMOUNT),
+            )],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(1_000_000u128),
+            )],
+        ),
+    ]);
+    let res = query_pool(&deps).unwrap();
+    assert_eq!(res.assets[0].amount, Uint128(99u128));
+    assert_eq!(res.assets[1].amount, Uint128(995_700u128));
+    assert_eq!(res.total_share, Uint128(995_700u128));
+}
+
+#[test]
+fn limit_orders() {
+    fn with_pools(
+        deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>,
+        asset0_balance: u128,
+        asset1_balance: u128,
+    ) {
+        deps.querier.with_token_balances(&[
+            (
+                &HumanAddr::from("liquidity0000"),
+                &[(&HumanAddr::from("addr0000"), &Uint128(1_000_000u128))],
+            ),
+            (
+                &HumanAddr::from("asset0000"),
+                &[(
+                    &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                    &Uint128(asset0_balance),
+                )],
+            ),
+            (
+                &HumanAddr::from("asset0001"),
+                &[(
+                    &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                    &Uint128(asset1_balance),
+                )],
+            ),
+        ]);
+    }
+    let place_order = |ask_min_price: Decimal, expires_at: u64| HandleMsg::Receive {
+        from: HumanAddr::from("addr0000"),
+        amount: Uint128(10_000u128),
+        msg: Some(
+            to_binary(&Cw20HookMsg::PlaceOrder {
+                ask_min_price,
+                expires_at,
+                bounty_bps: 100,
+            })
+            .unwrap(),
+        ),
+    };
+    let now = mock_env("addr0000", &[]).block.time;
+
+    let mut deps = mock_dependencies(20, &[]);
+    with_pools(&mut deps, 1_000_000, 1_000_000);
+    init_token_pair(&mut deps);
+
+    // 10_000 asset0000 fetch 9_870 asset0001 after the commission, 9_772 after the bounty
+    with_pools(&mut deps, 1_010_000, 1_000_000);
+    let env = mock_env("asset0000", &[]);
+    let res = handle(
+        &mut deps,
+        env,
+        place_order(Decimal::percent(97), now + 1_000),
+    )
+    .unwrap();
+    assert_eq!(
+        res.log,
+        vec![log("action", "place_order"), log("order_id", 0)]
+    );
+
+    with_pools(&mut deps, 1_020_000, 1_000_000);
+    let env = mock_env("asset0000", &[]);
+    let _res = handle(
+        &mut deps,
+        env,
+        place_order(Decimal::percent(98), now + 1_000),
+    )
+    .unwrap();
+
+    // the orders are only readable by their owner
+    let env = mock_env("addr0000", &[]);
+    let msg = HandleMsg::SetViewingKey {
+        key: "ownerkey".to_string(),
+    };
+    let _res = handle(&mut deps, env, msg).unwrap();
+    let order_query = |address: &str, key: &str| QueryMsg::Order {
+        address: HumanAddr::from(address),
+        key: key.to_string(),
+        order_id: 0,
+    };
+    let order: OrderResponse =
+        from_binary(&query(&deps, order_query("addr0000", "ownerkey")).unwrap()).unwrap();
+    assert_eq!(
+        order,
+        OrderResponse {
+            offer_asset: Asset {
+                info: token_asset_info("asset0000"),
+                amount: Uint128(10_000u128),
+            },
+            ask_min_price: Decimal::percent(97),
+            expires_at: now + 1_000,
+            bounty_bps: 100,
+        }
+    );
+    match query(&deps, order_query("addr0000", "wrongkey")) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    match query(&deps, order_query("addr0001", "ownerkey")) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    // the limit price stays out of the error
+    let env = mock_env("keeper0000", &[]);
+    match handle(&mut deps, env, HandleMsg::ExecuteOrder { order_id: 1 }) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(
+                msg,
+                "invalid_state: The swap can't be filled at the current price"
+            )
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    // priced on the pools without the 20_000 in escrow
+    let env = mock_env("keeper0000", &[]);
+    let res = handle(&mut deps, env, HandleMsg::ExecuteOrder { order_id: 0 }).unwrap();
+    let payout = |amount: u128, recipient: &str| {
+        Asset {
+            info: token_asset_info("asset0001"),
+            amount: Uint128(amount),
+        }
+        .into_msg(
+            &deps,
+            HumanAddr::from(MOCK_CONTRACT_ADDR),
+            HumanAddr::from(recipient),
+        )
+        .unwrap()
+    };
+    assert_eq!(
+        res.messages,
+        vec![payout(9_772, "addr0000"), payout(98, "keeper0000")]
+    );
+    let env = mock_env("keeper0000", &[]);
+    match handle(&mut deps, env, HandleMsg::ExecuteOrder { order_id: 0 }) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "not_found: Order not found"),
+        _ => panic!("Must return generic error"),
+    }
+
+    // only the owner cancels, for the whole offer
+    with_pools(&mut deps, 1_020_000, 990_130);
+    let env = mock_env("addr0001", &[]);
+    match handle(&mut deps, env, HandleMsg::CancelOrder { order_id: 1 }) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    let env = mock_env("addr0000", &[]);
+    let res = handle(&mut deps, env, HandleMsg::CancelOrder { order_id: 1 }).unwrap();
+    assert_eq!(
+        res.messages,
+        vec![Asset {
+            info: token_asset_info("asset0000"),
+            amount: Uint128(10_000u128),
+        }
+        .into_msg(
+            &deps,
+            HumanAddr::from(MOCK_CONTRACT_ADDR),
+            HumanAddr::from("addr0000"),
+        )
+        .unwrap()]
+    );
+
+    // expired orders can only be cancelled
+    let env = mock_env("asset0000", &[]);
+    let _res = handle(&mut deps, env, place_order(Decimal::percent(50), now + 100)).unwrap();
+    let mut env = mock_env("keeper0000", &[]);
+    env.block.time = now + 100;
+    match handle(&mut deps, env, HandleMsg::ExecuteOrder { order_id: 2 }) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(
+                msg,
+                "expired_deadline: The order has expired, it can only be cancelled"
+            )
+        }
+        _ => panic!("Must return generic error"),
+    }
+    let mut env = mock_env("addr0000", &[]);
+    env.block.time = now + 100;
+    let _res = handle(&mut deps, env, HandleMsg::CancelOrder { order_id: 2 }).unwrap();
+}
+
+#[test]
+fn dca_schedule() {
+    // 1_000_000 of each asset in the pools, plus what the schedule still holds in escrow
+    fn with_escrow(deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>, escrowed: u128) {
+        deps.querier.with_token_balances(&[
+            (
+                &HumanAddr::from("liquidity0000"),
+                &[(&HumanAddr::from("addr0000"), &Uint128(1_000_000u128))],
+            ),
+            (
+                &HumanAddr::from("asset0000"),
+                &[(
+                    &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                    &Uint128(1_000_000u128 + escrowed),
+                )],
+            ),
+            (
+                &HumanAddr::from("asset0001"),
+                &[(
+                    &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                    &Uint128(1_000_000u128),
+                )],
+            ),
+        ]);
+    }
+    let execute_at = |deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>, time: u64| {
+        let mut env = mock_env("keeper0000", &[]);
+        env.block.time = time;
+        handle(deps, env, HandleMsg::ExecuteDca { id: 0 })
+    };
+    let now = mock_env("addr0000", &[]).block.time;
+
+    let mut deps = mock_dependencies(20, &[]);
+    with_escrow(&mut deps, 0);
+    init_token_pair(&mut deps);
+
+    with_escrow(&mut deps, 50_000);
+    let env = mock_env("asset0000", &[]);
+    let msg = HandleMsg::Receive {
+        from: HumanAddr::from("addr0000"),
+        amount: Uint128(50_000u128),
+        msg: Some(
+            to_binary(&Cw20HookMsg::PlaceDca {
+                per_interval_amount: Uint128(10_000u128),
+                interval_seconds: 3_600,
+                min_price: Some(Decimal::percent(97)),
+            })
+            .unwrap(),
+        ),
+    };
+    let res = handle(&mut deps, env, msg).unwrap();
+    assert_eq!(res.log, vec![log("action", "place_dca"), log("dca_id", 0)]);
+
+    // every tranche fetches 9_870 asset0001, 9 of which go to the keeper
+    let payouts = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>| {
+        let payout = |amount: u128, recipient: &str| {
+            Asset {
+                info: token_asset_info("asset0001"),
+                amount: Uint128(amount),
+            }
+            .into_msg(
+                deps,
+                HumanAddr::from(MOCK_CONTRACT_ADDR),
+                HumanAddr::from(recipient),
+            )
+            .unwrap()
+        };
+        vec![payout(9_861, "addr0000"), payout(9, "keeper0000")]
+    };
+
+    // the first tranche runs right away, the next one waits for its interval
+    let res = execute_at(&mut deps, now).unwrap();
+    assert_eq!(res.messages, payouts(&deps));
+    with_escrow(&mut deps, 40_000);
+    match execute_at(&mut deps, now + 3_599) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            format!(
+                "invalid_state: The next DCA tranche can't run before {}",
+                now + 3_600
+            )
+        ),
+        _ => panic!("Must return generic error"),
+    }
+    let res = execute_at(&mut deps, now + 3_600).unwrap();
+    assert_eq!(res.messages, payouts(&deps));
+
+    // a late keeper runs one tranche, and the one after keeps to the schedule
+    with_escrow(&mut deps, 30_000);
+    let res = execute_at(&mut deps, now + 7_700).unwrap();
+    assert_eq!(res.messages, payouts(&deps));
+    with_escrow(&mut deps, 20_000);
+    match execute_at(&mut deps, now + 7_800) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            format!(
+                "invalid_state: The next DCA tranche can't run before {}",
+                now + 10_800
+            )
+        ),
+        _ => panic!("Must return generic error"),
+    }
+
+    // the schedule is only readable by its owner
+    let env = mock_env("addr0000", &[]);
+    let msg = HandleMsg::SetViewingKey {
+        key: "ownerkey".to_string(),
+    };
+    let _res = handle(&mut deps, env, msg).unwrap();
+    let dca_query = |address: &str| QueryMsg::Dca {
+        address: HumanAddr::from(address),
+        key: "ownerkey".to_string(),
+        id: 0,
+    };
+    let dca: DcaResponse = from_binary(&query(&deps, dca_query("addr0000")).unwrap()).unwrap();
+    assert_eq!(
+        dca,
+        DcaResponse {
+            offer_asset: Asset {
+                info: token_asset_info("asset0000"),
+                amount: Uint128(20_000u128),
+            },
+            per_interval_amount: Uint128(10_000u128),
+            interval_seconds: 3_600,
+            min_price: Some(Decimal::percent(97)),
+            next_execution_at: now + 10_800,
+        }
+    );
+    match query(&deps, dca_query("addr0001")) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    // only the owner cancels, for what's left of the budget
+    let env = mock_env("addr0001", &[]);
+    match handle(&mut deps, env, HandleMsg::CancelDca { id: 0 }) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    let env = mock_env("addr0000", &[]);
+    let res = handle(&mut deps, env, HandleMsg::CancelDca { id: 0 }).unwrap();
+    assert_eq!(
+        res.messages,
+        vec![Asset {
+            info: token_asset_info("asset0000"),
+            amount: Uint128(20_000u128),
+        }
+        .into_msg(
+            &deps,
+            HumanAddr::from(MOCK_CONTRACT_ADDR),
+            HumanAddr::from("addr0000"),
+        )
+        .unwrap()]
+    );
+    match execute_at(&mut deps, now + 10_800) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "not_found: DCA not found"),
+        _ => panic!("Must return generic error"),
+    }
+}
+
+#[test]
+fn estimate_lp_mint() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("liquidity0000"),
+            &[(&HumanAddr::from("addr0000"), &Uint128(1_000_000u128))],
+        ),
+        (
+            &HumanAddr::from("asset0000"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(1_000_000u128),
+            )],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                &Uint128(2_000_000u128),
+            )],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    let estimate = |amounts: [u128; 2], slippage_tolerance: Option<Decimal>| {
+        let msg = QueryMsg::EstimateLpMint {
+            assets: [
+                Asset {
+                    info: token_asset_info("asset0000"),
+                    amount: Uint128(amounts[0]),
+                },
+                Asset {
+                    info: token_asset_info("asset0001"),
+                    amount: Uint128(amounts[1]),
+                },
+            ],
+            slippage_tolerance,
+        };
+        from_binary::<EstimateLpMintResponse>(&query(&deps, msg).unwrap()).unwrap()
+    };
+
+    // a deposit at the pool ratio is used in full
+    assert_eq!(
+        estimate([1_000, 2_000], Some(Decimal::percent(1))),
+        EstimateLpMintResponse {
+            share: Uint128(1_000u128),
+            refund: None,
+            slippage_ok: true,
+        }
+    );
+
+    // off the ratio the extra asset0001 mints nothing, and is too far off for 10%
+    assert_eq!(
+        estimate([1_000, 3_000], Some(Decimal::percent(10))),
+        EstimateLpMintResponse {
+            share: Uint128(1_000u128),
+            refund: Some([Uint128::zero(), Uint128(1_000u128)]),
+            slippage_ok: false,
+        }
+    );
+    assert!(estimate([1_000, 3_000], None).slippage_ok);
+    assert!(estimate([1_000, 2_100], Some(Decimal::percent(10))).slippage_ok);
+
+    let msg = QueryMsg::EstimateLpMint {
+        assets: [
+            Asset {
+                info: token_asset_info("asset0000"),
+                amount: Uint128(1_000u128),
+            },
+            Asset {
+                info: token_asset_info("asset0002"),
+                amount: Uint128(2_000u128),
+            },
+        ],
+        slippage_tolerance: None,
+    };
+    match query(&deps, msg) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "invalid_parameter: assets must include asset0001")
+        }
+        _ => panic!("Must return generic error"),
+    }
+}
+
+#[test]
+fn integrity_report() {
+    let mut deps = mock_dependencies(20, &[]);
+    let with_balances = |deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>,
+                         balances: [u128; 2]| {
+        deps.querier.with_token_balances(&[
+            (
+                &HumanAddr::from("asset0000"),
+                &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(balances[0]))],
+            ),
+            (
+                &HumanAddr::from("asset0001"),
+                &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(balances[1]))],
+            ),
+        ])
+    };
+    with_balances(&mut deps, [0, 0]);
+    init_token_pair(&mut deps);
+
+    let integrity = |deps: &Extern<MockStorage, MockApi, WasmMockQuerier>| {
+        let msg = QueryMsg::Integrity {
+            key: "debugkey".to_string(),
+        };
+        from_binary::<IntegrityResponse>(&query(deps, msg).unwrap()).unwrap()
+    };
+
+    match query(
+        &deps,
+        QueryMsg::Integrity {
+            key: "debugkey".to_string(),
+        },
+    ) {
+        Err(StdError::Unauthorized { .. }) => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+    let msg = HandleMsg::SetDebugKey {
+        key: Some("debugkey".to_string()),
+    };
+    let _res = handle(&mut deps, mock_env("factory0000", &[]), msg).unwrap();
+
+    let res = integrity(&deps);
+    assert_eq!(res.accounted, Some([Uint128::zero(); 2]));
+    assert_eq!(res.surplus, [Uint128::zero(); 2]);
+    assert_eq!(res.shortfall, [Uint128::zero(); 2]);
+
+    // tokens sent without a handle are a surplus
+    with_balances(&mut deps, [1_000_000, 1_000_000]);
+    let res = integrity(&deps);
+    assert_eq!(res.balances[0].amount, Uint128(1_000_000u128));
+    assert_eq!(
+        res.surplus,
+        [Uint128(1_000_000u128), Uint128(1_000_000u128)]
+    );
+
+    // pairs from before the accounting report nothing until their next handle
+    deps.storage.remove(&to_length_prefixed(b"accounting"));
+    let res = integrity(&deps);
+    assert_eq!(res.accounted, None);
+    assert_eq!(res.surplus, [Uint128::zero(); 2]);
+
+    // which starts from the balances, the received offer already among them
+    with_balances(&mut deps, [1_010_000, 1_000_000]);
+    let env = mock_env("asset0000", &[]);
+    let _res = handle(&mut deps, env, swap_msg(Uint128(10_000u128))).unwrap();
+    assert_eq!(
+        integrity(&deps).accounted,
+        Some([Uint128(1_010_000u128), Uint128(990_130u128)])
+    );
+
+    // then moves by what each handle receives and pays out
+    with_balances(&mut deps, [1_020_000, 990_130]);
+    let env = mock_env("asset0000", &[]);
+    let res = handle(&mut deps, env, swap_msg(Uint128(10_000u128))).unwrap();
+    let paid = match &res.messages[0] {
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr, msg, ..
+        }) => {
+            assert_eq!(contract_addr, &HumanAddr::from("asset0001"));
+            match from_binary(msg).unwrap() {
+                Snip20HandleMsg::Transfer { amount, .. } => amount,
+                _ => panic!("Must transfer the return"),
+            }
+        }
+        _ => panic!("Must transfer the return"),
+    };
+    let ask_balance = (Uint128(990_130u128) - paid).unwrap();
+    with_balances(&mut deps, [1_020_000, ask_balance.u128()]);
+    let res = integrity(&deps);
+    assert_eq!(res.accounted, Some([Uint128(1_020_000u128), ask_balance]));
+    assert_eq!(res.surplus, [Uint128::zero(); 2]);
+    assert_eq!(res.shortfall, [Uint128::zero(); 2]);
+
+    // a donation and a loss show up against the accounting
+    with_balances(&mut deps, [1_019_993, ask_balance.u128() + 500]);
+    let res = integrity(&deps);
+    assert_eq!(res.surplus, [Uint128::zero(), Uint128(500u128)]);
+    assert_eq!(res.shortfall, [Uint128(7u128), Uint128::zero()]);
+}
+
+#[test]
+fn error_codes_are_stable() {
+    let generic_msg = |err: PairError| match StdError::from(err) {
+        StdError::GenericErr { msg, .. } => msg,
+        _ => panic!("Must return generic error"),
+    };
+
+    // the codes are part of the interface, renaming one breaks clients
+    for (err, code) in vec![
+        (PairError::ZeroAmount, "zero_amount"),
+        (
+            PairError::asset_not_in_pair(&token_asset_info("asset0002")),
+            "asset_not_in_pair",
+        ),
+        (
+            PairError::insufficient_liquidity("The ask pool is empty"),
+            "insufficient_liquidity",
+        ),
+        (
+            PairError::OfferNotReceived {
+                offered: Uint128(10u128),
+                pool: Uint128(5u128),
+            },
+            "offer_not_received",
+        ),
+        (
+            PairError::ExpectedReturnNotMet {
+                expected: Uint128(9u128),
+                actual: Uint128(7u128),
+            },
+            "expected_return_not_met",
+        ),
+        (
+            PairError::SpreadLimitExceeded {
+                max: Decimal::percent(1),
+                actual: Decimal::percent(2),
+            },
+            "spread_limit_exceeded",
+        ),
+        (
+            PairError::PriceImpactExceeded {
+                max: Decimal::percent(1),
+                actual: Decimal::percent(2),
+            },
+            "price_impact_exceeded",
+        ),
+        (PairError::slippage("Refund too low"), "slippage_exceeded"),
+        (PairError::CommissionNotMet, "commission_not_met"),
+        (PairError::invariant("k decreased"), "invariant_violated"),
+        (
+            PairError::expired("The order has expired"),
+            "expired_deadline",
+        ),
+        (PairError::paused("trading is globally paused"), "paused"),
+        (
+            PairError::oracle("The price oracle is unavailable"),
+            "oracle_rejected",
+        ),
+        (PairError::NotFound { item: "Order" }, "not_found"),
+        (
+            PairError::invalid("amp must be positive"),
+            "invalid_parameter",
+        ),
+        (
+            PairError::unsupported("Batched swaps need the constant product curve"),
+            "unsupported",
+        ),
+        (
+            PairError::state("Pair is already initialized"),
+            "invalid_state",
+        ),
+        (
+            PairError::funds("Unexpected native funds sent: uatom"),
+            "funds_mismatch",
+        ),
+        (PairError::overflow("the spot price"), "overflow"),
+    ] {
+        assert_eq!(err.code(), code);
+        assert!(generic_msg(err).starts_with(&format!("{}: ", code)));
+    }
+
+    assert_eq!(
+        generic_msg(PairError::SpreadLimitExceeded {
+            max: Decimal::percent(1),
+            actual: Decimal::percent(2),
+        }),
+        "spread_limit_exceeded: The spread of 0.02 is more than the max_spread of 0.01"
+    );
+    assert_eq!(
+        generic_msg(PairError::overflow("the spot price")),
+        "overflow: Cannot calculate the spot price"
+    );
+    match StdError::from(PairError::Unauthorized) {
+        StdError::Unauthorized { .. } => {}
+        _ => panic!("Must return unauthorized error"),
+    }
+
+    // the handlers surface the same prefixes
+    match assert_max_spread(
+        None,
+        Some(Decimal::percent(1)),
+        None,
+        Uint128(100u128),
+        Uint128(90u128),
+        Uint128::zero(),
+        Uint128(10u128),
+    ) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            "spread_limit_exceeded: The spread of 0.1 is more than the max_spread of 0.01"
+        ),
+        _ => panic!("Must return generic error"),
+    }
+    match assert_max_spread(
+        None,
+        None,
+        Some(Uint128(91u128)),
+        Uint128(100u128),
+        Uint128(90u128),
+        Uint128::zero(),
+        Uint128(10u128),
+    ) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            "expected_return_not_met: The return of 90 fell short of the expected_return of 91"
+        ),
+        _ => panic!("Must return generic error"),
+    }
+}
+
+#[test]
+fn provide_liquidity_without_a_pool_asset() {
+    let mut deps = mock_dependencies(20, &[]);
+    deps.querier.with_token_balances(&[
+        (
+            &HumanAddr::from("asset0000"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0u128))],
+        ),
+        (
+            &HumanAddr::from("asset0001"),
+            &[(&HumanAddr::from(MOCK_CONTRACT_ADDR), &Uint128(0u128))],
+        ),
+    ]);
+    init_token_pair(&mut deps);
+
+    // a deposit missing one of the pools is an error, not a panic
+    let msg = HandleMsg::ProvideLiquidity {
+        assets: [
+            Asset {
+                info: token_asset_info("asset0000"),
+                amount: Uint128(100u128),
+            },
+            Asset {
+                info: token_asset_info("asset0002"),
+                amount: Uint128(100u128),
+            },
+        ],
+        slippage_tolerance: None,
+        auto_stake: None,
+        receiver: None,
+    };
+    match handle(&mut deps, mock_env("addr0000", &[]), msg) {
+        Err(StdError::GenericErr { msg, .. }) => assert!(msg.starts_with("asset_not_in_pair: ")),
+        _ => panic!("Must return generic error"),
+    }
 }
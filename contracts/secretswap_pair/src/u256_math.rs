@@ -22,6 +22,25 @@ pub fn div(nom: Option<U256>, denom: Option<U256>) -> Option<U256> {
     }
 }
 
+fn checked_div_ceil(denom: U256) -> impl Fn(U256) -> Option<U256> {
+    move |nom: U256| {
+        let quotient = nom.checked_div(denom)?;
+        if (nom % denom).is_zero() {
+            Some(quotient)
+        } else {
+            quotient.checked_add(U256::one())
+        }
+    }
+}
+
+/// Same as `div` but rounds up, for amounts that have to round in favor of the pool
+pub fn div_ceil(nom: Option<U256>, denom: Option<U256>) -> Option<U256> {
+    match denom {
+        Some(denom) => nom.and_then(checked_div_ceil(denom)),
+        None => None,
+    }
+}
+
 fn checked_add(b: U256) -> impl Fn(U256) -> Option<U256> {
     move |a: U256| a.checked_add(b)
 }
@@ -78,3 +97,203 @@ pub fn u256_sqrt(y: U256) -> Option<U256> {
 
     return Some(z);
 }
+
+/// Newton iterations before the stableswap solvers give up
+const STABLE_MAX_ITERATIONS: usize = 255;
+
+/// Stableswap invariant D of a two-asset pool, found with Newton's method on
+///
+/// A * n^n * (x + y) + D = A * D * n^n + D^(n+1) / (n^n * x * y), n = 2
+///
+/// Ported from Curve's `get_D`.
+pub fn stable_invariant(x: U256, y: U256, amp: U256) -> Option<U256> {
+    let sum = x.checked_add(y)?;
+    if sum.is_zero() {
+        return Some(U256::zero());
+    }
+    let two = U256::from(2);
+    let ann = amp.checked_mul(U256::from(4))?;
+
+    let mut d = sum;
+    for _ in 0..STABLE_MAX_ITERATIONS {
+        // d_p = D^3 / (4 * x * y)
+        let d_p = d
+            .checked_mul(d)?
+            .checked_div(x.checked_mul(two)?)?
+            .checked_mul(d)?
+            .checked_div(y.checked_mul(two)?)?;
+        let d_prev = d;
+        // D = (Ann * S + 2 * d_p) * D / ((Ann - 1) * D + 3 * d_p)
+        d = ann
+            .checked_mul(sum)?
+            .checked_add(d_p.checked_mul(two)?)?
+            .checked_mul(d)?
+            .checked_div(
+                ann.checked_sub(U256::one())?
+                    .checked_mul(d)?
+                    .checked_add(d_p.checked_mul(U256::from(3))?)?,
+            )?;
+        if abs_diff(d, d_prev) <= U256::one() {
+            return Some(d);
+        }
+    }
+    None
+}
+
+/// Balance of the other asset that keeps the invariant at `d` when one asset's
+/// balance is `x`, ported from Curve's `get_y`
+pub fn stable_other_balance(x: U256, d: U256, amp: U256) -> Option<U256> {
+    let two = U256::from(2);
+    let ann = amp.checked_mul(U256::from(4))?;
+
+    // c = D^3 / (4 * x * Ann), b = x + D / Ann
+    let c = d
+        .checked_mul(d)?
+        .checked_div(x.checked_mul(two)?)?
+        .checked_mul(d)?
+        .checked_div(ann.checked_mul(two)?)?;
+    let b = x.checked_add(d.checked_div(ann)?)?;
+
+    let mut y = d;
+    for _ in 0..STABLE_MAX_ITERATIONS {
+        let y_prev = y;
+        // y = (y^2 + c) / (2 * y + b - D)
+        y = y
+            .checked_mul(y)?
+            .checked_add(c)?
+            .checked_div(y.checked_mul(two)?.checked_add(b)?.checked_sub(d)?)?;
+        if abs_diff(y, y_prev) <= U256::one() {
+            return Some(y);
+        }
+    }
+    None
+}
+
+/// Decimals of the fixed point numbers `weighted_pow` returns
+pub const POW_DECIMALS: usize = 36;
+
+/// Bisection steps before `weighted_pow` gives up, one is `10^36 < 2^120`
+const POW_MAX_ITERATIONS: usize = 128;
+
+/// `(num / den)^(p / q)` for `num <= den`, as a fixed point number of `POW_DECIMALS`
+/// decimals. `round_up` gives an upper bound of the exact power, a lower bound
+/// otherwise, so a swap can always round against the trader. The q-th root is
+/// found by bisection, the base at most one keeps every step in range.
+pub fn weighted_pow(num: U256, den: U256, p: u64, q: u64, round_up: bool) -> Option<U256> {
+    if den.is_zero() || num > den || q == 0 {
+        return None;
+    }
+    let one = U256::exp10(POW_DECIMALS);
+    let divisor = gcd(p, q);
+    let (p, q) = (p / divisor, q / divisor);
+
+    let base = fixed_div(num, den, round_up)?;
+    let target = fixed_pow(base, p, round_up)?;
+    if q == 1 {
+        return Some(target);
+    }
+
+    let (mut low, mut high) = (U256::zero(), one);
+    for _ in 0..POW_MAX_ITERATIONS {
+        if low >= high {
+            return Some(low);
+        }
+        if round_up {
+            // the smallest y with y^q >= target, y^q rounded down
+            let mid = low + (high - low) / 2;
+            if fixed_pow(mid, q, false)? >= target {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        } else {
+            // the largest y with y^q <= target, y^q rounded up
+            let mid = low + (high - low + 1) / 2;
+            if fixed_pow(mid, q, true)? <= target {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+    }
+    None
+}
+
+/// `x^(x_weight / total) * y^(y_weight / total)` with `total = x_weight + y_weight`,
+/// rounded down. `sqrt(x * y)` at equal weights.
+pub fn weighted_geometric_mean(x: U256, y: U256, x_weight: u64, y_weight: u64) -> Option<U256> {
+    // = large * (small / large)^(small_weight / total), the ratio is at most one
+    let (small, large, small_weight) = if x <= y {
+        (x, y, x_weight)
+    } else {
+        (y, x, y_weight)
+    };
+    if large.is_zero() {
+        return Some(U256::zero());
+    }
+    let ratio = weighted_pow(
+        small,
+        large,
+        small_weight,
+        x_weight.checked_add(y_weight)?,
+        false,
+    )?;
+    large
+        .checked_mul(ratio)?
+        .checked_div(U256::exp10(POW_DECIMALS))
+}
+
+/// `a * b` for fixed point `a` and `b` of `POW_DECIMALS` decimals
+fn fixed_mul(a: U256, b: U256, round_up: bool) -> Option<U256> {
+    let one = U256::exp10(POW_DECIMALS);
+    let product = a.checked_mul(b)?;
+    let rounding = if round_up {
+        one - U256::one()
+    } else {
+        U256::zero()
+    };
+    product.checked_add(rounding)?.checked_div(one)
+}
+
+/// `num / den` as a fixed point number of `POW_DECIMALS` decimals
+fn fixed_div(num: U256, den: U256, round_up: bool) -> Option<U256> {
+    let scaled = num.checked_mul(U256::exp10(POW_DECIMALS))?;
+    let rounding = if round_up {
+        den.checked_sub(U256::one())?
+    } else {
+        U256::zero()
+    };
+    scaled.checked_add(rounding)?.checked_div(den)
+}
+
+/// `base^exp` for fixed point `base` of `POW_DECIMALS` decimals, by squaring
+fn fixed_pow(base: U256, exp: u64, round_up: bool) -> Option<U256> {
+    let mut result = U256::exp10(POW_DECIMALS);
+    let (mut base, mut exp) = (base, exp);
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = fixed_mul(result, base, round_up)?;
+        }
+        exp >>= 1;
+        if exp > 0 {
+            base = fixed_mul(base, base, round_up)?;
+        }
+    }
+    Some(result)
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn abs_diff(a: U256, b: U256) -> U256 {
+    if a > b {
+        a - b
+    } else {
+        b - a
+    }
+}
@@ -0,0 +1,16 @@
+//! Runs the exporter behind `cargo schema` into a scratch directory.
+
+#[allow(dead_code)]
+#[path = "../examples/schema.rs"]
+mod schema;
+
+#[test]
+fn export_schemas() {
+    let out_dir = std::env::temp_dir().join("secretswap_pair_schema");
+    std::fs::create_dir_all(&out_dir).unwrap();
+
+    schema::export_schemas(&out_dir);
+
+    assert!(out_dir.join("handle_msg.json").exists());
+    assert!(out_dir.join("query_msg.json").exists());
+}
@@ -0,0 +1,34 @@
+use std::env::current_dir;
+use std::fs::create_dir_all;
+use std::path::Path;
+
+use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
+use cosmwasm_std::HandleResponse;
+
+use secretswap_router::msg::{
+    HandleMsg, InitMsg, QueryMsg, ReceiveMsg, Route, SimulateSplitSwapResponse,
+    SimulateSwapOperationsResponse,
+};
+
+fn main() {
+    let mut out_dir = current_dir().unwrap();
+    out_dir.push("schema");
+    create_dir_all(&out_dir).unwrap();
+    remove_schemas(&out_dir).unwrap();
+
+    export_schemas(&out_dir);
+}
+
+/// Every message the router takes and every response it gives
+pub fn export_schemas(out_dir: &Path) {
+    let out_dir = &out_dir.to_path_buf();
+
+    export_schema(&schema_for!(InitMsg), out_dir);
+    export_schema(&schema_for!(HandleMsg), out_dir);
+    export_schema(&schema_for!(Route), out_dir);
+    export_schema(&schema_for!(ReceiveMsg), out_dir);
+    export_schema(&schema_for!(HandleResponse), out_dir);
+    export_schema(&schema_for!(QueryMsg), out_dir);
+    export_schema(&schema_for!(SimulateSwapOperationsResponse), out_dir);
+    export_schema(&schema_for!(SimulateSplitSwapResponse), out_dir);
+}
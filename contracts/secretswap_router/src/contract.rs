@@ -1,19 +1,38 @@
+use std::collections::VecDeque;
+
 use cosmwasm_std::{
     debug_print, from_binary, to_binary, Api, BankMsg, Binary, Coin, CosmosMsg, Env, Extern,
     HandleResponse, HumanAddr, InitResponse, Querier, StdError, StdResult, Storage, Uint128,
     WasmMsg,
 };
 use secret_toolkit::snip20;
-use secretswap::{Asset, AssetInfo};
+
+use secretswap::{
+    query_balance, query_token_balance, reverse_simulate, simulate, Asset, AssetInfo, PairInfo,
+    DEFAULT_NOISE_BPS_MAX,
+};
 
 use crate::{
-    msg::{HandleMsg, Hop, InitMsg, NativeSwap, QueryMsg, Route, Snip20Data, Snip20Swap, Token},
+    msg::{
+        HandleMsg, Hop, HopSimulation, InitMsg, NativeSwap, QueryMsg, ReceiveMsg, Route,
+        SimulateSplitSwapResponse, SimulateSwapOperationsResponse, Snip20Data, Snip20Swap,
+        SplitRoute, Token,
+    },
+    querier::{query_pair, query_pair_settings},
     state::{
-        delete_route_state, read_cashback, read_owner, read_route_state, read_tokens,
-        store_cashback, store_owner, store_route_state, store_tokens, RouteState,
+        delete_route_state, delete_split_state, read_cashback, read_owner, read_route_state,
+        read_split_state, read_sscrt, read_tokens, store_cashback, store_owner, store_route_state,
+        store_split_state, store_sscrt, store_tokens, RouteState, SplitState,
     },
 };
 
+/// Longest route the router executes or simulates
+pub const MAX_ROUTE_HOPS: usize = 4;
+/// Most routes a split swap divides its offer over
+pub const MAX_SPLIT_ROUTES: usize = 4;
+/// What the weights of a split swap's routes add up to
+pub const SPLIT_WEIGHT_TOTAL: u16 = 10_000;
+
 pub fn init<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
@@ -44,6 +63,18 @@ pub fn init<S: Storage, A: Api, Q: Querier>(
         )?);
     }
 
+    if let Some(sscrt) = msg.sscrt {
+        store_sscrt(&mut deps.storage, &sscrt)?;
+        output_msgs.extend(register_tokens(
+            deps,
+            &env,
+            vec![Snip20Data {
+                address: sscrt.address,
+                code_hash: sscrt.code_hash,
+            }],
+        )?);
+    }
+
     Ok(InitResponse {
         messages: output_msgs,
         log: vec![],
@@ -60,13 +91,22 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
             from: _,
             msg: Some(msg),
             amount,
-        } => handle_first_hop(deps, &env, msg, amount),
+        } => match from_binary(&msg) {
+            Ok(ReceiveMsg::ExecuteSplitSwap {
+                routes,
+                minimum_receive_total,
+                to,
+            }) => handle_split_swap(deps, &env, amount, routes, minimum_receive_total, to),
+            Err(_) => handle_first_hop(deps, &env, msg, amount),
+        },
         HandleMsg::Receive {
             from,
             msg: None,
             amount,
         } => handle_hop(deps, &env, from, amount),
         HandleMsg::FinalizeRoute {} => finalize_route(deps, &env),
+        HandleMsg::ExecuteSplitRoute {} => execute_split_route(deps, &env),
+        HandleMsg::FinalizeSplitSwap {} => finalize_split_swap(deps, &env),
         HandleMsg::RegisterTokens { tokens } => {
             check_owner(deps, &env)?;
 
@@ -112,6 +152,7 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
         HandleMsg::UpdateSettings {
             new_owner,
             new_cashback,
+            new_sscrt,
         } => {
             check_owner(deps, &env)?;
 
@@ -123,7 +164,24 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
                 store_cashback(&mut deps.storage, &new_cashback)?;
             }
 
-            Ok(HandleResponse::default())
+            let mut output_msgs = vec![];
+            if let Some(new_sscrt) = new_sscrt {
+                store_sscrt(&mut deps.storage, &new_sscrt)?;
+                output_msgs = register_tokens(
+                    deps,
+                    &env,
+                    vec![Snip20Data {
+                        address: new_sscrt.address,
+                        code_hash: new_sscrt.code_hash,
+                    }],
+                )?;
+            }
+
+            Ok(HandleResponse {
+                messages: output_msgs,
+                log: vec![],
+                data: None,
+            })
         }
     }
 }
@@ -139,63 +197,166 @@ fn handle_first_hop<S: Storage, A: Api, Q: Querier>(
     // 2. send `amount` X to pair X/Y
     // 3. call FinalizeRoute to make sure everything went ok, otherwise revert the tx
 
-    let Route {
-        mut hops,
-        to,
-        expected_return,
-    } = from_binary(&msg)?;
+    let route: Route = from_binary(&msg)?;
+    let hops = &route.hops;
 
     if hops.len() < 2 {
         return Err(StdError::generic_err("route must be at least 2 hops"));
     }
+    if hops.len() > MAX_ROUTE_HOPS {
+        return Err(StdError::generic_err(format!(
+            "route must be at most {} hops",
+            MAX_ROUTE_HOPS
+        )));
+    }
+
+    check_route_tokens(deps, hops.iter().skip(1))?;
 
+    // uscrt sent for a route that starts in sSCRT is wrapped first
+    let sscrt = read_sscrt(&deps.storage)?;
+    let wrap_input = match (&hops[0].from_token, &sscrt) {
+        (Token::Snip20(Snip20Data { address, .. }), Some(sscrt)) => {
+            *address == sscrt.address && received_token(env, &Token::Scrt, amount)
+        }
+        _ => false,
+    };
+
+    if !wrap_input && !received_token(env, &hops[0].from_token, amount) {
+        return Err(StdError::generic_err(
+            "route can only be initiated by sending here the token of the first hop",
+        ));
+    }
+
+    if route.unwrap_output {
+        let ends_in_sscrt = match &sscrt {
+            Some(sscrt) => {
+                let (_, _, ask_info) = hop_assets(deps, &hops[hops.len() - 1])?;
+                match ask_info {
+                    AssetInfo::Token { contract_addr, .. } => contract_addr == sscrt.address,
+                    AssetInfo::NativeToken { .. } => false,
+                }
+            }
+            None => false,
+        };
+        if !ends_in_sscrt {
+            return Err(StdError::generic_err(
+                "unwrap_output needs a route that ends in sSCRT",
+            ));
+        }
+    }
+
+    let mut msgs = vec![];
+    if wrap_input {
+        // unwrap is cool because `wrap_input` is only set with an sSCRT contract
+        let sscrt = sscrt.unwrap();
+        msgs.push(snip20::deposit_msg(
+            amount,
+            None,
+            256,
+            sscrt.code_hash,
+            sscrt.address,
+        )?);
+    }
+    msgs.extend(start_route(deps, env, route, amount)?);
+
+    Ok(HandleResponse {
+        messages: msgs,
+        log: vec![],
+        data: None,
+    })
+}
+
+/// Checks the input token of every hop but the first, which the router receives
+/// from the hop before it
+fn check_route_tokens<'a, S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    hops: impl Iterator<Item = &'a Hop>,
+) -> StdResult<()> {
     // uscrt can only be the input or output token
     // check that uscrt is not the input token for any hop that is not the first hop
     // (we don't need to check if it's the output token because it's handled in the swap_pair contract)
-    for i in 1..(hops.len() - 1) {
-        match hops[i].from_token {
+    // every other hop's input token is received by the router, so it must be registered
+    // here, otherwise the route would only fail once that token reaches us
+    let registered_tokens = read_tokens(&deps.storage)?;
+    for hop in hops {
+        match &hop.from_token {
             Token::Scrt => {
                 return Err(StdError::generic_err(
                     "cannot route via uscrt. uscrt can only be route input token or output token.",
                 ))
             }
-            _ => continue,
+            Token::Snip20(Snip20Data { address, .. }) => {
+                if !registered_tokens.contains(address) {
+                    return Err(StdError::generic_err(format!(
+                        "token {} is not registered with the router",
+                        address
+                    )));
+                }
+            }
         }
     }
 
-    let first_hop: Hop = hops.pop_front().unwrap(); // unwrap is cool because `hops.len() >= 2`
+    Ok(())
+}
 
-    let received_first_hop: bool = match first_hop.from_token {
-        Token::Snip20(Snip20Data {
-            ref address,
-            code_hash: _,
-        }) => env.message.sender == *address,
+/// Whether the message brought `amount` of `token` with it
+fn received_token(env: &Env, token: &Token, amount: Uint128) -> bool {
+    match token {
+        Token::Snip20(Snip20Data { address, .. }) => env.message.sender == *address,
         Token::Scrt => {
             env.message.sent_funds.len() == 1
                 && env.message.sent_funds[0].amount == amount
                 && env.message.sent_funds[0].denom == "uscrt"
         }
-    };
-
-    if !received_first_hop {
-        return Err(StdError::generic_err(
-            "route can only be initiated by sending here the token of the first hop",
-        ));
     }
+}
+
+/// Saves the route state and sends `amount` to the first pair, followed by
+/// FinalizeRoute. A route of a single hop pays out straight away.
+fn start_route<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    route: Route,
+    amount: Uint128,
+) -> StdResult<Vec<CosmosMsg>> {
+    let Route {
+        mut hops,
+        expected_return,
+        to,
+        unwrap_output,
+    } = route;
+    let first_hop: Hop = match hops.pop_front() {
+        Some(first_hop) => first_hop,
+        None => return Err(StdError::generic_err("route must be at least 1 hop")),
+    };
+    let is_last_hop = hops.is_empty();
 
     store_route_state(
         &mut deps.storage,
         &RouteState {
-            is_done: false,
-            current_hop: Some(first_hop.clone()),
+            is_done: is_last_hop,
+            current_hop: if is_last_hop {
+                None
+            } else {
+                Some(first_hop.clone())
+            },
             remaining_route: Route {
                 hops, // hops was mutated earlier when we did `hops.pop_front()`
                 expected_return,
-                to,
+                to: to.clone(),
+                unwrap_output,
             },
         },
     )?;
 
+    let (expected_return, recipient) = if is_last_hop {
+        (expected_return, final_recipient(env, to, unwrap_output))
+    } else {
+        // set expected_return to None because we don't care about slippage mid-route
+        // set the recepient of the swap to be this contract (the router)
+        (None, env.contract.address.clone())
+    };
+
     let mut msgs = vec![];
 
     match first_hop.from_token {
@@ -206,10 +367,8 @@ fn handle_first_hop<S: Storage, A: Api, Q: Querier>(
                 amount,
                 // build swap msg for the next hop
                 Some(to_binary(&Snip20Swap::Swap {
-                    // set expected_return to None because we don't care about slippage mid-route
-                    expected_return: None,
-                    // set the recepient of the swap to be this contract (the router)
-                    to: Some(env.contract.address.clone()),
+                    expected_return,
+                    to: Some(recipient),
                 })?),
                 None,
                 256,
@@ -231,10 +390,8 @@ fn handle_first_hop<S: Storage, A: Api, Q: Querier>(
                                 denom: "uscrt".into(),
                             },
                         },
-                        // set expected_return to None because we don't care about slippage mid-route
-                        expected_return: None,
-                        // set the recepient of the swap to be this contract (the router)
-                        to: Some(env.contract.address.clone()),
+                        expected_return,
+                        to: Some(recipient),
                     })?,
                     send: vec![Coin::new(amount.u128(), "uscrt")],
                 }),
@@ -244,14 +401,83 @@ fn handle_first_hop<S: Storage, A: Api, Q: Querier>(
 
     msgs.push(
         // finalize the route at the end, to make sure the route was completed successfully
-        CosmosMsg::Wasm(WasmMsg::Execute {
-            contract_addr: env.contract.address.clone(),
-            callback_code_hash: env.contract_code_hash.clone(),
-            msg: to_binary(&HandleMsg::FinalizeRoute {})?,
-            send: vec![],
-        }),
+        self_msg(env, &HandleMsg::FinalizeRoute {})?,
     );
 
+    Ok(msgs)
+}
+
+/// Who the last hop pays, the router itself when it redeems the output for `to`
+fn final_recipient(env: &Env, to: HumanAddr, unwrap_output: bool) -> HumanAddr {
+    if unwrap_output {
+        env.contract.address.clone()
+    } else {
+        to
+    }
+}
+
+fn self_msg(env: &Env, msg: &HandleMsg) -> StdResult<CosmosMsg> {
+    Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: env.contract.address.clone(),
+        callback_code_hash: env.contract_code_hash.clone(),
+        msg: to_binary(msg)?,
+        send: vec![],
+    }))
+}
+
+fn handle_split_swap<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    amount: Uint128,
+    routes: Vec<SplitRoute>,
+    minimum_receive_total: Uint128,
+    to: HumanAddr,
+) -> StdResult<HandleResponse> {
+    // The routes run one after the other, each through the same machinery as a
+    // single route but paying the router, then FinalizeSplitSwap pays out whatever
+    // the router's balance of the ask token grew by
+    if read_split_state(&deps.storage)?.is_some() {
+        return Err(StdError::generic_err("a split swap is already in progress"));
+    }
+
+    let amounts = split_amounts(amount, &routes)?;
+    let (offer_token, ask_token) = resolve_split(deps, &routes)?;
+    for route in routes.iter() {
+        check_route_tokens(deps, route.operations.iter().skip(1))?;
+    }
+
+    if !received_token(env, &offer_token, amount) {
+        return Err(StdError::generic_err(
+            "route can only be initiated by sending here the token of the first hop",
+        ));
+    }
+
+    let remaining_routes: VecDeque<(Uint128, VecDeque<Hop>)> = routes
+        .into_iter()
+        .zip(amounts)
+        // too small a share to swap
+        .filter(|(_, amount)| !amount.is_zero())
+        .map(|(route, amount)| (amount, VecDeque::from(route.operations)))
+        .collect();
+
+    let mut msgs = vec![];
+    for _ in remaining_routes.iter() {
+        msgs.push(self_msg(env, &HandleMsg::ExecuteSplitRoute {})?);
+    }
+    msgs.push(self_msg(env, &HandleMsg::FinalizeSplitSwap {})?);
+
+    let balance_before = query_router_balance(deps, env, &ask_token)?;
+    store_split_state(
+        &mut deps.storage,
+        &SplitState {
+            remaining_routes,
+            ask_token,
+            balance_before,
+            minimum_receive_total,
+            to,
+        },
+    )?;
+
     Ok(HandleResponse {
         messages: msgs,
         log: vec![],
@@ -259,6 +485,223 @@ fn handle_first_hop<S: Storage, A: Api, Q: Querier>(
     })
 }
 
+/// Offer amount of each route of a split swap, the rounding remainder going to
+/// the first one
+fn split_amounts(amount: Uint128, routes: &[SplitRoute]) -> StdResult<Vec<Uint128>> {
+    if routes.is_empty() {
+        return Err(StdError::generic_err("a split swap needs at least 1 route"));
+    }
+    if routes.len() > MAX_SPLIT_ROUTES {
+        return Err(StdError::generic_err(format!(
+            "a split swap has at most {} routes",
+            MAX_SPLIT_ROUTES
+        )));
+    }
+    let total_weight: u32 = routes.iter().map(|route| route.weight_bps as u32).sum();
+    if total_weight != SPLIT_WEIGHT_TOTAL as u32 {
+        return Err(StdError::generic_err(format!(
+            "route weights must sum to {}",
+            SPLIT_WEIGHT_TOTAL
+        )));
+    }
+
+    let mut amounts: Vec<Uint128> = routes
+        .iter()
+        .map(|route| amount.multiply_ratio(route.weight_bps, SPLIT_WEIGHT_TOTAL))
+        .collect();
+    let split: u128 = amounts.iter().map(|amount| amount.u128()).sum();
+    amounts[0] = Uint128(amounts[0].u128() + amount.u128() - split);
+
+    Ok(amounts)
+}
+
+/// The token all the routes of a split swap offer and the one they all return
+fn resolve_split<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    routes: &[SplitRoute],
+) -> StdResult<(Token, Token)> {
+    let mut tokens: Option<(Token, AssetInfo)> = None;
+    for route in routes {
+        let resolved = resolve_operations(deps, &route.operations)?;
+        // resolve_operations made sure there is a hop
+        let offer_token = route.operations[0].from_token.clone();
+        let (_, ask_info, _) = resolved[resolved.len() - 1].clone();
+
+        match &tokens {
+            None => tokens = Some((offer_token, ask_info)),
+            Some((first_offer_token, first_ask_info)) => {
+                if !same_token(first_offer_token, &offer_token) || !first_ask_info.equal(&ask_info)
+                {
+                    return Err(StdError::generic_err(
+                        "every route of a split swap must offer and return the same tokens",
+                    ));
+                }
+            }
+        }
+    }
+
+    let (offer_token, ask_info) = match tokens {
+        Some(tokens) => tokens,
+        None => return Err(StdError::generic_err("a split swap needs at least 1 route")),
+    };
+    let ask_token = match ask_info {
+        AssetInfo::Token {
+            contract_addr,
+            token_code_hash,
+            ..
+        } => {
+            if !read_tokens(&deps.storage)?.contains(&contract_addr) {
+                return Err(StdError::generic_err(format!(
+                    "token {} is not registered with the router",
+                    contract_addr
+                )));
+            }
+            Token::Snip20(Snip20Data {
+                address: contract_addr,
+                code_hash: token_code_hash,
+            })
+        }
+        AssetInfo::NativeToken { denom } if denom == "uscrt" => Token::Scrt,
+        AssetInfo::NativeToken { denom } => {
+            return Err(StdError::generic_err(format!(
+                "the router cannot pay out {}",
+                denom
+            )))
+        }
+    };
+
+    if same_token(&offer_token, &ask_token) {
+        return Err(StdError::generic_err(
+            "a split swap must return a different token than it offers",
+        ));
+    }
+
+    Ok((offer_token, ask_token))
+}
+
+fn same_token(a: &Token, b: &Token) -> bool {
+    match (a, b) {
+        (Token::Snip20(a), Token::Snip20(b)) => a.address == b.address,
+        (Token::Scrt, Token::Scrt) => true,
+        _ => false,
+    }
+}
+
+fn query_router_balance<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    env: &Env,
+    token: &Token,
+) -> StdResult<Uint128> {
+    match token {
+        Token::Snip20(Snip20Data { address, code_hash }) => query_token_balance(
+            deps,
+            address,
+            code_hash,
+            &env.contract.address,
+            &"SecretSwap Router".to_string(),
+        ),
+        Token::Scrt => query_balance(deps, &env.contract.address, "uscrt".to_string()),
+    }
+}
+
+fn execute_split_route<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+) -> StdResult<HandleResponse> {
+    if env.contract.address != env.message.sender {
+        return Err(StdError::unauthorized());
+    }
+
+    let mut split = match read_split_state(&deps.storage)? {
+        Some(split) => split,
+        None => return Err(StdError::generic_err("no split swap in progress")),
+    };
+    let (amount, hops) = match split.remaining_routes.pop_front() {
+        Some(route) => route,
+        None => return Err(StdError::generic_err("split swap has no routes left")),
+    };
+    store_split_state(&mut deps.storage, &split)?;
+
+    // the route pays the router, FinalizeSplitSwap checks the total
+    let route = Route {
+        hops,
+        expected_return: None,
+        to: env.contract.address.clone(),
+        unwrap_output: false,
+    };
+    let msgs = start_route(deps, env, route, amount)?;
+
+    Ok(HandleResponse {
+        messages: msgs,
+        log: vec![],
+        data: None,
+    })
+}
+
+fn finalize_split_swap<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+) -> StdResult<HandleResponse> {
+    if env.contract.address != env.message.sender {
+        return Err(StdError::unauthorized());
+    }
+
+    let split = match read_split_state(&deps.storage)? {
+        Some(split) => split,
+        None => return Err(StdError::generic_err("no split swap to finalize")),
+    };
+    if !split.remaining_routes.is_empty() {
+        return Err(StdError::generic_err(
+            "cannot finalize: split swap still has routes to run",
+        ));
+    }
+
+    let return_amount =
+        (query_router_balance(deps, env, &split.ask_token)? - split.balance_before)?;
+    if return_amount < split.minimum_receive_total {
+        return Err(StdError::generic_err(
+            "Operation fell short of minimum_receive_total",
+        ));
+    }
+
+    delete_split_state(&mut deps.storage);
+
+    let mut messages = vec![];
+    if !return_amount.is_zero() {
+        messages.push(match &split.ask_token {
+            Token::Snip20(Snip20Data { address, code_hash }) => snip20::send_msg(
+                split.to.clone(),
+                return_amount,
+                None,
+                None,
+                256,
+                code_hash.clone(),
+                address.clone(),
+            )?,
+            Token::Scrt => CosmosMsg::Bank(BankMsg::Send {
+                from_address: env.contract.address.clone(),
+                to_address: split.to.clone(),
+                amount: vec![Coin::new(return_amount.u128(), "uscrt")],
+            }),
+        });
+    }
+
+    // when cashback is the ask token itself it's already part of `return_amount`
+    let cashback_is_ask_token = match (read_cashback(&deps.storage)?, &split.ask_token) {
+        (Some(cashback), Token::Snip20(Snip20Data { address, .. })) => cashback.address == *address,
+        _ => false,
+    };
+    if !cashback_is_ask_token {
+        messages.extend(cashback_msgs(deps, env, &split.to)?);
+    }
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![],
+        data: None,
+    })
+}
+
 fn handle_hop<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: &Env,
@@ -274,6 +717,63 @@ fn handle_hop<S: Storage, A: Api, Q: Querier>(
     // 2'. this is the last hop so delete the entire route state
     // 3'. send `amount` Z to pair Z/W with recepient `to`
     match read_route_state(&deps.storage)? {
+        Some(RouteState {
+            is_done: true,
+            current_hop: None,
+            remaining_route: Route { ref to, .. },
+        }) if *to == env.contract.address => {
+            // the last hop of a split swap's route paid the router,
+            // FinalizeSplitSwap accounts for it
+            Ok(HandleResponse::default())
+        }
+        Some(RouteState {
+            is_done: true,
+            current_hop: None,
+            remaining_route,
+        }) if remaining_route.unwrap_output => {
+            // the last hop paid the router the sSCRT to redeem for `to`
+            let sscrt = match read_sscrt(&deps.storage)? {
+                Some(sscrt) if sscrt.address == env.message.sender => sscrt,
+                _ => {
+                    return Err(StdError::generic_err(
+                        "unwrap_output needs a route that ends in sSCRT",
+                    ))
+                }
+            };
+
+            let to = remaining_route.to.clone();
+            store_route_state(
+                &mut deps.storage,
+                &RouteState {
+                    is_done: true,
+                    current_hop: None,
+                    remaining_route: Route {
+                        unwrap_output: false,
+                        ..remaining_route
+                    },
+                },
+            )?;
+
+            Ok(HandleResponse {
+                messages: vec![
+                    snip20::redeem_msg(
+                        amount,
+                        Some("uscrt".to_string()),
+                        None,
+                        256,
+                        sscrt.code_hash,
+                        sscrt.address,
+                    )?,
+                    CosmosMsg::Bank(BankMsg::Send {
+                        from_address: env.contract.address.clone(),
+                        to_address: to,
+                        amount: vec![Coin::new(amount.u128(), "uscrt")],
+                    }),
+                ],
+                log: vec![],
+                data: None,
+            })
+        }
         Some(RouteState {
             is_done,
             current_hop,
@@ -282,6 +782,7 @@ fn handle_hop<S: Storage, A: Api, Q: Querier>(
                     mut hops,
                     expected_return,
                     to,
+                    unwrap_output,
                 },
         }) => {
             let next_hop: Hop = match hops.pop_front() {
@@ -320,7 +821,8 @@ fn handle_hop<S: Storage, A: Api, Q: Querier>(
                 // last hop
                 // 1. set is_done to true for FinalizeRoute
                 // 2. set expected_return for the final swap
-                // 3. set the recipient of the final swap to be the user
+                // 3. set the recipient of the final swap to be the user, or the router
+                //    when it redeems the output for the user
                 is_done = true;
                 current_hop = None;
                 msgs.push(snip20::send_msg(
@@ -328,7 +830,7 @@ fn handle_hop<S: Storage, A: Api, Q: Querier>(
                     amount,
                     Some(to_binary(&Snip20Swap::Swap {
                         expected_return,
-                        to: Some(to.clone()),
+                        to: Some(final_recipient(env, to.clone(), unwrap_output)),
                     })?),
                     None,
                     256,
@@ -362,6 +864,7 @@ fn handle_hop<S: Storage, A: Api, Q: Querier>(
                         hops, // hops was mutated earlier when we did `hops.pop_front()`
                         expected_return,
                         to,
+                        unwrap_output,
                     },
                 },
             )?;
@@ -414,43 +917,58 @@ fn finalize_route<S: Storage, A: Api, Q: Querier>(
 
             delete_route_state(&mut deps.storage);
 
-            if let Some(cashback) = read_cashback(&deps.storage)? {
-                let balance = snip20::balance_query(
-                    &deps.querier,
-                    env.contract.address.clone(),
-                    "SecretSwap Router".into(),
-                    256,
-                    cashback.code_hash.clone(),
-                    cashback.address.clone(),
-                )?;
-
-                let mut messages = vec![];
-                if balance.amount.u128() > 0 {
-                    let msg = snip20::send_msg(
-                        remaining_route.to,
-                        balance.amount,
-                        None,
-                        None,
-                        256,
-                        cashback.code_hash,
-                        cashback.address,
-                    )?;
-                    messages.push(msg);
-                }
-
-                Ok(HandleResponse {
-                    messages,
-                    log: vec![],
-                    data: None,
-                })
-            } else {
-                Ok(HandleResponse::default())
+            // a split swap's routes pay the router, FinalizeSplitSwap sends the cashback
+            if remaining_route.to == env.contract.address {
+                return Ok(HandleResponse::default());
             }
+
+            Ok(HandleResponse {
+                messages: cashback_msgs(deps, env, &remaining_route.to)?,
+                log: vec![],
+                data: None,
+            })
         }
         None => Err(StdError::generic_err("no route to finalize")),
     }
 }
 
+/// Sends the router's whole cashback balance to `to`, if there is a cashback token
+fn cashback_msgs<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    env: &Env,
+    to: &HumanAddr,
+) -> StdResult<Vec<CosmosMsg>> {
+    let cashback = match read_cashback(&deps.storage)? {
+        Some(cashback) => cashback,
+        None => return Ok(vec![]),
+    };
+
+    let balance = snip20::balance_query(
+        &deps.querier,
+        env.contract.address.clone(),
+        "SecretSwap Router".into(),
+        256,
+        cashback.code_hash.clone(),
+        cashback.address.clone(),
+    )?;
+
+    let mut messages = vec![];
+    if balance.amount.u128() > 0 {
+        let msg = snip20::send_msg(
+            to.clone(),
+            balance.amount,
+            None,
+            None,
+            256,
+            cashback.code_hash,
+            cashback.address,
+        )?;
+        messages.push(msg);
+    }
+
+    Ok(messages)
+}
+
 fn check_owner<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: &Env,
@@ -510,5 +1028,206 @@ pub fn query<S: Storage, A: Api, Q: Querier>(
             let tokens = read_tokens(&deps.storage)?;
             Ok(to_binary(&tokens)?)
         }
+        QueryMsg::SimulateSwapOperations {
+            offer_amount,
+            operations,
+        } => to_binary(&simulate_swap_operations(deps, offer_amount, operations)?),
+        QueryMsg::SimulateReverseSwapOperations {
+            ask_amount,
+            operations,
+        } => to_binary(&simulate_reverse_swap_operations(
+            deps, ask_amount, operations,
+        )?),
+        QueryMsg::SimulateSplitSwap {
+            offer_amount,
+            routes,
+        } => to_binary(&simulate_split_swap(deps, offer_amount, routes)?),
+    }
+}
+
+/// The hop's pair, and the asset it offers the pair and the one it gets back
+fn hop_assets<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    hop: &Hop,
+) -> StdResult<(PairInfo, AssetInfo, AssetInfo)> {
+    let pair_info = query_pair(deps, &hop.pair_address, &hop.pair_code_hash)?;
+
+    let offer_index = pair_info
+        .asset_infos
+        .iter()
+        .position(|info| match (&hop.from_token, info) {
+            (Token::Snip20(Snip20Data { address, .. }), AssetInfo::Token { contract_addr, .. }) => {
+                address == contract_addr
+            }
+            (Token::Scrt, AssetInfo::NativeToken { denom }) => denom == "uscrt",
+            _ => false,
+        })
+        .ok_or_else(|| {
+            StdError::generic_err(format!(
+                "pair {} does not trade the token of its hop",
+                hop.pair_address
+            ))
+        })?;
+    let offer_info = pair_info.asset_infos[offer_index].clone();
+    let ask_info = pair_info.asset_infos[1 - offer_index].clone();
+
+    Ok((pair_info, offer_info, ask_info))
+}
+
+/// Pair, offer and ask asset and noise width of each hop, checking that every hop
+/// offers what the one before it returns
+fn resolve_operations<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    operations: &[Hop],
+) -> StdResult<Vec<(AssetInfo, AssetInfo, u16)>> {
+    if operations.is_empty() {
+        return Err(StdError::generic_err("route must be at least 1 hop"));
+    }
+    if operations.len() > MAX_ROUTE_HOPS {
+        return Err(StdError::generic_err(format!(
+            "route must be at most {} hops",
+            MAX_ROUTE_HOPS
+        )));
+    }
+
+    let mut resolved: Vec<(AssetInfo, AssetInfo, u16)> = vec![];
+    for hop in operations {
+        let (pair_info, offer_info, ask_info) = hop_assets(deps, hop)?;
+
+        if let Some((_, previous_ask_info, _)) = resolved.last() {
+            if !previous_ask_info.equal(&offer_info) {
+                return Err(StdError::generic_err(format!(
+                    "pair {} does not offer what the previous hop returns",
+                    hop.pair_address
+                )));
+            }
+        }
+
+        let settings = query_pair_settings(deps, &pair_info.factory, &hop.pair_address)?;
+        let noise_bps = settings.noise_bps_max.unwrap_or(DEFAULT_NOISE_BPS_MAX);
+
+        resolved.push((offer_info, ask_info, noise_bps));
+    }
+
+    Ok(resolved)
+}
+
+fn simulate_swap_operations<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    offer_amount: Uint128,
+    operations: Vec<Hop>,
+) -> StdResult<SimulateSwapOperationsResponse> {
+    let resolved = resolve_operations(deps, &operations)?;
+
+    let mut amount = offer_amount;
+    let mut hops = vec![];
+    for (hop, (offer_info, _, noise_bps)) in operations.into_iter().zip(resolved) {
+        let simulation = simulate(
+            deps,
+            &hop.pair_address,
+            &hop.pair_code_hash,
+            &Asset {
+                info: offer_info,
+                amount,
+            },
+        )?;
+
+        hops.push(HopSimulation {
+            pair_address: hop.pair_address,
+            offer_amount: amount,
+            return_amount: simulation.return_amount,
+            spread_amount: simulation.spread_amount,
+            commission_amount: simulation.commission_amount,
+            noise_bps,
+        });
+        amount = simulation.return_amount;
+    }
+
+    Ok(SimulateSwapOperationsResponse {
+        offer_amount,
+        return_amount: amount,
+        noise_bps: hops.iter().map(|hop| hop.noise_bps).sum(),
+        hops,
+    })
+}
+
+fn simulate_reverse_swap_operations<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    ask_amount: Uint128,
+    operations: Vec<Hop>,
+) -> StdResult<SimulateSwapOperationsResponse> {
+    let resolved = resolve_operations(deps, &operations)?;
+
+    // walk the route backwards, each hop must return what the next one is offered
+    let mut amount = ask_amount;
+    let mut hops = vec![];
+    for (hop, (_, ask_info, noise_bps)) in operations.into_iter().zip(resolved).rev() {
+        let simulation = reverse_simulate(
+            deps,
+            &hop.pair_address,
+            &hop.pair_code_hash,
+            &Asset {
+                info: ask_info,
+                amount,
+            },
+        )?;
+
+        hops.push(HopSimulation {
+            pair_address: hop.pair_address,
+            offer_amount: simulation.offer_amount,
+            return_amount: amount,
+            spread_amount: simulation.spread_amount,
+            commission_amount: simulation.commission_amount,
+            noise_bps,
+        });
+        amount = simulation.offer_amount;
     }
+    hops.reverse();
+
+    Ok(SimulateSwapOperationsResponse {
+        offer_amount: amount,
+        return_amount: ask_amount,
+        noise_bps: hops.iter().map(|hop| hop.noise_bps).sum(),
+        hops,
+    })
+}
+
+fn simulate_split_swap<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    offer_amount: Uint128,
+    routes: Vec<SplitRoute>,
+) -> StdResult<SimulateSplitSwapResponse> {
+    let amounts = split_amounts(offer_amount, &routes)?;
+    resolve_split(deps, &routes)?;
+
+    let mut simulations = vec![];
+    for (route, amount) in routes.into_iter().zip(amounts) {
+        simulations.push(if amount.is_zero() {
+            // too small a share to swap, ExecuteSplitSwap skips it as well
+            SimulateSwapOperationsResponse {
+                offer_amount: amount,
+                return_amount: Uint128::zero(),
+                hops: vec![],
+                noise_bps: 0,
+            }
+        } else {
+            simulate_swap_operations(deps, amount, route.operations)?
+        });
+    }
+
+    Ok(SimulateSplitSwapResponse {
+        offer_amount,
+        return_amount: Uint128(
+            simulations
+                .iter()
+                .map(|simulation| simulation.return_amount.u128())
+                .sum(),
+        ),
+        noise_bps: simulations
+            .iter()
+            .map(|simulation| simulation.noise_bps)
+            .max()
+            .unwrap_or_default(),
+        routes: simulations,
+    })
 }
@@ -11,6 +11,9 @@ pub struct InitMsg {
     pub register_tokens: Option<Vec<Snip20Data>>,
     pub cashback: Option<SecretContract>,
     pub owner: Option<HumanAddr>,
+    /// Wraps native uscrt sent for routes starting in sSCRT, and unwraps the
+    /// output of routes with `unwrap_output`
+    pub sscrt: Option<SecretContract>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -25,6 +28,31 @@ pub struct Route {
     pub hops: VecDeque<Hop>,
     pub expected_return: Option<Uint128>,
     pub to: HumanAddr,
+    /// Redeems the sSCRT the route ends in and pays `to` native uscrt instead
+    #[serde(default)]
+    pub unwrap_output: bool,
+}
+
+/// Share of a split swap's offer that goes through `operations`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SplitRoute {
+    /// Out of 10,000 across all the routes of the split
+    pub weight_bps: u16,
+    pub operations: Vec<Hop>,
+}
+
+/// Hook of the token sent to the router, when it isn't a `Route`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiveMsg {
+    /// Divides the amount received over `routes` by weight, the remainder going to
+    /// the first route, and pays out the sum of what they return once it reaches
+    /// `minimum_receive_total`
+    ExecuteSplitSwap {
+        routes: Vec<SplitRoute>,
+        minimum_receive_total: Uint128,
+        to: HumanAddr,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -49,6 +77,11 @@ pub enum HandleMsg {
         amount: Uint128,
     },
     FinalizeRoute {},
+    /// Runs the next route of the split swap in progress, only the router calls it
+    ExecuteSplitRoute {},
+    /// Checks and pays out what the routes of a split swap returned, only the
+    /// router calls it
+    FinalizeSplitSwap {},
     RegisterTokens {
         tokens: Vec<Snip20Data>,
     },
@@ -61,6 +94,7 @@ pub enum HandleMsg {
     UpdateSettings {
         new_owner: Option<HumanAddr>,
         new_cashback: Option<SecretContract>,
+        new_sscrt: Option<SecretContract>,
     },
 }
 
@@ -68,6 +102,59 @@ pub enum HandleMsg {
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
     SupportedTokens {},
+    /// Quotes `offer_amount` of the first hop's token through every hop in turn,
+    /// each pair's return being the next pair's offer
+    SimulateSwapOperations {
+        offer_amount: Uint128,
+        operations: Vec<Hop>,
+    },
+    /// Quotes the offer of the first hop's token needed for the last hop to
+    /// return `ask_amount`
+    SimulateReverseSwapOperations {
+        ask_amount: Uint128,
+        operations: Vec<Hop>,
+    },
+    /// Quotes an ExecuteSplitSwap of `offer_amount`
+    SimulateSplitSwap {
+        offer_amount: Uint128,
+        routes: Vec<SplitRoute>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct HopSimulation {
+    pub pair_address: HumanAddr,
+    pub offer_amount: Uint128,
+    pub return_amount: Uint128,
+    pub spread_amount: Uint128,
+    pub commission_amount: Uint128,
+    /// Widest noise, in basis points, the pair applies to its reserves
+    pub noise_bps: u16,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SimulateSwapOperationsResponse {
+    /// Amount of the first hop's token going in
+    pub offer_amount: Uint128,
+    /// Amount of the last hop's ask token coming out
+    pub return_amount: Uint128,
+    /// One entry per hop, in route order
+    pub hops: Vec<HopSimulation>,
+    /// Sum of the hops' `noise_bps`, pair simulations run on noised reserves so
+    /// the quote can be off by about this much
+    pub noise_bps: u16,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SimulateSplitSwapResponse {
+    pub offer_amount: Uint128,
+    /// Sum of what the routes return
+    pub return_amount: Uint128,
+    /// One entry per route, in the order given
+    pub routes: Vec<SimulateSwapOperationsResponse>,
+    /// Largest `noise_bps` of the routes, the blended quote is off by at most
+    /// about this much
+    pub noise_bps: u16,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -0,0 +1,46 @@
+use cosmwasm_std::{
+    to_binary, Api, Extern, HumanAddr, Querier, QueryRequest, StdResult, Storage, WasmQuery,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use secretswap::{Factory, PairInfo, PairSettings};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryPair {
+    Pair {},
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryFactory {
+    /// The factory answers with the settings in effect for the pair
+    PairSettings { pair: Option<HumanAddr> },
+}
+
+pub fn query_pair<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    pair_address: &HumanAddr,
+    pair_code_hash: &String,
+) -> StdResult<PairInfo> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: pair_address.clone(),
+        callback_code_hash: pair_code_hash.clone(),
+        msg: to_binary(&QueryPair::Pair {})?,
+    }))
+}
+
+pub fn query_pair_settings<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    factory: &Factory,
+    pair_address: &HumanAddr,
+) -> StdResult<PairSettings> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: factory.address.clone(),
+        callback_code_hash: factory.code_hash.clone(),
+        msg: to_binary(&QueryFactory::PairSettings {
+            pair: Some(pair_address.clone()),
+        })?,
+    }))
+}
@@ -1,10 +1,12 @@
-use cosmwasm_std::{HumanAddr, StdResult, Storage};
+use std::collections::VecDeque;
+
+use cosmwasm_std::{HumanAddr, StdResult, Storage, Uint128};
 use cosmwasm_storage::{ReadonlySingleton, Singleton};
 
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::msg::{Hop, Route};
+use crate::msg::{Hop, Route, Token};
 
 static KEY_OWNER: &[u8] = b"owner";
 
@@ -32,6 +34,16 @@ pub fn read_cashback<S: Storage>(storage: &S) -> StdResult<Option<SecretContract
     ReadonlySingleton::new(storage, KEY_CASHBACK).may_load()
 }
 
+static KEY_SSCRT: &[u8] = b"sscrt";
+
+pub fn store_sscrt<S: Storage>(storage: &mut S, data: &SecretContract) -> StdResult<()> {
+    Singleton::new(storage, KEY_SSCRT).save(data)
+}
+
+pub fn read_sscrt<S: Storage>(storage: &S) -> StdResult<Option<SecretContract>> {
+    ReadonlySingleton::new(storage, KEY_SSCRT).may_load()
+}
+
 static KEY_ROUTE_STATE: &[u8] = b"route_state";
 
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
@@ -50,7 +62,34 @@ pub fn read_route_state<S: Storage>(storage: &S) -> StdResult<Option<RouteState>
 }
 
 pub fn delete_route_state<S: Storage>(storage: &mut S) {
-    storage.remove(KEY_ROUTE_STATE);
+    Singleton::<S, RouteState>::new(storage, KEY_ROUTE_STATE).remove();
+}
+
+static KEY_SPLIT_STATE: &[u8] = b"split_state";
+
+/// A split swap whose routes run one after the other, each paying the router
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct SplitState {
+    /// Offer amount and hops of the routes that haven't run yet
+    pub remaining_routes: VecDeque<(Uint128, VecDeque<Hop>)>,
+    /// Token every route returns
+    pub ask_token: Token,
+    /// The router's balance of `ask_token` before the first route ran
+    pub balance_before: Uint128,
+    pub minimum_receive_total: Uint128,
+    pub to: HumanAddr,
+}
+
+pub fn store_split_state<S: Storage>(storage: &mut S, data: &SplitState) -> StdResult<()> {
+    Singleton::new(storage, KEY_SPLIT_STATE).save(data)
+}
+
+pub fn read_split_state<S: Storage>(storage: &S) -> StdResult<Option<SplitState>> {
+    ReadonlySingleton::new(storage, KEY_SPLIT_STATE).may_load()
+}
+
+pub fn delete_split_state<S: Storage>(storage: &mut S) {
+    Singleton::<S, SplitState>::new(storage, KEY_SPLIT_STATE).remove();
 }
 
 static KEY_TOKENS: &[u8] = b"tokens";
@@ -0,0 +1,1047 @@
+use std::collections::{HashMap, VecDeque};
+
+use cosmwasm_std::testing::{mock_env, MockApi, MockQuerier, MockStorage, MOCK_CONTRACT_ADDR};
+use cosmwasm_std::{
+    from_binary, from_slice, to_binary, BalanceResponse, BankMsg, BankQuery, Binary, Coin,
+    CosmosMsg, Empty, Extern, HumanAddr, Querier, QuerierResult, QueryRequest, StdError, StdResult,
+    Uint128, WasmMsg, WasmQuery,
+};
+use secret_toolkit::snip20;
+use secretswap::{
+    Asset, AssetInfo, CurveType, Factory, Fee, PairInfo, PairSettings, ReverseSimulationResponse,
+    SimulationResponse,
+};
+use serde::Deserialize;
+
+use crate::contract::{handle, init, query};
+use crate::msg::{
+    HandleMsg, Hop, HopSimulation, InitMsg, NativeSwap, QueryMsg, ReceiveMsg, Route,
+    SimulateSplitSwapResponse, SimulateSwapOperationsResponse, Snip20Data, Snip20Swap, SplitRoute,
+    Token,
+};
+use crate::state::{read_route_state, read_split_state, SecretContract};
+
+/// The snip20 handle the router and the pairs pay out with
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Snip20Msg {
+    Send {
+        recipient: HumanAddr,
+        amount: Uint128,
+        msg: Option<Binary>,
+    },
+    Deposit {},
+    Redeem {
+        amount: Uint128,
+        denom: Option<String>,
+    },
+}
+
+/// The pair and factory queries the router simulates routes with
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum MockQueryMsg {
+    Pair {},
+    Simulation { offer_asset: Asset },
+    ReverseSimulation { ask_asset: Asset },
+    PairSettings { pair: Option<HumanAddr> },
+    Balance { address: HumanAddr, key: String },
+}
+
+/// Answers pair queries from the same one way, fixed rate pairs the chain
+/// swaps with, all of them registered with "factory0000", and balance queries
+/// from the chain's balances
+struct PairQuerier {
+    base: MockQuerier,
+    /// offer token, ask token and rate of each pair
+    pairs: HashMap<HumanAddr, (String, String, u128)>,
+    /// noise_bps_max of each pair, unset when missing
+    noise: HashMap<HumanAddr, u16>,
+    balances: HashMap<(String, HumanAddr), u128>,
+}
+
+impl PairQuerier {
+    fn balance(&self, token: &str, holder: &HumanAddr) -> u128 {
+        self.balances
+            .get(&(token.to_string(), holder.clone()))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+impl Querier for PairQuerier {
+    fn raw_query(&self, bin_request: &[u8]) -> QuerierResult {
+        let request: QueryRequest<Empty> = from_slice(bin_request).unwrap();
+        let (contract_addr, msg) = match &request {
+            QueryRequest::Wasm(WasmQuery::Smart {
+                contract_addr, msg, ..
+            }) => (contract_addr, msg),
+            QueryRequest::Bank(BankQuery::Balance { address, denom }) => {
+                return Ok(to_binary(&BalanceResponse {
+                    amount: Coin::new(self.balance(denom, address), denom),
+                }));
+            }
+            _ => return self.base.handle_query(&request),
+        };
+
+        let res = match from_slice(msg.as_slice()).unwrap() {
+            MockQueryMsg::Balance { address, .. } => to_binary(&snip20::BalanceResponse {
+                balance: snip20::Balance {
+                    amount: Uint128(self.balance(contract_addr.as_str(), &address)),
+                },
+            }),
+            MockQueryMsg::PairSettings { pair } => {
+                assert_eq!(contract_addr, &HumanAddr::from("factory0000"));
+                let pair = pair.unwrap();
+                to_binary(&PairSettings {
+                    swap_fee: Fee {
+                        commission_rate_nom: Uint128(3),
+                        commission_rate_denom: Uint128(1000),
+                        min_commission: Uint128::zero(),
+                    },
+                    swap_data_endpoint: None,
+                    curve: CurveType::ConstantProduct,
+                    initial_share_multiplier: None,
+                    paused: false,
+                    min_reserve: None,
+                    noise_bps_max: self.noise.get(&pair).cloned(),
+                    noise_per_block: false,
+                    private_logs: false,
+                    max_block_volume_bps: None,
+                    reserve_floor_bps: 0,
+                    price_oracle: None,
+                    fee_on_input: false,
+                })
+            }
+            msg => {
+                let (offer, ask, rate) = self.pairs[contract_addr].clone();
+                match msg {
+                    MockQueryMsg::Pair {} => to_binary(&PairInfo {
+                        asset_infos: [asset_info(&offer), asset_info(&ask)],
+                        contract_addr: contract_addr.clone(),
+                        liquidity_token: HumanAddr::from("liquidity0000"),
+                        token_code_hash: "tokenhash".to_string(),
+                        asset0_volume: Uint128::zero(),
+                        asset1_volume: Uint128::zero(),
+                        factory: Factory {
+                            address: HumanAddr::from("factory0000"),
+                            code_hash: "factoryhash".to_string(),
+                        },
+                        asset_decimals: None,
+                        asset_symbols: None,
+                        created_at_height: None,
+                        created_at_time: None,
+                    }),
+                    MockQueryMsg::Simulation { offer_asset } => {
+                        assert_eq!(offer_asset.info, asset_info(&offer));
+                        to_binary(&SimulationResponse {
+                            return_amount: Uint128(offer_asset.amount.u128() * rate),
+                            spread_amount: Uint128::zero(),
+                            commission_amount: Uint128::zero(),
+                            lp_commission_amount: Uint128::zero(),
+                            protocol_commission_amount: Uint128::zero(),
+                        })
+                    }
+                    MockQueryMsg::ReverseSimulation { ask_asset } => {
+                        assert_eq!(ask_asset.info, asset_info(&ask));
+                        to_binary(&ReverseSimulationResponse {
+                            offer_amount: Uint128((ask_asset.amount.u128() + rate - 1) / rate),
+                            spread_amount: Uint128::zero(),
+                            commission_amount: Uint128::zero(),
+                        })
+                    }
+                    _ => unreachable!(),
+                }
+            }
+        };
+        Ok(res)
+    }
+}
+
+/// Runs the messages of a route the way the chain would, depth first, against
+/// pairs that swap one way at a fixed rate. Balances are keyed by token
+/// address, or by "uscrt" for native coins.
+struct Chain {
+    deps: Extern<MockStorage, MockApi, PairQuerier>,
+}
+
+impl Chain {
+    fn new(tokens: &[&str], pairs: &[(&str, &str, &str, u128)]) -> Self {
+        let mut deps = Extern {
+            storage: MockStorage::default(),
+            api: MockApi::new(20),
+            querier: PairQuerier {
+                base: MockQuerier::new(&[]),
+                pairs: pairs
+                    .iter()
+                    .map(|(pair, offer, ask, rate)| {
+                        (
+                            HumanAddr::from(*pair),
+                            (offer.to_string(), ask.to_string(), *rate),
+                        )
+                    })
+                    .collect(),
+                noise: HashMap::new(),
+                balances: HashMap::new(),
+            },
+        };
+        let msg = InitMsg {
+            register_tokens: Some(tokens.iter().map(|token| snip20_data(token)).collect()),
+            cashback: None,
+            owner: None,
+            sscrt: None,
+        };
+        let _res = init(&mut deps, mock_env("owner0000", &[]), msg).unwrap();
+
+        let mut chain = Chain { deps };
+        for (pair, _, ask, _) in pairs.iter() {
+            chain.mint(ask, pair, 1_000_000_000);
+        }
+        chain
+    }
+
+    fn mint(&mut self, token: &str, holder: &str, amount: u128) {
+        *self
+            .deps
+            .querier
+            .balances
+            .entry((token.to_string(), HumanAddr::from(holder)))
+            .or_default() += amount;
+    }
+
+    fn balance(&self, token: &str, holder: &str) -> u128 {
+        self.deps.querier.balance(token, &HumanAddr::from(holder))
+    }
+
+    fn transfer(
+        &mut self,
+        token: &str,
+        from: &HumanAddr,
+        to: &HumanAddr,
+        amount: u128,
+    ) -> StdResult<()> {
+        let from_balance = self
+            .deps
+            .querier
+            .balances
+            .entry((token.to_string(), from.clone()))
+            .or_default();
+        if *from_balance < amount {
+            return Err(StdError::generic_err(format!(
+                "{} has insufficient {}",
+                from, token
+            )));
+        }
+        *from_balance -= amount;
+        *self
+            .deps
+            .querier
+            .balances
+            .entry((token.to_string(), to.clone()))
+            .or_default() += amount;
+        Ok(())
+    }
+
+    /// A snip20 Send of `token` from `from` to the router
+    fn send_to_router(
+        &mut self,
+        token: &str,
+        from: &str,
+        amount: u128,
+        msg: Option<Binary>,
+    ) -> StdResult<()> {
+        let send = snip20::send_msg(
+            HumanAddr::from(MOCK_CONTRACT_ADDR),
+            Uint128(amount),
+            msg,
+            None,
+            256,
+            "tokenhash".to_string(),
+            HumanAddr::from(token),
+        )?;
+        self.dispatch(&HumanAddr::from(from), send)
+    }
+
+    fn execute_router(
+        &mut self,
+        sender: &HumanAddr,
+        funds: &[Coin],
+        msg: HandleMsg,
+    ) -> StdResult<()> {
+        let router = HumanAddr::from(MOCK_CONTRACT_ADDR);
+        for coin in funds.iter() {
+            self.transfer(&coin.denom, sender, &router, coin.amount.u128())?;
+        }
+        let res = handle(&mut self.deps, mock_env(sender.as_str(), funds), msg)?;
+        for msg in res.messages {
+            self.dispatch(&router, msg)?;
+        }
+        Ok(())
+    }
+
+    fn dispatch(&mut self, sender: &HumanAddr, msg: CosmosMsg) -> StdResult<()> {
+        match msg {
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr,
+                msg,
+                send,
+                ..
+            }) => {
+                if contract_addr == HumanAddr::from(MOCK_CONTRACT_ADDR) {
+                    return self.execute_router(sender, &send, from_binary(&msg)?);
+                }
+                if self.deps.querier.pairs.contains_key(&contract_addr) {
+                    let NativeSwap::Swap {
+                        offer_asset,
+                        expected_return,
+                        to,
+                    } = from_binary(&msg)?;
+                    let amount = offer_asset.amount.u128();
+                    self.transfer("uscrt", sender, &contract_addr, amount)?;
+                    let to = to.unwrap_or_else(|| sender.clone());
+                    return self.swap(&contract_addr, "uscrt", amount, expected_return, to);
+                }
+
+                let token = contract_addr.to_string();
+                let (recipient, amount, msg) = match from_binary(&msg)? {
+                    Snip20Msg::Send {
+                        recipient,
+                        amount,
+                        msg,
+                    } => (recipient, amount, msg),
+                    // sSCRT, backed one to one by the uscrt it holds
+                    Snip20Msg::Deposit {} => {
+                        let amount = send[0].amount.u128();
+                        self.transfer("uscrt", sender, &contract_addr, amount)?;
+                        self.mint(&token, sender.as_str(), amount);
+                        return Ok(());
+                    }
+                    Snip20Msg::Redeem { amount, denom } => {
+                        assert_eq!(denom, Some("uscrt".to_string()));
+                        self.transfer(&token, sender, &contract_addr, amount.u128())?;
+                        self.transfer("uscrt", &contract_addr, sender, amount.u128())?;
+                        return Ok(());
+                    }
+                };
+                self.transfer(&token, sender, &recipient, amount.u128())?;
+                if self.deps.querier.pairs.contains_key(&recipient) {
+                    let Snip20Swap::Swap {
+                        expected_return,
+                        to,
+                    } = from_binary(&msg.expect("a swap needs a hook"))?;
+                    let to = to.unwrap_or_else(|| sender.clone());
+                    self.swap(&recipient, &token, amount.u128(), expected_return, to)
+                } else if recipient == HumanAddr::from(MOCK_CONTRACT_ADDR) {
+                    let msg = HandleMsg::Receive {
+                        from: sender.clone(),
+                        msg,
+                        amount,
+                    };
+                    self.execute_router(&contract_addr, &[], msg)
+                } else {
+                    Ok(())
+                }
+            }
+            CosmosMsg::Bank(BankMsg::Send {
+                from_address,
+                to_address,
+                amount,
+            }) => {
+                for coin in amount.iter() {
+                    self.transfer(&coin.denom, &from_address, &to_address, coin.amount.u128())?;
+                }
+                Ok(())
+            }
+            _ => panic!("DO NOT ENTER HERE"),
+        }
+    }
+
+    /// Same slippage check as the pair, then pays the return out to `to`
+    fn swap(
+        &mut self,
+        pair: &HumanAddr,
+        offer_token: &str,
+        amount: u128,
+        expected_return: Option<Uint128>,
+        to: HumanAddr,
+    ) -> StdResult<()> {
+        let (offer, ask, rate) = self.deps.querier.pairs[pair].clone();
+        assert_eq!(offer, offer_token);
+
+        let return_amount = amount * rate;
+        if let Some(expected_return) = expected_return {
+            if return_amount < expected_return.u128() {
+                return Err(StdError::generic_err(
+                    "Operation fell short of expected_return",
+                ));
+            }
+        }
+
+        if ask == "uscrt" {
+            self.dispatch(
+                pair,
+                CosmosMsg::Bank(BankMsg::Send {
+                    from_address: pair.clone(),
+                    to_address: to,
+                    amount: vec![Coin::new(return_amount, "uscrt")],
+                }),
+            )
+        } else {
+            let send = snip20::send_msg(
+                to,
+                Uint128(return_amount),
+                None,
+                None,
+                256,
+                "tokenhash".to_string(),
+                HumanAddr::from(ask),
+            )?;
+            self.dispatch(pair, send)
+        }
+    }
+}
+
+fn snip20_data(token: &str) -> Snip20Data {
+    Snip20Data {
+        address: HumanAddr::from(token),
+        code_hash: "tokenhash".to_string(),
+    }
+}
+
+fn asset_info(token: &str) -> AssetInfo {
+    if token == "uscrt" {
+        AssetInfo::NativeToken {
+            denom: token.to_string(),
+        }
+    } else {
+        AssetInfo::Token {
+            contract_addr: HumanAddr::from(token),
+            token_code_hash: "tokenhash".to_string(),
+            viewing_key: "".to_string(),
+        }
+    }
+}
+
+fn hop(from_token: Token, pair: &str) -> Hop {
+    Hop {
+        from_token,
+        pair_address: HumanAddr::from(pair),
+        pair_code_hash: "pairhash".to_string(),
+    }
+}
+
+fn route(hops: Vec<Hop>, expected_return: Option<u128>) -> Binary {
+    to_binary(&Route {
+        hops: VecDeque::from(hops),
+        expected_return: expected_return.map(Uint128),
+        to: HumanAddr::from("user0000"),
+        unwrap_output: false,
+    })
+    .unwrap()
+}
+
+fn unwrapped_route(hops: Vec<Hop>, expected_return: Option<u128>) -> Binary {
+    to_binary(&Route {
+        hops: VecDeque::from(hops),
+        expected_return: expected_return.map(Uint128),
+        to: HumanAddr::from("user0000"),
+        unwrap_output: true,
+    })
+    .unwrap()
+}
+
+/// A chain whose router has sscrt0000 set as its sSCRT
+fn sscrt_chain(pairs: &[(&str, &str, &str, u128)]) -> Chain {
+    let mut chain = Chain::new(&["token0000", "token0001"], pairs);
+    let res = handle(
+        &mut chain.deps,
+        mock_env("owner0000", &[]),
+        HandleMsg::UpdateSettings {
+            new_owner: None,
+            new_cashback: None,
+            new_sscrt: Some(SecretContract {
+                address: HumanAddr::from("sscrt0000"),
+                code_hash: "tokenhash".to_string(),
+            }),
+        },
+    )
+    .unwrap();
+    // registered to receive it
+    assert_eq!(res.messages.len(), 2);
+    chain.mint("uscrt", "sscrt0000", 1_000_000_000);
+    chain
+}
+
+fn split_routes(routes: Vec<(u16, Vec<Hop>)>) -> Vec<SplitRoute> {
+    routes
+        .into_iter()
+        .map(|(weight_bps, operations)| SplitRoute {
+            weight_bps,
+            operations,
+        })
+        .collect()
+}
+
+fn split_swap(routes: Vec<(u16, Vec<Hop>)>, minimum_receive_total: u128) -> Binary {
+    to_binary(&ReceiveMsg::ExecuteSplitSwap {
+        routes: split_routes(routes),
+        minimum_receive_total: Uint128(minimum_receive_total),
+        to: HumanAddr::from("user0000"),
+    })
+    .unwrap()
+}
+
+#[test]
+fn route_through_two_pairs() {
+    let pairs = [
+        ("pair0000", "token0000", "token0001", 2),
+        ("pair0001", "token0001", "token0002", 3),
+    ];
+    let hops = || {
+        vec![
+            hop(Token::Snip20(snip20_data("token0000")), "pair0000"),
+            hop(Token::Snip20(snip20_data("token0001")), "pair0001"),
+        ]
+    };
+
+    let mut chain = Chain::new(&["token0000", "token0001", "token0002"], &pairs);
+    chain.mint("token0000", "user0000", 1_000);
+    chain
+        .send_to_router(
+            "token0000",
+            "user0000",
+            1_000,
+            Some(route(hops(), Some(6_000))),
+        )
+        .unwrap();
+    assert_eq!(chain.balance("token0000", "user0000"), 0);
+    assert_eq!(chain.balance("token0002", "user0000"), 6_000);
+    for token in ["token0000", "token0001", "token0002"].iter() {
+        assert_eq!(chain.balance(token, MOCK_CONTRACT_ADDR), 0);
+    }
+    assert!(read_route_state(&chain.deps.storage).unwrap().is_none());
+
+    // the last pair enforces the minimum for the whole route
+    let mut chain = Chain::new(&["token0000", "token0001", "token0002"], &pairs);
+    chain.mint("token0000", "user0000", 1_000);
+    let res = chain.send_to_router(
+        "token0000",
+        "user0000",
+        1_000,
+        Some(route(hops(), Some(6_001))),
+    );
+    match res {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "Operation fell short of expected_return")
+        }
+        _ => panic!("Must return generic error"),
+    }
+}
+
+#[test]
+fn route_native_legs() {
+    // uscrt in
+    let mut chain = Chain::new(
+        &["token0000", "token0001"],
+        &[
+            ("pair0000", "uscrt", "token0000", 2),
+            ("pair0001", "token0000", "token0001", 3),
+        ],
+    );
+    chain.mint("uscrt", "user0000", 1_000);
+    let msg = HandleMsg::Receive {
+        from: HumanAddr::from("user0000"),
+        msg: Some(route(
+            vec![
+                hop(Token::Scrt, "pair0000"),
+                hop(Token::Snip20(snip20_data("token0000")), "pair0001"),
+            ],
+            Some(6_000),
+        )),
+        amount: Uint128(1_000),
+    };
+    chain
+        .execute_router(
+            &HumanAddr::from("user0000"),
+            &[Coin::new(1_000, "uscrt")],
+            msg,
+        )
+        .unwrap();
+    assert_eq!(chain.balance("uscrt", "user0000"), 0);
+    assert_eq!(chain.balance("token0001", "user0000"), 6_000);
+
+    // uscrt out
+    let mut chain = Chain::new(
+        &["token0000", "token0001"],
+        &[
+            ("pair0000", "token0000", "token0001", 2),
+            ("pair0001", "token0001", "uscrt", 3),
+        ],
+    );
+    chain.mint("token0000", "user0000", 1_000);
+    let msg = route(
+        vec![
+            hop(Token::Snip20(snip20_data("token0000")), "pair0000"),
+            hop(Token::Snip20(snip20_data("token0001")), "pair0001"),
+        ],
+        Some(6_000),
+    );
+    chain
+        .send_to_router("token0000", "user0000", 1_000, Some(msg))
+        .unwrap();
+    assert_eq!(chain.balance("uscrt", "user0000"), 6_000);
+    assert_eq!(chain.balance("uscrt", MOCK_CONTRACT_ADDR), 0);
+
+    // uscrt can't be the input of a later hop, not even the last one
+    let mut chain = Chain::new(
+        &["token0000", "token0001"],
+        &[
+            ("pair0000", "token0000", "uscrt", 2),
+            ("pair0001", "uscrt", "token0001", 3),
+        ],
+    );
+    chain.mint("token0000", "user0000", 1_000);
+    let msg = route(
+        vec![
+            hop(Token::Snip20(snip20_data("token0000")), "pair0000"),
+            hop(Token::Scrt, "pair0001"),
+        ],
+        None,
+    );
+    match chain.send_to_router("token0000", "user0000", 1_000, Some(msg)) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            "cannot route via uscrt. uscrt can only be route input token or output token."
+        ),
+        _ => panic!("Must return generic error"),
+    }
+}
+
+#[test]
+fn route_requires_registered_tokens() {
+    let mut chain = Chain::new(
+        &["token0000", "token0002"],
+        &[
+            ("pair0000", "token0000", "token0001", 2),
+            ("pair0001", "token0001", "token0002", 3),
+        ],
+    );
+    chain.mint("token0000", "user0000", 1_000);
+    let msg = route(
+        vec![
+            hop(Token::Snip20(snip20_data("token0000")), "pair0000"),
+            hop(Token::Snip20(snip20_data("token0001")), "pair0001"),
+        ],
+        None,
+    );
+    match chain.send_to_router("token0000", "user0000", 1_000, Some(msg.clone())) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "token token0001 is not registered with the router")
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    // RegisterTokens sets the router up to receive it
+    let res = handle(
+        &mut chain.deps,
+        mock_env("owner0000", &[]),
+        HandleMsg::RegisterTokens {
+            tokens: vec![snip20_data("token0001")],
+        },
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 2);
+    chain.mint("token0000", "user0000", 1_000);
+    chain
+        .send_to_router("token0000", "user0000", 1_000, Some(msg))
+        .unwrap();
+    assert_eq!(chain.balance("token0002", "user0000"), 6_000);
+}
+
+#[test]
+fn simulate_swap_operations() {
+    let mut chain = Chain::new(
+        &["token0000", "token0001", "token0002"],
+        &[
+            ("pair0000", "token0000", "token0001", 2),
+            ("pair0001", "token0001", "token0002", 3),
+            ("pair0002", "token0002", "uscrt", 5),
+        ],
+    );
+    chain
+        .deps
+        .querier
+        .noise
+        .insert(HumanAddr::from("pair0001"), 250);
+    let operations = vec![
+        hop(Token::Snip20(snip20_data("token0000")), "pair0000"),
+        hop(Token::Snip20(snip20_data("token0001")), "pair0001"),
+        hop(Token::Snip20(snip20_data("token0002")), "pair0002"),
+    ];
+    let hop_simulation =
+        |pair: &str, offer_amount: u128, return_amount: u128, noise_bps| HopSimulation {
+            pair_address: HumanAddr::from(pair),
+            offer_amount: Uint128(offer_amount),
+            return_amount: Uint128(return_amount),
+            spread_amount: Uint128::zero(),
+            commission_amount: Uint128::zero(),
+            noise_bps,
+        };
+    let expected = SimulateSwapOperationsResponse {
+        offer_amount: Uint128(1_000),
+        return_amount: Uint128(30_000),
+        hops: vec![
+            hop_simulation("pair0000", 1_000, 2_000, 99),
+            hop_simulation("pair0001", 2_000, 6_000, 250),
+            hop_simulation("pair0002", 6_000, 30_000, 99),
+        ],
+        noise_bps: 448,
+    };
+
+    let res = query(
+        &chain.deps,
+        QueryMsg::SimulateSwapOperations {
+            offer_amount: Uint128(1_000),
+            operations: operations.clone(),
+        },
+    )
+    .unwrap();
+    let res: SimulateSwapOperationsResponse = from_binary(&res).unwrap();
+    assert_eq!(res, expected);
+
+    // the reverse walks the same route back from what comes out of it
+    let res = query(
+        &chain.deps,
+        QueryMsg::SimulateReverseSwapOperations {
+            ask_amount: Uint128(30_000),
+            operations,
+        },
+    )
+    .unwrap();
+    let res: SimulateSwapOperationsResponse = from_binary(&res).unwrap();
+    assert_eq!(res, expected);
+}
+
+#[test]
+fn simulate_swap_operations_checks_route() {
+    let chain = Chain::new(
+        &["token0000", "token0001", "token0002"],
+        &[
+            ("pair0000", "token0000", "token0001", 2),
+            ("pair0001", "token0001", "token0002", 3),
+        ],
+    );
+    let simulate = |operations: Vec<Hop>| {
+        query(
+            &chain.deps,
+            QueryMsg::SimulateSwapOperations {
+                offer_amount: Uint128(1_000),
+                operations,
+            },
+        )
+    };
+
+    match simulate(vec![]) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "route must be at least 1 hop"),
+        _ => panic!("Must return generic error"),
+    }
+
+    let too_long = vec![hop(Token::Snip20(snip20_data("token0000")), "pair0000"); 5];
+    match simulate(too_long) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "route must be at most 4 hops"),
+        _ => panic!("Must return generic error"),
+    }
+
+    match simulate(vec![hop(
+        Token::Snip20(snip20_data("token0002")),
+        "pair0000",
+    )]) {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "pair pair0000 does not trade the token of its hop")
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    // the second hop offers token0002, but the first one returns token0001
+    let res = simulate(vec![
+        hop(Token::Snip20(snip20_data("token0000")), "pair0000"),
+        hop(Token::Snip20(snip20_data("token0002")), "pair0001"),
+    ]);
+    match res {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+            msg,
+            "pair pair0001 does not offer what the previous hop returns"
+        ),
+        _ => panic!("Must return generic error"),
+    }
+}
+
+#[test]
+fn route_is_capped() {
+    let mut chain = Chain::new(
+        &["token0000", "token0001"],
+        &[
+            ("pair0000", "token0000", "token0001", 1),
+            ("pair0001", "token0001", "token0000", 1),
+        ],
+    );
+    chain.mint("token0000", "user0000", 1_000);
+    let msg = route(
+        vec![
+            hop(Token::Snip20(snip20_data("token0000")), "pair0000"),
+            hop(Token::Snip20(snip20_data("token0001")), "pair0001"),
+            hop(Token::Snip20(snip20_data("token0000")), "pair0000"),
+            hop(Token::Snip20(snip20_data("token0001")), "pair0001"),
+            hop(Token::Snip20(snip20_data("token0000")), "pair0000"),
+        ],
+        None,
+    );
+    match chain.send_to_router("token0000", "user0000", 1_000, Some(msg)) {
+        Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "route must be at most 4 hops"),
+        _ => panic!("Must return generic error"),
+    }
+}
+
+#[test]
+fn split_swap_through_parallel_routes() {
+    let tokens = ["token0000", "token0001", "token0002"];
+    // 60% straight to token0002, 40% via token0001
+    let routes = || {
+        vec![
+            (
+                6_000,
+                vec![hop(Token::Snip20(snip20_data("token0000")), "pair0000")],
+            ),
+            (
+                4_000,
+                vec![
+                    hop(Token::Snip20(snip20_data("token0000")), "pair0001"),
+                    hop(Token::Snip20(snip20_data("token0001")), "pair0002"),
+                ],
+            ),
+        ]
+    };
+    let pairs = |ask: &'static str| {
+        [
+            ("pair0000", "token0000", ask, 2),
+            ("pair0001", "token0000", "token0001", 3),
+            ("pair0002", "token0001", ask, 1),
+        ]
+    };
+
+    // the remainder of dividing 1,001 goes to the first route, 601 * 2 + 400 * 3
+    let mut chain = Chain::new(&tokens, &pairs("token0002"));
+    let res = query(
+        &chain.deps,
+        QueryMsg::SimulateSplitSwap {
+            offer_amount: Uint128(1_001),
+            routes: split_routes(routes()),
+        },
+    )
+    .unwrap();
+    let res: SimulateSplitSwapResponse = from_binary(&res).unwrap();
+    assert_eq!(res.offer_amount, Uint128(1_001));
+    assert_eq!(res.return_amount, Uint128(2_402));
+    assert_eq!(res.routes[0].offer_amount, Uint128(601));
+    assert_eq!(res.routes[0].return_amount, Uint128(1_202));
+    assert_eq!(res.routes[1].offer_amount, Uint128(400));
+    assert_eq!(res.routes[1].return_amount, Uint128(1_200));
+    assert_eq!(res.noise_bps, 198);
+
+    chain.mint("token0000", "user0000", 1_001);
+    chain
+        .send_to_router(
+            "token0000",
+            "user0000",
+            1_001,
+            Some(split_swap(routes(), 2_402)),
+        )
+        .unwrap();
+    assert_eq!(chain.balance("token0000", "user0000"), 0);
+    assert_eq!(chain.balance("token0002", "user0000"), 2_402);
+    for token in tokens.iter() {
+        assert_eq!(chain.balance(token, MOCK_CONTRACT_ADDR), 0);
+    }
+    assert!(read_route_state(&chain.deps.storage).unwrap().is_none());
+    assert!(read_split_state(&chain.deps.storage).unwrap().is_none());
+
+    // only the total has a minimum
+    let mut chain = Chain::new(&tokens, &pairs("token0002"));
+    chain.mint("token0000", "user0000", 1_001);
+    let res = chain.send_to_router(
+        "token0000",
+        "user0000",
+        1_001,
+        Some(split_swap(routes(), 2_403)),
+    );
+    match res {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "Operation fell short of minimum_receive_total")
+        }
+        _ => panic!("Must return generic error"),
+    }
+
+    // uscrt out
+    let mut chain = Chain::new(&tokens, &pairs("uscrt"));
+    chain.mint("token0000", "user0000", 1_001);
+    chain
+        .send_to_router(
+            "token0000",
+            "user0000",
+            1_001,
+            Some(split_swap(routes(), 2_402)),
+        )
+        .unwrap();
+    assert_eq!(chain.balance("uscrt", "user0000"), 2_402);
+    assert_eq!(chain.balance("uscrt", MOCK_CONTRACT_ADDR), 0);
+}
+
+#[test]
+fn split_swap_checks_routes() {
+    let mut chain = Chain::new(
+        &["token0000", "token0001", "token0002"],
+        &[
+            ("pair0000", "token0000", "token0001", 2),
+            ("pair0001", "token0000", "token0002", 3),
+        ],
+    );
+    let direct = |pair: &str| vec![hop(Token::Snip20(snip20_data("token0000")), pair)];
+    let mut expect_error = |routes: Vec<(u16, Vec<Hop>)>, error: &str| {
+        chain.mint("token0000", "user0000", 1_000);
+        match chain.send_to_router("token0000", "user0000", 1_000, Some(split_swap(routes, 0))) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, error),
+            _ => panic!("Must return generic error"),
+        }
+    };
+
+    expect_error(
+        vec![(6_000, direct("pair0000")), (3_000, direct("pair0000"))],
+        "route weights must sum to 10000",
+    );
+    expect_error(
+        vec![(2_000, direct("pair0000")); 5],
+        "a split swap has at most 4 routes",
+    );
+    expect_error(
+        vec![(6_000, direct("pair0000")), (4_000, direct("pair0001"))],
+        "every route of a split swap must offer and return the same tokens",
+    );
+
+    // the routes are only run by the router itself
+    for msg in vec![
+        HandleMsg::ExecuteSplitRoute {},
+        HandleMsg::FinalizeSplitSwap {},
+    ] {
+        match handle(&mut chain.deps, mock_env("user0000", &[]), msg) {
+            Err(StdError::Unauthorized { .. }) => {}
+            _ => panic!("Must return unauthorized error"),
+        }
+    }
+}
+
+#[test]
+fn route_wraps_native_input() {
+    let mut chain = sscrt_chain(&[
+        ("pair0000", "sscrt0000", "token0000", 2),
+        ("pair0001", "token0000", "token0001", 3),
+    ]);
+    chain.mint("uscrt", "user0000", 1_000);
+    let msg = HandleMsg::Receive {
+        from: HumanAddr::from("user0000"),
+        msg: Some(route(
+            vec![
+                hop(Token::Snip20(snip20_data("sscrt0000")), "pair0000"),
+                hop(Token::Snip20(snip20_data("token0000")), "pair0001"),
+            ],
+            Some(6_000),
+        )),
+        amount: Uint128(1_000),
+    };
+    chain
+        .execute_router(
+            &HumanAddr::from("user0000"),
+            &[Coin::new(1_000, "uscrt")],
+            msg,
+        )
+        .unwrap();
+    assert_eq!(chain.balance("uscrt", "user0000"), 0);
+    assert_eq!(chain.balance("token0001", "user0000"), 6_000);
+    for token in ["uscrt", "sscrt0000", "token0000", "token0001"].iter() {
+        assert_eq!(chain.balance(token, MOCK_CONTRACT_ADDR), 0);
+    }
+}
+
+#[test]
+fn route_unwraps_output() {
+    let hops = || {
+        vec![
+            hop(Token::Snip20(snip20_data("token0000")), "pair0000"),
+            hop(Token::Snip20(snip20_data("token0001")), "pair0001"),
+        ]
+    };
+
+    let mut chain = sscrt_chain(&[
+        ("pair0000", "token0000", "token0001", 2),
+        ("pair0001", "token0001", "sscrt0000", 3),
+    ]);
+    chain.mint("token0000", "user0000", 1_000);
+    chain
+        .send_to_router(
+            "token0000",
+            "user0000",
+            1_000,
+            Some(unwrapped_route(hops(), Some(6_000))),
+        )
+        .unwrap();
+    assert_eq!(chain.balance("uscrt", "user0000"), 6_000);
+    assert_eq!(chain.balance("sscrt0000", "user0000"), 0);
+    for token in ["uscrt", "sscrt0000", "token0000", "token0001"].iter() {
+        assert_eq!(chain.balance(token, MOCK_CONTRACT_ADDR), 0);
+    }
+    assert!(read_route_state(&chain.deps.storage).unwrap().is_none());
+
+    // the minimum still holds for the sSCRT the last pair returns
+    let mut chain = sscrt_chain(&[
+        ("pair0000", "token0000", "token0001", 2),
+        ("pair0001", "token0001", "sscrt0000", 3),
+    ]);
+    chain.mint("token0000", "user0000", 1_000);
+    let res = chain.send_to_router(
+        "token0000",
+        "user0000",
+        1_000,
+        Some(unwrapped_route(hops(), Some(6_001))),
+    );
+    match res {
+        Err(StdError::GenericErr { msg, .. }) => {
+            assert_eq!(msg, "Operation fell short of expected_return")
+        }
+        _ => panic!("Must return generic error"),
+    }
+}
+
+#[test]
+fn route_unwrap_requires_sscrt_output() {
+    let pairs = [
+        ("pair0000", "token0000", "token0001", 2),
+        ("pair0001", "token0001", "token0000", 3),
+    ];
+    let hops = vec![
+        hop(Token::Snip20(snip20_data("token0000")), "pair0000"),
+        hop(Token::Snip20(snip20_data("token0001")), "pair0001"),
+    ];
+
+    for mut chain in vec![
+        Chain::new(&["token0000", "token0001"], &pairs),
+        sscrt_chain(&pairs),
+    ] {
+        chain.mint("token0000", "user0000", 1_000);
+        let msg = unwrapped_route(hops.clone(), None);
+        match chain.send_to_router("token0000", "user0000", 1_000, Some(msg)) {
+            Err(StdError::GenericErr { msg, .. }) => {
+                assert_eq!(msg, "unwrap_output needs a route that ends in sSCRT")
+            }
+            _ => panic!("Must return generic error"),
+        }
+    }
+}
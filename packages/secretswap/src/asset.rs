@@ -1,10 +1,11 @@
 use std::fmt;
 
 use cosmwasm_std::{
-    to_binary, Api, BankMsg, CanonicalAddr, Coin, CosmosMsg, Env, Extern, HumanAddr, Querier,
-    StdError, StdResult, Storage, Uint128, WasmMsg,
+    to_binary, Api, BankMsg, Binary, CanonicalAddr, Coin, CosmosMsg, Env, Extern, HumanAddr,
+    Querier, StdError, StdResult, Storage, Uint128, WasmMsg,
 };
 use schemars::JsonSchema;
+use secret_toolkit::crypto::sha_256;
 use secret_toolkit::snip20::HandleMsg;
 use serde::{Deserialize, Serialize};
 
@@ -57,7 +58,14 @@ impl Asset {
         deps: &Extern<S, A, Q>,
     ) -> StdResult<Coin> {
         let amount = self.amount;
-        Err(StdError::generic_err("cannot deduct tax from token asset"))
+        if let AssetInfo::NativeToken { denom } = &self.info {
+            Ok(Coin {
+                denom: denom.to_string(),
+                amount: (amount - self.compute_tax(deps)?)?,
+            })
+        } else {
+            Err(StdError::generic_err("cannot deduct tax from token asset"))
+        }
     }
 
     pub fn into_msg<S: Storage, A: Api, Q: Querier>(
@@ -86,10 +94,68 @@ impl Asset {
                 })?,
                 send: vec![],
             })),
+            AssetInfo::NativeToken { .. } => Ok(CosmosMsg::Bank(BankMsg::Send {
+                from_address: sender,
+                to_address: recipient,
+                amount: vec![self.deduct_tax(deps)?],
+            })),
+        }
+    }
+
+    /// Like into_msg, but notifies a recipient contract with `msg`. Tokens are
+    /// delivered with a snip20 Send, native coins are attached to an execute of
+    /// `msg` on the recipient.
+    pub fn into_send_msg<S: Storage, A: Api, Q: Querier>(
+        self,
+        deps: &Extern<S, A, Q>,
+        recipient: HumanAddr,
+        recipient_code_hash: String,
+        msg: Binary,
+    ) -> StdResult<CosmosMsg> {
+        let amount = self.amount;
+
+        match &self.info {
+            AssetInfo::Token {
+                contract_addr,
+                token_code_hash,
+                ..
+            } => Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: contract_addr.clone(),
+
+                callback_code_hash: token_code_hash.clone(),
+
+                msg: to_binary(&HandleMsg::Send {
+                    recipient,
+                    amount,
+                    padding: None,
+                    msg: Some(msg),
+                })?,
+                send: vec![],
+            })),
+            AssetInfo::NativeToken { .. } => Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: recipient,
+                callback_code_hash: recipient_code_hash,
+                msg,
+                send: vec![self.deduct_tax(deps)?],
+            })),
         }
     }
 
     pub fn assert_sent_native_token_balance(&self, env: &Env) -> StdResult<()> {
+        if let AssetInfo::NativeToken { denom } = &self.info {
+            let sent = env
+                .message
+                .sent_funds
+                .iter()
+                .find(|x| x.denom == *denom)
+                .map_or(Uint128::zero(), |coin| coin.amount);
+            if self.amount != sent {
+                return Err(StdError::generic_err(
+                    "Native token balance mismatch between the argument and the transferred",
+                ));
+            }
+        }
+
         Ok(())
     }
 
@@ -108,6 +174,9 @@ impl Asset {
                     token_code_hash: token_code_hash.clone(),
                     viewing_key: viewing_key.clone(),
                 },
+                AssetInfo::NativeToken { denom } => AssetInfoRaw::NativeToken {
+                    denom: denom.to_string(),
+                },
             },
             amount: self.amount,
         })
@@ -122,12 +191,16 @@ pub enum AssetInfo {
         token_code_hash: String,
         viewing_key: String,
     },
+    NativeToken {
+        denom: String,
+    },
 }
 
 impl fmt::Display for AssetInfo {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             AssetInfo::Token { contract_addr, .. } => write!(f, "{}", contract_addr),
+            AssetInfo::NativeToken { denom } => write!(f, "{}", denom),
         }
     }
 }
@@ -147,11 +220,15 @@ impl AssetInfo {
                 viewing_key: viewing_key.clone(),
                 token_code_hash: token_code_hash.clone(),
             }),
+            AssetInfo::NativeToken { denom } => Ok(AssetInfoRaw::NativeToken {
+                denom: denom.to_string(),
+            }),
         }
     }
 
     pub fn is_native_token(&self) -> bool {
         match self {
+            AssetInfo::NativeToken { .. } => true,
             AssetInfo::Token { .. } => false,
         }
     }
@@ -165,13 +242,26 @@ impl AssetInfo {
                 contract_addr,
                 viewing_key,
                 token_code_hash,
-            } => query_token_balance(
-                deps,
-                &contract_addr,
-                token_code_hash,
-                &pool_addr,
-                &viewing_key,
-            ),
+            } => {
+                // a wrong viewing key makes the token answer with an error rather than a
+                // balance, surface it instead of letting the pool look empty
+                query_token_balance(
+                    deps,
+                    &contract_addr,
+                    token_code_hash,
+                    &pool_addr,
+                    &viewing_key,
+                )
+                .map_err(|err| {
+                    StdError::generic_err(format!(
+                        "Failed to query the reserve of {}, the viewing key may be wrong: {}",
+                        contract_addr, err
+                    ))
+                })
+            }
+            AssetInfo::NativeToken { denom, .. } => {
+                query_balance(deps, &pool_addr, denom.to_string())
+            }
         }
     }
 
@@ -181,10 +271,23 @@ impl AssetInfo {
                 let self_contract_addr = contract_addr;
                 match asset {
                     AssetInfo::Token { contract_addr, .. } => self_contract_addr == contract_addr,
+                    AssetInfo::NativeToken { .. } => false,
+                }
+            }
+            AssetInfo::NativeToken { denom, .. } => {
+                let self_denom = denom;
+                match asset {
+                    AssetInfo::Token { .. } => false,
+                    AssetInfo::NativeToken { denom, .. } => self_denom == denom,
                 }
             }
         }
     }
+
+    /// Whether both arrays hold the same two assets, in either order
+    pub fn equal_unordered(a: &[AssetInfo; 2], b: &[AssetInfo; 2]) -> bool {
+        (a[0].equal(&b[0]) && a[1].equal(&b[1])) || (a[0].equal(&b[1]) && a[1].equal(&b[0]))
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -209,6 +312,9 @@ impl AssetRaw {
                     viewing_key: viewing_key.clone(),
                     token_code_hash: token_code_hash.clone(),
                 },
+                AssetInfoRaw::NativeToken { denom } => AssetInfo::NativeToken {
+                    denom: denom.to_string(),
+                },
             },
             amount: self.amount,
         })
@@ -228,6 +334,9 @@ pub enum AssetInfoRaw {
         token_code_hash: String,
         viewing_key: String,
     },
+    NativeToken {
+        denom: String,
+    },
 }
 
 impl AssetInfoRaw {
@@ -245,15 +354,43 @@ impl AssetInfoRaw {
                 viewing_key: viewing_key.clone(),
                 token_code_hash: token_code_hash.clone(),
             }),
+            AssetInfoRaw::NativeToken { denom } => Ok(AssetInfo::NativeToken {
+                denom: denom.to_string(),
+            }),
         }
     }
 
     pub fn as_bytes(&self) -> &[u8] {
         match self {
+            AssetInfoRaw::NativeToken { denom } => denom.as_bytes(),
             AssetInfoRaw::Token { contract_addr, .. } => contract_addr.as_slice(),
         }
     }
 
+    /// Identity of a pair of assets, the same whichever order they're given in.
+    /// Natives sort by denom and tokens by canonical address, natives first, and
+    /// the sorted identities are hashed.
+    pub fn canonical_pair_key(asset_infos: &[AssetInfoRaw; 2]) -> Vec<u8> {
+        let mut identities: Vec<Vec<u8>> = asset_infos
+            .iter()
+            .map(|info| {
+                let tag = match info {
+                    AssetInfoRaw::NativeToken { .. } => 0u8,
+                    AssetInfoRaw::Token { .. } => 1u8,
+                };
+                // length prefixed so the two identities can't run into each other
+                [
+                    &[tag][..],
+                    &(info.as_bytes().len() as u32).to_be_bytes(),
+                    info.as_bytes(),
+                ]
+                .concat()
+            })
+            .collect();
+        identities.sort();
+        sha_256(&identities.concat()).to_vec()
+    }
+
     pub fn equal(&self, asset: &AssetInfoRaw) -> bool {
         match self {
             AssetInfoRaw::Token { contract_addr, .. } => {
@@ -262,6 +399,14 @@ impl AssetInfoRaw {
                     AssetInfoRaw::Token { contract_addr, .. } => {
                         self_contract_addr == contract_addr
                     }
+                    AssetInfoRaw::NativeToken { .. } => false,
+                }
+            }
+            AssetInfoRaw::NativeToken { denom, .. } => {
+                let self_denom = denom;
+                match asset {
+                    AssetInfoRaw::Token { .. } => false,
+                    AssetInfoRaw::NativeToken { denom, .. } => self_denom == denom,
                 }
             }
         }
@@ -275,9 +420,23 @@ pub struct PairInfo {
     pub contract_addr: HumanAddr,
     pub liquidity_token: HumanAddr,
     pub token_code_hash: String,
+    /// Cumulative amount of asset 0 offered in swaps
     pub asset0_volume: Uint128,
+    /// Cumulative amount of asset 1 offered in swaps
     pub asset1_volume: Uint128,
     pub factory: Factory,
+    /// Decimals of each asset, for displaying prices in human units
+    #[serde(default)]
+    pub asset_decimals: Option<[u8; 2]>,
+    /// Symbol of each asset, the denom for native coins
+    #[serde(default)]
+    pub asset_symbols: Option<[String; 2]>,
+    /// Block the pair was created at
+    #[serde(default)]
+    pub created_at_height: Option<u64>,
+    /// Block time in seconds the pair was created at
+    #[serde(default)]
+    pub created_at_time: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -307,6 +466,10 @@ impl PairInfoRaw {
             asset0_volume: self.asset0_volume.clone(),
             asset1_volume: self.asset1_volume.clone(),
             factory: self.factory.clone(),
+            asset_decimals: None,
+            asset_symbols: None,
+            created_at_height: None,
+            created_at_time: None,
         })
     }
 
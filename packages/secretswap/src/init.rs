@@ -2,8 +2,9 @@ use cosmwasm_std::{Binary, HumanAddr, StdError, StdResult, Uint128};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::asset::AssetInfo;
+use crate::asset::{AssetInfo, Factory};
 use crate::hook::InitHook;
+use crate::pair_settings::CurveType;
 
 //use secret_toolkit::snip20::{MinterResponse};
 
@@ -40,6 +41,53 @@ pub struct PairInitMsg {
     /// Hook for post initalization
     pub init_hook: Option<InitHook>,
     pub prng_seed: Binary,
+    /// How many blocks the pair may use cached factory settings before re-querying,
+    /// below the 600 blocks after which a pair stops swapping on cached settings
+    pub settings_ttl_blocks: Option<u64>,
+    /// Lets native uscrt users trade against the pair's sSCRT asset
+    pub wrap_config: Option<WrapConfig>,
+    /// LP token name, defaults to one naming both assets
+    pub lp_token_name: Option<String>,
+    /// LP token symbol, defaults to SWAP-ANON-LP
+    pub lp_token_symbol: Option<String>,
+    /// LP token decimals, defaults to 18
+    pub lp_token_decimals: Option<u8>,
+    /// Keep the asset names out of the LP token label and default name
+    #[serde(default)]
+    pub generic_label: bool,
+    /// May run operational commands on the pair alongside the factory
+    pub admin: Option<HumanAddr>,
+    /// Factory the init hook must target, set by the factory when it creates the pair
+    pub expected_factory: Option<Factory>,
+    /// LP token to pair with instead of instantiating a new one
+    pub existing_liquidity_token: Option<ExistingLiquidityToken>,
+    /// Only addresses the factory whitelists may trade and provide liquidity
+    #[serde(default)]
+    pub restricted: bool,
+    /// Curve the pair swaps on instead of the factory's, e.g. stable for pegged assets
+    #[serde(default)]
+    pub curve: Option<CurveType>,
+    /// Percents of the pool value in each asset, e.g. [80, 20], for a weighted pair
+    /// instead of a 50/50 one. They sum to 100 and replace `curve`.
+    #[serde(default)]
+    pub weights: Option<[u8; 2]>,
+}
+
+/// An LP token contract a pair takes over at init
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ExistingLiquidityToken {
+    pub address: HumanAddr,
+    pub code_hash: String,
+    /// Accept a token that already has holders, when migrating an existing pool
+    #[serde(default)]
+    pub allow_nonzero_supply: bool,
+}
+
+/// The sSCRT contract a pair deposits native uscrt into
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct WrapConfig {
+    pub sscrt_addr: HumanAddr,
+    pub sscrt_code_hash: String,
 }
 
 /// TokenContract InitMsg
@@ -1,10 +1,13 @@
 pub use crate::asset::{Asset, AssetInfo, AssetInfoRaw, AssetRaw, Factory, PairInfo, PairInfoRaw};
 pub use crate::hook::InitHook;
-pub use crate::init::{Balance, PairInitMsg, TokenInitMsg};
+pub use crate::init::{Balance, ExistingLiquidityToken, PairInitMsg, TokenInitMsg, WrapConfig};
 pub use crate::msg::{
     FactoryHandleMsg, FactoryQueryMsg, PairCw20HookMsg, PairHandleMsg, PairQueryMsg,
 };
-pub use crate::pair_settings::{Fee, PairSettings, SwapDataEndpoint, SwapDataEndpointMsg};
+pub use crate::pair_settings::{
+    CurveType, Fee, PairSettings, PriceOracle, PriceOracleQueryMsg, ReferencePriceResponse,
+    SwapDataEndpoint, SwapDataEndpointMsg, DEFAULT_NOISE_BPS_MAX, MAX_AMP, MAX_NOISE_BPS,
+};
 pub use crate::querier::{
     query_all_balances, query_balance, query_pair_info, query_supply, query_token_balance,
     reverse_simulate, simulate,
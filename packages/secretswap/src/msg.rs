@@ -64,6 +64,10 @@ pub struct SimulationResponse {
     pub return_amount: Uint128,
     pub spread_amount: Uint128,
     pub commission_amount: Uint128,
+    #[serde(default)]
+    pub lp_commission_amount: Uint128,
+    #[serde(default)]
+    pub protocol_commission_amount: Uint128,
 }
 
 /// ReverseSimulationResponse returns reverse swap simulation response
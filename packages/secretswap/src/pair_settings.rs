@@ -1,8 +1,17 @@
-use crate::Asset;
-use cosmwasm_std::{to_binary, CosmosMsg, HumanAddr, StdResult, Uint128, WasmMsg};
+use crate::{Asset, AssetInfo};
+use cosmwasm_std::{to_binary, CosmosMsg, Decimal, HumanAddr, StdResult, Uint128, WasmMsg};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+/// Noise bound pairs apply to their reserves when `noise_bps_max` is unset
+pub const DEFAULT_NOISE_BPS_MAX: u16 = 99;
+
+/// Widest noise a factory can configure, 10% of the reserves
+pub const MAX_NOISE_BPS: u16 = 1_000;
+
+/// Highest amp a stable curve can have, Curve's `MAX_A`
+pub const MAX_AMP: u64 = 1_000_000;
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct SwapDataEndpoint {
     pub address: HumanAddr,
@@ -12,11 +21,91 @@ pub struct SwapDataEndpoint {
 pub struct Fee {
     pub commission_rate_nom: Uint128,
     pub commission_rate_denom: Uint128,
+    /// Lowest commission charged on a swap, in units of the returned asset.
+    /// Keeps dust swaps from rounding the commission down to zero.
+    #[serde(default)]
+    pub min_commission: Uint128,
 }
+/// Invariant a pair prices its swaps with
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CurveType {
+    /// x * y = k
+    ConstantProduct,
+    /// Curve's stableswap invariant for pegged assets, a higher `amp` gives
+    /// less slippage around the peg
+    Stable { amp: u64 },
+    /// Balancer's weighted constant mean `x^w0 * y^w1 = k`, for pools that aren't
+    /// 50/50. `weights` are the percents of the pool value in each asset, they sum
+    /// to 100. Only a pair can have weights, see `PairInitMsg::weights`.
+    Weighted { weights: [u8; 2] },
+}
+
+impl Default for CurveType {
+    fn default() -> Self {
+        CurveType::ConstantProduct
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct PairSettings {
     pub swap_fee: Fee,
     pub swap_data_endpoint: Option<SwapDataEndpoint>,
+    #[serde(default)]
+    pub curve: CurveType,
+    /// Scales the first LP supply of a pair, `sqrt(deposit_0 * deposit_1)` alone
+    /// can be tiny for low-decimal assets and round poorly for later depositors
+    #[serde(default)]
+    pub initial_share_multiplier: Option<Uint128>,
+    /// Halts swaps and deposits on every pair of the factory, withdrawals stay open
+    #[serde(default)]
+    pub paused: bool,
+    /// Neither pool is drained below this by a swap, and withdrawals are capped
+    /// to stay above it unless the factory opened the pair for a full exit
+    #[serde(default)]
+    pub min_reserve: Option<Uint128>,
+    /// Widest noise, in basis points, the reserve queries apply.
+    /// `DEFAULT_NOISE_BPS_MAX` when unset
+    #[serde(default)]
+    pub noise_bps_max: Option<u16>,
+    /// Draws the noise once per block instead of after every transaction
+    #[serde(default)]
+    pub noise_per_block: bool,
+    /// Leaves amounts and reserves out of the swap, deposit and withdrawal logs
+    #[serde(default)]
+    pub private_logs: bool,
+    /// Swaps fail once a block's offer volume of either asset would go past this
+    /// share, in basis points, of its reserve when the block started. Withdrawals
+    /// are never stopped.
+    #[serde(default)]
+    pub max_block_volume_bps: Option<u16>,
+    /// A swap that would leave a reserve below this share, in basis points, of what
+    /// it held before is refused and pauses the pair until the factory resets it.
+    /// Zero disables it, withdrawals are never checked.
+    #[serde(default)]
+    pub reserve_floor_bps: u16,
+    /// Swaps that would leave the pool price too far from this oracle's price are
+    /// rejected
+    #[serde(default)]
+    pub price_oracle: Option<PriceOracle>,
+    /// Takes the commission off the offer before the curve, like Uniswap, instead of
+    /// off the return after it. The same rate then returns a little more, the curve
+    /// prices the smaller offer at a better rate.
+    #[serde(default)]
+    pub fee_on_input: bool,
+}
+
+/// External reference price a pair checks its swaps against
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PriceOracle {
+    pub contract: HumanAddr,
+    pub code_hash: String,
+    /// Widest gap, in basis points, allowed between the pool price after a swap
+    /// and the reference price
+    pub max_deviation_bps: u16,
+    /// Lets swaps through when the oracle can't be queried, they fail otherwise
+    #[serde(default)]
+    pub fail_open: bool,
 }
 
 impl SwapDataEndpoint {
@@ -39,7 +128,22 @@ impl SwapDataEndpoint {
     }
 }
 
-#[derive(Serialize, Clone, Debug, PartialEq)]
+/// The query a `PriceOracle` has to answer
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceOracleQueryMsg {
+    /// Answered with a `ReferencePriceResponse`
+    ReferencePrice { asset_infos: [AssetInfo; 2] },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ReferencePriceResponse {
+    /// Units of `asset_infos[1]` one unit of `asset_infos[0]` is worth, in raw
+    /// amounts without decimals
+    pub price: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum SwapDataEndpointMsg {
     ReceiveSwapData {
@@ -1,9 +1,11 @@
 use cosmwasm_std::testing::MOCK_CONTRACT_ADDR;
-use cosmwasm_std::{to_binary, BankMsg, Coin, CosmosMsg, Decimal, HumanAddr, Uint128, WasmMsg};
+use cosmwasm_std::{
+    to_binary, BankMsg, CanonicalAddr, Coin, CosmosMsg, Decimal, HumanAddr, Uint128, WasmMsg,
+};
 
 use secret_toolkit::snip20;
 
-use crate::asset::{Asset, AssetInfo, PairInfo};
+use crate::asset::{Asset, AssetInfo, AssetInfoRaw, PairInfo};
 use crate::mock_querier::mock_dependencies;
 use crate::querier::{
     query_all_balances, query_balance, query_pair_info, query_supply, query_token_balance,
@@ -304,3 +306,79 @@ fn query_terraswap_pair_contract() {
     assert_eq!(pair_info.contract_addr, HumanAddr::from("pair0000"),);
     assert_eq!(pair_info.liquidity_token, HumanAddr::from("liquidity0000"),);
 }
+
+#[test]
+fn canonical_pair_key() {
+    let native = |denom: &str| AssetInfoRaw::NativeToken {
+        denom: denom.to_string(),
+    };
+    let token = |addr: &[u8]| AssetInfoRaw::Token {
+        contract_addr: CanonicalAddr::from(addr),
+        token_code_hash: "tokenhash".to_string(),
+        viewing_key: "vk".to_string(),
+    };
+
+    let key = AssetInfoRaw::canonical_pair_key(&[native("uscrt"), token(b"asset0000")]);
+    assert_eq!(
+        key,
+        AssetInfoRaw::canonical_pair_key(&[token(b"asset0000"), native("uscrt")])
+    );
+    assert_eq!(
+        AssetInfoRaw::canonical_pair_key(&[token(b"asset0000"), token(b"asset0001")]),
+        AssetInfoRaw::canonical_pair_key(&[token(b"asset0001"), token(b"asset0000")])
+    );
+
+    // only the identities count, not the code hash or viewing key
+    assert_eq!(
+        key,
+        AssetInfoRaw::canonical_pair_key(&[
+            native("uscrt"),
+            AssetInfoRaw::Token {
+                contract_addr: CanonicalAddr::from(&b"asset0000"[..]),
+                token_code_hash: "otherhash".to_string(),
+                viewing_key: "othervk".to_string(),
+            }
+        ])
+    );
+
+    for other in [
+        [native("uscrt"), token(b"asset0001")],
+        [native("uusd"), token(b"asset0000")],
+        [token(b"asset0000"), token(b"asset0001")],
+        // a denom spelling out a token address is still a different asset
+        [native("asset0000"), native("uscrt")],
+        // the boundary between the two identities matters
+        [native("uscrtasset"), token(b"0000")],
+    ]
+    .iter()
+    {
+        assert_ne!(key, AssetInfoRaw::canonical_pair_key(other));
+    }
+}
+
+#[test]
+fn equal_unordered() {
+    let native = |denom: &str| AssetInfo::NativeToken {
+        denom: denom.to_string(),
+    };
+    let token = |addr: &str| AssetInfo::Token {
+        contract_addr: HumanAddr::from(addr),
+        token_code_hash: "tokenhash".to_string(),
+        viewing_key: "vk".to_string(),
+    };
+
+    let pair = [native("uscrt"), token("asset0000")];
+    assert!(AssetInfo::equal_unordered(&pair, &pair));
+    assert!(AssetInfo::equal_unordered(
+        &pair,
+        &[token("asset0000"), native("uscrt")]
+    ));
+    assert!(!AssetInfo::equal_unordered(
+        &pair,
+        &[token("asset0000"), native("uusd")]
+    ));
+    assert!(!AssetInfo::equal_unordered(
+        &pair,
+        &[native("uscrt"), native("uscrt")]
+    ));
+}
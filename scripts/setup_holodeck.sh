@@ -91,8 +91,8 @@ export TX_HASH=$(
 wait_for_tx "$TX_HASH" "Waiting for tx to finish on-chain..."
 secretcli q compute tx $TX_HASH
 
-# update factory with the dummy contract as a swap data endpoint
-secretcli tx compute execute $(echo "$factory_contract" | tr -d '"') '{"update_config": {"swap_data_endpoint": {"address":'$dummy_contract', "code_hash":'$dummy_code_hash'}}}' -b block -y --from $deployer_name
+# queue the dummy contract as swap data endpoint, apply_settings enables it after the timelock
+secretcli tx compute execute $(echo "$factory_contract" | tr -d '"') '{"set_data_endpoint": {"endpoint": {"address":'$dummy_contract', "code_hash":'$dummy_code_hash'}}}' -b block -y --from $deployer_name
 
 echo Factory: "$factory_contract" | tr -d '"'
 echo Dummy: "$dummy_contract" | tr -d '"'